@@ -1,8 +1,21 @@
+pub mod account_switcher;
 pub mod additional_authorization;
+pub mod attachment;
+pub mod composer;
+pub mod file;
 pub mod form;
+pub mod identity_provider_button;
+pub mod image;
+pub mod matrix_image;
 pub mod message;
+pub mod recent_servers;
 pub mod room_selection_button;
+pub mod room_trust;
+pub mod session_tile;
 pub mod space_selection_button;
+pub mod timeline;
+pub mod user_profile;
+pub mod verification;
 pub mod vertical_sidebar;
 
 pub static ICON: &[u8] = include_bytes!("../../icon.svg");
@@ -0,0 +1,121 @@
+use dioxus_router::prelude::navigator;
+use freya::prelude::*;
+use ruma::UserId;
+use tracing::warn;
+
+use crate::components::vertical_sidebar::VerticalSideBar;
+use crate::hook::session::{start_adding_account, switch_account};
+use crate::{ACCOUNTS, CLIENT, MatrixClientState, Route};
+
+/// Sidebar column with every logged in account, the badge on top adds up the unread count of all of them
+#[component]
+pub fn AccountSwitcher() -> Element {
+    let navigator = navigator();
+
+    let active = match CLIENT() {
+        MatrixClientState::Connected(client) => client.user_id().map(ToOwned::to_owned),
+        _ => None,
+    };
+    let total_unread: u64 = ACCOUNTS.read().iter().map(|account| account.unread).sum();
+
+    let add_account = move |_| {
+        start_adding_account();
+        navigator.push(Route::Login);
+    };
+
+    rsx!(
+        rect {
+            width: "64",
+            height: "fill",
+            padding: "8",
+            spacing: "8",
+            content: "flex",
+            direction: "vertical",
+            cross_align: "center",
+            background: "#f2f2f2",
+
+            UnreadBadge { count: total_unread }
+
+            rect {
+                width: "fill",
+                height: "flex(1)",
+
+                VerticalSideBar {
+                    for account in ACCOUNTS.read().iter() {
+                        AccountButton {
+                            key: "{account.user_id}",
+                            user_id: account.user_id.to_string(),
+                            unread: account.unread,
+                            selected: active.as_ref() == Some(&account.user_id),
+                        }
+                    }
+                }
+            }
+
+            Button {
+                onclick: add_account,
+                label { "+" }
+            }
+        }
+    )
+}
+
+/// Selects the account it belongs to when clicked
+#[component]
+fn AccountButton(user_id: String, unread: u64, selected: bool) -> Element {
+    let initial = user_id
+        .trim_start_matches('@')
+        .chars()
+        .next()
+        .unwrap_or('?')
+        .to_uppercase()
+        .to_string();
+    let border = if selected { "2 inner #6ddbff" } else { "none" };
+
+    let clicked = move |_| {
+        let Ok(user_id) = UserId::parse(&user_id) else {
+            warn!("account switcher got invalid user id {user_id}");
+            return;
+        };
+        spawn(async move {
+            switch_account(&user_id).await;
+        });
+    };
+
+    rsx!(
+        rect {
+            margin: "0 0 8 0",
+            cross_align: "center",
+            border: border,
+            corner_radius: "24",
+
+            Button {
+                onclick: clicked,
+                label { "{initial}" }
+            }
+
+            UnreadBadge { count: unread }
+        }
+    )
+}
+
+#[component]
+fn UnreadBadge(count: u64) -> Element {
+    if count == 0 {
+        return rsx!();
+    }
+
+    rsx!(
+        rect {
+            padding: "2 6",
+            corner_radius: "8",
+            background: "#ff74d1",
+
+            label {
+                color: "white",
+                font_size: "12",
+                "{count}"
+            }
+        }
+    )
+}
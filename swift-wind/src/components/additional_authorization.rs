@@ -1,16 +1,35 @@
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 use freya::prelude::*;
-use ruma::api::client::uiaa::{AuthData, AuthType, Dummy, RegistrationToken};
-use tracing::error;
+use ruma::api::client::uiaa::{
+    AuthData, AuthType, Dummy, FallbackAcknowledgement, Password, RegistrationToken, Terms,
+    UserIdentifier,
+};
+use serde_json::value::RawValue as RawJsonValue;
+use swift_wind::matrix_register::registration_token_validity;
+use swift_wind::matrix_uiaa::{
+    TermsPolicy, UiaaOperation, fallback_url, submit_msisdn_token, terms_policies,
+    threepid_auth_data,
+};
+use tracing::{error, trace, warn};
 
-use crate::hook::{CommonUserAuthData, submit_additional_auth::*};
+use crate::components::form::FormField;
+use crate::hook::submit_additional_auth::*;
+use crate::{CLIENT, MatrixClientState};
+
+/// How often a stage finished in the browser is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// How long a stage in the browser is waited for before polling stops.
+const POLL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
 
 pub enum AuthenticationState {
     Authorized,
     AdditionalAuthRequired {
         chosen_flow: VecDeque<AuthType>,
-        common_user_data: CommonUserAuthData,
+        session: Option<String>,
+        /// Stage parameters from the server, like the policies of a terms stage.
+        params: Box<RawJsonValue>,
     },
 }
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -25,18 +44,27 @@ pub fn AdditonalAuthHandler(
     mut state: Signal<Option<AuthenticationState>>,
     additional_auth_type: AdditionalAuthType,
 ) -> Element {
-    //Gonna need a lot more forms
     let mut token_form = use_signal(RegisterTokenForm::default);
+    let mut token_err = use_signal(String::new);
 
-    let (error_string, mut run_auth) = use_matrix_additional_auth(move |auth_res| match auth_res {
-        //Go to next stage of auth, force update by popping auth type from flow
-        HookAuthResult::NextStage => {
-            if let AuthenticationState::AdditionalAuthRequired {
-                chosen_flow,
-                common_user_data: _,
-            } = state.write().as_mut().unwrap()
+    let (error_string, run_auth) = use_matrix_additional_auth(move |auth_res| match auth_res {
+        // Drop the stages the server counts as done, a stage still pending in the browser
+        // leaves the flow as it is
+        HookAuthResult::NextStage(completed) => {
+            let progressed = matches!(
+                state.peek().as_ref(),
+                Some(AuthenticationState::AdditionalAuthRequired { chosen_flow, .. })
+                    if chosen_flow.iter().any(|stage| completed.contains(stage))
+            );
+            if !progressed {
+                trace!("authentication stage not completed yet");
+                return;
+            }
+            if let Some(AuthenticationState::AdditionalAuthRequired { chosen_flow, .. }) =
+                state.write().as_mut()
             {
-                if chosen_flow.pop_front().is_none() {
+                chosen_flow.retain(|stage| !completed.contains(stage));
+                if chosen_flow.is_empty() {
                     error!(
                         "No more authentication flow to complete but server still request additional auth"
                     );
@@ -48,70 +76,481 @@ pub fn AdditonalAuthHandler(
         }
     });
 
-    rsx! {
-        match state.read().as_ref().unwrap() {
-            AuthenticationState::Authorized => {
-                rsx! {label { "Authentication completed, please wait..." }}
-            }
-            AuthenticationState::AdditionalAuthRequired {
-                chosen_flow,
-                common_user_data,
-            } => {
-                // Depending on the first element choose what should be displayed
-                // Then, whether through automatic or form submission give the hook the authorization data
-                let current_auth_step = chosen_flow.front().unwrap();
-                let common_user_data = common_user_data.clone();
-
-                match current_auth_step {
-                    AuthType::ReCaptcha => todo!(),
-                    AuthType::EmailIdentity => todo!(),
-                    AuthType::Msisdn => todo!(),
-                    AuthType::Sso => todo!(),
-                    AuthType::Dummy => {
-                        let mut dummy = Dummy::new();
-                        dummy.session = common_user_data.session_id.clone();
-                        run_auth(AuthData::Dummy(dummy), additional_auth_type);
-                        rsx! {
-                            //Probably can just remove this
-                            label { "Attempting authentication" }
-                            Loader{}
-                        }
+    let stage = match state.read().as_ref() {
+        None => rsx!(),
+        Some(AuthenticationState::Authorized) => {
+            rsx! {label { "Authentication completed, please wait..." }}
+        }
+        Some(AuthenticationState::AdditionalAuthRequired {
+            chosen_flow,
+            session,
+            params,
+        }) => {
+            // Depending on the first element choose what should be displayed
+            // Then, whether through automatic or form submission give the hook the authorization data
+            let Some(current_auth_step) = chosen_flow.front().cloned() else {
+                return rsx!(
+                    label {
+                        color: "red",
+                        "The server asked for more authentication than its flow describes"
                     }
-                    AuthType::RegistrationToken => {
-                        let send_registration_token = move |_| {
-                            let mut token = RegistrationToken::new(token_form().token);
-                            token.session = common_user_data.session_id.clone();
-                            run_auth(
-                                AuthData::RegistrationToken(token),
-                                additional_auth_type.clone(),
-                            );
-                        };
+                );
+            };
+            let session = session.clone();
+            let stage_name = current_auth_step.as_str().to_owned();
+            let submit = {
+                let run_auth = run_auth.clone();
+                let additional_auth_type = additional_auth_type.clone();
+                move |data: AuthData| {
+                    let mut run_auth = run_auth.clone();
+                    run_auth(data, additional_auth_type.clone());
+                }
+            };
 
-                        rsx! {
-                            label { "Enter your Registration Token" }
-                            Input {
-                                placeholder: "Token",
-                                value: token_form().token,
-                                onchange: move|txt|{
-                                    token_form.write().token = txt
+            match current_auth_step {
+                AuthType::Dummy => {
+                    let mut dummy = Dummy::new();
+                    dummy.session = session;
+                    submit(AuthData::Dummy(dummy));
+                    rsx! {
+                        //Probably can just remove this
+                        label { "Attempting authentication" }
+                        Loader{}
+                    }
+                }
+                AuthType::RegistrationToken => {
+                    let send_registration_token = move |_| {
+                        let MatrixClientState::Connected(client) = CLIENT() else {
+                            return;
+                        };
+                        token_err.write().clear();
+                        let token = token_form().token.trim().to_owned();
+                        let session = session.clone();
+                        let submit = submit.clone();
+                        spawn(async move {
+                            // Catch a mistyped token before the server counts it as a failed stage
+                            match registration_token_validity(&client, &token).await {
+                                Ok(Some(false)) => {
+                                    *token_err.write() =
+                                        "This registration token isn't valid".to_string();
+                                    return;
                                 }
+                                Ok(_) => {}
+                                Err(err) => warn!("Checking registration token failed: {err}"),
                             }
-                            Button {
-                                onclick: send_registration_token,
-                                label {
-                                    "Submit"
-                                }
+                            let mut token = RegistrationToken::new(token);
+                            token.session = session;
+                            submit(AuthData::RegistrationToken(token));
+                        });
+                    };
+
+                    rsx! {
+                        label { "Enter your Registration Token" }
+                        Input {
+                            placeholder: "Token",
+                            value: token_form().token,
+                            onchange: move|txt|{
+                                token_form.write().token = txt
                             }
                         }
+                        Button {
+                            onclick: send_registration_token,
+                            label {
+                                "Submit"
+                            }
+                        }
+                        label {
+                            color: "red",
+                            "{token_err}"
+                        }
                     }
-                    AuthType::Terms => todo!(),
-                    _ => todo!(),
+                }
+                AuthType::Password => {
+                    let user = match &additional_auth_type {
+                        AdditionalAuthType::Login(data) | AdditionalAuthType::Register(data) => {
+                            Some(data.username.clone())
+                        }
+                        AdditionalAuthType::Operation(_) => match CLIENT() {
+                            MatrixClientState::Connected(client) => {
+                                client.user_id().map(|user_id| user_id.to_string())
+                            }
+                            _ => None,
+                        },
+                    };
+                    rsx!(PasswordStage {
+                        onsubmit: move |password| {
+                            let Some(user) = user.clone() else {
+                                warn!("password stage without a user to authenticate");
+                                return;
+                            };
+                            let mut password =
+                                Password::new(UserIdentifier::UserIdOrLocalpart(user), password);
+                            password.session = session.clone();
+                            submit(AuthData::Password(password));
+                        },
+                    })
+                }
+                AuthType::Terms => {
+                    rsx!(TermsStage {
+                        policies: terms_policies(params, "en"),
+                        onaccept: move |_| {
+                            let mut terms = Terms::new();
+                            terms.session = session.clone();
+                            submit(AuthData::Terms(terms));
+                        },
+                    })
+                }
+                AuthType::EmailIdentity | AuthType::Msisdn => {
+                    rsx!(ThreepidStage {
+                        key: "{stage_name}",
+                        stage: current_auth_step,
+                        session,
+                        additional_auth_type: additional_auth_type.clone(),
+                        onsubmit: submit,
+                    })
+                }
+                // ReCaptcha, SSO and stages swift-wind doesn't know are done in the browser
+                stage => {
+                    rsx!(FallbackStage {
+                        key: "{stage_name}",
+                        stage,
+                        session,
+                        error: error_string,
+                        onsubmit: submit,
+                    })
                 }
             }
         }
+    };
+
+    rsx! {
+        {stage}
         label {
             color: "red",
             "{error_string}"
         }
     }
 }
+
+/// Operation waiting on user-interactive auth, the id gives each attempt a fresh prompt
+#[derive(Clone, PartialEq, Debug)]
+pub struct PendingOperation {
+    pub id: u32,
+    pub operation: UiaaOperation,
+}
+
+/// Sends `operation` and walks the user through the auth the server asks for
+#[component]
+pub fn UiaaPrompt(operation: UiaaOperation, ondone: EventHandler<()>) -> Element {
+    let (error_string, run_operation, state) = use_matrix_uiaa();
+
+    use_hook({
+        let operation = operation.clone();
+        let mut run_operation = run_operation.clone();
+        move || run_operation(operation)
+    });
+
+    use_effect(move || {
+        if matches!(&*state.read(), Some(AuthenticationState::Authorized)) {
+            ondone.call(());
+        }
+    });
+
+    rsx!(
+        if state.read().is_some() {
+            AdditonalAuthHandler {
+                state,
+                additional_auth_type: AdditionalAuthType::Operation(operation),
+            }
+        } else if error_string.read().is_empty() {
+            Loader {}
+        }
+
+        label {
+            color: "red",
+            "{error_string}"
+        }
+    )
+}
+
+/// Asks for the account password again
+#[component]
+fn PasswordStage(onsubmit: EventHandler<String>) -> Element {
+    let mut password = use_signal(String::new);
+
+    rsx!(
+        FormField {
+            name: "Confirm your password to continue",
+            value: password,
+            hidden: true,
+            onchange: move |txt| password.set(txt),
+        }
+        Button {
+            onclick: move |_| onsubmit.call(password()),
+            label { "Continue" }
+        }
+    )
+}
+
+/// Lists the policies of a terms stage with links to read them
+#[component]
+fn TermsStage(policies: Vec<TermsPolicy>, onaccept: EventHandler<()>) -> Element {
+    rsx!(
+        label { "Please read and accept the server's policies" }
+
+        {policies.into_iter().map(|policy| {
+            let url = policy.url.clone();
+            rsx!(
+                Button {
+                    key: "{policy.id}",
+                    onclick: move |_| {
+                        if let Err(err) = open::that(url.as_str()) {
+                            warn!("failed to open policy {:?}", err);
+                        }
+                    },
+                    label { "{policy.name} (version {policy.version})" }
+                }
+            )
+        })}
+
+        Button {
+            onclick: move |_| onaccept.call(()),
+            label { "Accept and continue" }
+        }
+    )
+}
+
+/// Sends a validation code to an email or phone number and submits it once validated
+#[component]
+fn ThreepidStage(
+    stage: AuthType,
+    session: Option<String>,
+    additional_auth_type: AdditionalAuthType,
+    onsubmit: EventHandler<AuthData>,
+) -> Element {
+    let mut email = use_signal(String::new);
+    let mut country = use_signal(String::new);
+    let mut phone_number = use_signal(String::new);
+    let mut code = use_signal(String::new);
+    let mut code_err = use_signal(String::new);
+    let (request_err, mut request_token, threepid_session) = use_matrix_request_threepid_token();
+    let is_email = stage == AuthType::EmailIdentity;
+
+    let send_code = move |_| {
+        let address = if is_email {
+            ThreepidAddress::Email(email().trim().to_owned())
+        } else {
+            ThreepidAddress::Msisdn {
+                country: country().trim().to_uppercase(),
+                phone_number: phone_number().trim().to_owned(),
+            }
+        };
+        request_token(address, additional_auth_type.clone());
+    };
+
+    let submit_validated = {
+        let stage = stage.clone();
+        move |_| {
+            let Some(threepid) = threepid_session() else {
+                return;
+            };
+            code_err.write().clear();
+            let Some(data) = threepid_auth_data(
+                &stage,
+                session.clone(),
+                &threepid.sid,
+                &threepid.client_secret,
+            ) else {
+                return;
+            };
+            if is_email {
+                onsubmit.call(data);
+                return;
+            }
+            let Some(submit_url) = threepid.submit_url else {
+                *code_err.write() = "This server validates phone numbers through an identity server, which swift-wind doesn't support".to_string();
+                return;
+            };
+            spawn(async move {
+                let res = submit_msisdn_token(
+                    &submit_url,
+                    &threepid.sid,
+                    &threepid.client_secret,
+                    &code(),
+                )
+                .await;
+                match res {
+                    Ok(()) => onsubmit.call(data),
+                    Err(err) => {
+                        error!("Phone number validation failed: {err}");
+                        *code_err.write() = err.to_string();
+                    }
+                }
+            });
+        }
+    };
+
+    rsx!(
+        if is_email {
+            FormField {
+                name: "Email",
+                value: email,
+                onchange: move |txt| email.set(txt),
+            }
+        } else {
+            FormField {
+                name: "Country code",
+                value: country,
+                placeholder: "GB",
+                onchange: move |txt| country.set(txt),
+            }
+            FormField {
+                name: "Phone number",
+                value: phone_number,
+                onchange: move |txt| phone_number.set(txt),
+            }
+        }
+
+        Button {
+            onclick: send_code,
+            label {
+                if threepid_session.read().is_some() { "Send again" } else { "Send code" }
+            }
+        }
+
+        label {
+            color: "red",
+            "{request_err}"
+        }
+
+        if threepid_session.read().is_some() {
+            if is_email {
+                label { "Open the link sent to {email} then continue" }
+            } else {
+                FormField {
+                    name: "Code",
+                    value: code,
+                    onchange: move |txt| code.set(txt),
+                }
+            }
+
+            Button {
+                onclick: submit_validated,
+                label { "Continue" }
+            }
+
+            label {
+                color: "red",
+                "{code_err}"
+            }
+        }
+    )
+}
+
+/// Opens the homeserver's fallback page for a stage and polls until it's done
+///
+/// Polling stops once `error` reports a failed check, after [`POLL_TIMEOUT`] or on cancel.
+#[component]
+fn FallbackStage(
+    stage: AuthType,
+    session: Option<String>,
+    error: ReadOnlySignal<String>,
+    onsubmit: EventHandler<AuthData>,
+) -> Element {
+    let mut polling: Signal<Option<Task>> = use_signal(|| None);
+    let mut open_err = use_signal(String::new);
+
+    let homeserver = match CLIENT() {
+        MatrixClientState::Connected(client) => Some(client.homeserver()),
+        _ => None,
+    };
+    let stage_name = stage.as_str().to_owned();
+    let (Some(session), Some(homeserver)) = (session, homeserver) else {
+        return rsx!(
+            label {
+                color: "red",
+                "The server asks for a \"{stage_name}\" step swift-wind can't complete"
+            }
+        );
+    };
+    let url = fallback_url(&homeserver, &stage, &session);
+    let acknowledgement = AuthData::FallbackAcknowledgement(FallbackAcknowledgement::new(session));
+    let description = match stage {
+        AuthType::ReCaptcha => "Prove you're not a robot in your browser".to_string(),
+        AuthType::Sso => "Confirm your identity with single sign-on in your browser".to_string(),
+        _ => format!("Complete the \"{stage_name}\" step in your browser"),
+    };
+
+    let open = {
+        let acknowledgement = acknowledgement.clone();
+        move |_| {
+            open_err.write().clear();
+            if let Err(err) = open::that(url.as_str()) {
+                *open_err.write() = format!("couldn't open the browser: {err}");
+                return;
+            }
+            if polling.peek().is_some() {
+                return;
+            }
+            let acknowledgement = acknowledgement.clone();
+            // The stage component goes away once the server counts the stage as done,
+            // which stops this task.
+            let task = spawn(async move {
+                let started = Instant::now();
+                tokio::time::sleep(POLL_INTERVAL).await;
+                loop {
+                    if started.elapsed() > POLL_TIMEOUT {
+                        *open_err.write() =
+                            "Stopped waiting for the browser, open it again to retry".to_string();
+                        break;
+                    }
+                    onsubmit.call(acknowledgement.clone());
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    // A stage that isn't done yet leaves no error, anything else won't get better
+                    if !error.peek().is_empty() {
+                        break;
+                    }
+                }
+                polling.set(None);
+            });
+            polling.set(Some(task));
+        }
+    };
+
+    rsx!(
+        label { "{description}" }
+
+        Button {
+            onclick: open,
+            label { "Open browser" }
+        }
+
+        if polling.read().is_some() {
+            label { "Waiting for the browser..." }
+            rect {
+                content: "flex",
+                direction: "horizontal",
+                spacing: "10",
+
+                Button {
+                    onclick: move |_| onsubmit.call(acknowledgement.clone()),
+                    label { "I'm done" }
+                }
+
+                Button {
+                    onclick: move |_| {
+                        if let Some(task) = polling.take() {
+                            task.cancel();
+                        }
+                    },
+                    label { "Cancel" }
+                }
+            }
+        }
+
+        label {
+            color: "red",
+            "{open_err}"
+        }
+    )
+}
@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use freya::prelude::*;
+use swift_wind::matrix_attachment::{Attachment, AttachmentKind};
+use swift_wind::matrix_image::fit_size;
+
+use crate::components::form::FormField;
+use crate::hook::attachment::{use_matrix_attachment_preview, use_matrix_save_attachment};
+
+/// Video or audio message, decrypted in memory and saved where the user asks
+#[component]
+pub fn AttachmentMessage(attachment: ReadOnlySignal<Attachment>) -> Element {
+    let Attachment {
+        kind,
+        name,
+        size,
+        thumbnail,
+        ..
+    } = attachment();
+    let kind_label = match kind {
+        AttachmentKind::Image => "Image",
+        AttachmentKind::File => "File",
+        AttachmentKind::Video => "Video",
+        AttachmentKind::Audio => "Audio",
+    };
+    let size_label = size
+        .map(|size| format!("({})", readable_size(size)))
+        .unwrap_or_default();
+    let lock = if attachment.read().is_encrypted() {
+        "🔒 "
+    } else {
+        ""
+    };
+
+    rsx!(
+        rect {
+            direction: "vertical",
+            spacing: "5",
+            padding: "8",
+            corner_radius: "8",
+            background: "#f2f2f2",
+
+            //Images have their own component, this is for video thumbnails
+            if thumbnail.is_some() {
+                AttachmentPreview { attachment }
+            }
+
+            label { "{lock}{kind_label}: {name} {size_label}" }
+
+            SaveAttachment { attachment }
+        }
+    )
+}
+
+/// Path field that streams the attachment to disk, asking before replacing a file
+#[component]
+pub fn SaveAttachment(attachment: ReadOnlySignal<Attachment>) -> Element {
+    let (save_err, run_save_attachment, saved_path, mut existing_path) =
+        use_matrix_save_attachment();
+    let mut form_save_path = use_signal(String::new);
+
+    let name = attachment.read().name.clone();
+    let saved_path = saved_path().map(|path| path.display().to_string());
+
+    let save = {
+        let mut run_save_attachment = run_save_attachment.clone();
+        move |_| {
+            run_save_attachment(
+                attachment.read().source.clone(),
+                PathBuf::from(form_save_path().trim()),
+                false,
+            )
+        }
+    };
+    let replace = {
+        let mut run_save_attachment = run_save_attachment.clone();
+        move |_| {
+            if let Some(path) = existing_path() {
+                run_save_attachment(attachment.read().source.clone(), path, true)
+            }
+        }
+    };
+
+    rsx!(
+        rect {
+            direction: "vertical",
+            spacing: "5",
+
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+                cross_align: "center",
+
+                FormField {
+                    name: "Save to",
+                    value: form_save_path,
+                    placeholder: "/path/to/{name}",
+                    errors: save_err,
+                    onchange: move |txt| {
+                        *form_save_path.write() = txt;
+                     },
+                }
+
+                Button {
+                    onclick: save,
+                    label { "Save" }
+                }
+            }
+
+            if let Some(path) = existing_path() {
+                rect {
+                    direction: "horizontal",
+                    spacing: "5",
+                    cross_align: "center",
+
+                    label { "{path.display()} already exists" }
+
+                    Button {
+                        onclick: replace,
+                        label { "Replace" }
+                    }
+
+                    Button {
+                        onclick: move |_| existing_path.set(None),
+                        label { "Cancel" }
+                    }
+                }
+            }
+
+            if let Some(path) = saved_path {
+                label {
+                    color: "green",
+
+                    "Saved to {path}"
+                }
+            }
+        }
+    )
+}
+
+#[component]
+fn AttachmentPreview(attachment: ReadOnlySignal<Attachment>) -> Element {
+    let content = use_matrix_attachment_preview(attachment);
+    let (width, height) = fit_size(attachment.read().dimensions, 320, 240);
+
+    match &*content.read() {
+        None => rsx!(Loader {}),
+        Some(Err(err)) => rsx!(
+            label {
+                color: "red",
+
+                "{err}"
+            }
+        ),
+        Some(Ok(data)) => rsx!(image {
+            width: "{width}",
+            height: "{height}",
+            image_data: dynamic_bytes(data.clone()),
+        }),
+    }
+}
+
+pub fn readable_size(bytes: u64) -> String {
+    match bytes {
+        0..1_000 => format!("{bytes} B"),
+        1_000..1_000_000 => format!("{:.1} kB", bytes as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.1} GB", bytes as f64 / 1e9),
+    }
+}
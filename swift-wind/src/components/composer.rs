@@ -0,0 +1,50 @@
+use freya::prelude::*;
+use ruma::OwnedRoomId;
+
+use crate::hook::send_message::use_matrix_send_message;
+
+/// Text field and send button under a room
+#[component]
+pub fn MessageComposer(room_id: ReadOnlySignal<OwnedRoomId>) -> Element {
+    let mut body = use_signal(String::new);
+    let (send_err, run_send) = use_matrix_send_message(move || body.write().clear());
+
+    let send = move |_| {
+        let text = body();
+        if text.trim().is_empty() {
+            return;
+        }
+        let mut run_send = run_send.clone();
+        run_send(room_id(), text);
+    };
+
+    rsx!(
+        rect {
+            direction: "vertical",
+            spacing: "5",
+
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+                cross_align: "center",
+
+                Input {
+                    placeholder: "Message",
+                    value: body(),
+                    onchange: move |txt| body.set(txt),
+                }
+
+                Button {
+                    onclick: send,
+                    label { "Send" }
+                }
+            }
+
+            label {
+                color: "red",
+
+                "{send_err}"
+            }
+        }
+    )
+}
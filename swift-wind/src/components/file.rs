@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use freya::prelude::*;
+use swift_wind::matrix_attachment::Attachment;
+use swift_wind::matrix_download::default_download_dir;
+
+use crate::components::attachment::readable_size;
+use crate::components::form::FormField;
+use crate::hook::download::use_matrix_download;
+
+/// File message as a card, downloads go through the download manager
+#[component]
+pub fn FileMessage(attachment: ReadOnlySignal<Attachment>) -> Element {
+    let (download_err, mut run_download) = use_matrix_download();
+    let mut form_folder = use_signal(String::new);
+    let mut started = use_signal(|| false);
+
+    let Attachment {
+        name,
+        mimetype,
+        size,
+        ..
+    } = attachment();
+    let icon = mimetype_icon(mimetype.as_deref());
+    let details = [mimetype, size.map(readable_size)]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" · ");
+    let lock = if attachment.read().is_encrypted() {
+        "🔒 "
+    } else {
+        ""
+    };
+    let default_folder = default_download_dir().display().to_string();
+
+    rsx!(
+        rect {
+            direction: "vertical",
+            spacing: "5",
+            padding: "8",
+            corner_radius: "8",
+            background: "#f2f2f2",
+
+            rect {
+                direction: "horizontal",
+                spacing: "8",
+                cross_align: "center",
+
+                label { font_size: "32", "{icon}" }
+
+                rect {
+                    direction: "vertical",
+
+                    label { "{lock}{name}" }
+                    label {
+                        color: "grey",
+                        font_size: "10",
+
+                        "{details}"
+                    }
+                }
+            }
+
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+                cross_align: "center",
+
+                FormField {
+                    name: "Download to",
+                    value: form_folder,
+                    placeholder: default_folder,
+                    errors: download_err,
+                    onchange: move |txt| {
+                        *form_folder.write() = txt;
+                    },
+                }
+
+                Button {
+                    onclick: move |_| {
+                        let folder = form_folder();
+                        let folder = folder.trim();
+                        let folder = (!folder.is_empty()).then(|| PathBuf::from(folder));
+                        run_download(attachment(), folder);
+                        started.set(true);
+                    },
+                    label { "Download" }
+                }
+            }
+
+            if started() && download_err.read().is_empty() {
+                label {
+                    color: "grey",
+                    font_size: "10",
+
+                    "Follow it in Downloads"
+                }
+            }
+        }
+    )
+}
+
+fn mimetype_icon(mimetype: Option<&str>) -> &'static str {
+    let Some(mimetype) = mimetype else {
+        return "📎";
+    };
+    match mimetype.split_once('/') {
+        Some(("image", _)) => "🖼",
+        Some(("audio", _)) => "🎵",
+        Some(("video", _)) => "🎞",
+        Some(("text", _)) => "📄",
+        Some((_, "pdf")) => "📕",
+        Some((_, "zip" | "gzip" | "x-tar" | "x-7z-compressed" | "x-rar-compressed" | "zstd")) => {
+            "📦"
+        }
+        _ => "📎",
+    }
+}
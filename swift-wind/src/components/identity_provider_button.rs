@@ -0,0 +1,57 @@
+use freya::prelude::*;
+use matrix_sdk::media::{MediaFormat, MediaRequestParameters};
+use ruma::{OwnedMxcUri, events::room::MediaSource};
+use tracing::warn;
+
+use crate::{CLIENT, MatrixClientState};
+
+/// Single sign-on button for one identity provider, with the provider icon when the server has one
+#[component]
+pub fn IdentityProviderButton(
+    name: String,
+    icon: Option<String>,
+    onclick: EventHandler<()>,
+) -> Element {
+    let icon_data = use_resource(move || {
+        let icon = icon.clone();
+        async move {
+            let icon = OwnedMxcUri::from(icon?);
+            let MatrixClientState::Connected(client) = CLIENT() else {
+                return None;
+            };
+            let request = MediaRequestParameters {
+                source: MediaSource::Plain(icon),
+                format: MediaFormat::File,
+            };
+            match client.media().get_media_content(&request, true).await {
+                Ok(data) => Some(data),
+                Err(err) => {
+                    warn!("failed to load identity provider icon {:?}", err);
+                    None
+                }
+            }
+        }
+    });
+
+    rsx!(
+        Button {
+            onclick: move |_| onclick.call(()),
+
+            rect {
+                direction: "horizontal",
+                spacing: "8",
+                cross_align: "center",
+
+                if let Some(Some(data)) = icon_data.read().as_ref() {
+                    image {
+                        width: "20",
+                        height: "20",
+                        image_data: dynamic_bytes(data.clone()),
+                    }
+                }
+
+                label { "{name}" }
+            }
+        }
+    )
+}
@@ -0,0 +1,316 @@
+use std::time::Duration;
+
+use freya::prelude::*;
+use ruma::OwnedEventId;
+use swift_wind::matrix_attachment::Attachment;
+use swift_wind::matrix_image::{AnimationFrame, DecodedImage, blurhash_png, fit_size};
+use tracing::warn;
+
+use crate::components::attachment::SaveAttachment;
+use crate::hook::image::use_matrix_image;
+use crate::{GALLERY, LIGHTBOX, TIMELINE};
+
+const TIMELINE_WIDTH: u32 = 320;
+const TIMELINE_HEIGHT: u32 = 240;
+const LIGHTBOX_WIDTH: u32 = 960;
+const LIGHTBOX_HEIGHT: u32 = 440;
+//Blurhashes are blurry anyway, a few pixels stretched to size look the same
+const PLACEHOLDER_SIZE: u32 = 32;
+const ZOOM_STEP: f64 = 1.25;
+const MAX_ZOOM: f64 = 8.0;
+
+/// Image message at its aspect ratio, blurhash while it loads, click to open the lightbox
+#[component]
+pub fn ImageMessage(event_id: OwnedEventId, attachment: ReadOnlySignal<Attachment>) -> Element {
+    //Register with the gallery so the lightbox can page through it
+    use_effect({
+        let event_id = event_id.clone();
+        move || {
+            let attachment = attachment();
+            let mut gallery = GALLERY.write();
+            match gallery.iter_mut().find(|(id, _)| *id == event_id) {
+                Some(entry) => entry.1 = attachment,
+                None => gallery.push((event_id.clone(), attachment)),
+            }
+        }
+    });
+    use_drop({
+        let event_id = event_id.clone();
+        move || GALLERY.write().retain(|(id, _)| *id != event_id)
+    });
+
+    let image = use_matrix_image(attachment, Some((TIMELINE_WIDTH, TIMELINE_HEIGHT)));
+    let placeholder = use_memo(move || {
+        let attachment = attachment.read();
+        let hash = attachment.blurhash.as_deref()?;
+        let (width, height) = fit_size(attachment.dimensions, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE);
+        blurhash_png(hash, width, height)
+            .inspect_err(|err| warn!("Rendering blurhash failed: {err}"))
+            .ok()
+    });
+
+    let Attachment {
+        name, dimensions, ..
+    } = attachment();
+    let (width, height) = fit_size(dimensions, TIMELINE_WIDTH, TIMELINE_HEIGHT);
+    let lock = if attachment.read().is_encrypted() {
+        "🔒 "
+    } else {
+        ""
+    };
+
+    rsx!(
+        rect {
+            direction: "vertical",
+            spacing: "5",
+
+            rect {
+                width: "{width}",
+                height: "{height}",
+                background: "#e6e6e6",
+                main_align: "center",
+                cross_align: "center",
+                onclick: move |_| *LIGHTBOX.write() = Some(event_id.clone()),
+
+                {decoded_image(&image.read(), placeholder(), width, height)}
+            }
+
+            label {
+                font_size: "10",
+                color: "grey",
+
+                "{lock}{name}"
+            }
+        }
+    )
+}
+
+/// Shows the image message picked in the timeline on top of whatever page is open
+#[component]
+pub fn Lightbox() -> Element {
+    let Some(event_id) = LIGHTBOX() else {
+        return rsx!();
+    };
+    //Messages mount in any order, page in the order the timeline shows them
+    let timeline = TIMELINE.read();
+    let gallery = GALLERY.read();
+    let mut ordered: Vec<_> = gallery
+        .iter()
+        .filter_map(|(id, attachment)| {
+            let position = timeline.iter().position(|event| event == id)?;
+            Some((position, id, attachment))
+        })
+        .collect();
+    ordered.sort_by_key(|(position, ..)| *position);
+    let Some(position) = ordered.iter().position(|(_, id, _)| **id == event_id) else {
+        return rsx!();
+    };
+    let attachment = ordered[position].2.clone();
+    let previous = position
+        .checked_sub(1)
+        .map(|previous| ordered[previous].1.clone());
+    let next = ordered.get(position + 1).map(|(_, id, _)| (*id).clone());
+
+    rsx!(LightboxDialog {
+        key: "{event_id}",
+        attachment,
+        previous,
+        next
+    })
+}
+
+#[component]
+fn LightboxDialog(
+    attachment: ReadOnlySignal<Attachment>,
+    previous: Option<OwnedEventId>,
+    next: Option<OwnedEventId>,
+) -> Element {
+    let image = use_matrix_image(attachment, None);
+    let mut zoom = use_signal(|| 1.0);
+    let mut offset = use_signal(|| (0.0, 0.0));
+    let mut dragging = use_signal(|| None::<CursorPoint>);
+
+    let Attachment {
+        name, dimensions, ..
+    } = attachment();
+    let (width, height) = fit_size(dimensions, LIGHTBOX_WIDTH, LIGHTBOX_HEIGHT);
+    let lock = if attachment.read().is_encrypted() {
+        "🔒 "
+    } else {
+        ""
+    };
+    let (offset_x, offset_y) = offset();
+    let zoom_percent = (zoom() * 100.0).round();
+
+    let onwheel = move |evt: WheelEvent| {
+        let factor = if evt.get_delta_y() > 0.0 {
+            ZOOM_STEP
+        } else {
+            1.0 / ZOOM_STEP
+        };
+        let zoomed = (zoom() * factor).clamp(1.0, MAX_ZOOM);
+        zoom.set(zoomed);
+        if zoomed == 1.0 {
+            offset.set((0.0, 0.0));
+        }
+    };
+    let onglobalmousemove = move |evt: MouseEvent| {
+        let Some(start) = dragging() else {
+            return;
+        };
+        let cursor = evt.get_screen_coordinates();
+        offset.with_mut(|(x, y)| {
+            *x += cursor.x - start.x;
+            *y += cursor.y - start.y;
+        });
+        dragging.set(Some(cursor));
+    };
+    let onglobalkeydown = {
+        let previous = previous.clone();
+        let next = next.clone();
+        move |evt: KeyboardEvent| {
+            let target = match evt.key {
+                Key::ArrowLeft => previous.clone(),
+                Key::ArrowRight => next.clone(),
+                _ => return,
+            };
+            if target.is_some() {
+                *LIGHTBOX.write() = target;
+            }
+        }
+    };
+
+    rsx!(
+        Popup {
+            theme: theme_with!(PopupTheme {
+                width: "90%".into(),
+                height: "90%".into(),
+            }),
+            oncloserequest: move |_| *LIGHTBOX.write() = None,
+            PopupTitle {
+                label { "{lock}{name}" }
+            }
+            PopupContent {
+                rect {
+                    spacing: "10",
+                    cross_align: "center",
+                    onglobalkeydown,
+
+                    rect {
+                        width: "{LIGHTBOX_WIDTH}",
+                        height: "{LIGHTBOX_HEIGHT}",
+                        overflow: "clip",
+                        main_align: "center",
+                        cross_align: "center",
+                        onwheel,
+                        onmousedown: move |evt: MouseEvent| dragging.set(Some(evt.get_screen_coordinates())),
+                        onglobalmousemove,
+                        onglobalclick: move |_| dragging.set(None),
+
+                        rect {
+                            offset_x: "{offset_x}",
+                            offset_y: "{offset_y}",
+                            scale: "{zoom} {zoom}",
+
+                            {decoded_image(&image.read(), None, width, height)}
+                        }
+                    }
+
+                    rect {
+                        direction: "horizontal",
+                        spacing: "5",
+                        cross_align: "center",
+
+                        if let Some(previous) = previous {
+                            Button {
+                                onclick: move |_| *LIGHTBOX.write() = Some(previous.clone()),
+                                label { "Previous" }
+                            }
+                        }
+                        Button {
+                            onclick: move |_| {
+                                zoom.set(1.0);
+                                offset.set((0.0, 0.0));
+                            },
+                            label { "{zoom_percent}%" }
+                        }
+                        if let Some(next) = next {
+                            Button {
+                                onclick: move |_| *LIGHTBOX.write() = Some(next.clone()),
+                                label { "Next" }
+                            }
+                        }
+                    }
+
+                    SaveAttachment { attachment }
+                }
+            }
+        }
+    )
+}
+
+/// Plays the frames on their own delays, looping forever like browsers do
+#[component]
+fn AnimatedImage(frames: ReadOnlySignal<Vec<AnimationFrame>>, width: u32, height: u32) -> Element {
+    let mut current = use_signal(|| 0);
+
+    use_future(move || async move {
+        loop {
+            let delay = frames
+                .peek()
+                .get(*current.peek())
+                .map_or(Duration::from_millis(100), |frame| frame.delay);
+            tokio::time::sleep(delay).await;
+            let count = frames.peek().len().max(1);
+            current.set((*current.peek() + 1) % count);
+        }
+    });
+
+    let Some(frame) = frames.read().get(current()).cloned() else {
+        return rsx!();
+    };
+
+    rsx!(image {
+        width: "{width}",
+        height: "{height}",
+        image_data: dynamic_bytes(frame.png),
+    })
+}
+
+fn decoded_image(
+    image: &Option<Result<DecodedImage, String>>,
+    placeholder: Option<Vec<u8>>,
+    width: u32,
+    height: u32,
+) -> Element {
+    match image {
+        None => match placeholder {
+            Some(placeholder) => rsx!(image {
+                width: "{width}",
+                height: "{height}",
+                image_data: dynamic_bytes(placeholder),
+            }),
+            None => rsx!(Loader {}),
+        },
+        Some(Err(err)) => rsx!(
+            TooltipContainer {
+                tooltip: rsx!(Tooltip { text: "{err}" }),
+                label {
+                    color: "#a0a0a0",
+
+                    "⚠"
+                }
+            }
+        ),
+        Some(Ok(DecodedImage::Still(data))) => rsx!(image {
+            width: "{width}",
+            height: "{height}",
+            image_data: dynamic_bytes(data.clone()),
+        }),
+        Some(Ok(DecodedImage::Animated(frames))) => rsx!(AnimatedImage {
+            frames: frames.clone(),
+            width,
+            height,
+        }),
+    }
+}
@@ -0,0 +1,57 @@
+use freya::prelude::*;
+use ruma::OwnedMxcUri;
+use swift_wind::matrix_media::MediaSize;
+
+use crate::hook::media::use_matrix_media;
+
+/// Image behind an `mxc` URI, a thumbnail of the given size unless `full` is set
+#[component]
+pub fn MatrixImage(
+    uri: ReadOnlySignal<OwnedMxcUri>,
+    width: ReadOnlySignal<u32>,
+    height: ReadOnlySignal<u32>,
+    #[props(default)] full: ReadOnlySignal<bool>,
+) -> Element {
+    let size = use_memo(move || {
+        if full() {
+            MediaSize::Full
+        } else {
+            MediaSize::Thumbnail {
+                width: width(),
+                height: height(),
+            }
+        }
+    });
+    let media = use_matrix_media(uri, size);
+
+    let content = match &*media.read() {
+        None => rsx!(Loader {}),
+        Some(Err(err)) => rsx!(
+            TooltipContainer {
+                tooltip: rsx!(Tooltip { text: "{err}" }),
+                label {
+                    color: "#a0a0a0",
+
+                    "⚠"
+                }
+            }
+        ),
+        Some(Ok(data)) => rsx!(image {
+            width: "{width}",
+            height: "{height}",
+            image_data: dynamic_bytes(data.clone()),
+        }),
+    };
+
+    rsx!(
+        rect {
+            width: "{width}",
+            height: "{height}",
+            background: "#e6e6e6",
+            main_align: "center",
+            cross_align: "center",
+
+            {content}
+        }
+    )
+}
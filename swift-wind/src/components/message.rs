@@ -1,13 +1,32 @@
 use freya::prelude::*;
-use matrix_sdk::reqwest::Url;
+use ruma::events::room::encrypted::OriginalSyncRoomEncryptedEvent;
 use ruma::events::room::message::{LimitType, OriginalSyncRoomMessageEvent, ServerNoticeType};
+use ruma::serde::Raw;
+use ruma::{OwnedEventId, OwnedRoomId, OwnedUserId};
+use swift_wind::matrix_attachment::Attachment;
+use swift_wind::matrix_decryption::{DecryptedMessage, UtdReason};
+use swift_wind::matrix_service::bevy_matrix_send_message::LocalEchoState;
+use swift_wind::matrix_trust::MessageShield;
+use swift_wind::matrix_verification::VerificationFlow;
 use tracing::{info, warn};
 
+use crate::components::attachment::AttachmentMessage;
+use crate::components::file::FileMessage;
+use crate::components::image::ImageMessage;
+use crate::components::matrix_image::MatrixImage;
+use crate::components::user_profile::UserProfileCard;
+use crate::hook::decryption::use_matrix_decrypt;
+use crate::hook::send_message::{PendingMessage, cancel_message, resend_message};
+use crate::hook::verification::queue_verification;
 use crate::{CLIENT, MatrixClientState};
 
 #[component]
-pub fn RoomMessage(evt: OriginalSyncRoomMessageEvent) -> Element {
+pub fn RoomMessage(
+    evt: OriginalSyncRoomMessageEvent,
+    #[props(default)] shield: MessageShield,
+) -> Element {
     let user_id = evt.sender.clone();
+    let mut show_profile = use_signal(|| false);
 
     //TODO: Replace this with a caching system so we dont have to hammer the server, can also act as a way to
     //provide fallback PFPs if we cant get em. Can also act as a way to transparently modify display names if two are unique
@@ -27,15 +46,28 @@ pub fn RoomMessage(evt: OriginalSyncRoomMessageEvent) -> Element {
         }
     });
 
+    //Media messages share one component, plain and encrypted alike
+    let attachment = Attachment::from_message(&evt.content.msgtype);
+
     //Massive match statement that selects what component to use based on the message,
     //makes it easy to compartmentalize every message type into their own sub-component
     let message_contents = match evt.content.msgtype {
-        ruma::events::room::message::MessageType::Audio(audio_message_event_content) => {
-            info!(
-                "Got audio message content: {:#?}",
-                audio_message_event_content
-            );
-            rsx! {label { "Missing audio implementation" }}
+        ruma::events::room::message::MessageType::Image(_) => {
+            rsx!(ImageMessage {
+                event_id: evt.event_id.clone(),
+                attachment: attachment.expect("media messages have an attachment")
+            })
+        }
+        ruma::events::room::message::MessageType::File(_) => {
+            rsx!(FileMessage {
+                attachment: attachment.expect("media messages have an attachment")
+            })
+        }
+        ruma::events::room::message::MessageType::Audio(_)
+        | ruma::events::room::message::MessageType::Video(_) => {
+            rsx!(AttachmentMessage {
+                attachment: attachment.expect("media messages have an attachment")
+            })
         }
         ruma::events::room::message::MessageType::Emote(emote_message_event_content) => {
             if let Some(format) = emote_message_event_content.formatted {
@@ -50,20 +82,6 @@ pub fn RoomMessage(evt: OriginalSyncRoomMessageEvent) -> Element {
                 })
             }
         }
-        ruma::events::room::message::MessageType::File(file_message_event_content) => {
-            info!(
-                "Got file message content: {:#?}",
-                file_message_event_content
-            );
-            rsx! {label { "Missing file message implementation" }}
-        }
-        ruma::events::room::message::MessageType::Image(image_message_event_content) => {
-            info!(
-                "Got image message content: {:#?}",
-                image_message_event_content
-            );
-            rsx! {label { "Missing image message implementation" }}
-        }
         ruma::events::room::message::MessageType::Location(location_message_event_content) => {
             info!(
                 "Got location message content: {:#?}",
@@ -107,21 +125,14 @@ pub fn RoomMessage(evt: OriginalSyncRoomMessageEvent) -> Element {
                 })
             }
         }
-        ruma::events::room::message::MessageType::Video(video_message_event_content) => {
-            info!(
-                "Got video message content: {:#?}",
-                video_message_event_content
-            );
-            rsx! {label { "Missing video message implementation" }}
-        }
         ruma::events::room::message::MessageType::VerificationRequest(
             key_verification_request_event_content,
         ) => {
-            info!(
-                "Got video message content: {:#?}",
-                key_verification_request_event_content
-            );
-            rsx! {label { "Missing key verification message implementation" }}
+            rsx!(verification_request_component {
+                sender: evt.sender.clone(),
+                to: key_verification_request_event_content.to,
+                event_id: evt.event_id.clone(),
+            })
         }
         _ => todo!(),
     };
@@ -130,6 +141,11 @@ pub fn RoomMessage(evt: OriginalSyncRoomMessageEvent) -> Element {
     let name = user_data
         .displayname
         .unwrap_or_else(|| format!("User ID: {}", evt.sender));
+    let shield = match shield {
+        MessageShield::None => None,
+        MessageShield::Warning(reason) => Some(("grey", reason)),
+        MessageShield::Danger(reason) => Some(("red", reason)),
+    };
     //TODO: Add "controls" like reply, delete, react, copy text, etc
     rsx! {
         rect {
@@ -138,17 +154,192 @@ pub fn RoomMessage(evt: OriginalSyncRoomMessageEvent) -> Element {
             rect {
                 direction: "horizontal",
                 if let Some(avatar_url) = user_data.avatar_url{
-                    NetworkImage{
-                        url: avatar_url.as_str().parse::<Url>().unwrap(),
+                    MatrixImage {
+                        uri: avatar_url,
+                        width: 32,
+                        height: 32,
+                    }
+                }
+                rect {
+                    onclick: move |_| show_profile.toggle(),
+                    label { "UserID: {name}" }
+                }
+                if let Some((color, reason)) = shield {
+                    label {
+                        color: color,
+                        font_size: "10",
+                        "⚠ {reason}"
                     }
                 }
-                label { "UserID: {name}" }
+            }
+            if show_profile() {
+                UserProfileCard { user_id: evt.sender.clone() }
             }
             //Message contents
             {message_contents}
         }
     }
 }
+/// `m.room.encrypted` event, shown as its message once decrypted or with the reason it can't be
+#[component]
+pub fn EncryptedMessage(
+    room_id: ReadOnlySignal<OwnedRoomId>,
+    evt: ReadOnlySignal<Raw<OriginalSyncRoomEncryptedEvent>>,
+) -> Element {
+    let (request_err, mut run_request_keys, decrypted) = use_matrix_decrypt(room_id, evt);
+
+    match &*decrypted.read() {
+        None => rsx! {
+            label {
+                color: "grey",
+                font_style: "italic",
+                "Decrypting…"
+            }
+        },
+        Some(Err(err)) => rsx! {
+            label {
+                color: "red",
+                "{err}"
+            }
+        },
+        Some(Ok(DecryptedMessage::Message { message, shield })) => rsx! {
+            RoomMessage { evt: *message.clone(), shield: *shield }
+        },
+        Some(Ok(DecryptedMessage::Other)) => rsx! {},
+        Some(Ok(DecryptedMessage::Undecryptable { reason, .. })) => {
+            let sender = evt
+                .read()
+                .get_field::<OwnedUserId>("sender")
+                .ok()
+                .flatten()
+                .map(|sender| sender.to_string())
+                .unwrap_or_default();
+            let explanation = match reason {
+                UtdReason::MissingKeys => {
+                    "The key for this message hasn't reached this session yet, it'll show up once it does".to_string()
+                }
+                UtdReason::Withheld(code) => {
+                    format!("The sender withheld the key for this message ({})", code.as_str())
+                }
+                UtdReason::UnverifiedDevice => {
+                    "The key for this message was withheld because a session isn't verified".to_string()
+                }
+                UtdReason::Unreadable => "This message is broken and can't be decrypted".to_string(),
+            };
+            let can_request = !matches!(reason, UtdReason::Unreadable);
+            rsx! {
+                rect {
+                    direction: "vertical",
+                    spacing: "5",
+                    label { "UserID: {sender}" }
+                    label {
+                        color: "grey",
+                        font_style: "italic",
+                        "{explanation}"
+                    }
+                    if can_request {
+                        Button {
+                            onclick: move |_| run_request_keys(),
+                            label { "Request keys" }
+                        }
+                    }
+                    label {
+                        color: "red",
+                        "{request_err}"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Message we sent that sync didn't bring back yet, with how sending it is going
+#[component]
+pub fn LocalEchoMessage(room_id: ReadOnlySignal<OwnedRoomId>, message: PendingMessage) -> Element {
+    let echo = message.echo;
+    let (color, status) = match &message.state {
+        LocalEchoState::Sending => ("grey", "Sending…".to_string()),
+        LocalEchoState::Retrying(reason) => ("grey", format!("Retrying: {reason}")),
+        LocalEchoState::Sent(_) => ("grey", "Sent".to_string()),
+        LocalEchoState::Failed(reason) => ("red", format!("Not sent: {reason}")),
+    };
+    let failed = matches!(message.state, LocalEchoState::Failed(_));
+    let sent = matches!(message.state, LocalEchoState::Sent(_));
+
+    rsx! {
+        rect {
+            direction: "vertical",
+            spacing: "5",
+            label {
+                color: "grey",
+                "{message.body}"
+            }
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+                cross_align: "center",
+                label {
+                    color: color,
+                    font_size: "10",
+                    "{status}"
+                }
+                if failed {
+                    Button {
+                        onclick: move |_| resend_message(room_id(), echo),
+                        label { "Resend" }
+                    }
+                }
+                if !sent {
+                    Button {
+                        onclick: move |_| cancel_message(room_id(), echo),
+                        label { "Cancel" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// In-room verification request, can be answered while it's still open
+#[component]
+fn verification_request_component(
+    sender: OwnedUserId,
+    to: OwnedUserId,
+    event_id: OwnedEventId,
+) -> Element {
+    let request = use_resource(move || {
+        let sender = sender.clone();
+        let event_id = event_id.clone();
+        async move {
+            let MatrixClientState::Connected(client) = CLIENT() else {
+                return None;
+            };
+            VerificationFlow::incoming(&client, &sender, event_id.as_str()).await
+        }
+    });
+
+    rsx! {
+        rect {
+            direction: "vertical",
+            spacing: "5",
+            label {
+                color: "grey",
+                font_style: "italic",
+                "Asked {to} to verify"
+            }
+            if let Some(Some(flow)) = request.read().as_ref() {
+                Button {
+                    onclick: {
+                        let flow = flow.clone();
+                        move |_| queue_verification(flow.clone())
+                    },
+                    label { "Verify" }
+                }
+            }
+        }
+    }
+}
+
 #[component]
 fn emote_component(body: String, formatted: bool) -> Element {
     //TODO: Process formatted message differently
@@ -0,0 +1,57 @@
+use freya::prelude::*;
+use swift_wind::matrix_store::recent_servers;
+use tracing::warn;
+
+/// Servers connected to before, fills an empty server field with the last one and sets it to the clicked one
+#[component]
+pub fn RecentServers(mut value: Signal<String>) -> Element {
+    let servers = use_resource(move || async move {
+        let servers = match recent_servers().await {
+            Ok(servers) => servers,
+            Err(err) => {
+                warn!("failed to read recent servers {:?}", err);
+                Vec::new()
+            }
+        };
+        if let Some(last) = servers.first() {
+            if value.peek().is_empty() {
+                value.set(last.clone());
+            }
+        }
+        servers
+    });
+
+    let servers = servers.read().clone().unwrap_or_default();
+    if servers.is_empty() {
+        return rsx!();
+    }
+
+    rsx!(
+        rect {
+            content: "flex",
+            direction: "vertical",
+            spacing: "5",
+            width: "100%",
+
+            label {
+                color: "#454545",
+                font_size: "12",
+                "Recent servers"
+            }
+
+            {servers.into_iter().map(|server| {
+                let selected = server.clone();
+                rsx!(
+                    Button {
+                        key: "{server}",
+                        onclick: move |_| value.set(selected.clone()),
+                        label {
+                            font_size: "12",
+                            "{server}"
+                        }
+                    }
+                )
+            })}
+        }
+    )
+}
@@ -0,0 +1,159 @@
+use freya::prelude::*;
+use ruma::OwnedRoomId;
+use swift_wind::matrix_trust::RoomShield;
+
+use crate::hook::trust::{
+    use_matrix_accept_identity, use_matrix_identity_changes, use_matrix_room_trust,
+};
+use crate::hook::verification::use_matrix_request_verification;
+
+/// Room-level shield and the member list with a verification badge per member
+#[component]
+pub fn RoomMembers(room_id: ReadOnlySignal<OwnedRoomId>) -> Element {
+    let trust = use_matrix_room_trust(room_id);
+    let (verification_err, run_request_verification) = use_matrix_request_verification();
+
+    let content = match &*trust.read() {
+        None => rsx!(Loader {}),
+        Some(Err(err)) => rsx!(
+            label {
+                color: "red",
+
+                "{err}"
+            }
+        ),
+        Some(Ok(trust)) => {
+            let (shield_color, shield_text) = match trust.shield {
+                RoomShield::NotEncrypted => ("grey", "This room isn't encrypted".to_string()),
+                RoomShield::AllVerified => {
+                    ("green", "Everyone in this room is verified".to_string())
+                }
+                RoomShield::Unverified { members } => (
+                    "orange",
+                    format!("{members} members of this room aren't verified"),
+                ),
+            };
+            let members = trust.members.iter().map(|member| {
+                let name = member
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| member.user_id.to_string());
+                (member.clone(), name)
+            });
+            rsx!(
+                label {
+                    color: shield_color,
+                    font_weight: "bold",
+
+                    "{shield_text}"
+                }
+
+                for (member, name) in members {
+                    rect {
+                        key: "{member.user_id}",
+                        direction: "horizontal",
+                        spacing: "5",
+                        cross_align: "center",
+
+                        label {
+                            color: if member.verified { "green" } else { "grey" },
+
+                            if member.verified { "✔" } else { "•" }
+                        }
+
+                        label { "{name}" }
+
+                        if !member.verified && member.has_identity {
+                            Button {
+                                onclick: {
+                                    let mut run_request_verification = run_request_verification.clone();
+                                    let user_id = member.user_id.clone();
+                                    move |_| run_request_verification(user_id.clone(), None)
+                                },
+                                label { "Verify" }
+                            }
+                        }
+                    }
+                }
+            )
+        }
+    };
+
+    rsx!(
+        rect {
+            spacing: "5",
+            direction: "vertical",
+
+            {content}
+
+            label {
+                color: "red",
+
+                "{verification_err}"
+            }
+        }
+    )
+}
+
+/// Warns about members whose identity changed, until they're verified again or accepted
+#[component]
+pub fn IdentityChangeBanner(room_id: ReadOnlySignal<OwnedRoomId>) -> Element {
+    let changed = use_matrix_identity_changes(room_id);
+    let (accept_err, run_accept_identity) = use_matrix_accept_identity(|| {});
+    let (verification_err, run_request_verification) = use_matrix_request_verification();
+
+    if changed.read().is_empty() {
+        return rsx!();
+    }
+
+    rsx!(
+        rect {
+            width: "100%",
+            padding: "10",
+            spacing: "5",
+            corner_radius: "8",
+            background: "#ffe9c7",
+
+            for user_id in changed() {
+                rect {
+                    key: "{user_id}",
+                    direction: "horizontal",
+                    spacing: "5",
+                    cross_align: "center",
+
+                    label { "{user_id}'s identity has changed, verify them again to be sure it's still them" }
+
+                    Button {
+                        onclick: {
+                            let mut run_request_verification = run_request_verification.clone();
+                            let user_id = user_id.clone();
+                            move |_| run_request_verification(user_id.clone(), None)
+                        },
+                        label { "Verify again" }
+                    }
+
+                    Button {
+                        onclick: {
+                            let mut run_accept_identity = run_accept_identity.clone();
+                            let user_id = user_id.clone();
+                            move |_| run_accept_identity(user_id.clone())
+                        },
+                        label { "Dismiss" }
+                    }
+                }
+            }
+
+            label {
+                color: "red",
+
+                "{accept_err}"
+            }
+
+            label {
+                color: "red",
+
+                "{verification_err}"
+            }
+        }
+    )
+}
@@ -0,0 +1,133 @@
+use chrono::prelude::*;
+use freya::prelude::*;
+use swift_wind::matrix_devices::DeviceInfo;
+
+use crate::components::form::FormField;
+
+/// One signed in session with its rename field, the current one can't be selected or signed out here
+#[component]
+pub fn SessionTile(
+    device: ReadOnlySignal<DeviceInfo>,
+    selected: bool,
+    onselect: EventHandler<()>,
+    onrename: EventHandler<String>,
+    onsignout: EventHandler<()>,
+    onverify: EventHandler<()>,
+) -> Element {
+    let mut form_name = use_signal(|| device.peek().display_name.clone().unwrap_or_default());
+
+    let DeviceInfo {
+        device_id,
+        display_name,
+        last_seen_ip,
+        last_seen,
+        is_current,
+        verified,
+    } = device();
+    let title = display_name.unwrap_or_else(|| device_id.to_string());
+    let last_seen = last_seen
+        .and_then(|ts| ts.to_system_time())
+        .map(|ts| {
+            DateTime::<Local>::from(ts)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| "never".to_string());
+    let last_seen_ip = last_seen_ip.unwrap_or_else(|| "unknown address".to_string());
+    let (verification, verification_color) = match verified {
+        Some(true) => ("Verified", "green"),
+        Some(false) => ("Not verified", "red"),
+        None => ("No encryption keys", "#454545"),
+    };
+
+    rsx!(
+        rect {
+            width: "100%",
+            padding: "10",
+            spacing: "5",
+            corner_radius: "8",
+            background: "#f2f2f2",
+
+            rect {
+                direction: "horizontal",
+                spacing: "10",
+                cross_align: "center",
+
+                if !is_current {
+                    Tile {
+                        onselect: move |_| onselect(()),
+                        leading: rsx!(
+                            Checkbox {
+                                selected,
+                            }
+                        ),
+                        label { "Select" }
+                    }
+                }
+
+                label {
+                    font_size: "18",
+                    font_weight: "bold",
+
+                    "{title}"
+                }
+
+                if is_current {
+                    label {
+                        color: "#454545",
+
+                        "This device"
+                    }
+                }
+
+                label {
+                    color: verification_color,
+
+                    "{verification}"
+                }
+            }
+
+            label {
+                color: "#454545",
+
+                "{device_id} · last seen {last_seen} from {last_seen_ip}"
+            }
+
+            FormField {
+                name: "Session name",
+                value: form_name,
+                onchange: move |txt| {
+                    *form_name.write() = txt;
+                 },
+            }
+
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+
+                Button {
+                    onclick: move |_| onrename(form_name()),
+                    label { "Rename" }
+                }
+
+                if !is_current && verified == Some(false) {
+                    Button {
+                        onclick: move |_| onverify(()),
+                        label { "Verify" }
+                    }
+                }
+
+                if !is_current {
+                    Button {
+                        onclick: move |_| onsignout(()),
+                        label {
+                            color: "red",
+
+                            "Sign out"
+                        }
+                    }
+                }
+            }
+        }
+    )
+}
@@ -1,14 +1,14 @@
 use dioxus_router::prelude::navigator;
 use freya::prelude::*;
-use matrix_sdk::reqwest::Url;
-use ruma::RoomId;
+use ruma::{OwnedMxcUri, RoomId};
 use tracing::warn;
 
+use crate::components::matrix_image::MatrixImage;
 use crate::{CLIENT, MatrixClientState};
 
 #[derive(Clone)]
 struct RoomData {
-    avatar_url: Option<Url>,
+    avatar_url: Option<OwnedMxcUri>,
     name: String,
     id: String,
 }
@@ -29,9 +29,7 @@ pub fn SpaceSelectionButton(space: String, selected: bool) -> Element {
             };
 
             let room = client.get_room(&value).unwrap();
-            let avatar_url = room
-                .avatar_url()
-                .map(|url| url.as_str().parse::<Url>().unwrap());
+            let avatar_url = room.avatar_url();
 
             let name = {
                 if let Ok(display_name) = room.display_name().await {
@@ -76,8 +74,10 @@ pub fn SpaceSelectionButton(space: String, selected: bool) -> Element {
                 Button {
                     onclick: clicked,
                     if let Some(avatar_url) = room_data.avatar_url{
-                        NetworkImage{
-                            url: avatar_url.as_str().parse::<Url>().unwrap(),
+                        MatrixImage {
+                            uri: avatar_url,
+                            width: 48,
+                            height: 48,
                         }
                     }
                     else{
@@ -0,0 +1,87 @@
+use freya::prelude::*;
+use ruma::OwnedRoomId;
+use swift_wind::matrix_service::bevy_matrix_send_message::LocalEchoState;
+use swift_wind::matrix_timeline::TimelineItem;
+
+use crate::components::message::{EncryptedMessage, LocalEchoMessage, RoomMessage};
+use crate::hook::send_message::use_local_echoes;
+use crate::hook::timeline::use_matrix_timeline;
+use crate::{LOCAL_ECHOES, TIMELINE};
+
+/// The open room's messages, encrypted ones are decrypted as they're shown
+///
+/// Messages that are still being sent follow, until sync brings them back
+#[component]
+pub fn RoomTimeline(room_id: ReadOnlySignal<OwnedRoomId>) -> Element {
+    let (timeline_err, timeline) = use_matrix_timeline(room_id);
+    use_local_echoes(room_id);
+
+    //The lightbox pages through images in this order
+    use_effect(move || {
+        *TIMELINE.write() = timeline
+            .read()
+            .iter()
+            .map(|item| item.event_id().to_owned())
+            .collect();
+    });
+    use_drop(|| TIMELINE.write().clear());
+
+    //A sent message shows up in the timeline a bit before its echo goes away
+    let echoes = LOCAL_ECHOES
+        .read()
+        .iter()
+        .filter(|message| match &message.state {
+            LocalEchoState::Sent(event_id) => !timeline
+                .read()
+                .iter()
+                .any(|item| item.event_id() == &**event_id),
+            _ => true,
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    rsx!(
+        ScrollView {
+            height: "flex(1)",
+
+            rect {
+                width: "100%",
+                spacing: "10",
+                direction: "vertical",
+
+                for item in timeline.read().iter() {
+                    {match item {
+                        TimelineItem::Message { message, shield } => rsx! {
+                            RoomMessage {
+                                key: "{message.event_id}",
+                                evt: *message.clone(),
+                                shield: *shield,
+                            }
+                        },
+                        TimelineItem::Encrypted { event_id, event } => rsx! {
+                            EncryptedMessage {
+                                key: "{event_id}",
+                                room_id,
+                                evt: event.clone(),
+                            }
+                        },
+                    }}
+                }
+
+                for message in echoes {
+                    LocalEchoMessage {
+                        key: "{message.echo:?}",
+                        room_id,
+                        message,
+                    }
+                }
+            }
+        }
+
+        label {
+            color: "red",
+
+            "{timeline_err}"
+        }
+    )
+}
@@ -0,0 +1,69 @@
+use freya::prelude::*;
+use ruma::OwnedUserId;
+use tracing::warn;
+
+use crate::hook::verification::use_matrix_request_verification;
+use crate::{CLIENT, MatrixClientState};
+
+/// Small card about a user, opened from their name
+#[component]
+pub fn UserProfileCard(user_id: ReadOnlySignal<OwnedUserId>) -> Element {
+    let profile = use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return None;
+        };
+        match client.account().fetch_user_profile_of(&user_id()).await {
+            Ok(profile) => Some(profile),
+            Err(err) => {
+                warn!("failed to fetch profile of {} {err}", user_id());
+                None
+            }
+        }
+    });
+    let (verification_err, mut run_request_verification) = use_matrix_request_verification();
+
+    let display_name = profile
+        .read()
+        .as_ref()
+        .and_then(|profile| profile.as_ref()?.displayname.clone());
+    let is_own_user = matches!(
+        CLIENT(),
+        MatrixClientState::Connected(client) if client.user_id() == Some(&*user_id())
+    );
+
+    rsx!(
+        rect {
+            padding: "10",
+            spacing: "5",
+            corner_radius: "8",
+            background: "#f2f2f2",
+
+            if let Some(display_name) = display_name {
+                label {
+                    font_weight: "bold",
+
+                    "{display_name}"
+                }
+            }
+
+            label {
+                color: "#454545",
+
+                "{user_id}"
+            }
+
+            if !is_own_user {
+                Button {
+                    onclick: move |_| run_request_verification(user_id(), None),
+                    label { "Verify" }
+                }
+            }
+
+            label {
+                color: "red",
+
+                "{verification_err}"
+            }
+        }
+    )
+}
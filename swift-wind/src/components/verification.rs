@@ -0,0 +1,182 @@
+use freya::prelude::*;
+use swift_wind::matrix_verification::{VerificationFlow, VerificationStage};
+
+use crate::VERIFICATIONS;
+use crate::hook::verification::{VerificationAction, use_matrix_verification};
+
+/// Shows the oldest queued verification on top of whatever page is open
+#[component]
+pub fn VerificationDialogs() -> Element {
+    let Some(flow) = VERIFICATIONS.read().first().cloned() else {
+        return rsx!();
+    };
+
+    let key = format!("{}{}", flow.other_user_id(), flow.flow_id());
+
+    rsx!(VerificationDialog { key: "{key}", flow })
+}
+
+#[component]
+fn VerificationDialog(flow: VerificationFlow) -> Element {
+    let (verification_err, mut act, stage) = use_matrix_verification(flow.clone());
+
+    let other_user = flow.other_user_id().to_string();
+    let title = if flow.is_self_verification() {
+        "Verify your other session".to_string()
+    } else {
+        format!("Verify {other_user}")
+    };
+    let finished = matches!(
+        stage(),
+        VerificationStage::Done
+            | VerificationStage::Cancelled { .. }
+            | VerificationStage::Failed(_)
+    );
+
+    let mut close = {
+        let flow = flow.clone();
+        let mut act = act.clone();
+        move || {
+            if !finished {
+                act(VerificationAction::Cancel);
+            }
+            VERIFICATIONS.write().retain(|queued| *queued != flow);
+        }
+    };
+
+    let content = match stage() {
+        VerificationStage::Requested => rsx!(
+            label { "Waiting for the other session to accept the request…" }
+        ),
+        VerificationStage::Incoming => rsx!(
+            label { "{other_user} wants to verify this session." }
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+
+                Button {
+                    onclick: {
+                        let mut act = act.clone();
+                        move |_| act(VerificationAction::Accept)
+                    },
+                    label { "Accept" }
+                }
+                Button {
+                    onclick: {
+                        let mut close = close.clone();
+                        move |_| close()
+                    },
+                    label { "Decline" }
+                }
+            }
+        ),
+        VerificationStage::Starting => rsx!(
+            label { "Exchanging keys…" }
+        ),
+        VerificationStage::Compare { emojis, decimals } => {
+            let (first, second, third) = decimals;
+            rsx!(
+                label { "Check that the other session shows the same, in the same order." }
+                if let Some(emojis) = emojis {
+                    rect {
+                        direction: "horizontal",
+                        spacing: "10",
+
+                        {emojis.into_iter().enumerate().map(|(index, emoji)| rsx!(
+                            rect {
+                                key: "{index}",
+                                cross_align: "center",
+
+                                label { font_size: "32", "{emoji.symbol}" }
+                                label { font_size: "12", "{emoji.description}" }
+                            }
+                        ))}
+                    }
+                } else {
+                    label {
+                        font_size: "24",
+
+                        "{first} {second} {third}"
+                    }
+                }
+                rect {
+                    direction: "horizontal",
+                    spacing: "5",
+
+                    Button {
+                        onclick: {
+                            let mut act = act.clone();
+                            move |_| act(VerificationAction::Confirm)
+                        },
+                        label { "They match" }
+                    }
+                    Button {
+                        onclick: move |_| act(VerificationAction::Mismatch),
+                        label {
+                            color: "red",
+
+                            "They don't match"
+                        }
+                    }
+                }
+            )
+        }
+        VerificationStage::Confirmed => rsx!(
+            label { "Waiting for the other session to confirm…" }
+        ),
+        VerificationStage::Done => rsx!(
+            label {
+                color: "green",
+
+                "Verified"
+            }
+        ),
+        VerificationStage::Cancelled { reason, by_us } => {
+            let by = if by_us { "You" } else { "The other session" };
+            rsx!(
+                label {
+                    color: "red",
+
+                    "{by} cancelled the verification: {reason}"
+                }
+            )
+        }
+        VerificationStage::Failed(err) => rsx!(
+            label {
+                color: "red",
+
+                "The verification failed: {err}"
+            }
+        ),
+    };
+
+    rsx!(
+        Popup {
+            oncloserequest: {
+                let mut close = close.clone();
+                move |_| close()
+            },
+            PopupTitle {
+                label { "{title}" }
+            }
+            PopupContent {
+                rect {
+                    spacing: "10",
+
+                    {content}
+
+                    label {
+                        color: "red",
+
+                        "{verification_err}"
+                    }
+
+                    Button {
+                        onclick: move |_| close(),
+                        label { if finished { "Close" } else { "Cancel" } }
+                    }
+                }
+            }
+        }
+    )
+}
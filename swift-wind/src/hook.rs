@@ -1,7 +1,23 @@
+pub mod account;
+pub mod attachment;
+pub mod backup;
 pub mod connect;
+pub mod decryption;
+pub mod devices;
+pub mod download;
+pub mod image;
+pub mod key_export;
 pub mod login;
+pub mod media;
+pub mod password_reset;
+pub mod recovery;
 pub mod register;
+pub mod send_message;
+pub mod session;
 pub mod submit_additional_auth;
+pub mod timeline;
+pub mod trust;
+pub mod verification;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct CommonUserAuthData {
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use freya::prelude::*;
+use ruma::OwnedMxcUri;
+use swift_wind::matrix_account::{remove_avatar, set_avatar_from_file, set_display_name};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Profile {
+    pub display_name: Option<String>,
+    pub avatar_url: Option<OwnedMxcUri>,
+}
+
+/// Profile of the active account as the homeserver has it
+pub fn use_matrix_profile() -> Resource<Result<Profile, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        let profile = client.account().fetch_user_profile().await.map_err(|err| {
+            warn!("Fetching profile failed: {err}");
+            err.to_string()
+        })?;
+        Ok(Profile {
+            display_name: profile.displayname,
+            avatar_url: profile.avatar_url,
+        })
+    })
+}
+
+pub fn use_matrix_set_display_name<F>(callback: F) -> (Signal<String>, impl FnMut(String) + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run = move |name: String| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to set the display name before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            match set_display_name(&client, &name).await {
+                Ok(()) => callback(),
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run)
+}
+
+/// Uploads the picture at the path as the avatar, `None` removes the avatar
+pub fn use_matrix_set_avatar<F>(
+    callback: F,
+) -> (Signal<String>, impl FnMut(Option<PathBuf>) + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run = move |path: Option<PathBuf>| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to change the avatar before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            let res = match path {
+                Some(path) => set_avatar_from_file(&client, &path).await.map(|uri| {
+                    trace!("Avatar uploaded to {uri}");
+                }),
+                None => remove_avatar(&client).await,
+            };
+            match res {
+                Ok(()) => callback(),
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run)
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use freya::prelude::*;
+use ruma::events::room::MediaSource;
+use swift_wind::matrix_attachment::{Attachment, AttachmentErr, fetch_attachment, save_attachment};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// Thumbnail, or the media itself without one, decrypted in memory for rendering
+pub fn use_matrix_attachment_preview(
+    attachment: ReadOnlySignal<Attachment>,
+) -> Resource<Result<Vec<u8>, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        let source = {
+            let attachment = attachment.read();
+            attachment
+                .thumbnail
+                .clone()
+                .unwrap_or_else(|| attachment.source.clone())
+        };
+        fetch_attachment(&client, &source).await.map_err(|err| {
+            warn!("Fetching attachment failed: {err}");
+            err.to_string()
+        })
+    })
+}
+
+/// Streams the decrypted media to a file the user picked, its path lands in the third signal
+///
+/// A file that is already there is left alone and lands in the last signal, saving again with
+/// `overwrite` replaces it
+pub fn use_matrix_save_attachment() -> (
+    Signal<String>,
+    impl FnMut(MediaSource, PathBuf, bool) + Clone,
+    Signal<Option<PathBuf>>,
+    Signal<Option<PathBuf>>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut saved = use_signal(|| None);
+    let mut existing = use_signal(|| None);
+
+    let run = move |source: MediaSource, path: PathBuf, overwrite: bool| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to save an attachment before connected");
+            return;
+        };
+        error_string.write().clear();
+        saved.set(None);
+        existing.set(None);
+        spawn(async move {
+            match save_attachment(&client, &source, &path, overwrite).await {
+                Ok(()) => {
+                    trace!("Attachment saved");
+                    saved.set(Some(path));
+                }
+                Err(AttachmentErr::Exists(path)) => {
+                    trace!("{} exists, asking before replacing it", path.display());
+                    existing.set(Some(path));
+                }
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run, saved, existing)
+}
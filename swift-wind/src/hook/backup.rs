@@ -0,0 +1,142 @@
+use freya::prelude::*;
+use swift_wind::matrix_backup::{
+    BackupStatus, RestoreProgress, backup_status, delete_backup, enable_backup, reset_backup,
+    restore_backup, watch_upload,
+};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BackupAction {
+    Enable,
+    /// Replaces the backup, the passphrase protects the new recovery key
+    Reset(Option<String>),
+    Delete,
+}
+
+/// State of the room key backup of the active account
+pub fn use_matrix_backup_status() -> Resource<Result<BackupStatus, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        backup_status(&client).await.map_err(|err| {
+            warn!("Checking key backup failed: {err}");
+            err.to_string()
+        })
+    })
+}
+
+/// Backed up and total room keys while this session is uploading, `None` once it caught up
+pub fn use_matrix_backup_upload() -> (Signal<Option<(usize, usize)>>, impl FnMut() + Clone) {
+    let mut progress = use_signal(|| None);
+
+    let watch = move || {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return;
+        };
+        spawn(async move {
+            watch_upload(&client, |backed_up, total| {
+                progress.set(Some((backed_up, total)))
+            })
+            .await;
+            progress.set(None);
+        });
+    };
+
+    use_hook({
+        let mut watch = watch.clone();
+        move || watch()
+    });
+
+    (progress, watch)
+}
+
+/// Creates, replaces or deletes the backup, a reset lands its new recovery key in the signal
+pub fn use_matrix_backup_action<F>(
+    callback: F,
+) -> (
+    Signal<String>,
+    impl FnMut(BackupAction) + Clone,
+    Signal<Option<String>>,
+)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+    let mut recovery_key = use_signal(|| None);
+
+    let run = move |action: BackupAction| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to change the key backup before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            let res = match action {
+                BackupAction::Enable => enable_backup(&client).await,
+                BackupAction::Reset(passphrase) => reset_backup(&client, passphrase.as_deref())
+                    .await
+                    .map(|key| recovery_key.set(key)),
+                BackupAction::Delete => delete_backup(&client).await,
+            };
+            match res {
+                Ok(()) => callback(),
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run, recovery_key)
+}
+
+/// Downloads the room keys in the backup, unlocked with the recovery key or passphrase
+pub fn use_matrix_restore_backup<F>(
+    callback: F,
+) -> (
+    Signal<String>,
+    impl FnMut(String) + Clone,
+    Signal<Option<RestoreProgress>>,
+)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+    let mut progress = use_signal(|| None);
+
+    let run = move |key_or_passphrase: String| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to restore the key backup before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            let res = restore_backup(&client, &key_or_passphrase, |restored| {
+                progress.set(Some(restored))
+            })
+            .await;
+            match res {
+                Ok(()) => {
+                    trace!("Key backup restored");
+                    callback();
+                }
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                    progress.set(None);
+                }
+            }
+        });
+    };
+
+    (error_string, run, progress)
+}
@@ -2,56 +2,99 @@ use std::ops::Deref;
 
 use freya::prelude::*;
 use matrix_sdk::Client;
-use matrix_sdk::reqwest::Url;
+use swift_wind::matrix_discovery::discover_homeserver;
+use swift_wind::matrix_store::{AccountDb, add_recent_server, new_account_path};
 use tracing::error;
 use tracing::trace;
 use tracing::warn;
 
+use crate::ACCOUNT_DB;
 use crate::CLIENT;
 use crate::MatrixClientState;
 
+/// Connects to a server name, user ID or homeserver URL
 pub fn use_matrix_connect<F>(callback: F) -> (Signal<String>, impl FnMut(String) + Clone)
 where
     F: FnMut() + Clone + 'static,
 {
     let mut get_connect_err = use_signal(String::new);
 
-    let run_connect = move |url: String| {
+    let run_connect = move |server: String| {
         if let MatrixClientState::Connecting = CLIENT.peek().deref() {
             warn!("already trying to connect to matrix server");
             return;
         }
-        trace!("connecting to: {url}");
+        trace!("connecting to: {server}");
+        get_connect_err.write().clear();
         *CLIENT.write() = MatrixClientState::Connecting;
         let mut callback = callback.clone();
         spawn(async move {
-            let url = match Url::parse(&url) {
-                Ok(url) => url,
+            let discovered = match discover_homeserver(&server).await {
+                Ok(discovered) => discovered,
                 Err(err) => {
                     *get_connect_err.write() = err.to_string();
                     *CLIENT.write() = MatrixClientState::Disconnected;
-                    error!("URL parse error in connect {:?}", err);
+                    error!("homeserver discovery failed for {server}: {:?}", err);
+                    return;
+                }
+            };
+            trace!(
+                "{server} resolved to {}, supporting protocol version(s): {:#?}",
+                discovered.homeserver, discovered.versions
+            );
+
+            // The database of an earlier connect nobody logged in through is replaced
+            let previous = ACCOUNT_DB.peek().clone();
+            if let Some(db) = previous
+                && matches!(db.has_session().await, Ok(false))
+            {
+                *ACCOUNT_DB.write() = None;
+                discard(db).await;
+            }
+            let db = match AccountDb::open(new_account_path()).await {
+                Ok(db) => db,
+                Err(err) => {
+                    *get_connect_err.write() = err.to_string();
+                    *CLIENT.write() = MatrixClientState::Error(err.to_string());
+                    error!("failed to open account store {:?}", err);
+                    return;
+                }
+            };
+            let store_config = match db.store_config().await {
+                Ok(store_config) => store_config,
+                Err(err) => {
+                    *get_connect_err.write() = err.to_string();
+                    *CLIENT.write() = MatrixClientState::Error(err.to_string());
+                    error!("failed to open account store {:?}", err);
+                    discard(db).await;
                     return;
                 }
             };
             let client = Client::builder()
-                .homeserver_url(url)
+                .homeserver_url(discovered.homeserver)
+                .store_config(store_config)
                 .handle_refresh_tokens()
                 .build()
-                .await
-                .unwrap();
-            let version = client.server_versions().await;
-            let version = match version {
-                Ok(v) => v,
+                .await;
+            let client = match client {
+                Ok(client) => client,
                 Err(err) => {
                     *CLIENT.write() = MatrixClientState::Error(err.to_string());
+                    error!("failed to build client {:?}", err);
+                    discard(db).await;
                     return;
                 }
             };
-            trace!(
-                "Homeserver reports it can support protocol version(s): {:#?}",
-                version
-            );
+
+            let recent = discovered
+                .server_name
+                .map(|server_name| server_name.to_string())
+                .unwrap_or_else(|| server.trim().to_owned());
+            if let Err(err) = add_recent_server(&recent).await {
+                warn!("failed to remember recent server {:?}", err);
+            }
+
+            *ACCOUNT_DB.write() = Some(db);
             *CLIENT.write() = MatrixClientState::Connected(client);
             callback();
         });
@@ -59,3 +102,10 @@ where
 
     (get_connect_err, run_connect)
 }
+
+/// Removes the database of a connect that was abandoned before logging in.
+async fn discard(db: AccountDb) {
+    if let Err(err) = db.discard().await {
+        warn!("failed to remove unused account store {:?}", err);
+    }
+}
@@ -0,0 +1,94 @@
+use freya::prelude::*;
+use ruma::events::room::encrypted::OriginalSyncRoomEncryptedEvent;
+use ruma::{OwnedRoomId, serde::Raw};
+use swift_wind::matrix_decryption::{DecryptedMessage, KeyArrivals, decrypt_message, request_keys};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// Decrypts the event and tries again whenever its missing key arrives
+pub fn use_matrix_decrypt(
+    room_id: ReadOnlySignal<OwnedRoomId>,
+    evt: ReadOnlySignal<Raw<OriginalSyncRoomEncryptedEvent>>,
+) -> (
+    Signal<String>,
+    impl FnMut() + Clone,
+    Resource<Result<DecryptedMessage, String>>,
+) {
+    let mut error_string = use_signal(String::new);
+
+    // Bumped when the missing key arrives, which decrypts again
+    let mut key_arrived = use_signal(|| 0u32);
+    let mut waiting = use_signal(|| None::<Task>);
+
+    let decrypted = use_resource(move || async move {
+        key_arrived();
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        let Some(room) = client.get_room(&room_id()) else {
+            return Err(format!("Unknown room {}", room_id()));
+        };
+        if let Some(task) = waiting.take() {
+            task.cancel();
+        }
+        let arrivals = KeyArrivals::subscribe(&client).await;
+        let message = decrypt_message(&room, &evt()).await.map_err(|err| {
+            warn!("Decrypting failed: {err}");
+            err.to_string()
+        })?;
+        if let DecryptedMessage::Undecryptable {
+            session_id: Some(session_id),
+            ..
+        } = &message
+        {
+            let session_id = session_id.clone();
+            match arrivals {
+                Some(arrivals) => {
+                    waiting.set(Some(spawn(async move {
+                        arrivals.wait_for(&session_id).await;
+                        trace!("Key {session_id} arrived, decrypting again");
+                        key_arrived += 1;
+                    })));
+                }
+                None => warn!("no crypto store to wait for key {session_id}"),
+            }
+        }
+        Ok(message)
+    });
+
+    let request = move || {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to request keys before connected");
+            return;
+        };
+        let Some(Ok(DecryptedMessage::Undecryptable {
+            session_id: Some(session_id),
+            ..
+        })) = decrypted.read().clone()
+        else {
+            return;
+        };
+        let Some(room) = client.get_room(&room_id()) else {
+            return;
+        };
+        error_string.write().clear();
+        spawn(async move {
+            match request_keys(&room, &evt(), &session_id).await {
+                Ok(DecryptedMessage::Undecryptable { .. }) => {
+                    trace!("Key {session_id} requested");
+                }
+                Ok(_) => key_arrived += 1,
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, request, decrypted)
+}
@@ -0,0 +1,50 @@
+use freya::prelude::*;
+use ruma::OwnedDeviceId;
+use swift_wind::matrix_devices::{DeviceInfo, list_devices, rename_device};
+use tracing::error;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// Sessions the active account is signed in on
+pub fn use_matrix_devices() -> Resource<Result<Vec<DeviceInfo>, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        list_devices(&client).await.map_err(|err| {
+            warn!("Listing sessions failed: {err}");
+            err.to_string()
+        })
+    })
+}
+
+pub fn use_matrix_rename_device<F>(
+    callback: F,
+) -> (Signal<String>, impl FnMut(OwnedDeviceId, String) + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run = move |device_id: OwnedDeviceId, name: String| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to rename a session before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            match rename_device(&client, &device_id, &name).await {
+                Ok(()) => callback(),
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run)
+}
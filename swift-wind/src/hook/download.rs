@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use freya::prelude::*;
+use ruma::MilliSecondsSinceUnixEpoch;
+use swift_wind::matrix_attachment::Attachment;
+use swift_wind::matrix_download::{
+    DownloadErr, DownloadProgress, DownloadState, default_download_dir, download_file, reserve_path,
+};
+use swift_wind::matrix_store::{DownloadOutcome, DownloadRecord};
+use tokio::sync::watch;
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::ACCOUNT_DB;
+use crate::CLIENT;
+use crate::DOWNLOADS;
+use crate::MatrixClientState;
+
+//Every chunk would be a redraw otherwise
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+static NEXT_DOWNLOAD: AtomicU64 = AtomicU64::new(0);
+
+/// Download the manager is following, it moves to the account's history once it ends
+#[derive(Debug, Clone)]
+pub struct ActiveDownload {
+    pub id: u64,
+    pub name: String,
+    pub path: PathBuf,
+    pub progress: DownloadProgress,
+    pub state: DownloadState,
+    control: Arc<watch::Sender<DownloadState>>,
+}
+
+/// Pauses, resumes or cancels a running download
+pub fn control_download(id: u64, state: DownloadState) {
+    let mut downloads = DOWNLOADS.write();
+    if let Some(download) = downloads.iter_mut().find(|download| download.id == id) {
+        download.state = state;
+        download.control.send_replace(state);
+    }
+}
+
+/// Downloads an attachment into the given folder, or the default one, decrypting it if needed
+///
+/// The download isn't tied to the component, it keeps going in the download manager
+pub fn use_matrix_download() -> (
+    Signal<String>,
+    impl FnMut(Attachment, Option<PathBuf>) + Clone,
+) {
+    let mut error_string = use_signal(String::new);
+
+    let run = move |attachment: Attachment, dir: Option<PathBuf>| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to download before connected");
+            return;
+        };
+        error_string.write().clear();
+        let db = ACCOUNT_DB();
+        let dir = dir.unwrap_or_else(default_download_dir);
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            error!("{err}");
+            *error_string.write() = format!("Couldn't create {}: {err}", dir.display());
+            return;
+        }
+        let path = match reserve_path(&dir, &attachment.name) {
+            Ok(path) => path,
+            Err(err) => {
+                error!("{err}");
+                *error_string.write() =
+                    format!("Couldn't create a file in {}: {err}", dir.display());
+                return;
+            }
+        };
+        let id = NEXT_DOWNLOAD.fetch_add(1, Ordering::Relaxed);
+        let (control, receiver) = watch::channel(DownloadState::Running);
+        DOWNLOADS.write().push(ActiveDownload {
+            id,
+            name: attachment.name.clone(),
+            path: path.clone(),
+            progress: DownloadProgress {
+                received: 0,
+                total: attachment.size,
+            },
+            state: DownloadState::Running,
+            control: Arc::new(control),
+        });
+
+        spawn_forever(async move {
+            let mut last_update = Instant::now();
+            let res = download_file(&client, &attachment.source, &path, receiver, |progress| {
+                let done = Some(progress.received) == progress.total;
+                if !done && last_update.elapsed() < PROGRESS_INTERVAL {
+                    return;
+                }
+                last_update = Instant::now();
+                let mut downloads = DOWNLOADS.write();
+                if let Some(download) = downloads.iter_mut().find(|download| download.id == id) {
+                    download.progress = DownloadProgress {
+                        total: progress.total.or(attachment.size),
+                        ..progress
+                    };
+                }
+            })
+            .await;
+
+            let outcome = match res {
+                Ok(()) => {
+                    trace!("Downloaded {}", path.display());
+                    DownloadOutcome::Done
+                }
+                Err(DownloadErr::Cancelled) => DownloadOutcome::Cancelled,
+                Err(err) => {
+                    error!("{err}");
+                    DownloadOutcome::Failed(err.to_string())
+                }
+            };
+            let size = DOWNLOADS
+                .peek()
+                .iter()
+                .find(|download| download.id == id)
+                .and_then(|download| download.progress.total);
+            let record = DownloadRecord {
+                name: attachment.name,
+                path,
+                size,
+                outcome,
+                finished: MilliSecondsSinceUnixEpoch::now(),
+            };
+            // Into the history before leaving the list, the history reloads on that
+            if let Some(db) = db
+                && let Err(err) = db.add_download(&record).await
+            {
+                warn!("failed to save download history {:?}", err);
+            }
+            DOWNLOADS.write().retain(|download| download.id != id);
+        });
+    };
+
+    (error_string, run)
+}
+
+/// The active account's past downloads, reloaded whenever a download ends
+pub fn use_matrix_download_history() -> (
+    Signal<String>,
+    impl FnMut() + Clone,
+    Resource<Result<Vec<DownloadRecord>, String>>,
+) {
+    let mut error_string = use_signal(String::new);
+
+    let running = use_memo(move || DOWNLOADS.read().len());
+    let mut history = use_resource(move || async move {
+        // Reload when a download ends, not on every bit of progress
+        let _ = running();
+        let Some(db) = ACCOUNT_DB() else {
+            return Err("No account is open".to_string());
+        };
+        db.downloads().await.map_err(|err| {
+            warn!("Reading download history failed: {err}");
+            err.to_string()
+        })
+    });
+
+    let clear = move || {
+        let Some(db) = ACCOUNT_DB() else {
+            warn!("trying to clear download history without an account");
+            return;
+        };
+        error_string.write().clear();
+        spawn(async move {
+            match db.clear_downloads().await {
+                Ok(()) => history.restart(),
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, clear, history)
+}
@@ -0,0 +1,60 @@
+use freya::prelude::*;
+use ruma::events::room::MediaSource;
+use swift_wind::matrix_attachment::{Attachment, fetch_attachment};
+use swift_wind::matrix_image::{DecodedImage, decode_image, fit_size};
+use swift_wind::matrix_media::{MediaCache, MediaSize, fetch_media};
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// Image message ready to render, a thumbnail fitting `bounds` or the full image without them
+///
+/// Images that may animate are always fetched whole, thumbnails never animate. Unencrypted
+/// images go through the media cache, plain ones without a thumbnail get one from the server.
+pub fn use_matrix_image(
+    attachment: ReadOnlySignal<Attachment>,
+    bounds: Option<(u32, u32)>,
+) -> Resource<Result<DecodedImage, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        let (source, size) = {
+            let attachment = attachment.read();
+            match bounds {
+                Some(_) if attachment.may_be_animated() => {
+                    (attachment.source.clone(), MediaSize::Full)
+                }
+                Some(_) if attachment.thumbnail.is_some() => (
+                    attachment.thumbnail.clone().expect("thumbnail was checked"),
+                    MediaSize::Full,
+                ),
+                Some((max_width, max_height)) => {
+                    let (width, height) = fit_size(attachment.dimensions, max_width, max_height);
+                    (
+                        attachment.source.clone(),
+                        MediaSize::Thumbnail { width, height },
+                    )
+                }
+                None => (attachment.source.clone(), MediaSize::Full),
+            }
+        };
+        let data = match &source {
+            MediaSource::Plain(uri) => fetch_media(&client, MediaCache::shared(), uri, size)
+                .await
+                .map_err(|err| err.to_string()),
+            MediaSource::Encrypted(_) => fetch_attachment(&client, &source)
+                .await
+                .map_err(|err| err.to_string()),
+        }
+        .inspect_err(|err| warn!("Fetching image failed: {err}"))?;
+        tokio::task::spawn_blocking(move || decode_image(data))
+            .await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| {
+                warn!("Decoding image failed: {err}");
+                err.to_string()
+            })
+    })
+}
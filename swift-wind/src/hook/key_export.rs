@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use freya::prelude::*;
+use swift_wind::matrix_key_export::{KeyImport, export_keys, import_keys};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// Writes the room keys to a megolm key file, the count of exported sessions lands in the signal
+pub fn use_matrix_export_keys() -> (
+    Signal<String>,
+    impl FnMut(PathBuf, String) + Clone,
+    Signal<Option<usize>>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut exported = use_signal(|| None);
+
+    let run = move |path: PathBuf, passphrase: String| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to export room keys before connected");
+            return;
+        };
+        error_string.write().clear();
+        exported.set(None);
+        spawn(async move {
+            match export_keys(&client, path, &passphrase).await {
+                Ok(count) => {
+                    trace!("Room keys exported");
+                    exported.set(Some(count));
+                }
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run, exported)
+}
+
+/// Reads a megolm key file, counting the imported sessions as they're stored
+pub fn use_matrix_import_keys() -> (
+    Signal<String>,
+    impl FnMut(PathBuf, String) + Clone,
+    Signal<Option<KeyImport>>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut progress = use_signal(|| None);
+
+    let run = move |path: PathBuf, passphrase: String| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to import room keys before connected");
+            return;
+        };
+        error_string.write().clear();
+        progress.set(None);
+        spawn(async move {
+            let res = import_keys(&client, path, &passphrase, |imported| {
+                progress.set(Some(KeyImport { imported, total: 0 }))
+            })
+            .await;
+            match res {
+                Ok(done) => {
+                    trace!("Room keys imported");
+                    progress.set(Some(done));
+                }
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                    progress.set(None);
+                }
+            }
+        });
+    };
+
+    (error_string, run, progress)
+}
@@ -1,19 +1,26 @@
 use freya::prelude::*;
+use matrix_sdk::Client;
 use matrix_sdk::HttpError;
 use matrix_sdk::RumaApiError;
+use matrix_sdk::authentication::matrix::MatrixSession;
 
-use ruma::api::client::uiaa::AuthType;
 use ruma::api::error::FromHttpResponseError;
-use std::collections::VecDeque;
+use swift_wind::matrix_discovery::LoginFlows;
+use swift_wind::matrix_oidc::{AuthMetadata, discover_auth_metadata, login_oidc};
+use swift_wind::matrix_register::{device_display_name, discard_guest, register_guest};
+use swift_wind::matrix_sso::login_sso;
+use swift_wind::matrix_uiaa::choose_flow;
 use tracing::error;
 use tracing::trace;
 use tracing::warn;
 
+use crate::ACCOUNT_DB;
 use crate::CLIENT;
 use crate::MatrixClientState;
 use crate::components::additional_authorization::AuthenticationState;
 
 use super::CommonUserAuthData;
+use super::session::save_session;
 
 pub fn use_matrix_login<F>(
     callback: F,
@@ -33,7 +40,7 @@ where
         trace!("yo, yo dropped this");
     });
 
-    let register = move |mut auth_data: CommonUserAuthData| {
+    let register = move |auth_data: CommonUserAuthData| {
         let MatrixClientState::Connected(client) = CLIENT() else {
             warn!("trying to login before connected");
             *error_string.write() =
@@ -46,6 +53,7 @@ where
             let resp = client
                 .matrix_auth()
                 .login_username(&auth_data.username, &auth_data.password)
+                .initial_device_display_name(&device_display_name())
                 .request_refresh_token()
                 .await;
 
@@ -53,33 +61,23 @@ where
                 RumaApiError::Uiaa(info),
             )))) = resp
             {
-                let chosen_flow: VecDeque<AuthType> = {
-                    if !info.flows.is_empty() {
-                        let shortest_flow = info
-                            .flows
-                            .iter()
-                            .min_by_key(|v| v.stages.len())
-                            .unwrap()
-                            .clone();
-                        VecDeque::from(shortest_flow.stages)
-                    } else {
-                        warn!("Server asked for additional auth but provided no flow");
-                        *error_string.write() =
-                                "Server requires no authentication flow yet requested User Interactive Authentication. This should not happen".to_string();
-                        return;
-                    }
+                let Some(chosen_flow) = choose_flow(&info) else {
+                    warn!("Server asked for additional auth but provided no flow");
+                    *error_string.write() =
+                            "Server requires no authentication flow yet requested User Interactive Authentication. This should not happen".to_string();
+                    return;
                 };
 
-                auth_data.session_id = info.session;
-
                 trace!("Login chose auth flow: {:#?}", chosen_flow);
                 *returned_state_machine.write() =
                     Some(AuthenticationState::AdditionalAuthRequired {
                         chosen_flow,
-                        common_user_data: auth_data,
+                        session: info.session,
+                        params: info.params,
                     });
             } else if resp.is_ok() {
                 trace!("Inital login got accepted");
+                save_session(&client).await;
                 *returned_state_machine.write() = Some(AuthenticationState::Authorized);
             } else if let Err(err) = resp {
                 error!("Inital login got unexpected api error: {err}");
@@ -92,3 +90,229 @@ where
 
     (error_string, register, returned_state_machine)
 }
+
+/// Login types offered by the connected server, `None` until connected
+pub fn use_matrix_login_flows() -> Resource<Option<Result<LoginFlows, String>>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return None;
+        };
+        trace!("Fetching login flows");
+        let flows = client
+            .matrix_auth()
+            .get_login_types()
+            .await
+            .map(|res| LoginFlows::new(res.flows))
+            .map_err(|err| {
+                error!("Failed to fetch login flows: {err}");
+                err.to_string()
+            });
+        let Ok(mut flows) = flows else {
+            return Some(flows);
+        };
+        flows.oidc = match discover_auth_metadata(&client.homeserver()).await {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                warn!("Failed to discover auth metadata: {err}");
+                None
+            }
+        };
+        Some(Ok(flows))
+    })
+}
+
+/// Signs in through the system browser, takes the identity provider to skip the server's picker
+pub fn use_matrix_sso_login<F>(
+    callback: F,
+) -> (
+    Signal<String>,
+    impl FnMut(Option<String>) + Clone,
+    Signal<Option<AuthenticationState>>,
+)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+    let mut returned_state_machine: Signal<Option<AuthenticationState>> =
+        use_signal(|| Option::None);
+
+    let login = move |idp_id: Option<String>| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to login before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            trace!("Starting sso login");
+            match login_sso(&client, idp_id.as_deref(), |url| open::that(url)).await {
+                Ok(_) => {
+                    trace!("Sso login got accepted");
+                    save_session(&client).await;
+                    *returned_state_machine.write() = Some(AuthenticationState::Authorized);
+                }
+                Err(err) => {
+                    error!("Sso login failed: {err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+
+            callback();
+        });
+    };
+
+    (error_string, login, returned_state_machine)
+}
+
+/// Signs in at the authentication server a homeserver delegates to
+pub fn use_matrix_oidc_login<F>(
+    callback: F,
+) -> (
+    Signal<String>,
+    impl FnMut(AuthMetadata) + Clone,
+    Signal<Option<AuthenticationState>>,
+)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+    let mut returned_state_machine: Signal<Option<AuthenticationState>> =
+        use_signal(|| Option::None);
+
+    let login = move |metadata: AuthMetadata| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to login before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            trace!("Starting oidc login at {}", metadata.issuer);
+            let oidc = match login_oidc(&client, metadata, |url| open::that(url)).await {
+                Ok(oidc) => oidc,
+                Err(err) => {
+                    error!("Oidc login failed: {err}");
+                    *error_string.write() = err.to_string();
+                    callback();
+                    return;
+                }
+            };
+            trace!("Oidc login got accepted");
+            if let Some(db) = ACCOUNT_DB() {
+                if let Err(err) = db.save_oidc_session(&oidc).await {
+                    error!("Failed to save oidc session {:?}", err);
+                }
+            }
+            save_session(&client).await;
+            *returned_state_machine.write() = Some(AuthenticationState::Authorized);
+            callback();
+        });
+    };
+
+    (error_string, login, returned_state_machine)
+}
+
+pub fn use_matrix_token_login<F>(callback: F) -> (Signal<String>, impl FnMut(String))
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let login = move |token: String| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to login before connected");
+            return;
+        };
+        let mut callback = callback.clone();
+        spawn(async move {
+            trace!("Sending token login request");
+            let resp = client
+                .matrix_auth()
+                .login_token(token.trim())
+                .request_refresh_token()
+                .await;
+            match resp {
+                Ok(_) => {
+                    save_session(&client).await;
+                    callback();
+                }
+                Err(err) => {
+                    error!("Token login failed: {err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, login)
+}
+
+/// Guest access, offered once a guest the server registered up front is ready to continue as
+pub fn use_matrix_guest_login<F>(callback: F) -> (Signal<String>, impl FnMut(), Memo<bool>)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+    let mut guest: Signal<Option<(Client, MatrixSession)>> = use_signal(|| None);
+
+    use_effect(move || {
+        if let Some((client, session)) = guest.write().take() {
+            spawn_forever(discard(client, session));
+        }
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return;
+        };
+        if client.matrix_auth().logged_in() {
+            return;
+        }
+        spawn(async move {
+            match register_guest(&client).await {
+                Ok(Some(session)) => guest.set(Some((client, session))),
+                Ok(None) => trace!("Server doesn't allow guests"),
+                Err(err) => warn!("Registering a guest failed: {err}"),
+            }
+        });
+    });
+
+    // A guest nobody continued as is logged out again
+    use_drop(move || {
+        if let Some((client, session)) = guest.try_write().ok().and_then(|mut guest| guest.take()) {
+            spawn_forever(discard(client, session));
+        }
+    });
+
+    let login = move || {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to login before connected");
+            return;
+        };
+        let Some((_, session)) = guest.write().take() else {
+            warn!("no guest to continue as");
+            return;
+        };
+        let mut callback = callback.clone();
+        spawn(async move {
+            trace!("Continuing as guest {}", session.meta.user_id);
+            match client.matrix_auth().restore_session(session).await {
+                Ok(()) => {
+                    save_session(&client).await;
+                    callback();
+                }
+                Err(err) => {
+                    error!("Guest access failed: {err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    let available = use_memo(move || guest.read().is_some());
+
+    (error_string, login, available)
+}
+
+async fn discard(client: Client, session: MatrixSession) {
+    if let Err(err) = discard_guest(&client, &session).await {
+        warn!("Discarding unused guest failed: {err}");
+    }
+}
@@ -0,0 +1,25 @@
+use freya::prelude::*;
+use ruma::OwnedMxcUri;
+use swift_wind::matrix_media::{MediaCache, MediaSize, fetch_media};
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// `mxc` media of the active account's homeserver, through the disk cache
+pub fn use_matrix_media(
+    uri: ReadOnlySignal<OwnedMxcUri>,
+    size: Memo<MediaSize>,
+) -> Resource<Result<Vec<u8>, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        fetch_media(&client, MediaCache::shared(), &uri(), size())
+            .await
+            .map_err(|err| {
+                warn!("Fetching {} failed: {err}", uri());
+                err.to_string()
+            })
+    })
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use freya::prelude::*;
+use ruma::ClientSecret;
+use swift_wind::matrix_password_reset::{PasswordReset, request_reset_email};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// How often the server is asked whether the emailed link was opened.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// How long the emailed link is waited for before giving up.
+const RESET_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PasswordResetState {
+    Idle,
+    /// The email was sent, the reset goes through once its link is opened
+    WaitingForEmail,
+    Done,
+}
+
+/// Emails a reset link then keeps trying the new password until the link is opened
+pub fn use_matrix_password_reset<F>(
+    callback: F,
+) -> (
+    Signal<String>,
+    impl FnMut(String, String, bool) + Clone,
+    Signal<PasswordResetState>,
+)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+    let mut state = use_signal(|| PasswordResetState::Idle);
+    let client_secret = use_hook(ClientSecret::new);
+    let mut send_attempt = use_signal(|| 0u32);
+
+    let reset = move |email: String, new_password: String, logout_devices: bool| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to reset the password before connected");
+            *error_string.write() = "Client has not connected to server".to_string();
+            return;
+        };
+        error_string.write().clear();
+        // The same secret with a higher attempt sends the email again
+        *send_attempt.write() += 1;
+        let attempt = send_attempt();
+        let client_secret = client_secret.clone();
+        let mut callback = callback.clone();
+        spawn(async move {
+            let sid =
+                match request_reset_email(&client, &email, client_secret.clone(), attempt).await {
+                    Ok(sid) => sid,
+                    Err(err) => {
+                        error!("Requesting password reset email failed: {err}");
+                        *error_string.write() = err.to_string();
+                        return;
+                    }
+                };
+            state.set(PasswordResetState::WaitingForEmail);
+
+            let reset = PasswordReset::new(new_password, logout_devices, &sid, &client_secret);
+            let started = Instant::now();
+            // Cancelling or sending the email again ends this loop
+            while *state.peek() == PasswordResetState::WaitingForEmail
+                && *send_attempt.peek() == attempt
+            {
+                if started.elapsed() > RESET_TIMEOUT {
+                    warn!("gave up waiting for the password reset link");
+                    *error_string.write() =
+                        "The link wasn't opened in time, send the email again".to_string();
+                    state.set(PasswordResetState::Idle);
+                    return;
+                }
+                match reset.try_reset(&client).await {
+                    Ok(true) => {
+                        trace!("Password reset");
+                        state.set(PasswordResetState::Done);
+                        callback();
+                        return;
+                    }
+                    Ok(false) => tokio::time::sleep(POLL_INTERVAL).await,
+                    Err(err) => {
+                        error!("Resetting password failed: {err}");
+                        *error_string.write() = err.to_string();
+                        state.set(PasswordResetState::Idle);
+                        return;
+                    }
+                }
+            }
+        });
+    };
+
+    (error_string, reset, state)
+}
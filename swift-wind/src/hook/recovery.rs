@@ -0,0 +1,53 @@
+use freya::prelude::*;
+use swift_wind::matrix_recovery::{EncryptionSetup, enable_recovery, encryption_setup};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// What's left to set up for encryption on the active account
+pub fn use_matrix_encryption_setup() -> Resource<Result<EncryptionSetup, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        encryption_setup(&client).await.map_err(|err| {
+            warn!("Checking encryption setup failed: {err}");
+            err.to_string()
+        })
+    })
+}
+
+/// Creates secret storage, the recovery key to show the user lands in the returned signal
+pub fn use_matrix_enable_recovery() -> (
+    Signal<String>,
+    impl FnMut(Option<String>) + Clone,
+    Signal<Option<String>>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut recovery_key = use_signal(|| None);
+
+    let run = move |passphrase: Option<String>| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to enable recovery before connected");
+            return;
+        };
+        error_string.write().clear();
+        spawn(async move {
+            match enable_recovery(&client, passphrase.as_deref()).await {
+                Ok(key) => {
+                    trace!("Recovery enabled");
+                    recovery_key.set(Some(key));
+                }
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run, recovery_key)
+}
@@ -1,12 +1,16 @@
+use std::time::Duration;
+
 use freya::prelude::*;
+use matrix_sdk::Client;
 use matrix_sdk::HttpError;
 use matrix_sdk::RumaApiError;
 
-use ruma::api::client::account::register::RegistrationKind;
-use ruma::api::client::uiaa::AuthType;
+use ruma::api::client::account::register::{self, RegistrationKind};
 use ruma::api::error::FromHttpResponseError;
-use std::collections::VecDeque;
-use tracing::debug;
+use swift_wind::matrix_register::{
+    UsernameAvailability, device_display_name, username_availability,
+};
+use swift_wind::matrix_uiaa::choose_flow;
 use tracing::error;
 use tracing::trace;
 use tracing::warn;
@@ -16,6 +20,10 @@ use crate::MatrixClientState;
 use crate::components::additional_authorization::AuthenticationState;
 
 use super::CommonUserAuthData;
+use super::session::save_session;
+
+/// How long typing has to pause before the username is checked.
+const AVAILABILITY_DELAY: Duration = Duration::from_millis(500);
 
 pub fn use_matrix_register<F>(
     callback: F,
@@ -31,63 +39,50 @@ where
     let mut returned_state_machine: Signal<Option<AuthenticationState>> =
         use_signal(|| Option::None);
 
-    let register = move |mut auth_data: CommonUserAuthData| {
+    let register = move |auth_data: CommonUserAuthData| {
         let MatrixClientState::Connected(client) = CLIENT() else {
             warn!("trying to register before connected");
             *error_string.write() =
                 "Client has not connected to server, how are you here?".to_string();
             return;
         };
+        error_string.write().clear();
         let mut callback = callback.clone();
         spawn(async move {
-            let mut register_request = ruma::api::client::account::register::v3::Request::new();
-            register_request.initial_device_display_name =
-                Some("app.element.io: Firefox on Linux".to_string());
-            // register_request.password = Some(auth_data.password.clone());
-            // register_request.username = Some(auth_data.username.clone());
-            // register_request.refresh_token = true;
-            // register_request.kind = RegistrationKind::User;
-
             trace!("Sending inital register request");
-            let resp = client.matrix_auth().register(register_request).await;
+            let resp = client
+                .matrix_auth()
+                .register(register_request(&auth_data))
+                .await;
 
-            debug!("what the flow: {:#?}", resp);
-            return;
             //Holy error! This is what we should expect, effectively means that the user needs to do another step of auth,
             //like a recaptcha, shared token, or read terms and conditions
             if let Err(matrix_sdk::Error::Http(HttpError::Api(FromHttpResponseError::Server(
                 RumaApiError::Uiaa(info),
             )))) = resp
             {
-                // todo!("complex reg");
-                let chosen_flow: VecDeque<AuthType> = {
-                    if !info.flows.is_empty() {
-                        let shortest_flow = info
-                            .flows
-                            .iter()
-                            .min_by_key(|v| v.stages.len())
-                            .unwrap()
-                            .clone();
-                        VecDeque::from(shortest_flow.stages)
-                    } else {
-                        warn!("Server asked for additional auth but provided no flow");
-                        *error_string.write() =
-                                "Server requires no authentication flow yet requested User Interactive Authentication. This should not happen".to_string();
-                        return;
-                    }
+                let Some(chosen_flow) = choose_flow(&info) else {
+                    warn!("Server asked for additional auth but provided no flow");
+                    *error_string.write() =
+                            "Server requires no authentication flow yet requested User Interactive Authentication. This should not happen".to_string();
+                    return;
                 };
 
-                auth_data.session_id = info.session;
-
                 trace!("Register chose auth flow: {:#?}", chosen_flow);
                 *returned_state_machine.write() =
                     Some(AuthenticationState::AdditionalAuthRequired {
                         chosen_flow,
-                        common_user_data: auth_data,
+                        session: info.session,
+                        params: info.params,
                     });
                 callback();
             } else if resp.is_ok() {
                 trace!("Inital register auth got accepted");
+                if let Err(err) = finish_registration(&client, &auth_data).await {
+                    error!("Logging in after registering failed: {err}");
+                    *error_string.write() = err.to_string();
+                    return;
+                }
                 *returned_state_machine.write() = Some(AuthenticationState::Authorized);
                 callback();
             } else if let Err(err) = resp {
@@ -99,3 +94,56 @@ where
 
     (error_string, register, returned_state_machine)
 }
+
+/// Checks the username with the homeserver once typing pauses, `None` while there is nothing to say
+pub fn use_matrix_username_availability(
+    username: ReadOnlySignal<String>,
+) -> Resource<Option<Result<UsernameAvailability, String>>> {
+    use_resource(move || async move {
+        let username = username().trim().to_owned();
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return None;
+        };
+        if username.is_empty() {
+            return None;
+        }
+        // A newer username restarts the resource, dropping this check
+        tokio::time::sleep(AVAILABILITY_DELAY).await;
+        let availability = username_availability(&client, &username)
+            .await
+            .map_err(|err| {
+                warn!("Checking username availability failed: {err}");
+                err.to_string()
+            });
+        Some(availability)
+    })
+}
+
+/// Register request for the user's username and password, sent again with each auth stage
+pub fn register_request(auth_data: &CommonUserAuthData) -> register::v3::Request {
+    let mut register_request = register::v3::Request::new();
+    register_request.username = Some(auth_data.username.trim().to_owned());
+    register_request.password = Some(auth_data.password.clone());
+    register_request.initial_device_display_name = Some(device_display_name());
+    register_request.refresh_token = true;
+    register_request.kind = RegistrationKind::User;
+    register_request
+}
+
+/// Saves the session of a new account, logging in first if the server didn't
+pub async fn finish_registration(
+    client: &Client,
+    auth_data: &CommonUserAuthData,
+) -> Result<(), matrix_sdk::Error> {
+    if !client.matrix_auth().logged_in() {
+        trace!("Server registered without logging in, logging in");
+        client
+            .matrix_auth()
+            .login_username(auth_data.username.trim(), &auth_data.password)
+            .initial_device_display_name(&device_display_name())
+            .request_refresh_token()
+            .await?;
+    }
+    save_session(client).await;
+    Ok(())
+}
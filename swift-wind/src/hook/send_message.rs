@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use bevy::prelude::Entity;
+use freya::prelude::*;
+use ruma::OwnedRoomId;
+use swift_wind::matrix_service::bevy_matrix_api::MakeReq;
+use swift_wind::matrix_service::bevy_matrix_send_message::{
+    CompletedErr, LocalEchoActions, LocalEchoState, SendMessageParams, SendMessageProgess,
+};
+use tracing::error;
+use tracing::trace;
+
+use crate::{LOCAL_ECHOES, MATRIX_APP};
+
+/// Retries and restored messages don't report back, so the echoes are read again this often
+const ECHO_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Message of the open room that sync didn't bring back yet, shown at the end of the timeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingMessage {
+    pub echo: Entity,
+    pub body: String,
+    pub state: LocalEchoState,
+}
+
+async fn refresh_local_echoes(room_id: OwnedRoomId) {
+    let echoes = MATRIX_APP
+        .local_echoes(room_id)
+        .await
+        .into_iter()
+        .map(|(echo, item, local_echo)| PendingMessage {
+            echo,
+            body: item.body,
+            state: local_echo.state,
+        })
+        .collect();
+    *LOCAL_ECHOES.write() = echoes;
+}
+
+/// Keeps [`LOCAL_ECHOES`] on the messages of the room that is open
+pub fn use_local_echoes(room_id: ReadOnlySignal<OwnedRoomId>) {
+    let mut refreshing = use_signal(|| None::<Task>);
+
+    use_effect(move || {
+        if let Some(task) = refreshing.take() {
+            task.cancel();
+        }
+        let room_id = room_id();
+        LOCAL_ECHOES.write().clear();
+        let task = spawn(async move {
+            loop {
+                refresh_local_echoes(room_id.clone()).await;
+                tokio::time::sleep(ECHO_REFRESH_INTERVAL).await;
+            }
+        });
+        refreshing.set(Some(task));
+    });
+    use_drop(|| LOCAL_ECHOES.write().clear());
+}
+
+/// Queues a message that failed to send again
+pub fn resend_message(room_id: OwnedRoomId, echo: Entity) {
+    spawn(async move {
+        MATRIX_APP.resend_local_echo(echo).await;
+        refresh_local_echoes(room_id).await;
+    });
+}
+
+/// Takes a message that isn't sent yet out of the outbox, along with its echo
+pub fn cancel_message(room_id: OwnedRoomId, echo: Entity) {
+    spawn(async move {
+        MATRIX_APP.cancel_local_echo(echo).await;
+        refresh_local_echoes(room_id).await;
+    });
+}
+
+/// Hands a text message to the outbox, it's sent once the homeserver is reachable
+///
+/// How sending goes shows on its echo in the timeline, the error is only for messages
+/// that never got one
+pub fn use_matrix_send_message<F>(
+    callback: F,
+) -> (Signal<String>, impl FnMut(OwnedRoomId, String) + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run = move |room_id: OwnedRoomId, body: String| {
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            let progress = MATRIX_APP
+                .make_req::<SendMessageProgess>(SendMessageParams {
+                    room_id: room_id.clone(),
+                    body,
+                })
+                .await;
+            // A failed message can be resent, so keep listening until it goes through.
+            while let Ok(progress) = progress.recv().await {
+                match progress {
+                    SendMessageProgess::Executed(txn_id) => {
+                        trace!("Message queued with txn id {txn_id}");
+                        callback();
+                    }
+                    SendMessageProgess::Completed(Ok(event_id)) => {
+                        trace!("Message sent as {event_id}");
+                        refresh_local_echoes(room_id).await;
+                        return;
+                    }
+                    SendMessageProgess::Completed(Err(CompletedErr::Cancelled)) => return,
+                    SendMessageProgess::Completed(Err(CompletedErr::NotLoggedIn)) => {
+                        *error_string.write() = CompletedErr::NotLoggedIn.to_string();
+                        return;
+                    }
+                    SendMessageProgess::Completed(Err(err)) => error!("{err}"),
+                    SendMessageProgess::WaitingForExecution => {}
+                }
+                refresh_local_echoes(room_id.clone()).await;
+            }
+        });
+    };
+
+    (error_string, run)
+}
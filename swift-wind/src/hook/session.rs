@@ -0,0 +1,475 @@
+use std::ops::Deref;
+use std::time::Duration;
+
+use freya::prelude::*;
+use matrix_sdk::{
+    Client, LoopCtrl, SessionChange,
+    authentication::matrix::{MatrixSession, MatrixSessionTokens},
+    config::SyncSettings,
+};
+use ruma::{OwnedUserId, UserId};
+use swift_wind::matrix_store::{AccountDb, StoredSession, stored_sessions};
+use swift_wind::matrix_verification::incoming_verifications;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::ACCOUNT_DB;
+use crate::ACCOUNTS;
+use crate::Account;
+use crate::CLIENT;
+use crate::CURRENT_ROOM;
+use crate::CURRENT_SPACE;
+use crate::MatrixClientState;
+use crate::hook::verification::queue_verification;
+
+/// Wait before trying a failed OIDC refresh again.
+const REFRESH_RETRY: Duration = Duration::from_secs(10);
+
+static RESTORED: GlobalSignal<bool> = Global::new(bool::default);
+
+/// Saves the session of a client that just logged in and brings the account to the front.
+pub async fn save_session(client: &Client) {
+    let Some(db) = ACCOUNT_DB() else {
+        warn!("no account database to save the session in");
+        return;
+    };
+    let Some(session) = client.matrix_auth().session() else {
+        warn!("trying to save the session of a client that isn't logged in");
+        return;
+    };
+    if let Err(err) = db.save_session(&client.homeserver(), &session).await {
+        error!("failed to save session {:?}", err);
+        return;
+    }
+    let Some(user_id) = add_account(client.clone(), db).await else {
+        return;
+    };
+    switch_account(&user_id).await;
+}
+
+/// Starts the sync loop and session watcher of a logged in account.
+async fn add_account(client: Client, db: AccountDb) -> Option<OwnedUserId> {
+    let Some(user_id) = client.user_id().map(ToOwned::to_owned) else {
+        warn!("trying to add an account that isn't logged in");
+        return None;
+    };
+    let path = db.path().map(ToOwned::to_owned);
+    let tasks = [
+        watch_session_changes(client.clone(), db.clone()),
+        refresh_oidc_ahead(client.clone(), db.clone()),
+        sync_account(client.clone()),
+        watch_verifications(client.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let account = Account {
+        user_id: user_id.clone(),
+        client,
+        db,
+        unread: 0,
+        tasks,
+    };
+
+    let previous = {
+        let mut accounts = ACCOUNTS.write();
+        let previous = accounts
+            .iter()
+            .position(|account| account.user_id == user_id)
+            .map(|index| accounts.remove(index));
+        accounts.push(account);
+        previous
+    };
+    // Logging in again with the same user replaces the older session, a client rebuilt
+    // after a token refresh shares its database with the one it replaces.
+    if let Some(previous) = previous {
+        previous.tasks.iter().for_each(|task| task.cancel());
+        if previous.db.path() != path.as_deref() {
+            if let Err(err) = previous.db.remove_session().await {
+                warn!("failed to remove replaced session {:?}", err);
+            }
+        }
+    }
+    Some(user_id)
+}
+
+/// Makes an already added account the one shown in the interface.
+pub async fn switch_account(user_id: &UserId) {
+    let Some(account) = ACCOUNTS
+        .peek()
+        .iter()
+        .find(|account| account.user_id == user_id)
+        .cloned()
+    else {
+        warn!("trying to switch to unknown account {user_id}");
+        return;
+    };
+    let last_room = match account.db.last_room().await {
+        Ok(room) => room.map(|room| room.to_string()),
+        Err(err) => {
+            warn!("failed to read last open room {:?}", err);
+            None
+        }
+    };
+    if let Err(err) = account.db.touch_active().await {
+        warn!("failed to mark account as active {:?}", err);
+    }
+
+    trace!("switching to account {user_id}");
+    *ACCOUNT_DB.write() = Some(account.db);
+    *CLIENT.write() = MatrixClientState::Connected(account.client);
+    *CURRENT_SPACE.write() = None;
+    *CURRENT_ROOM.write() = last_room;
+}
+
+/// Leaves the other accounts syncing while the login page adds a new one.
+pub fn start_adding_account() {
+    *ACCOUNT_DB.write() = None;
+    *CLIENT.write() = MatrixClientState::Disconnected;
+    *CURRENT_SPACE.write() = None;
+    *CURRENT_ROOM.write() = None;
+}
+
+/// Stops an account, the next one is brought to the front if it was active.
+async fn remove_account(user_id: &UserId) {
+    let removed = {
+        let mut accounts = ACCOUNTS.write();
+        accounts
+            .iter()
+            .position(|account| account.user_id == user_id)
+            .map(|index| accounts.remove(index))
+    };
+    if let Some(removed) = removed {
+        removed.tasks.iter().for_each(|task| task.cancel());
+    }
+
+    let was_active = matches!(
+        CLIENT.peek().deref(),
+        MatrixClientState::Connected(client) if client.user_id() == Some(user_id)
+    );
+    if !was_active {
+        return;
+    }
+    let next = ACCOUNTS
+        .peek()
+        .first()
+        .map(|account| account.user_id.clone());
+    match next {
+        Some(next) => switch_account(&next).await,
+        None => {
+            *ACCOUNT_DB.write() = None;
+            *CLIENT.write() = MatrixClientState::Disconnected;
+            *CURRENT_SPACE.write() = None;
+            *CURRENT_ROOM.write() = None;
+        }
+    }
+}
+
+fn sync_account(client: Client) -> Option<Task> {
+    spawn_forever(async move {
+        let res = client
+            .sync_with_callback(SyncSettings::default(), |_| {
+                let client = client.clone();
+                async move {
+                    update_unread(&client);
+                    LoopCtrl::Continue
+                }
+            })
+            .await;
+        if let Err(err) = res {
+            error!("sync of {:?} stopped {err}", client.user_id());
+        }
+    })
+}
+
+/// Queues verification requests from other sessions for the verification dialog.
+fn watch_verifications(client: Client) -> Option<Task> {
+    let mut incoming = incoming_verifications(&client);
+    spawn_forever(async move {
+        while let Some(flow) = incoming.recv().await {
+            queue_verification(flow);
+        }
+    })
+}
+
+fn update_unread(client: &Client) {
+    let unread: u64 = client
+        .joined_rooms()
+        .iter()
+        .map(|room| room.unread_notification_counts().notification_count)
+        .sum();
+    let mut accounts = ACCOUNTS.write();
+    let account = accounts
+        .iter_mut()
+        .find(|account| Some(account.user_id.deref()) == client.user_id());
+    if let Some(account) = account {
+        account.unread = unread;
+    }
+}
+
+/// Refresh tokens rotate the access token, so every refresh has to be written back.
+fn watch_session_changes(client: Client, db: AccountDb) -> Option<Task> {
+    spawn_forever(async move {
+        let mut changes = client.subscribe_to_session_changes();
+        loop {
+            match changes.recv().await {
+                Ok(SessionChange::TokensRefreshed) => {
+                    let Some(session) = client.matrix_auth().session() else {
+                        continue;
+                    };
+                    trace!("saving refreshed session tokens");
+                    if let Err(err) = db.save_session(&client.homeserver(), &session).await {
+                        error!("failed to save refreshed session {:?}", err);
+                    }
+                }
+                Ok(SessionChange::UnknownToken { soft_logout }) => {
+                    warn!("homeserver rejected the session token, soft logout: {soft_logout}");
+                    // Replacing or removing the account cancels this task, so it can't happen
+                    // from inside it.
+                    spawn_forever(async move { refresh_or_remove_account(client, db).await });
+                    return;
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            }
+        }
+    })
+}
+
+/// OIDC access tokens are refreshed shortly before they expire, so the account switches over
+/// to the new token without any request being rejected first.
+fn refresh_oidc_ahead(client: Client, db: AccountDb) -> Option<Task> {
+    spawn_forever(async move {
+        let oidc = match db.oidc_session().await {
+            Ok(Some(oidc)) => oidc,
+            Ok(None) => return,
+            Err(err) => {
+                warn!("failed to read oidc session {:?}", err);
+                return;
+            }
+        };
+        let Some(refresh_in) = oidc.refresh_in() else {
+            trace!("oidc token expiry unknown, refreshing once it's rejected");
+            return;
+        };
+        trace!("refreshing oidc token in {refresh_in:?}");
+        tokio::time::sleep(refresh_in).await;
+        // Replacing the account cancels this task, so it can't happen from inside it.
+        spawn_forever(async move {
+            // The token still works for a while, a rejected one is handled by the session watcher.
+            while is_current(&client) {
+                match refresh_account(&client, &db).await {
+                    Ok(_) => return,
+                    Err(err) => warn!("refreshing oidc session failed, trying again {err}"),
+                }
+                tokio::time::sleep(REFRESH_RETRY).await;
+            }
+        });
+    })
+}
+
+/// Whether the client still runs its account, it's replaced after a refresh or logout.
+fn is_current(client: &Client) -> bool {
+    ACCOUNTS.peek().iter().any(|account| {
+        Some(account.user_id.deref()) == client.user_id()
+            && account.client.access_token() == client.access_token()
+    })
+}
+
+/// OIDC sessions get a new access token and a rebuilt client, others are logged out.
+async fn refresh_or_remove_account(client: Client, db: AccountDb) {
+    let Some(user_id) = client.user_id().map(ToOwned::to_owned) else {
+        return;
+    };
+    match refresh_account(&client, &db).await {
+        Ok(true) => return,
+        Ok(false) => {}
+        Err(err) => error!("failed to refresh oidc session {err}"),
+    }
+    if let Err(err) = db.remove_session().await {
+        error!("failed to remove rejected session {:?}", err);
+    }
+    remove_account(&user_id).await;
+}
+
+/// Swaps in a client with a refreshed OIDC token, `false` for sessions that aren't OIDC.
+async fn refresh_account(client: &Client, db: &AccountDb) -> Result<bool, String> {
+    let Some(user_id) = client.user_id().map(ToOwned::to_owned) else {
+        return Ok(false);
+    };
+    let Some(refreshed) = refresh_oidc_client(client, db).await? else {
+        return Ok(false);
+    };
+    let was_active = matches!(
+        CLIENT.peek().deref(),
+        MatrixClientState::Connected(active) if active.user_id() == Some(&user_id)
+    );
+    add_account(refreshed, db.clone()).await;
+    if was_active {
+        switch_account(&user_id).await;
+    }
+    Ok(true)
+}
+
+/// The client can't take new tokens once logged in, so a new one is built on the same stores.
+async fn refresh_oidc_client(client: &Client, db: &AccountDb) -> Result<Option<Client>, String> {
+    let Some(mut oidc) = db.oidc_session().await.map_err(|err| err.to_string())? else {
+        return Ok(None);
+    };
+    let Some(meta) = client.session_meta().cloned() else {
+        return Ok(None);
+    };
+    let access_token = oidc.refresh().await.map_err(|err| err.to_string())?;
+    db.save_oidc_session(&oidc)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let stored = StoredSession {
+        homeserver: client.homeserver(),
+        session: MatrixSession {
+            meta,
+            tokens: MatrixSessionTokens {
+                access_token,
+                refresh_token: None,
+            },
+        },
+    };
+    db.save_session(&stored.homeserver, &stored.session)
+        .await
+        .map_err(|err| err.to_string())?;
+    trace!("rebuilding client with refreshed oidc tokens");
+    restore_client(db, stored).await.map(Some)
+}
+
+/// Restores every saved session once per launch, the callback only runs when one was restored.
+pub fn use_matrix_restore<F>(callback: F) -> (Signal<String>, impl FnMut() + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run_restore = move || {
+        if std::mem::replace(&mut *RESTORED.write(), true) {
+            return;
+        }
+        if !matches!(CLIENT(), MatrixClientState::Disconnected) {
+            warn!("trying to restore sessions while already connected");
+            return;
+        }
+        *CLIENT.write() = MatrixClientState::Connecting;
+        let mut callback = callback.clone();
+        spawn(async move {
+            let sessions = match stored_sessions().await {
+                Ok(sessions) => sessions,
+                Err(err) => {
+                    error!("failed to read stored sessions {:?}", err);
+                    *error_string.write() = err.to_string();
+                    *CLIENT.write() = MatrixClientState::Disconnected;
+                    return;
+                }
+            };
+
+            let mut front = None;
+            for (db, stored) in sessions {
+                trace!("restoring session of {}", stored.session.meta.user_id);
+                let client = match restore_client(&db, stored).await {
+                    Ok(client) => client,
+                    Err(err) => {
+                        error!("failed to restore session {err}");
+                        *error_string.write() = err;
+                        continue;
+                    }
+                };
+                let user_id = add_account(client, db).await;
+                front = front.or(user_id);
+            }
+
+            let Some(front) = front else {
+                trace!("no stored session to restore");
+                *CLIENT.write() = MatrixClientState::Disconnected;
+                return;
+            };
+            switch_account(&front).await;
+            callback();
+        });
+    };
+
+    (error_string, run_restore)
+}
+
+async fn restore_client(db: &AccountDb, stored: StoredSession) -> Result<Client, String> {
+    let store_config = db.store_config().await.map_err(|err| err.to_string())?;
+    let client = Client::builder()
+        .homeserver_url(stored.homeserver)
+        .store_config(store_config)
+        .handle_refresh_tokens()
+        .build()
+        .await
+        .map_err(|err| err.to_string())?;
+    client
+        .matrix_auth()
+        .restore_session(stored.session)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(client)
+}
+
+/// Logs the active account out, another logged in account takes its place if there is one.
+pub fn use_matrix_logout<F>(callback: F) -> (Signal<String>, impl FnMut() + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run_logout = move || {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to logout while not connected");
+            return;
+        };
+        let Some(user_id) = client.user_id().map(ToOwned::to_owned) else {
+            warn!("trying to logout a client that isn't logged in");
+            return;
+        };
+        let mut callback = callback.clone();
+        spawn(async move {
+            let db = ACCOUNTS
+                .peek()
+                .iter()
+                .find(|account| account.user_id == user_id)
+                .map(|account| account.db.clone());
+            let oidc = match &db {
+                Some(db) => db.oidc_session().await.unwrap_or_else(|err| {
+                    warn!("failed to read oidc session {:?}", err);
+                    None
+                }),
+                None => None,
+            };
+            // The session is forgotten locally even if the homeserver can't be reached.
+            match oidc {
+                Some(oidc) => {
+                    let access_token = client.access_token().unwrap_or_default();
+                    if let Err(err) = oidc.revoke(&access_token).await {
+                        warn!("revoking oidc session failed {err}");
+                    }
+                }
+                None => {
+                    if let Err(err) = client.matrix_auth().logout().await {
+                        warn!("homeserver logout failed {err}");
+                    }
+                }
+            }
+            if let Some(db) = db {
+                if let Err(err) = db.remove_session().await {
+                    error!("failed to remove stored session {:?}", err);
+                    *error_string.write() = err.to_string();
+                }
+            }
+            remove_account(&user_id).await;
+            callback();
+        });
+    };
+
+    (error_string, run_logout)
+}
@@ -3,17 +3,31 @@ use std::error::Error;
 use freya::prelude::*;
 use matrix_sdk::{HttpError, RumaApiError};
 use ruma::api::{
-    client::{account::register::RegistrationKind, uiaa::AuthData},
+    client::{
+        account::{
+            request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
+            request_registration_token_via_email, request_registration_token_via_msisdn,
+        },
+        uiaa::{AuthData, AuthType},
+    },
     error::FromHttpResponseError,
 };
+use ruma::{ClientSecret, OwnedClientSecret, OwnedSessionId, UInt};
+use swift_wind::matrix_register::device_display_name;
+use swift_wind::matrix_uiaa::{UiaaOperation, UiaaStep, choose_flow};
 use tracing::{error, trace, warn};
+use url::Url;
 
+use crate::components::additional_authorization::AuthenticationState;
 use crate::{CLIENT, MatrixClientState};
 
 use super::CommonUserAuthData;
+use super::register::{finish_registration, register_request};
+use super::session::save_session;
 
 pub enum HookAuthResult {
-    NextStage,
+    /// The server wants more, the stages it counts as done so far are listed.
+    NextStage(Vec<AuthType>),
     AuthFinished,
 }
 
@@ -21,12 +35,17 @@ pub enum HookAuthResult {
 pub enum AdditionalAuthType {
     Login(CommonUserAuthData),
     Register(CommonUserAuthData),
+    /// A sensitive account operation like deleting devices or changing the password
+    Operation(UiaaOperation),
 }
 
 //TODO: Form requests based on additional auth type. Or maybe create a callback???
 pub fn use_matrix_additional_auth<F>(
     callback: F,
-) -> (Signal<String>, impl FnMut(AuthData, AdditionalAuthType))
+) -> (
+    Signal<String>,
+    impl FnMut(AuthData, AdditionalAuthType) + Clone,
+)
 where
     //This callback should know if we're finished authenticating or if there is more to be done
     F: FnMut(HookAuthResult) + Clone + 'static,
@@ -39,6 +58,7 @@ where
             "Got additional auth data: {:#?} for auth type :{:#?}",
             data, auth_type
         );
+        error_string.write().clear();
         let mut callback = callback.clone();
         spawn(async move {
             let res = match auth_type {
@@ -48,6 +68,7 @@ where
                 AdditionalAuthType::Register(common_user_auth_data) => {
                     auth_register(&data, common_user_auth_data).await
                 }
+                AdditionalAuthType::Operation(operation) => auth_operation(data, operation).await,
             };
             match res {
                 Ok(auth_res) => callback(auth_res),
@@ -60,20 +81,171 @@ where
     (error_string, run_auth)
 }
 
+/// Sends an operation that may need user-interactive auth, the state says which stages are left
+pub fn use_matrix_uiaa() -> (
+    Signal<String>,
+    impl FnMut(UiaaOperation) + Clone,
+    Signal<Option<AuthenticationState>>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut returned_state_machine: Signal<Option<AuthenticationState>> =
+        use_signal(|| Option::None);
+
+    let run = move |operation: UiaaOperation| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to run an operation before connected");
+            *error_string.write() = "Client has not connected to server".to_string();
+            return;
+        };
+        error_string.write().clear();
+        spawn(async move {
+            trace!("Sending operation {:?}", operation);
+            match operation.send(&client, None).await {
+                Ok(UiaaStep::Done) => {
+                    *returned_state_machine.write() = Some(AuthenticationState::Authorized);
+                }
+                Ok(UiaaStep::Auth(info)) => {
+                    let Some(chosen_flow) = choose_flow(&info) else {
+                        warn!("Server asked for additional auth but provided no flow");
+                        *error_string.write() =
+                            "Server requires no authentication flow yet requested User Interactive Authentication. This should not happen".to_string();
+                        return;
+                    };
+                    trace!("Operation chose auth flow: {:#?}", chosen_flow);
+                    *returned_state_machine.write() =
+                        Some(AuthenticationState::AdditionalAuthRequired {
+                            chosen_flow,
+                            session: info.session,
+                            params: info.params,
+                        });
+                }
+                Err(err) => {
+                    error!("Operation failed: {err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run, returned_state_machine)
+}
+
+/// Where to send the validation code for an email or phone number.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ThreepidAddress {
+    Email(String),
+    Msisdn {
+        country: String,
+        phone_number: String,
+    },
+}
+
+/// An email or phone number validation the homeserver started.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ThreepidSession {
+    pub sid: OwnedSessionId,
+    pub client_secret: OwnedClientSecret,
+    pub submit_url: Option<Url>,
+}
+
+/// Asks the homeserver to send a validation code, registration uses its own endpoints
+pub fn use_matrix_request_threepid_token() -> (
+    Signal<String>,
+    impl FnMut(ThreepidAddress, AdditionalAuthType) + Clone,
+    Signal<Option<ThreepidSession>>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut threepid_session: Signal<Option<ThreepidSession>> = use_signal(|| None);
+    let mut send_attempt = use_signal(|| 0u32);
+
+    let request = move |address: ThreepidAddress, auth_type: AdditionalAuthType| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to request a validation code before connected");
+            return;
+        };
+        error_string.write().clear();
+        // Retrying with the same secret and a higher attempt resends the code.
+        let client_secret = threepid_session
+            .peek()
+            .as_ref()
+            .map(|session| session.client_secret.clone())
+            .unwrap_or_else(ClientSecret::new);
+        *send_attempt.write() += 1;
+        let attempt = UInt::from(send_attempt());
+        let register = matches!(auth_type, AdditionalAuthType::Register(_));
+        spawn(async move {
+            trace!("Requesting validation code for {:?}", address);
+            let res = match address {
+                ThreepidAddress::Email(email) if register => client
+                    .send(request_registration_token_via_email::v3::Request::new(
+                        client_secret.clone(),
+                        email,
+                        attempt,
+                    ))
+                    .await
+                    .map(|res| (res.sid, None)),
+                ThreepidAddress::Email(email) => client
+                    .send(request_3pid_management_token_via_email::v3::Request::new(
+                        client_secret.clone(),
+                        email,
+                        attempt,
+                    ))
+                    .await
+                    .map(|res| (res.sid, None)),
+                ThreepidAddress::Msisdn {
+                    country,
+                    phone_number,
+                } if register => client
+                    .send(request_registration_token_via_msisdn::v3::Request::new(
+                        client_secret.clone(),
+                        country,
+                        phone_number,
+                        attempt,
+                    ))
+                    .await
+                    .map(|res| (res.sid, res.submit_url)),
+                ThreepidAddress::Msisdn {
+                    country,
+                    phone_number,
+                } => client
+                    .send(request_3pid_management_token_via_msisdn::v3::Request::new(
+                        client_secret.clone(),
+                        country,
+                        phone_number,
+                        attempt,
+                    ))
+                    .await
+                    .map(|res| (res.sid, res.submit_url)),
+            };
+            match res {
+                Ok((sid, submit_url)) => {
+                    *threepid_session.write() = Some(ThreepidSession {
+                        sid,
+                        client_secret,
+                        submit_url: submit_url.and_then(|url| Url::parse(&url).ok()),
+                    });
+                }
+                Err(err) => {
+                    error!("Requesting validation code failed: {err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, request, threepid_session)
+}
+
 async fn auth_register(
     finished_auth_data: &AuthData,
     common_user_data: CommonUserAuthData,
 ) -> Result<HookAuthResult, Box<dyn Error>> {
     let MatrixClientState::Connected(client) = CLIENT() else {
         warn!("trying to authenticate before connected");
-        panic!("Use thiserror to return a state mismatch error");
+        return Err("Client has not connected to server".into());
     };
 
-    let mut register_request = ruma::api::client::account::register::v3::Request::new();
-    register_request.password = Some(common_user_data.password.clone());
-    register_request.username = Some(common_user_data.username.clone());
-    register_request.refresh_token = true;
-    register_request.kind = RegistrationKind::User;
+    let mut register_request = register_request(&common_user_data);
     register_request.auth = Some(finished_auth_data.clone());
 
     trace!("Sending additional auth register request");
@@ -84,14 +256,12 @@ async fn auth_register(
     {
         if let Some(auth_error) = info.auth_error {
             error!("Failed to authenticate: {:#?}", auth_error);
-            panic!(
-                "Use thiserror to return authentication error: {:#?}",
-                auth_error
-            );
+            return Err(auth_error.message.into());
         }
-        return Ok(HookAuthResult::NextStage);
+        return Ok(HookAuthResult::NextStage(info.completed));
     } else if resp.is_ok() {
         trace!("Additional authentication registration completed");
+        finish_registration(&client, &common_user_data).await?;
         return Ok(HookAuthResult::AuthFinished);
     } else if let Err(err) = resp {
         error!("Additional Authentication got unexpected api error: {err}");
@@ -100,18 +270,42 @@ async fn auth_register(
     unreachable!()
 }
 
+async fn auth_operation(
+    finished_auth_data: AuthData,
+    operation: UiaaOperation,
+) -> Result<HookAuthResult, Box<dyn Error>> {
+    let MatrixClientState::Connected(client) = CLIENT() else {
+        warn!("trying to authenticate before connected");
+        return Err("Client has not connected to server".into());
+    };
+
+    trace!("Sending additional auth for {:?}", operation);
+    match operation.send(&client, Some(finished_auth_data)).await? {
+        // A refused stage keeps the session, the user can try it again
+        UiaaStep::Auth(info) => match info.auth_error {
+            Some(auth_error) => Err(auth_error.message.into()),
+            None => Ok(HookAuthResult::NextStage(info.completed)),
+        },
+        UiaaStep::Done => {
+            trace!("Additional authentication for operation completed");
+            Ok(HookAuthResult::AuthFinished)
+        }
+    }
+}
+
 async fn auth_login(
     common_user_data: CommonUserAuthData,
 ) -> Result<HookAuthResult, Box<dyn Error>> {
     let MatrixClientState::Connected(client) = CLIENT() else {
         warn!("trying to authenticate before connected");
-        panic!("Use thiserror to return a state mismatch error");
+        return Err("Client has not connected to server".into());
     };
 
     trace!("Sending additional auth login request");
     let resp = client
         .matrix_auth()
         .login_username(common_user_data.username, &common_user_data.password)
+        .initial_device_display_name(&device_display_name())
         .request_refresh_token()
         .await;
 
@@ -121,14 +315,12 @@ async fn auth_login(
     {
         if let Some(auth_error) = info.auth_error {
             error!("Failed to authenticate: {:#?}", auth_error);
-            panic!(
-                "Use thiserror to return authentication error: {:#?}",
-                auth_error
-            );
+            return Err(auth_error.message.into());
         }
-        return Ok(HookAuthResult::NextStage);
+        return Ok(HookAuthResult::NextStage(info.completed));
     } else if resp.is_ok() {
         trace!("Additional authentication login completed");
+        save_session(&client).await;
         return Ok(HookAuthResult::AuthFinished);
     } else if let Err(err) = resp {
         error!("Additional Authentication got unexpected api error: {err}");
@@ -0,0 +1,40 @@
+use freya::prelude::*;
+use ruma::OwnedRoomId;
+use swift_wind::matrix_timeline::{TimelineItem, watch_timeline};
+use tracing::error;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// The room's latest messages, oldest first, kept up to date while the room is open
+pub fn use_matrix_timeline(
+    room_id: ReadOnlySignal<OwnedRoomId>,
+) -> (Signal<String>, Signal<Vec<TimelineItem>>) {
+    let mut error_string = use_signal(String::new);
+    let mut timeline = use_signal(Vec::new);
+    let mut watching = use_signal(|| None::<Task>);
+
+    use_effect(move || {
+        if let Some(task) = watching.take() {
+            task.cancel();
+        }
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return;
+        };
+        let Some(room) = client.get_room(&room_id()) else {
+            *error_string.write() = format!("Unknown room {}", room_id());
+            return;
+        };
+        error_string.write().clear();
+        timeline.write().clear();
+        let task = spawn(async move {
+            if let Err(err) = watch_timeline(&room, |items| timeline.set(items)).await {
+                error!("{err}");
+                *error_string.write() = err.to_string();
+            }
+        });
+        watching.set(Some(task));
+    });
+
+    (error_string, timeline)
+}
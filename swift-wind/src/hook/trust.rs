@@ -0,0 +1,84 @@
+use freya::prelude::*;
+use ruma::{OwnedRoomId, OwnedUserId};
+use swift_wind::matrix_trust::{
+    RoomTrust, accept_identity_change, room_trust, watch_identity_changes,
+};
+use tracing::error;
+use tracing::trace;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+
+/// The room's members with their verification, and the room-level shield
+pub fn use_matrix_room_trust(
+    room_id: ReadOnlySignal<OwnedRoomId>,
+) -> Resource<Result<RoomTrust, String>> {
+    use_resource(move || async move {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return Err("Client has not connected to server".to_string());
+        };
+        let Some(room) = client.get_room(&room_id()) else {
+            return Err(format!("Unknown room {}", room_id()));
+        };
+        room_trust(&room).await.map_err(|err| {
+            warn!("Checking members' verification failed: {err}");
+            err.to_string()
+        })
+    })
+}
+
+/// Members of the room whose identity changed and wasn't accepted or verified again
+pub fn use_matrix_identity_changes(
+    room_id: ReadOnlySignal<OwnedRoomId>,
+) -> Signal<Vec<OwnedUserId>> {
+    let mut changed = use_signal(Vec::new);
+
+    use_effect(move || {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return;
+        };
+        let Some(room) = client.get_room(&room_id()) else {
+            return;
+        };
+        changed.write().clear();
+        spawn(async move {
+            watch_identity_changes(&room, |users| changed.set(users)).await;
+        });
+    });
+
+    changed
+}
+
+/// Accepts a member's new identity, dismissing the warning without verifying them again
+pub fn use_matrix_accept_identity<F>(
+    callback: F,
+) -> (Signal<String>, impl FnMut(OwnedUserId) + Clone)
+where
+    F: FnMut() + Clone + 'static,
+{
+    let mut error_string = use_signal(String::new);
+
+    let run = move |user_id: OwnedUserId| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to accept an identity before connected");
+            return;
+        };
+        error_string.write().clear();
+        let mut callback = callback.clone();
+        spawn(async move {
+            match accept_identity_change(&client, &user_id).await {
+                Ok(()) => {
+                    trace!("Accepted new identity of {user_id}");
+                    callback();
+                }
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run)
+}
@@ -0,0 +1,98 @@
+use freya::prelude::*;
+use ruma::{OwnedDeviceId, OwnedUserId};
+use swift_wind::matrix_verification::{VerificationFlow, VerificationStage};
+use tracing::error;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+use crate::VERIFICATIONS;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VerificationAction {
+    Accept,
+    /// The emojis match
+    Confirm,
+    /// The emojis don't match
+    Mismatch,
+    Cancel,
+}
+
+/// Adds a verification to the dialog queue unless it's already there
+pub fn queue_verification(flow: VerificationFlow) {
+    let mut flows = VERIFICATIONS.write();
+    if !flows.contains(&flow) {
+        flows.push(flow);
+    }
+}
+
+/// Follows a verification from the moment the component mounts, the actions answer its prompts
+pub fn use_matrix_verification(
+    flow: VerificationFlow,
+) -> (
+    Signal<String>,
+    impl FnMut(VerificationAction) + Clone,
+    Signal<VerificationStage>,
+) {
+    let mut error_string = use_signal(String::new);
+    let mut stage = use_signal(|| VerificationStage::Starting);
+
+    let running = flow.clone();
+    use_hook(move || {
+        spawn(async move {
+            running.run(|next| stage.set(next)).await;
+        })
+    });
+
+    let act = move |action: VerificationAction| {
+        error_string.write().clear();
+        let flow = flow.clone();
+        spawn(async move {
+            let res = match action {
+                VerificationAction::Accept => flow.accept().await,
+                VerificationAction::Confirm => flow.confirm().await,
+                VerificationAction::Mismatch => flow.mismatch().await,
+                VerificationAction::Cancel => flow.cancel().await,
+            };
+            if let Err(err) = res {
+                error!("{err}");
+                *error_string.write() = err.to_string();
+            }
+        });
+    };
+
+    (error_string, act, stage)
+}
+
+/// Asks a user, or one of their sessions when a device is given, to verify
+pub fn use_matrix_request_verification() -> (
+    Signal<String>,
+    impl FnMut(OwnedUserId, Option<OwnedDeviceId>) + Clone,
+) {
+    let mut error_string = use_signal(String::new);
+
+    let run = move |user_id: OwnedUserId, device_id: Option<OwnedDeviceId>| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            warn!("trying to request verification before connected");
+            return;
+        };
+        error_string.write().clear();
+        spawn(async move {
+            let res = match device_id {
+                Some(device_id) => {
+                    VerificationFlow::request_device(&client, &user_id, &device_id).await
+                }
+                None => VerificationFlow::request_user(&client, &user_id).await,
+            };
+            match res {
+                Ok(flow) => queue_verification(flow),
+                Err(err) => {
+                    error!("{err}");
+                    *error_string.write() = err.to_string();
+                }
+            }
+        });
+    };
+
+    (error_string, run)
+}
@@ -1,12 +1,39 @@
+pub mod matrix_account;
+pub mod matrix_attachment;
+pub mod matrix_backup;
+pub mod matrix_decryption;
+pub mod matrix_devices;
+pub mod matrix_discovery;
+pub mod matrix_download;
+pub mod matrix_image;
+pub mod matrix_key_export;
+pub mod matrix_loopback;
+pub mod matrix_media;
+pub mod matrix_oidc;
+pub mod matrix_password_reset;
+pub mod matrix_recovery;
+pub mod matrix_register;
+pub mod matrix_sso;
+pub mod matrix_store;
+pub mod matrix_timeline;
+pub mod matrix_trust;
+pub mod matrix_uiaa;
+pub mod matrix_verification;
+#[cfg(test)]
+mod test_support;
+
 pub mod matrix_service {
 
     pub mod bevy_matrix_app {
         use std::thread::{self, JoinHandle};
 
         use crate::matrix_service::{
+            bevy_matrix_outbox::OutboxPlugin,
+            bevy_matrix_send_message::SendMessagePlugin,
             bevy_tokio::TokioPlugin,
             reactive_runner_plugin::{ReactiveRunner, ReactiveRunnerPlugin},
         };
+        use crate::matrix_store::data_dir;
         use bevy::app::App;
 
         pub fn run_matrix_app() -> (JoinHandle<()>, ReactiveRunner) {
@@ -15,6 +42,8 @@ pub mod matrix_service {
                 App::new()
                     .add_plugins(reactive_runner_plugin)
                     .add_plugins(TokioPlugin::new())
+                    .add_plugins(OutboxPlugin::new(data_dir().join("outbox")))
+                    .add_plugins(SendMessagePlugin::new())
                     .run();
             });
             (handle, reactive_runner)
@@ -328,7 +357,7 @@ pub mod matrix_service {
             where
                 T: Send + Sync + 'static,
                 F: FnOnce() -> R + Send + Sync + 'static,
-                R: Future<Output = T> + Send + Sync + 'static,
+                R: Future<Output = T> + Send + 'static,
                 F2: FnOnce(&mut App, T) + Send + Sync + 'static,
             {
                 let reactive_runtime = self.reactive_runner.clone();
@@ -960,4 +989,2053 @@ pub mod matrix_service {
             }
         }
     }
+
+    pub mod bevy_matrix_client {
+        use std::ops::Deref;
+
+        use bevy::prelude::*;
+        use matrix_sdk::Client;
+
+        use crate::matrix_service::reactive_runner_plugin::ReactiveRunner;
+
+        #[derive(Resource, Debug, Clone)]
+        pub struct MatrixClient {
+            pub client: Client,
+        }
+
+        impl MatrixClient {
+            pub fn new(client: Client) -> Self {
+                Self { client }
+            }
+        }
+
+        impl Deref for MatrixClient {
+            type Target = Client;
+
+            fn deref(&self) -> &Self::Target {
+                &self.client
+            }
+        }
+
+        pub trait MatrixClientActions {
+            /// Makes the client the one the app sends with, `None` when no account is logged in.
+            fn set_matrix_client(&self, client: Option<Client>) -> impl Future<Output = ()> + Send;
+        }
+
+        impl MatrixClientActions for ReactiveRunner {
+            async fn set_matrix_client(&self, client: Option<Client>) {
+                self.send_fn(move |app| {
+                    let world = app.world_mut();
+                    // removing it first marks the new client as added, even when it replaces one
+                    world.remove_resource::<MatrixClient>();
+                    if let Some(client) = client {
+                        world.insert_resource(MatrixClient::new(client));
+                    }
+                })
+                .await;
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use bevy::prelude::*;
+            use matrix_sdk::Client;
+            use test_log::test;
+
+            use crate::matrix_service::{
+                bevy_matrix_client::{MatrixClient, MatrixClientActions},
+                bevy_tokio::{TokioPlugin, TokioRt},
+                reactive_runner_plugin::{ReactiveRunnerPlugin, tick_blocking},
+            };
+
+            #[derive(Resource, Default)]
+            struct Added(usize);
+
+            fn count_added(client: Option<Res<MatrixClient>>, mut added: ResMut<Added>) {
+                if client.is_some_and(|client| client.is_added()) {
+                    added.0 += 1;
+                }
+            }
+
+            #[test]
+            fn replacing_client_marks_it_added() {
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((runner_plugin, TokioPlugin::new()))
+                    .init_resource::<Added>()
+                    .add_systems(Update, count_added);
+
+                let rt = app.world().resource::<TokioRt>().clone();
+                let client = || {
+                    rt.block_on(
+                        Client::builder()
+                            .homeserver_url("http://localhost:8008")
+                            .build(),
+                    )
+                    .unwrap()
+                };
+
+                rt.block_on(reactive_runner.set_matrix_client(Some(client())));
+                tick_blocking(&mut app, &runner_rx);
+                rt.block_on(reactive_runner.set_matrix_client(Some(client())));
+                tick_blocking(&mut app, &runner_rx);
+                app.update();
+                assert_eq!(app.world().resource::<Added>().0, 2);
+
+                rt.block_on(reactive_runner.set_matrix_client(None));
+                tick_blocking(&mut app, &runner_rx);
+                assert!(app.world().get_resource::<MatrixClient>().is_none());
+            }
+        }
+    }
+
+    pub mod bevy_matrix_send_message {
+        use std::collections::HashSet;
+
+        use bevy::prelude::*;
+        use matrix_sdk::{Client, event_handler::EventHandlerHandle, room::Room};
+        use ruma::{
+            OwnedEventId, OwnedRoomId, OwnedTransactionId, OwnedUserId, TransactionId,
+            events::room::message::OriginalSyncRoomMessageEvent,
+        };
+        use thiserror::Error;
+
+        use crate::matrix_service::{
+            bevy_matrix_api::{APIProccesingLabel, APITx},
+            bevy_matrix_client::MatrixClient,
+            bevy_matrix_outbox::{Outbox, OutboxEntry, OutboxEvent, OutboxQueue, enqueue},
+            bevy_tokio::AsyncQueue,
+            reactive_runner_plugin::ReactiveRunner,
+        };
+
+        #[derive(Debug, Component, Clone, Hash)]
+        pub struct SendMessageParams {
+            pub room_id: OwnedRoomId,
+            pub body: String,
+        }
+
+        #[derive(Debug, Component, Clone, Default, PartialEq, Eq)]
+        pub enum SendMessageProgess {
+            #[default]
+            WaitingForExecution,
+            Executed(OwnedTransactionId),
+            Completed(Result<OwnedEventId, CompletedErr>),
+        }
+
+        #[derive(Debug, Component, Clone, Error, PartialEq, Eq)]
+        pub enum CompletedErr {
+            #[error("room {0} is not known to the client")]
+            UnknownRoom(OwnedRoomId),
+
+            #[error("{0}")]
+            SendFailed(String),
+
+            #[error("message was cancelled")]
+            Cancelled,
+
+            #[error("no account is logged in")]
+            NotLoggedIn,
+        }
+
+        /// Message in a room timeline, either echoed locally or received from the homeserver.
+        #[derive(Debug, Component, Clone, PartialEq, Eq)]
+        pub struct TimelineItem {
+            pub room_id: OwnedRoomId,
+            pub body: String,
+        }
+
+        /// Marks a [`TimelineItem`] that was sent by us and not yet seen in sync.
+        #[derive(Debug, Component, Clone, PartialEq, Eq)]
+        pub struct LocalEcho {
+            /// Account sending the message.
+            pub user_id: OwnedUserId,
+            pub txn_id: OwnedTransactionId,
+            pub state: LocalEchoState,
+        }
+
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum LocalEchoState {
+            /// Waiting in the outbox or in flight.
+            Sending,
+            /// Sending failed for a reason that may go away, it's tried again a few more times.
+            Retrying(String),
+            Sent(OwnedEventId),
+            Failed(String),
+        }
+
+        /// Event id of a [`TimelineItem`] that came down sync.
+        #[derive(Debug, Component, Clone, PartialEq, Eq)]
+        pub struct RemoteEvent {
+            pub event_id: OwnedEventId,
+        }
+
+        /// Room message received from sync, waiting to be merged into the timeline.
+        #[derive(Debug, Component, Clone, PartialEq, Eq)]
+        pub struct SyncedMessage {
+            pub room_id: OwnedRoomId,
+            pub event_id: OwnedEventId,
+            pub txn_id: Option<OwnedTransactionId>,
+            pub body: String,
+        }
+
+        #[derive(Debug, Component, Clone, Copy, Default)]
+        pub struct ResendLocalEcho;
+
+        /// Drops a queued or failed echo. Echoes already in flight are left to finish, the homeserver may have the event.
+        #[derive(Debug, Component, Clone, Copy, Default)]
+        pub struct CancelLocalEcho;
+
+        /// Links a local echo to the request that created it, so the requester hears about resends.
+        #[derive(Debug, Component, Clone)]
+        pub struct LocalEchoRequest {
+            pub tx: APITx<SendMessageProgess>,
+        }
+
+        #[derive(Debug, Clone, Copy, Default)]
+        pub struct SendMessagePlugin {}
+
+        impl Plugin for SendMessagePlugin {
+            fn build(&self, app: &mut App) {
+                app.add_systems(
+                    Update,
+                    (
+                        register_sync_handler,
+                        send_message_executor,
+                        resend_local_echo,
+                        cancel_local_echo,
+                        reconcile_local_echo,
+                    )
+                        .chain(),
+                );
+            }
+        }
+
+        impl SendMessagePlugin {
+            pub fn new() -> Self {
+                Self::default()
+            }
+        }
+
+        pub trait LocalEchoActions {
+            /// Messages the current account sent in `room_id` that sync didn't bring back yet, oldest first.
+            fn local_echoes(
+                &self,
+                room_id: OwnedRoomId,
+            ) -> impl Future<Output = Vec<(Entity, TimelineItem, LocalEcho)>> + Send;
+            fn resend_local_echo(&self, echo: Entity) -> impl Future<Output = ()> + Send;
+            fn cancel_local_echo(&self, echo: Entity) -> impl Future<Output = ()> + Send;
+        }
+
+        impl LocalEchoActions for ReactiveRunner {
+            async fn local_echoes(
+                &self,
+                room_id: OwnedRoomId,
+            ) -> Vec<(Entity, TimelineItem, LocalEcho)> {
+                let (tx, rx) = async_channel::bounded(1);
+                self.send_fn(move |app| {
+                    let world = app.world_mut();
+                    let user_id = world
+                        .get_resource::<MatrixClient>()
+                        .and_then(|client| client.user_id().map(ToOwned::to_owned));
+                    let mut q = world.query::<(Entity, Ref<TimelineItem>, &LocalEcho)>();
+                    let mut echoes = q
+                        .iter(world)
+                        .filter(|(_, item, echo)| {
+                            item.room_id == room_id && Some(&echo.user_id) == user_id.as_ref()
+                        })
+                        .map(|(entity, item, echo)| {
+                            (
+                                item.added(),
+                                entity,
+                                TimelineItem::clone(&item),
+                                echo.clone(),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    // spawn order, echoes are never re-added
+                    echoes.sort_by_key(|(added, ..)| added.get());
+                    let _ = tx.try_send(
+                        echoes
+                            .into_iter()
+                            .map(|(_, entity, item, echo)| (entity, item, echo))
+                            .collect(),
+                    );
+                })
+                .await;
+                rx.recv().await.unwrap_or_default()
+            }
+
+            async fn resend_local_echo(&self, echo: Entity) {
+                self.send_fn(move |app| {
+                    if let Ok(mut entity) = app.world_mut().get_entity_mut(echo) {
+                        entity.insert(ResendLocalEcho);
+                    }
+                })
+                .await;
+            }
+
+            async fn cancel_local_echo(&self, echo: Entity) {
+                self.send_fn(move |app| {
+                    if let Ok(mut entity) = app.world_mut().get_entity_mut(echo) {
+                        entity.insert(CancelLocalEcho);
+                    }
+                })
+                .await;
+            }
+        }
+
+        /// Listens for synced messages of the current client, the handler moves along when the client is replaced.
+        pub fn register_sync_handler(
+            client: Option<Res<MatrixClient>>,
+            reactive_runner: Res<ReactiveRunner>,
+            mut registered: Local<Option<(Client, EventHandlerHandle)>>,
+        ) {
+            let replaced = match &client {
+                Some(client) => client.is_added(),
+                None => registered.is_some(),
+            };
+            if !replaced {
+                return;
+            }
+            if let Some((previous, handle)) = registered.take() {
+                trace!("removing local echo sync handler of the previous client");
+                previous.remove_event_handler(handle);
+            }
+            let Some(client) = client else {
+                return;
+            };
+            trace!("registering local echo sync handler");
+            let reactive_runner = reactive_runner.clone();
+            let handle =
+                client.add_event_handler(move |ev: OriginalSyncRoomMessageEvent, room: Room| {
+                    let reactive_runner = reactive_runner.clone();
+                    async move {
+                        let synced = SyncedMessage {
+                            room_id: room.room_id().to_owned(),
+                            event_id: ev.event_id.clone(),
+                            txn_id: ev.unsigned.transaction_id.clone(),
+                            body: ev.content.body().to_string(),
+                        };
+                        reactive_runner
+                            .send_fn(move |app| {
+                                app.world_mut().spawn(synced);
+                            })
+                            .await;
+                    }
+                });
+            *registered = Some((client.client.clone(), handle));
+        }
+
+        pub fn send_message_executor(
+            requests: Query<
+                (Entity, &SendMessageParams, &APITx<SendMessageProgess>),
+                Without<APIProccesingLabel>,
+            >,
+            client: Option<Res<MatrixClient>>,
+            mut outbox_queue: ResMut<OutboxQueue>,
+            outbox: Res<Outbox>,
+            async_queue: AsyncQueue,
+            mut commands: Commands,
+        ) {
+            let user_id = client.as_ref().and_then(|client| client.user_id());
+            for (entity, params, api_tx) in requests {
+                commands.entity(entity).despawn();
+                let Some(user_id) = user_id else {
+                    warn!("send message req made before logging in");
+                    let _ = api_tx.send_blocking(SendMessageProgess::Completed(Err(
+                        CompletedErr::NotLoggedIn,
+                    )));
+                    continue;
+                };
+                let txn_id = TransactionId::new();
+                trace!("send message req made with txn id {txn_id}");
+                if api_tx
+                    .send_blocking(SendMessageProgess::Executed(txn_id.clone()))
+                    .is_err()
+                {
+                    warn!("send message channel disconnected");
+                }
+
+                commands.spawn((
+                    TimelineItem {
+                        room_id: params.room_id.clone(),
+                        body: params.body.clone(),
+                    },
+                    LocalEcho {
+                        user_id: user_id.to_owned(),
+                        txn_id: txn_id.clone(),
+                        state: LocalEchoState::Sending,
+                    },
+                    LocalEchoRequest { tx: api_tx.clone() },
+                ));
+
+                enqueue(
+                    &async_queue,
+                    &outbox,
+                    &mut outbox_queue,
+                    OutboxEntry::new(
+                        user_id.to_owned(),
+                        params.room_id.clone(),
+                        txn_id,
+                        OutboxEvent::Message {
+                            body: params.body.clone(),
+                        },
+                    ),
+                );
+            }
+        }
+
+        pub fn resend_local_echo(
+            echoes: Query<(Entity, &TimelineItem, &mut LocalEcho), With<ResendLocalEcho>>,
+            mut outbox_queue: ResMut<OutboxQueue>,
+            outbox: Res<Outbox>,
+            async_queue: AsyncQueue,
+            mut commands: Commands,
+        ) {
+            for (entity, item, mut echo) in echoes {
+                commands.entity(entity).remove::<ResendLocalEcho>();
+                let LocalEchoState::Failed(_) = echo.state else {
+                    warn!("only failed messages can be resent");
+                    continue;
+                };
+                trace!("resending message with txn id {}", echo.txn_id);
+                echo.state = LocalEchoState::Sending;
+                enqueue(
+                    &async_queue,
+                    &outbox,
+                    &mut outbox_queue,
+                    OutboxEntry::new(
+                        echo.user_id.clone(),
+                        item.room_id.clone(),
+                        echo.txn_id.clone(),
+                        OutboxEvent::Message {
+                            body: item.body.clone(),
+                        },
+                    ),
+                );
+            }
+        }
+
+        pub fn cancel_local_echo(
+            echoes: Query<
+                (Entity, &TimelineItem, &LocalEcho, Option<&LocalEchoRequest>),
+                With<CancelLocalEcho>,
+            >,
+            mut outbox_queue: ResMut<OutboxQueue>,
+            outbox: Res<Outbox>,
+            async_queue: AsyncQueue,
+            mut commands: Commands,
+        ) {
+            for (entity, item, echo, request) in echoes {
+                if let LocalEchoState::Sent(_) = echo.state {
+                    warn!("message was already accepted by the homeserver, cannot cancel");
+                    commands.entity(entity).remove::<CancelLocalEcho>();
+                    continue;
+                }
+                if outbox_queue.is_in_flight(&echo.txn_id) {
+                    warn!("message is being sent right now, cannot cancel");
+                    commands.entity(entity).remove::<CancelLocalEcho>();
+                    continue;
+                }
+                trace!("cancelling message with txn id {}", echo.txn_id);
+                outbox_queue.remove(&item.room_id, &echo.txn_id);
+                // enqueue removes it once it's written, removing it now could run before the write
+                if !outbox_queue.persisting.contains(&echo.txn_id) {
+                    let outbox = outbox.clone();
+                    let txn_id = echo.txn_id.clone();
+                    async_queue.spawn(async move {
+                        if let Err(err) = outbox.remove(&txn_id).await {
+                            warn!("failed to remove cancelled message from outbox: {err}");
+                        }
+                    });
+                }
+                if let Some(request) = request {
+                    let _ = request
+                        .tx
+                        .send_blocking(SendMessageProgess::Completed(Err(CompletedErr::Cancelled)));
+                }
+                commands.entity(entity).despawn();
+            }
+        }
+
+        pub fn reconcile_local_echo(
+            synced: Query<(Entity, &SyncedMessage)>,
+            echoes: Query<(Entity, &TimelineItem, &LocalEcho)>,
+            mut commands: Commands,
+        ) {
+            for (synced_entity, message) in synced {
+                commands.entity(synced_entity).despawn();
+                let remote_event = RemoteEvent {
+                    event_id: message.event_id.clone(),
+                };
+
+                let echo = message.txn_id.as_ref().and_then(|txn_id| {
+                    echoes.iter().find(|(_, item, echo)| {
+                        item.room_id == message.room_id && &echo.txn_id == txn_id
+                    })
+                });
+
+                match echo {
+                    Some((echo_entity, _, echo)) => {
+                        trace!("reconciled local echo {}", echo.txn_id);
+                        commands
+                            .entity(echo_entity)
+                            .remove::<(LocalEcho, LocalEchoRequest, CancelLocalEcho)>()
+                            .insert(remote_event);
+                    }
+                    None => {
+                        commands.spawn((
+                            TimelineItem {
+                                room_id: message.room_id.clone(),
+                                body: message.body.clone(),
+                            },
+                            remote_event,
+                        ));
+                    }
+                }
+            }
+        }
+
+        /// Recreates the echoes of messages that were still in the outbox when the app was closed.
+        pub fn restore_local_echoes(world: &mut World, entries: &[OutboxEntry]) {
+            let mut q = world.query::<&LocalEcho>();
+            let known = q
+                .iter(world)
+                .map(|echo| echo.txn_id.clone())
+                .collect::<HashSet<OwnedTransactionId>>();
+            for entry in entries {
+                let OutboxEvent::Message { body } = &entry.event else {
+                    continue;
+                };
+                if known.contains(&entry.txn_id) {
+                    continue;
+                }
+                trace!("restoring local echo {}", entry.txn_id);
+                world.spawn((
+                    TimelineItem {
+                        room_id: entry.room_id.clone(),
+                        body: body.clone(),
+                    },
+                    LocalEcho {
+                        user_id: entry.user_id.clone(),
+                        txn_id: entry.txn_id.clone(),
+                        state: LocalEchoState::Sending,
+                    },
+                ));
+            }
+        }
+
+        /// Applies the outcome of sending a message to its local echo, if it is still around.
+        pub fn update_local_echo(
+            world: &mut World,
+            txn_id: &TransactionId,
+            res: Result<OwnedEventId, CompletedErr>,
+        ) {
+            let mut q = world.query::<(&mut LocalEcho, Option<&LocalEchoRequest>)>();
+            let Some((mut echo, request)) =
+                q.iter_mut(world).find(|(echo, _)| echo.txn_id == txn_id)
+            else {
+                trace!("local echo {txn_id} is gone, dropping send result");
+                return;
+            };
+            if let Some(request) = request {
+                let _ = request
+                    .tx
+                    .send_blocking(SendMessageProgess::Completed(res.clone()));
+            }
+            echo.state = match res {
+                Ok(event_id) => LocalEchoState::Sent(event_id),
+                Err(err) => {
+                    warn!("send message err: {err}");
+                    LocalEchoState::Failed(err.to_string())
+                }
+            };
+        }
+
+        /// Shows on the local echo why sending it is being retried.
+        pub fn retrying_local_echo(world: &mut World, txn_id: &TransactionId, reason: String) {
+            let mut q = world.query::<&mut LocalEcho>();
+            let Some(mut echo) = q.iter_mut(world).find(|echo| echo.txn_id == txn_id) else {
+                return;
+            };
+            if let LocalEchoState::Sending | LocalEchoState::Retrying(_) = echo.state {
+                echo.state = LocalEchoState::Retrying(reason);
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use bevy::prelude::*;
+            use ruma::{
+                OwnedRoomId, OwnedUserId, TransactionId, owned_event_id, owned_room_id,
+                owned_user_id, user_id,
+            };
+            use test_log::test;
+
+            use crate::matrix_service::{
+                bevy_matrix_api::MakeReq,
+                bevy_matrix_client::MatrixClient,
+                bevy_matrix_outbox::{
+                    Outbox, OutboxEntry, OutboxEvent, OutboxPlugin, OutboxQueue, outbox_settled,
+                },
+                bevy_matrix_send_message::{
+                    CancelLocalEcho, CompletedErr, LocalEcho, LocalEchoActions, LocalEchoState,
+                    RemoteEvent, SendMessageParams, SendMessagePlugin, SendMessageProgess,
+                    SyncedMessage, TimelineItem, retrying_local_echo, update_local_echo,
+                },
+                bevy_tokio::{TokioPlugin, TokioRt},
+                reactive_runner_plugin::{ReactiveRunnerPlugin, tick_blocking},
+            };
+            use crate::test_support::offline_client;
+
+            fn log_in(app: &mut App) {
+                let rt = app.world().resource::<TokioRt>().clone();
+                let client = rt.block_on(offline_client(user_id!("@alice:localhost")));
+                app.insert_resource(MatrixClient::new(client));
+            }
+
+            #[test]
+            fn send_is_queued_in_outbox() {
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+                log_in(&mut app);
+
+                let request_rx =
+                    reactive_runner.make_req_blocking::<SendMessageProgess>(SendMessageParams {
+                        room_id: owned_room_id!("!room:localhost"),
+                        body: String::from("hello"),
+                    });
+                tick_blocking(&mut app, &runner_rx);
+                while !outbox_settled(&app) {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+                let r = request_rx.recv_blocking().unwrap();
+                let SendMessageProgess::Executed(txn_id) = r else {
+                    panic!("expected executed, got {r:?}");
+                };
+
+                let queue = app.world().resource::<OutboxQueue>();
+                let queued = &queue.rooms[&owned_room_id!("!room:localhost")];
+                assert_eq!(queued.len(), 1);
+                assert_eq!(queued[0].txn_id, txn_id);
+                assert_eq!(queued[0].user_id, user_id!("@alice:localhost"));
+
+                let world = app.world_mut();
+                let mut q = world.query::<&LocalEcho>();
+                let echoes = q.iter(world).cloned().collect::<Vec<LocalEcho>>();
+                assert_eq!(
+                    echoes,
+                    vec![LocalEcho {
+                        user_id: owned_user_id!("@alice:localhost"),
+                        txn_id,
+                        state: LocalEchoState::Sending,
+                    }]
+                );
+            }
+
+            #[test]
+            fn reconcile_with_synced_message() {
+                let (runner_plugin, _reactive_runner) = ReactiveRunnerPlugin::new();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+
+                let txn_id = TransactionId::new();
+                let room_id = owned_room_id!("!room:localhost");
+                let echo = app
+                    .world_mut()
+                    .spawn((
+                        TimelineItem {
+                            room_id: room_id.clone(),
+                            body: String::from("hello"),
+                        },
+                        LocalEcho {
+                            user_id: owned_user_id!("@alice:localhost"),
+                            txn_id: txn_id.clone(),
+                            state: LocalEchoState::Sending,
+                        },
+                    ))
+                    .id();
+                app.world_mut().spawn(SyncedMessage {
+                    room_id: room_id.clone(),
+                    event_id: owned_event_id!("$hello:localhost"),
+                    txn_id: Some(txn_id),
+                    body: String::from("hello"),
+                });
+                app.world_mut().spawn(SyncedMessage {
+                    room_id,
+                    event_id: owned_event_id!("$other:localhost"),
+                    txn_id: None,
+                    body: String::from("hi"),
+                });
+
+                app.update();
+
+                let world = app.world_mut();
+                assert!(world.get::<LocalEcho>(echo).is_none());
+                assert_eq!(
+                    world.get::<RemoteEvent>(echo),
+                    Some(&RemoteEvent {
+                        event_id: owned_event_id!("$hello:localhost"),
+                    })
+                );
+                let mut q = world.query::<&TimelineItem>();
+                assert_eq!(q.iter(world).count(), 2);
+                let mut q = world.query::<&SyncedMessage>();
+                assert_eq!(q.iter(world).count(), 0);
+            }
+
+            #[test]
+            fn retrying_echo_until_it_fails() {
+                let mut world = World::new();
+                let txn_id = TransactionId::new();
+                let echo = world
+                    .spawn(LocalEcho {
+                        user_id: owned_user_id!("@alice:localhost"),
+                        txn_id: txn_id.clone(),
+                        state: LocalEchoState::Sending,
+                    })
+                    .id();
+                let state = |world: &World| world.get::<LocalEcho>(echo).unwrap().state.clone();
+
+                retrying_local_echo(&mut world, &txn_id, String::from("bad gateway"));
+                assert_eq!(
+                    state(&world),
+                    LocalEchoState::Retrying(String::from("bad gateway"))
+                );
+
+                update_local_echo(
+                    &mut world,
+                    &txn_id,
+                    Err(CompletedErr::SendFailed(String::from("bad gateway"))),
+                );
+                retrying_local_echo(&mut world, &txn_id, String::from("timed out"));
+                assert_eq!(
+                    state(&world),
+                    LocalEchoState::Failed(String::from("bad gateway"))
+                );
+            }
+
+            #[test]
+            fn cancel_failed_echo() {
+                let (runner_plugin, _reactive_runner) = ReactiveRunnerPlugin::new();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+
+                let echo = app
+                    .world_mut()
+                    .spawn((
+                        TimelineItem {
+                            room_id: owned_room_id!("!room:localhost"),
+                            body: String::from("hello"),
+                        },
+                        LocalEcho {
+                            user_id: owned_user_id!("@alice:localhost"),
+                            txn_id: TransactionId::new(),
+                            state: LocalEchoState::Failed(String::from("offline")),
+                        },
+                        CancelLocalEcho,
+                    ))
+                    .id();
+
+                app.update();
+
+                assert!(app.world().get_entity(echo).is_err());
+            }
+
+            #[test]
+            fn local_echoes_of_current_account() {
+                fn spawn_echo(
+                    world: &mut World,
+                    user_id: OwnedUserId,
+                    room_id: OwnedRoomId,
+                    body: &str,
+                ) -> Entity {
+                    world
+                        .spawn((
+                            TimelineItem {
+                                room_id,
+                                body: body.to_string(),
+                            },
+                            LocalEcho {
+                                user_id,
+                                txn_id: TransactionId::new(),
+                                state: LocalEchoState::Failed(String::from("offline")),
+                            },
+                        ))
+                        .id()
+                }
+
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+                log_in(&mut app);
+                let room_id = owned_room_id!("!room:localhost");
+                let first = spawn_echo(
+                    app.world_mut(),
+                    owned_user_id!("@alice:localhost"),
+                    room_id.clone(),
+                    "first",
+                );
+                spawn_echo(
+                    app.world_mut(),
+                    owned_user_id!("@bob:localhost"),
+                    room_id.clone(),
+                    "bob's",
+                );
+                spawn_echo(
+                    app.world_mut(),
+                    owned_user_id!("@alice:localhost"),
+                    owned_room_id!("!other:localhost"),
+                    "elsewhere",
+                );
+                app.update();
+                while !outbox_settled(&app) {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+                let second = spawn_echo(
+                    app.world_mut(),
+                    owned_user_id!("@alice:localhost"),
+                    room_id.clone(),
+                    "second",
+                );
+
+                let rt = app.world().resource::<TokioRt>().clone();
+                let echoes = rt.spawn({
+                    let reactive_runner = reactive_runner.clone();
+                    async move { reactive_runner.local_echoes(room_id).await }
+                });
+                tick_blocking(&mut app, &runner_rx);
+                let echoes = rt.block_on(echoes).unwrap();
+
+                assert_eq!(
+                    echoes
+                        .iter()
+                        .map(|(entity, item, _)| (*entity, item.body.as_str()))
+                        .collect::<Vec<_>>(),
+                    vec![(first, "first"), (second, "second")]
+                );
+            }
+
+            #[test]
+            fn cancel_right_after_send() {
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+                log_in(&mut app);
+
+                let request_rx =
+                    reactive_runner.make_req_blocking::<SendMessageProgess>(SendMessageParams {
+                        room_id: owned_room_id!("!room:localhost"),
+                        body: String::from("hello"),
+                    });
+                while request_rx.is_empty() {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+                let r = request_rx.recv_blocking().unwrap();
+                let SendMessageProgess::Executed(txn_id) = r else {
+                    panic!("expected executed, got {r:?}");
+                };
+
+                // The echo is queued at once, whether or not it is persisted yet
+                let world = app.world_mut();
+                assert!(
+                    world
+                        .resource::<OutboxQueue>()
+                        .contains(&owned_room_id!("!room:localhost"), &txn_id)
+                );
+                let mut q = world.query_filtered::<Entity, With<LocalEcho>>();
+                let echo = q.single(world).unwrap();
+                world.entity_mut(echo).insert(CancelLocalEcho);
+                app.update();
+                while !outbox_settled(&app) {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+
+                assert!(app.world().get_entity(echo).is_err());
+                let queue = app.world().resource::<OutboxQueue>();
+                assert!(queue.rooms.is_empty());
+                assert!(queue.persisting.is_empty());
+                let outbox = app.world().resource::<Outbox>().clone();
+                let rt = app.world().resource::<TokioRt>().clone();
+                let stored = rt.block_on(async {
+                    for _ in 0..100 {
+                        let stored = outbox.load().await.unwrap();
+                        if stored.is_empty() {
+                            return stored;
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    }
+                    outbox.load().await.unwrap()
+                });
+                assert_eq!(stored, vec![]);
+            }
+
+            #[test]
+            fn echoes_are_restored_from_outbox() {
+                let (runner_plugin, _reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+
+                let message = OutboxEntry::new(
+                    owned_user_id!("@alice:localhost"),
+                    owned_room_id!("!room:localhost"),
+                    TransactionId::new(),
+                    OutboxEvent::Message {
+                        body: String::from("hello"),
+                    },
+                );
+                let reaction = OutboxEntry::new(
+                    owned_user_id!("@alice:localhost"),
+                    owned_room_id!("!room:localhost"),
+                    TransactionId::new(),
+                    OutboxEvent::Reaction {
+                        event_id: owned_event_id!("$liked:localhost"),
+                        key: String::from("+1"),
+                    },
+                );
+                let outbox = app.world().resource::<Outbox>().clone();
+                let rt = app.world().resource::<TokioRt>().clone();
+                rt.block_on(async {
+                    outbox.push(&message).await.unwrap();
+                    outbox.push(&reaction).await.unwrap();
+                });
+
+                app.update();
+                tick_blocking(&mut app, &runner_rx);
+
+                let world = app.world_mut();
+                assert_eq!(
+                    world
+                        .resource::<OutboxQueue>()
+                        .ready(user_id!("@alice:localhost")),
+                    vec![message.clone()]
+                );
+                let mut q = world.query::<(&TimelineItem, &LocalEcho)>();
+                let echoes = q
+                    .iter(world)
+                    .map(|(item, echo)| (item.clone(), echo.clone()))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    echoes,
+                    vec![(
+                        TimelineItem {
+                            room_id: message.room_id,
+                            body: String::from("hello"),
+                        },
+                        LocalEcho {
+                            user_id: message.user_id.clone(),
+                            txn_id: message.txn_id,
+                            state: LocalEchoState::Sending,
+                        }
+                    )]
+                );
+            }
+
+            #[test]
+            fn cancel_in_flight_echo_is_refused() {
+                let (runner_plugin, _reactive_runner) = ReactiveRunnerPlugin::new();
+
+                let mut app = App::new();
+
+                app.add_plugins((
+                    runner_plugin,
+                    TokioPlugin::new(),
+                    OutboxPlugin::in_memory(),
+                    SendMessagePlugin::new(),
+                ));
+
+                let room_id = owned_room_id!("!room:localhost");
+                let txn_id = TransactionId::new();
+                let mut queue = app.world_mut().resource_mut::<OutboxQueue>();
+                queue.push(OutboxEntry::new(
+                    owned_user_id!("@alice:localhost"),
+                    room_id.clone(),
+                    txn_id.clone(),
+                    OutboxEvent::Message {
+                        body: String::from("hello"),
+                    },
+                ));
+                queue.in_flight.insert(txn_id.clone());
+                let echo = app
+                    .world_mut()
+                    .spawn((
+                        TimelineItem {
+                            room_id: room_id.clone(),
+                            body: String::from("hello"),
+                        },
+                        LocalEcho {
+                            user_id: owned_user_id!("@alice:localhost"),
+                            txn_id: txn_id.clone(),
+                            state: LocalEchoState::Sending,
+                        },
+                        CancelLocalEcho,
+                    ))
+                    .id();
+
+                app.update();
+
+                let world = app.world();
+                assert!(world.get::<CancelLocalEcho>(echo).is_none());
+                assert_eq!(
+                    world.get::<LocalEcho>(echo).map(|echo| &echo.state),
+                    Some(&LocalEchoState::Sending)
+                );
+                assert!(world.resource::<OutboxQueue>().is_in_flight(&txn_id));
+            }
+        }
+    }
+
+    pub mod bevy_matrix_outbox {
+        use std::{
+            collections::{BTreeMap, HashMap, HashSet, VecDeque},
+            path::PathBuf,
+            time::Duration,
+        };
+
+        use bevy::prelude::*;
+        use matrix_sdk::{Client, HttpError};
+        use reqwest::StatusCode;
+        use ruma::{
+            OwnedEventId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId, TransactionId,
+            UserId,
+            api::client::discovery::get_supported_versions,
+            events::{
+                reaction::ReactionEventContent, relation::Annotation,
+                room::message::RoomMessageEventContent,
+            },
+        };
+        use serde::{Deserialize, Serialize};
+        use surrealdb::{
+            Surreal,
+            engine::local::{Db, Mem, SurrealKv},
+        };
+        use thiserror::Error;
+        use tokio::{sync::broadcast::error::RecvError, task::AbortHandle};
+
+        use crate::matrix_service::{
+            bevy_matrix_api::{APIProccesingLabel, APITx},
+            bevy_matrix_client::MatrixClient,
+            bevy_matrix_send_message::{
+                CompletedErr, restore_local_echoes, retrying_local_echo, update_local_echo,
+            },
+            bevy_tokio::{AsyncQueue, TokioRt},
+            reactive_runner_plugin::ReactiveRunner,
+        };
+
+        const OUTBOX_TABLE: &str = "outbox";
+
+        /// Times an entry is sent before a transient failure or an unknown room is reported as final.
+        pub const SEND_ATTEMPTS: u32 = 5;
+
+        /// Outgoing event that has not been accepted by the homeserver yet.
+        #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct OutboxEntry {
+            /// Account that queued the event, only its client sends it.
+            pub user_id: OwnedUserId,
+            pub room_id: OwnedRoomId,
+            pub txn_id: OwnedTransactionId,
+            pub event: OutboxEvent,
+            pub queued_at: i64,
+        }
+
+        impl OutboxEntry {
+            pub fn new(
+                user_id: OwnedUserId,
+                room_id: OwnedRoomId,
+                txn_id: OwnedTransactionId,
+                event: OutboxEvent,
+            ) -> Self {
+                Self {
+                    user_id,
+                    room_id,
+                    txn_id,
+                    event,
+                    queued_at: chrono::Utc::now().timestamp_micros(),
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+        pub enum OutboxEvent {
+            Message {
+                body: String,
+            },
+            Reaction {
+                event_id: OwnedEventId,
+                key: String,
+            },
+            Redaction {
+                event_id: OwnedEventId,
+                reason: Option<String>,
+            },
+        }
+
+        #[derive(Debug, Error)]
+        pub enum OutboxErr {
+            #[error("outbox database error: {0}")]
+            Db(#[from] surrealdb::Error),
+        }
+
+        /// Durable copy of every outgoing event, so queued events survive a crash or restart.
+        #[derive(Resource, Debug, Clone)]
+        pub struct Outbox {
+            db: Surreal<Db>,
+        }
+
+        impl Outbox {
+            pub async fn open(path: impl Into<PathBuf>) -> Result<Self, OutboxErr> {
+                let path: PathBuf = path.into();
+                let db = Surreal::new::<SurrealKv>(path.to_string_lossy().into_owned()).await?;
+                Self::init(db).await
+            }
+
+            pub async fn in_memory() -> Result<Self, OutboxErr> {
+                let db = Surreal::new::<Mem>(()).await?;
+                Self::init(db).await
+            }
+
+            async fn init(db: Surreal<Db>) -> Result<Self, OutboxErr> {
+                db.use_ns("swift_wind").use_db("outbox").await?;
+                Ok(Self { db })
+            }
+
+            pub async fn push(&self, entry: &OutboxEntry) -> Result<(), OutboxErr> {
+                let _: Option<OutboxEntry> = self
+                    .db
+                    .upsert((OUTBOX_TABLE, entry.txn_id.as_str()))
+                    .content(entry.clone())
+                    .await?;
+                Ok(())
+            }
+
+            pub async fn remove(&self, txn_id: &TransactionId) -> Result<(), OutboxErr> {
+                let _: Option<OutboxEntry> =
+                    self.db.delete((OUTBOX_TABLE, txn_id.as_str())).await?;
+                Ok(())
+            }
+
+            /// Every queued entry, oldest first.
+            pub async fn load(&self) -> Result<Vec<OutboxEntry>, OutboxErr> {
+                let mut entries: Vec<OutboxEntry> = self.db.select(OUTBOX_TABLE).await?;
+                entries.sort_by_key(|entry| entry.queued_at);
+                Ok(entries)
+            }
+        }
+
+        /// In-memory view of the outbox, one ordered queue per room shared by every account.
+        #[derive(Resource, Debug, Clone, Default)]
+        pub struct OutboxQueue {
+            pub rooms: BTreeMap<OwnedRoomId, VecDeque<OutboxEntry>>,
+            /// Entries being sent, at most one per room.
+            pub in_flight: HashSet<OwnedTransactionId>,
+            /// Queued entries that are still being written to the [`Outbox`], they aren't sent before that.
+            pub persisting: HashSet<OwnedTransactionId>,
+            /// Rooms the client didn't know when their entry was sent, they wait for the next sync.
+            pub waiting: HashSet<OwnedRoomId>,
+            /// Failed sends of entries that are retried, up to [`SEND_ATTEMPTS`].
+            pub attempts: HashMap<OwnedTransactionId, u32>,
+            pub loaded: bool,
+        }
+
+        impl OutboxQueue {
+            pub fn push(&mut self, entry: OutboxEntry) {
+                let queue = self.rooms.entry(entry.room_id.clone()).or_default();
+                if queue.iter().any(|queued| queued.txn_id == entry.txn_id) {
+                    return;
+                }
+                queue.push_back(entry);
+            }
+
+            pub fn remove(&mut self, room_id: &RoomId, txn_id: &TransactionId) {
+                let Some(queue) = self.rooms.get_mut(room_id) else {
+                    return;
+                };
+                queue.retain(|entry| entry.txn_id != txn_id);
+                if queue.is_empty() {
+                    self.rooms.remove(room_id);
+                }
+                self.attempts.remove(txn_id);
+            }
+
+            pub fn contains(&self, room_id: &RoomId, txn_id: &TransactionId) -> bool {
+                self.rooms
+                    .get(room_id)
+                    .is_some_and(|queue| queue.iter().any(|entry| entry.txn_id == txn_id))
+            }
+
+            pub fn is_in_flight(&self, txn_id: &TransactionId) -> bool {
+                self.in_flight.contains(txn_id)
+            }
+
+            /// Oldest entry `user_id` queued in every room that has nothing in flight. Rooms are drained one entry at a time to keep their order.
+            ///
+            /// Entries of other accounts stay queued until their client is the current one again.
+            pub fn ready(&self, user_id: &UserId) -> Vec<OutboxEntry> {
+                self.rooms
+                    .iter()
+                    .filter(|(room_id, queue)| {
+                        !self.waiting.contains(*room_id)
+                            && !queue
+                                .iter()
+                                .any(|entry| self.in_flight.contains(&entry.txn_id))
+                    })
+                    .filter_map(|(_, queue)| queue.iter().find(|entry| entry.user_id == user_id))
+                    .filter(|entry| !self.persisting.contains(&entry.txn_id))
+                    .cloned()
+                    .collect()
+            }
+        }
+
+        #[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub enum Connectivity {
+            #[default]
+            Online,
+            Offline,
+            Probing,
+        }
+
+        #[derive(Debug, Error, Clone, PartialEq, Eq)]
+        pub enum OutboxSendErr {
+            /// Network failure or homeserver error, retried once the homeserver is reachable again.
+            #[error("{0}")]
+            Transient(String),
+
+            /// Homeserver refused the event, sending it again would not help.
+            #[error("{0}")]
+            Rejected(String),
+
+            /// Room isn't known to the client yet, like right after startup before the first sync.
+            #[error("room {0} is not known to the client yet")]
+            UnknownRoom(OwnedRoomId),
+        }
+
+        #[derive(Debug, Component, Clone, Hash)]
+        pub struct SendReactionParams {
+            pub room_id: OwnedRoomId,
+            pub event_id: OwnedEventId,
+            pub key: String,
+        }
+
+        #[derive(Debug, Component, Clone, Hash)]
+        pub struct RedactEventParams {
+            pub room_id: OwnedRoomId,
+            pub event_id: OwnedEventId,
+            pub reason: Option<String>,
+        }
+
+        #[derive(Debug, Component, Clone, Default, PartialEq, Eq)]
+        pub enum OutboxProgess {
+            #[default]
+            WaitingForExecution,
+            Queued(OwnedTransactionId),
+            Completed(Result<OwnedEventId, CompletedErr>),
+        }
+
+        /// Links a queued reaction or redaction to the request that made it.
+        #[derive(Debug, Component, Clone, PartialEq, Eq)]
+        pub struct OutboxRequest {
+            pub txn_id: OwnedTransactionId,
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct OutboxPlugin {
+            pub path: Option<PathBuf>,
+        }
+
+        impl OutboxPlugin {
+            pub fn new(path: impl Into<PathBuf>) -> Self {
+                Self {
+                    path: Some(path.into()),
+                }
+            }
+
+            pub fn in_memory() -> Self {
+                Self { path: None }
+            }
+        }
+
+        impl Plugin for OutboxPlugin {
+            fn build(&self, app: &mut App) {
+                let rt = app
+                    .world()
+                    .get_resource::<TokioRt>()
+                    .expect("OutboxPlugin requires TokioPlugin")
+                    .clone();
+                let outbox = match self.path.clone() {
+                    Some(path) => rt.block_on(Outbox::open(path)),
+                    None => rt.block_on(Outbox::in_memory()),
+                };
+                // a locked or broken database shouldn't take the app down, entries just won't survive a restart
+                let outbox = match outbox {
+                    Ok(outbox) => outbox,
+                    Err(err) => {
+                        error!("failed to open outbox, falling back to memory: {err}");
+                        rt.block_on(Outbox::in_memory())
+                            .expect("failed to open in-memory outbox")
+                    }
+                };
+
+                app.insert_resource(outbox)
+                    .init_resource::<OutboxQueue>()
+                    .init_resource::<Connectivity>()
+                    .add_systems(
+                        Update,
+                        (
+                            load_outbox,
+                            watch_room_updates,
+                            outbox_executor,
+                            drain_outbox,
+                            probe_connectivity,
+                        )
+                            .chain(),
+                    );
+            }
+        }
+
+        /// Queues the entry right away, so it can be cancelled, and persists it.
+        ///
+        /// [`drain_outbox`] holds it back until it is persisted. If it was cancelled in the meantime it is removed from the [`Outbox`] again.
+        pub fn enqueue(
+            async_queue: &AsyncQueue,
+            outbox: &Outbox,
+            queue: &mut OutboxQueue,
+            entry: OutboxEntry,
+        ) {
+            trace!("outbox entry {} queued", entry.txn_id);
+            queue.persisting.insert(entry.txn_id.clone());
+            queue.push(entry.clone());
+            let outbox = outbox.clone();
+            async_queue.spawn_with_output(
+                async move || {
+                    if let Err(err) = outbox.push(&entry).await {
+                        warn!("failed to persist outbox entry {}: {err}", entry.txn_id);
+                    }
+                    (outbox, entry)
+                },
+                |app, (outbox, entry)| {
+                    let world = app.world_mut();
+                    let mut queue = world.resource_mut::<OutboxQueue>();
+                    queue.persisting.remove(&entry.txn_id);
+                    if queue.contains(&entry.room_id, &entry.txn_id) {
+                        return;
+                    }
+                    trace!(
+                        "outbox entry {} was cancelled while persisting",
+                        entry.txn_id
+                    );
+                    world.resource::<TokioRt>().spawn(async move {
+                        if let Err(err) = outbox.remove(&entry.txn_id).await {
+                            warn!("failed to remove outbox entry {}: {err}", entry.txn_id);
+                        }
+                    });
+                },
+            );
+        }
+
+        pub fn load_outbox(outbox: Res<Outbox>, async_queue: AsyncQueue, mut started: Local<bool>) {
+            if *started {
+                return;
+            }
+            *started = true;
+            let outbox = outbox.clone();
+            async_queue.spawn_with_output(
+                async move || outbox.load().await,
+                |app, entries| {
+                    let world = app.world_mut();
+                    let mut queue = world.resource_mut::<OutboxQueue>();
+                    queue.loaded = true;
+                    let entries = match entries {
+                        Ok(entries) => entries,
+                        Err(err) => {
+                            warn!("failed to load outbox: {err}");
+                            return;
+                        }
+                    };
+                    trace!("loaded {} outbox entries", entries.len());
+                    // entries queued before loading finished are newer than the stored ones
+                    let queued = std::mem::take(&mut queue.rooms);
+                    for entry in entries.iter().cloned() {
+                        queue.push(entry);
+                    }
+                    for entry in queued.into_values().flatten() {
+                        queue.push(entry);
+                    }
+                    restore_local_echoes(world, &entries);
+                },
+            );
+        }
+
+        pub fn outbox_executor(
+            reactions: Query<
+                (Entity, &SendReactionParams, &APITx<OutboxProgess>),
+                Without<APIProccesingLabel>,
+            >,
+            redactions: Query<
+                (Entity, &RedactEventParams, &APITx<OutboxProgess>),
+                Without<APIProccesingLabel>,
+            >,
+            client: Option<Res<MatrixClient>>,
+            mut queue: ResMut<OutboxQueue>,
+            outbox: Res<Outbox>,
+            async_queue: AsyncQueue,
+            mut commands: Commands,
+        ) {
+            let user_id = client.as_ref().and_then(|client| client.user_id());
+            let reactions = reactions.iter().map(|(entity, params, api_tx)| {
+                let event = OutboxEvent::Reaction {
+                    event_id: params.event_id.clone(),
+                    key: params.key.clone(),
+                };
+                (entity, params.room_id.clone(), event, api_tx)
+            });
+            let redactions = redactions.iter().map(|(entity, params, api_tx)| {
+                let event = OutboxEvent::Redaction {
+                    event_id: params.event_id.clone(),
+                    reason: params.reason.clone(),
+                };
+                (entity, params.room_id.clone(), event, api_tx)
+            });
+
+            for (entity, room_id, event, api_tx) in reactions.chain(redactions) {
+                let Some(user_id) = user_id else {
+                    warn!("outbox req made before logging in");
+                    let _ = api_tx
+                        .send_blocking(OutboxProgess::Completed(Err(CompletedErr::NotLoggedIn)));
+                    commands.entity(entity).despawn();
+                    continue;
+                };
+                let txn_id = TransactionId::new();
+                trace!("outbox req made with txn id {txn_id}");
+                let _ = api_tx.send_blocking(OutboxProgess::Queued(txn_id.clone()));
+                commands.entity(entity).insert((
+                    APIProccesingLabel,
+                    OutboxRequest {
+                        txn_id: txn_id.clone(),
+                    },
+                ));
+                enqueue(
+                    &async_queue,
+                    &outbox,
+                    &mut queue,
+                    OutboxEntry::new(user_id.to_owned(), room_id, txn_id, event),
+                );
+            }
+        }
+
+        pub fn drain_outbox(
+            mut queue: ResMut<OutboxQueue>,
+            connectivity: Res<Connectivity>,
+            client: Option<Res<MatrixClient>>,
+            outbox: Res<Outbox>,
+            async_queue: AsyncQueue,
+        ) {
+            let Some(client) = client else {
+                return;
+            };
+            let Some(user_id) = client.user_id() else {
+                return;
+            };
+            if !queue.loaded || *connectivity != Connectivity::Online {
+                return;
+            }
+
+            for entry in queue.ready(user_id) {
+                trace!("draining outbox entry {}", entry.txn_id);
+                queue.in_flight.insert(entry.txn_id.clone());
+                let client = client.client.clone();
+                let outbox = outbox.clone();
+                async_queue.spawn_with_output(
+                    async move || {
+                        let res = send_entry(&client, &entry).await;
+                        let retried = matches!(
+                            res,
+                            Err(OutboxSendErr::Transient(_) | OutboxSendErr::UnknownRoom(_))
+                        );
+                        if !retried && let Err(err) = outbox.remove(&entry.txn_id).await {
+                            warn!("failed to remove outbox entry {}: {err}", entry.txn_id);
+                        }
+                        (entry, res)
+                    },
+                    |app, (entry, res)| {
+                        let world = app.world_mut();
+                        world
+                            .resource_mut::<OutboxQueue>()
+                            .in_flight
+                            .remove(&entry.txn_id);
+
+                        let res = match res {
+                            Ok(event_id) => Ok(event_id),
+                            Err(OutboxSendErr::Rejected(reason)) => {
+                                warn!("outbox entry {} was rejected: {reason}", entry.txn_id);
+                                Err(CompletedErr::SendFailed(reason))
+                            }
+                            Err(err) => {
+                                let mut queue = world.resource_mut::<OutboxQueue>();
+                                let attempts =
+                                    queue.attempts.entry(entry.txn_id.clone()).or_default();
+                                *attempts += 1;
+                                if *attempts < SEND_ATTEMPTS {
+                                    retry_later(world, &entry, err);
+                                    return;
+                                }
+                                warn!(
+                                    "giving up on outbox entry {} after {SEND_ATTEMPTS} attempts: {err}",
+                                    entry.txn_id
+                                );
+                                let outbox = world.resource::<Outbox>().clone();
+                                let txn_id = entry.txn_id.clone();
+                                world.resource::<TokioRt>().spawn(async move {
+                                    if let Err(err) = outbox.remove(&txn_id).await {
+                                        warn!("failed to remove outbox entry {txn_id}: {err}");
+                                    }
+                                });
+                                match err {
+                                    OutboxSendErr::UnknownRoom(room_id) => {
+                                        Err(CompletedErr::UnknownRoom(room_id))
+                                    }
+                                    err => Err(CompletedErr::SendFailed(err.to_string())),
+                                }
+                            }
+                        };
+                        world
+                            .resource_mut::<OutboxQueue>()
+                            .remove(&entry.room_id, &entry.txn_id);
+
+                        match entry.event {
+                            OutboxEvent::Message { .. } => {
+                                update_local_echo(world, &entry.txn_id, res)
+                            }
+                            _ => complete_outbox_request(world, &entry.txn_id, res),
+                        }
+                    },
+                );
+            }
+        }
+
+        /// Holds an entry back after a failure that may go away: until the homeserver is reachable
+        /// again, or until the next sync for a room the client didn't know.
+        fn retry_later(world: &mut World, entry: &OutboxEntry, err: OutboxSendErr) {
+            if let OutboxEvent::Message { .. } = entry.event {
+                retrying_local_echo(world, &entry.txn_id, err.to_string());
+            }
+            match err {
+                OutboxSendErr::UnknownRoom(room_id) => {
+                    trace!(
+                        "outbox entry {} waits for room {room_id} to sync",
+                        entry.txn_id
+                    );
+                    world.resource_mut::<OutboxQueue>().waiting.insert(room_id);
+                }
+                err => {
+                    warn!("outbox entry {} will be retried: {err}", entry.txn_id);
+                    let mut connectivity = world.resource_mut::<Connectivity>();
+                    if *connectivity == Connectivity::Online {
+                        *connectivity = Connectivity::Offline;
+                    }
+                }
+            }
+        }
+
+        /// Tells the requester of a reaction or redaction how sending it went.
+        pub fn complete_outbox_request(
+            world: &mut World,
+            txn_id: &TransactionId,
+            res: Result<OwnedEventId, CompletedErr>,
+        ) {
+            let mut q = world.query::<(Entity, &OutboxRequest, &APITx<OutboxProgess>)>();
+            let Some((entity, _, api_tx)) = q
+                .iter(world)
+                .find(|(_, request, _)| request.txn_id == txn_id)
+            else {
+                trace!("nobody is waiting for outbox entry {txn_id}");
+                return;
+            };
+            let _ = api_tx.send_blocking(OutboxProgess::Completed(res));
+            world.despawn(entity);
+        }
+
+        /// Rooms the client didn't know may have arrived with a sync, so their entries are tried again.
+        pub fn watch_room_updates(
+            client: Option<Res<MatrixClient>>,
+            mut queue: ResMut<OutboxQueue>,
+            tokio_rt: Res<TokioRt>,
+            reactive_runner: Res<ReactiveRunner>,
+            mut watcher: Local<Option<AbortHandle>>,
+        ) {
+            let replaced = match &client {
+                Some(client) => client.is_added(),
+                None => watcher.is_some(),
+            };
+            if !replaced {
+                return;
+            }
+            if let Some(watcher) = watcher.take() {
+                watcher.abort();
+            }
+            // another client may know other rooms
+            queue.waiting.clear();
+            let Some(client) = client else {
+                return;
+            };
+
+            let mut updates = client.subscribe_to_all_room_updates();
+            let reactive_runner = reactive_runner.clone();
+            let handle = tokio_rt.spawn(async move {
+                loop {
+                    match updates.recv().await {
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return,
+                    }
+                    reactive_runner
+                        .send_fn(|app| {
+                            let mut queue = app.world_mut().resource_mut::<OutboxQueue>();
+                            if !queue.waiting.is_empty() {
+                                trace!("rooms synced, retrying waiting outbox entries");
+                                queue.waiting.clear();
+                            }
+                        })
+                        .await;
+                }
+            });
+            *watcher = Some(handle.abort_handle());
+        }
+
+        /// Whether the outbox is loaded and every queued entry is written, for tests waiting on it.
+        #[cfg(test)]
+        pub fn outbox_settled(app: &App) -> bool {
+            let queue = app.world().resource::<OutboxQueue>();
+            queue.loaded && queue.persisting.is_empty()
+        }
+
+        /// While offline, polls the homeserver with backoff until it answers again.
+        pub fn probe_connectivity(
+            mut connectivity: ResMut<Connectivity>,
+            client: Option<Res<MatrixClient>>,
+            async_queue: AsyncQueue,
+        ) {
+            let Some(client) = client else {
+                return;
+            };
+            if *connectivity != Connectivity::Offline {
+                return;
+            }
+            *connectivity = Connectivity::Probing;
+            trace!("homeserver unreachable, probing");
+
+            let client = client.client.clone();
+            async_queue.spawn_with_output(
+                async move || {
+                    let mut delay = Duration::from_secs(1);
+                    loop {
+                        tokio::time::sleep(delay).await;
+                        match client.send(get_supported_versions::Request::new()).await {
+                            Ok(_) => break,
+                            Err(err) => trace!("homeserver still unreachable: {err}"),
+                        }
+                        delay = (delay * 2).min(Duration::from_secs(60));
+                    }
+                },
+                |app, _| {
+                    trace!("homeserver reachable again");
+                    *app.world_mut().resource_mut::<Connectivity>() = Connectivity::Online;
+                },
+            );
+        }
+
+        pub async fn send_entry(
+            client: &Client,
+            entry: &OutboxEntry,
+        ) -> Result<OwnedEventId, OutboxSendErr> {
+            let room = client
+                .get_room(&entry.room_id)
+                .ok_or_else(|| OutboxSendErr::UnknownRoom(entry.room_id.clone()))?;
+            let txn_id = entry.txn_id.clone();
+
+            match &entry.event {
+                OutboxEvent::Message { body } => room
+                    .send(RoomMessageEventContent::text_plain(body.clone()))
+                    .with_transaction_id(txn_id)
+                    .await
+                    .map(|res| res.event_id)
+                    .map_err(classify),
+                OutboxEvent::Reaction { event_id, key } => room
+                    .send(ReactionEventContent::new(Annotation::new(
+                        event_id.clone(),
+                        key.clone(),
+                    )))
+                    .with_transaction_id(txn_id)
+                    .await
+                    .map(|res| res.event_id)
+                    .map_err(classify),
+                OutboxEvent::Redaction { event_id, reason } => room
+                    .redact(event_id, reason.as_deref(), Some(txn_id))
+                    .await
+                    .map(|res| res.event_id)
+                    .map_err(|err| classify(matrix_sdk::Error::Http(err))),
+            }
+        }
+
+        fn classify(err: matrix_sdk::Error) -> OutboxSendErr {
+            let transient = match err.as_client_api_error() {
+                Some(api_err) => {
+                    api_err.status_code.is_server_error()
+                        || api_err.status_code == StatusCode::TOO_MANY_REQUESTS
+                }
+                None => matches!(err, matrix_sdk::Error::Http(HttpError::Reqwest(_))),
+            };
+            if transient {
+                OutboxSendErr::Transient(err.to_string())
+            } else {
+                OutboxSendErr::Rejected(err.to_string())
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use bevy::prelude::*;
+            use ruma::{TransactionId, UserId, owned_event_id, owned_room_id, user_id};
+            use test_log::test;
+
+            use crate::matrix_service::{
+                bevy_matrix_api::{APITx, MakeReq},
+                bevy_matrix_client::MatrixClient,
+                bevy_matrix_outbox::{
+                    Outbox, OutboxEntry, OutboxEvent, OutboxPlugin, OutboxProgess, OutboxQueue,
+                    OutboxRequest, SEND_ATTEMPTS, SendReactionParams, complete_outbox_request,
+                    outbox_settled,
+                },
+                bevy_matrix_send_message::CompletedErr,
+                bevy_tokio::{TokioPlugin, TokioRt},
+                reactive_runner_plugin::{ReactiveRunnerPlugin, tick_blocking},
+            };
+            use crate::test_support::offline_client;
+
+            fn message(room: &str, body: &str) -> OutboxEntry {
+                message_of(user_id!("@alice:localhost"), room, body)
+            }
+
+            fn message_of(user_id: &UserId, room: &str, body: &str) -> OutboxEntry {
+                OutboxEntry::new(
+                    user_id.to_owned(),
+                    room.try_into().unwrap(),
+                    TransactionId::new(),
+                    OutboxEvent::Message {
+                        body: body.to_string(),
+                    },
+                )
+            }
+
+            #[test]
+            fn persist_and_reopen() {
+                let path = std::env::temp_dir()
+                    .join(format!("swift-wind-outbox-test-{}", rand::random::<u64>()));
+                let first = message("!a:localhost", "first");
+                let second = message("!a:localhost", "second");
+                let rt = TokioRt::default();
+
+                rt.block_on(async {
+                    let outbox = Outbox::open(&path).await.unwrap();
+                    outbox.push(&first).await.unwrap();
+                    outbox.push(&second).await.unwrap();
+                    outbox.push(&first).await.unwrap();
+                });
+
+                let entries = rt.block_on(async {
+                    let outbox = Outbox::open(&path).await.unwrap();
+                    let entries = outbox.load().await.unwrap();
+                    outbox.remove(&first.txn_id).await.unwrap();
+                    entries
+                });
+                assert_eq!(entries, vec![first, second.clone()]);
+
+                let entries =
+                    rt.block_on(async { Outbox::open(&path).await.unwrap().load().await.unwrap() });
+                assert_eq!(entries, vec![second]);
+
+                let _ = std::fs::remove_dir_all(&path);
+            }
+
+            #[test]
+            fn queue_keeps_room_order() {
+                let mut queue = OutboxQueue::default();
+                let a1 = message("!a:localhost", "a1");
+                let a2 = message("!a:localhost", "a2");
+                let b1 = message("!b:localhost", "b1");
+                queue.push(a1.clone());
+                queue.push(a2.clone());
+                queue.push(b1.clone());
+                queue.push(a1.clone());
+                let alice = user_id!("@alice:localhost");
+
+                assert_eq!(queue.ready(alice), vec![a1.clone(), b1.clone()]);
+
+                queue.in_flight.insert(a1.txn_id.clone());
+                assert!(queue.is_in_flight(&a1.txn_id));
+                assert!(!queue.is_in_flight(&a2.txn_id));
+                assert_eq!(queue.ready(alice), vec![b1.clone()]);
+
+                queue.in_flight.clear();
+                queue.persisting.insert(b1.txn_id.clone());
+                assert_eq!(queue.ready(alice), vec![a1.clone()]);
+
+                queue.persisting.clear();
+                queue.waiting.insert(b1.room_id.clone());
+                assert_eq!(queue.ready(alice), vec![a1.clone()]);
+
+                queue.waiting.clear();
+                queue.remove(&a1.room_id, &a1.txn_id);
+                queue.remove(&b1.room_id, &b1.txn_id);
+                assert_eq!(queue.ready(alice), vec![a2]);
+                assert!(!queue.rooms.contains_key(&b1.room_id));
+            }
+
+            #[test]
+            fn reaction_is_persisted_and_queued() {
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((runner_plugin, TokioPlugin::new(), OutboxPlugin::in_memory()));
+                let rt = app.world().resource::<TokioRt>().clone();
+                let client = rt.block_on(offline_client(user_id!("@alice:localhost")));
+                app.insert_resource(MatrixClient::new(client));
+
+                let request_rx =
+                    reactive_runner.make_req_blocking::<OutboxProgess>(SendReactionParams {
+                        room_id: owned_room_id!("!a:localhost"),
+                        event_id: owned_event_id!("$liked:localhost"),
+                        key: String::from("+1"),
+                    });
+                tick_blocking(&mut app, &runner_rx);
+                while !outbox_settled(&app) {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+
+                let r = request_rx.recv_blocking().unwrap();
+                let OutboxProgess::Queued(txn_id) = r else {
+                    panic!("expected queued, got {r:?}");
+                };
+
+                let queue = app.world().resource::<OutboxQueue>();
+                assert!(queue.loaded);
+                let queued = Vec::from(queue.rooms[&owned_room_id!("!a:localhost")].clone());
+                assert_eq!(queued.len(), 1);
+                assert_eq!(queued[0].txn_id, txn_id);
+                assert_eq!(queued[0].user_id, user_id!("@alice:localhost"));
+
+                let outbox = app.world().resource::<Outbox>().clone();
+                let stored = rt.block_on(outbox.load()).unwrap();
+                assert_eq!(stored, queued);
+            }
+
+            #[test]
+            fn unknown_room_waits_for_sync() {
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((runner_plugin, TokioPlugin::new(), OutboxPlugin::in_memory()));
+                let rt = app.world().resource::<TokioRt>().clone();
+                let client = rt.block_on(offline_client(user_id!("@alice:localhost")));
+                app.insert_resource(MatrixClient::new(client));
+
+                let request_rx =
+                    reactive_runner.make_req_blocking::<OutboxProgess>(SendReactionParams {
+                        room_id: owned_room_id!("!a:localhost"),
+                        event_id: owned_event_id!("$liked:localhost"),
+                        key: String::from("+1"),
+                    });
+                while app.world().resource::<OutboxQueue>().waiting.is_empty() {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+
+                let r = request_rx.recv_blocking().unwrap();
+                assert!(matches!(r, OutboxProgess::Queued(_)));
+                assert!(request_rx.is_empty());
+
+                let queue = app.world().resource::<OutboxQueue>();
+                assert!(queue.in_flight.is_empty());
+                assert!(queue.ready(user_id!("@alice:localhost")).is_empty());
+                assert_eq!(queue.rooms[&owned_room_id!("!a:localhost")].len(), 1);
+                let outbox = app.world().resource::<Outbox>().clone();
+                assert_eq!(rt.block_on(outbox.load()).unwrap().len(), 1);
+            }
+
+            #[test]
+            fn unknown_room_is_given_up() {
+                let (runner_plugin, reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((runner_plugin, TokioPlugin::new(), OutboxPlugin::in_memory()));
+                let rt = app.world().resource::<TokioRt>().clone();
+                let client = rt.block_on(offline_client(user_id!("@alice:localhost")));
+                app.insert_resource(MatrixClient::new(client));
+
+                let request_rx =
+                    reactive_runner.make_req_blocking::<OutboxProgess>(SendReactionParams {
+                        room_id: owned_room_id!("!a:localhost"),
+                        event_id: owned_event_id!("$liked:localhost"),
+                        key: String::from("+1"),
+                    });
+                let mut syncs = 0;
+                let r = 'completed: loop {
+                    tick_blocking(&mut app, &runner_rx);
+                    // Syncs keep coming without the room
+                    let mut queue = app.world_mut().resource_mut::<OutboxQueue>();
+                    if !queue.waiting.is_empty() {
+                        queue.waiting.clear();
+                        syncs += 1;
+                        app.update();
+                    }
+                    while let Ok(r) = request_rx.try_recv() {
+                        if let OutboxProgess::Completed(_) = r {
+                            break 'completed r;
+                        }
+                    }
+                };
+
+                assert_eq!(syncs, SEND_ATTEMPTS - 1);
+                assert_eq!(
+                    r,
+                    OutboxProgess::Completed(Err(CompletedErr::UnknownRoom(owned_room_id!(
+                        "!a:localhost"
+                    ))))
+                );
+                let queue = app.world().resource::<OutboxQueue>();
+                assert!(queue.rooms.is_empty());
+                assert!(queue.attempts.is_empty());
+                let outbox = app.world().resource::<Outbox>().clone();
+                while !rt.block_on(outbox.load()).unwrap().is_empty() {
+                    std::thread::yield_now();
+                }
+            }
+
+            #[test]
+            fn entries_wait_for_their_account() {
+                let (runner_plugin, _reactive_runner) = ReactiveRunnerPlugin::new();
+                let runner_rx = runner_plugin.rx.clone();
+
+                let mut app = App::new();
+
+                app.add_plugins((runner_plugin, TokioPlugin::new(), OutboxPlugin::in_memory()));
+                let alice = user_id!("@alice:localhost");
+                let bob = user_id!("@bob:localhost");
+                let rt = app.world().resource::<TokioRt>().clone();
+                let outbox = app.world().resource::<Outbox>().clone();
+                let queued = message_of(alice, "!shared:localhost", "from alice");
+                rt.block_on(outbox.push(&queued)).unwrap();
+
+                // Bob's client never picks up what Alice queued, even in a room both joined
+                app.insert_resource(MatrixClient::new(rt.block_on(offline_client(bob))));
+                app.update();
+                while !outbox_settled(&app) {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+                for _ in 0..10 {
+                    app.update();
+                }
+                let queue = app.world().resource::<OutboxQueue>();
+                assert!(queue.in_flight.is_empty());
+                assert!(queue.waiting.is_empty());
+                assert!(queue.ready(bob).is_empty());
+                assert_eq!(queue.ready(alice), vec![queued.clone()]);
+
+                // Switching back to Alice sends it, her client doesn't know the room so it waits
+                app.world_mut().remove_resource::<MatrixClient>();
+                app.insert_resource(MatrixClient::new(rt.block_on(offline_client(alice))));
+                app.update();
+                while app.world().resource::<OutboxQueue>().waiting.is_empty() {
+                    tick_blocking(&mut app, &runner_rx);
+                }
+                assert!(
+                    app.world()
+                        .resource::<OutboxQueue>()
+                        .contains(&queued.room_id, &queued.txn_id)
+                );
+            }
+
+            #[test]
+            fn rejected_reaction_is_reported() {
+                let mut world = World::new();
+                let (tx, rx) = async_channel::unbounded::<OutboxProgess>();
+                let txn_id = TransactionId::new();
+                let request = world
+                    .spawn((
+                        OutboxRequest {
+                            txn_id: txn_id.clone(),
+                        },
+                        APITx::new(tx),
+                    ))
+                    .id();
+
+                complete_outbox_request(
+                    &mut world,
+                    &txn_id,
+                    Err(CompletedErr::SendFailed(String::from("forbidden"))),
+                );
+
+                assert_eq!(
+                    rx.recv_blocking().unwrap(),
+                    OutboxProgess::Completed(Err(CompletedErr::SendFailed(String::from(
+                        "forbidden"
+                    ))))
+                );
+                assert!(world.get_entity(request).is_err());
+            }
+        }
+    }
 }
@@ -2,13 +2,26 @@ mod components;
 mod hook;
 mod page;
 
+use crate::components::image::Lightbox;
+use crate::components::verification::VerificationDialogs;
+use crate::hook::download::ActiveDownload;
+use crate::hook::send_message::PendingMessage;
 use crate::page::{
-    connect::Connect, login::Login, main_interface::MainInterface, register::Register,
-    settings::Settings,
+    connect::Connect, downloads::Downloads, login::Login, main_interface::MainInterface,
+    register::Register, reset_password::ResetPassword, settings::Settings,
+    setup_encryption::SetupEncryption,
 };
 use dioxus_router::prelude::{Routable, Router};
 use freya::prelude::*;
 use matrix_sdk::Client;
+use ruma::{OwnedEventId, OwnedUserId};
+use std::sync::LazyLock;
+use swift_wind::matrix_attachment::Attachment;
+use swift_wind::matrix_service::bevy_matrix_app::run_matrix_app;
+use swift_wind::matrix_service::bevy_matrix_client::MatrixClientActions;
+use swift_wind::matrix_service::reactive_runner_plugin::ReactiveRunner;
+use swift_wind::matrix_store::AccountDb;
+use swift_wind::matrix_verification::VerificationFlow;
 use tracing::info;
 
 #[derive(Debug, Routable, Clone, PartialEq)]
@@ -23,6 +36,9 @@ pub enum Route {
         #[route("/register")]
         Register,
 
+        #[route("/reset_password")]
+        ResetPassword,
+
     #[end_layout]
 
     #[route("/main_interface")]
@@ -30,14 +46,42 @@ pub enum Route {
 
     #[route("/settings")]
     Settings,
+
+    #[route("/setup_encryption")]
+    SetupEncryption,
+
+    #[route("/downloads")]
+    Downloads,
 }
 
+//Background app with the outbox, it sends with the client of the active account
+pub static MATRIX_APP: LazyLock<ReactiveRunner> = LazyLock::new(|| run_matrix_app().1);
+
+//The active account, or the one being logged in when adding an account
 pub static CLIENT: GlobalSignal<MatrixClientState> = Global::new(MatrixClientState::default);
+pub static ACCOUNT_DB: GlobalSignal<Option<AccountDb>> = Global::new(Option::default);
+
+//Every logged in account, each one keeps syncing while another is active
+pub static ACCOUNTS: GlobalSignal<Vec<Account>> = Global::new(Vec::new);
 
 //These two are mainly used for the navigation and router
 pub static CURRENT_SPACE: GlobalSignal<Option<String>> = Global::new(Option::default);
 pub static CURRENT_ROOM: GlobalSignal<Option<String>> = Global::new(Option::default);
 
+//Verifications waiting on the user, from any logged in account
+pub static VERIFICATIONS: GlobalSignal<Vec<VerificationFlow>> = Global::new(Vec::new);
+
+//Image messages on screen, the lightbox pages through them in timeline order
+pub static GALLERY: GlobalSignal<Vec<(OwnedEventId, Attachment)>> = Global::new(Vec::new);
+pub static TIMELINE: GlobalSignal<Vec<OwnedEventId>> = Global::new(Vec::new);
+pub static LIGHTBOX: GlobalSignal<Option<OwnedEventId>> = Global::new(Option::default);
+
+//Messages of the open room the outbox still holds or that sync didn't bring back yet
+pub static LOCAL_ECHOES: GlobalSignal<Vec<PendingMessage>> = Global::new(Vec::new);
+
+//Downloads of the running session, they move to the account's history once they end
+pub static DOWNLOADS: GlobalSignal<Vec<ActiveDownload>> = Global::new(Vec::new);
+
 #[derive(Debug, Default, Clone)]
 pub enum MatrixClientState {
     #[default]
@@ -47,6 +91,15 @@ pub enum MatrixClientState {
     Error(String),
 }
 
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub user_id: OwnedUserId,
+    pub client: Client,
+    pub db: AccountDb,
+    pub unread: u64,
+    pub tasks: Vec<Task>,
+}
+
 fn main() {
     tracing_subscriber::fmt()
         .event_format(
@@ -64,9 +117,19 @@ fn main() {
 }
 
 fn app() -> Element {
+    use_effect(move || {
+        let client = match CLIENT() {
+            MatrixClientState::Connected(client) => Some(client),
+            _ => None,
+        };
+        spawn(async move { MATRIX_APP.set_matrix_client(client).await });
+    });
+
     rsx!(
         rect{
             Router::<Route>{}
+            VerificationDialogs{}
+            Lightbox{}
         }
     )
 }
@@ -0,0 +1,170 @@
+use std::{io, path::Path};
+
+use matrix_sdk::Client;
+use mime_guess::mime;
+use ruma::OwnedMxcUri;
+use thiserror::Error;
+use tracing::trace;
+
+/// Biggest avatar swift-wind uploads, servers commonly cap media well above this.
+pub const MAX_AVATAR_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum AccountErr {
+    #[error("couldn't change the display name: {0}")]
+    DisplayName(#[source] matrix_sdk::Error),
+
+    #[error("couldn't read {path}: {source}")]
+    ReadAvatar {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{0} isn't a picture")]
+    NotAnImage(String),
+
+    #[error("the picture is {size} bytes, avatars can be at most {MAX_AVATAR_SIZE}")]
+    AvatarTooBig { size: usize },
+
+    #[error("couldn't upload the avatar: {0}")]
+    UploadAvatar(#[source] matrix_sdk::Error),
+
+    #[error("couldn't remove the avatar: {0}")]
+    RemoveAvatar(#[source] matrix_sdk::Error),
+}
+
+/// Sets the display name, a blank one removes it.
+pub async fn set_display_name(client: &Client, name: &str) -> Result<(), AccountErr> {
+    let name = Some(name.trim()).filter(|name| !name.is_empty());
+    trace!("setting display name to {name:?}");
+    client
+        .account()
+        .set_display_name(name)
+        .await
+        .map_err(AccountErr::DisplayName)
+}
+
+/// Uploads the picture at `path` and makes it the avatar.
+pub async fn set_avatar_from_file(client: &Client, path: &Path) -> Result<OwnedMxcUri, AccountErr> {
+    let shown_path = path.display().to_string();
+    let content_type = mime_guess::from_path(path)
+        .first()
+        .filter(|content_type| content_type.type_() == mime::IMAGE)
+        .ok_or_else(|| AccountErr::NotAnImage(shown_path.clone()))?;
+    let data = tokio::fs::read(path)
+        .await
+        .map_err(|source| AccountErr::ReadAvatar {
+            path: shown_path,
+            source,
+        })?;
+    if data.len() > MAX_AVATAR_SIZE {
+        return Err(AccountErr::AvatarTooBig { size: data.len() });
+    }
+
+    trace!("uploading {} byte {content_type} avatar", data.len());
+    client
+        .account()
+        .upload_avatar(&content_type, data)
+        .await
+        .map_err(AccountErr::UploadAvatar)
+}
+
+pub async fn remove_avatar(client: &Client) -> Result<(), AccountErr> {
+    trace!("removing avatar");
+    client
+        .account()
+        .set_avatar_url(None)
+        .await
+        .map_err(AccountErr::RemoveAvatar)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_json, header, method, path},
+    };
+
+    use super::{AccountErr, remove_avatar, set_avatar_from_file, set_display_name};
+    use crate::test_support::logged_in_client;
+
+    #[test_log::test(tokio::test)]
+    async fn display_name() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        let displayname = "/_matrix/client/v3/profile/@alice:example.org/displayname";
+        Mock::given(method("PUT"))
+            .and(path(displayname))
+            .and(body_json(json!({ "displayname": "Alice" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path(displayname))
+            .and(body_json(json!({})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        set_display_name(&client, " Alice ").await.unwrap();
+        set_display_name(&client, "  ").await.unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn avatar() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/media/v3/upload"))
+            .and(header("content-type", "image/png"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content_uri": "mxc://example.org/avatar"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let avatar_url = "/_matrix/client/v3/profile/@alice:example.org/avatar_url";
+        Mock::given(method("PUT"))
+            .and(path(avatar_url))
+            .and(body_json(
+                json!({ "avatar_url": "mxc://example.org/avatar" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path(avatar_url))
+            .and(body_json(json!({})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = std::env::temp_dir().join(format!("swift-wind-avatar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let picture = dir.join("avatar.png");
+        std::fs::write(&picture, b"\x89PNG\r\n\x1a\n").unwrap();
+        let notes = dir.join("notes.txt");
+        std::fs::write(&notes, b"not a picture").unwrap();
+
+        let uri = set_avatar_from_file(&client, &picture).await.unwrap();
+        assert_eq!(uri, "mxc://example.org/avatar");
+        assert_matches!(
+            set_avatar_from_file(&client, &notes).await,
+            Err(AccountErr::NotAnImage(_))
+        );
+        assert_matches!(
+            set_avatar_from_file(&client, &dir.join("missing.png")).await,
+            Err(AccountErr::ReadAvatar { .. })
+        );
+        remove_avatar(&client).await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
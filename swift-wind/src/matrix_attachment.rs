@@ -0,0 +1,329 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use matrix_sdk::{
+    Client,
+    crypto::{AttachmentDecryptor, DecryptorError},
+    media::{MediaFormat, MediaRequestParameters},
+};
+use ruma::events::room::{EncryptedFile, MediaSource, message::MessageType};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::trace;
+
+use crate::matrix_download::{DownloadErr, DownloadState, download_file, reserve_path};
+
+#[derive(Debug, Error)]
+pub enum AttachmentErr {
+    #[error("couldn't download or decrypt the attachment: {0}")]
+    Download(#[source] Box<matrix_sdk::Error>),
+
+    #[error("couldn't save the attachment: {0}")]
+    Write(#[source] std::io::Error),
+
+    #[error("the attachment's encryption info is invalid: {0}")]
+    Decrypt(#[from] DecryptorError),
+
+    #[error("couldn't verify the attachment: {0}")]
+    Verify(#[source] std::io::Error),
+
+    #[error("{} already exists", .0.display())]
+    Exists(PathBuf),
+
+    #[error("couldn't save the attachment: {0}")]
+    Save(#[source] Box<DownloadErr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    Image,
+    File,
+    Video,
+    Audio,
+}
+
+/// Media of an image, file, video or audio message, plain `mxc` or an `EncryptedFile`.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    pub source: MediaSource,
+    pub name: String,
+    pub mimetype: Option<String>,
+    pub size: Option<u64>,
+    pub thumbnail: Option<MediaSource>,
+    /// Width and height from the event's `info`, images and videos only.
+    pub dimensions: Option<(u32, u32)>,
+    pub blurhash: Option<String>,
+}
+
+impl Attachment {
+    pub fn from_message(msgtype: &MessageType) -> Option<Self> {
+        let attachment = match msgtype {
+            MessageType::Image(content) => {
+                let info = content.info.as_deref();
+                Self {
+                    kind: AttachmentKind::Image,
+                    source: content.source.clone(),
+                    name: content.filename().to_owned(),
+                    mimetype: info.and_then(|info| info.mimetype.clone()),
+                    size: info.and_then(|info| info.size).map(u64::from),
+                    thumbnail: info.and_then(|info| info.thumbnail_source.clone()),
+                    dimensions: info.and_then(|info| {
+                        Some((
+                            u32::try_from(info.width?).ok()?,
+                            u32::try_from(info.height?).ok()?,
+                        ))
+                    }),
+                    blurhash: info.and_then(|info| info.blurhash.clone()),
+                }
+            }
+            MessageType::File(content) => {
+                let info = content.info.as_deref();
+                Self {
+                    kind: AttachmentKind::File,
+                    source: content.source.clone(),
+                    name: content.filename().to_owned(),
+                    mimetype: info.and_then(|info| info.mimetype.clone()),
+                    size: info.and_then(|info| info.size).map(u64::from),
+                    thumbnail: info.and_then(|info| info.thumbnail_source.clone()),
+                    dimensions: None,
+                    blurhash: None,
+                }
+            }
+            MessageType::Video(content) => {
+                let info = content.info.as_deref();
+                Self {
+                    kind: AttachmentKind::Video,
+                    source: content.source.clone(),
+                    name: content.filename().to_owned(),
+                    mimetype: info.and_then(|info| info.mimetype.clone()),
+                    size: info.and_then(|info| info.size).map(u64::from),
+                    thumbnail: info.and_then(|info| info.thumbnail_source.clone()),
+                    dimensions: info.and_then(|info| {
+                        Some((
+                            u32::try_from(info.width?).ok()?,
+                            u32::try_from(info.height?).ok()?,
+                        ))
+                    }),
+                    blurhash: info.and_then(|info| info.blurhash.clone()),
+                }
+            }
+            MessageType::Audio(content) => {
+                let info = content.info.as_deref();
+                Self {
+                    kind: AttachmentKind::Audio,
+                    source: content.source.clone(),
+                    name: content.filename().to_owned(),
+                    mimetype: info.and_then(|info| info.mimetype.clone()),
+                    size: info.and_then(|info| info.size).map(u64::from),
+                    thumbnail: None,
+                    dimensions: None,
+                    blurhash: None,
+                }
+            }
+            _ => return None,
+        };
+        Some(attachment)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self.source, MediaSource::Encrypted(_))
+    }
+
+    /// GIF and WebP may animate, their thumbnails never do.
+    pub fn may_be_animated(&self) -> bool {
+        matches!(self.mimetype.as_deref(), Some("image/gif" | "image/webp"))
+    }
+}
+
+/// Downloads the media, encrypted files are checked against their SHA-256 and decrypted.
+///
+/// Decrypted media never goes to the shared media cache.
+pub async fn fetch_attachment(
+    client: &Client,
+    source: &MediaSource,
+) -> Result<Vec<u8>, AttachmentErr> {
+    let use_cache = !matches!(source, MediaSource::Encrypted(_));
+    let request = MediaRequestParameters {
+        source: source.clone(),
+        format: MediaFormat::File,
+    };
+    client
+        .media()
+        .get_media_content(&request, use_cache)
+        .await
+        .map_err(|err| AttachmentErr::Download(Box::new(err)))
+}
+
+/// Decrypts an `EncryptedFile` downloaded to `ciphertext` into `plaintext`, checking its SHA-256.
+///
+/// Blocking, the file is streamed through the decryptor instead of being read into memory.
+pub fn decrypt_file(
+    ciphertext: &Path,
+    plaintext: &Path,
+    file: &EncryptedFile,
+) -> Result<(), AttachmentErr> {
+    let ciphertext = std::fs::File::open(ciphertext).map_err(AttachmentErr::Write)?;
+    let mut ciphertext = std::io::BufReader::new(ciphertext);
+    let mut decryptor = AttachmentDecryptor::new(&mut ciphertext, file.clone().into())?;
+    let mut plaintext = std::fs::File::create(plaintext).map_err(AttachmentErr::Write)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = decryptor.read(&mut buf).map_err(AttachmentErr::Verify)?;
+        if read == 0 {
+            return Ok(());
+        }
+        plaintext
+            .write_all(&buf[..read])
+            .map_err(AttachmentErr::Write)?;
+    }
+}
+
+/// Streams the media, decrypted, to the path the user picked.
+///
+/// An existing file is only replaced with `overwrite`, and stays as it was if the download fails.
+pub async fn save_attachment(
+    client: &Client,
+    source: &MediaSource,
+    path: &Path,
+    overwrite: bool,
+) -> Result<(), AttachmentErr> {
+    let target = if overwrite {
+        // Downloaded next to it first, so a failed download doesn't cost the user the old file
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        reserve_path(dir, &format!(".{name}.saving")).map_err(AttachmentErr::Write)?
+    } else {
+        match std::fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(_) => path.to_owned(),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(AttachmentErr::Exists(path.to_owned()));
+            }
+            Err(err) => return Err(AttachmentErr::Write(err)),
+        }
+    };
+
+    let (control, state) = watch::channel(DownloadState::Running);
+    let res = download_file(client, source, &target, state, |_| {}).await;
+    drop(control);
+    res.map_err(|err| AttachmentErr::Save(Box::new(err)))?;
+    if target != path
+        && let Err(err) = tokio::fs::rename(&target, path).await
+    {
+        let _ = tokio::fs::remove_file(&target).await;
+        return Err(AttachmentErr::Write(err));
+    }
+    trace!("saved {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use matrix_sdk::crypto::AttachmentEncryptor;
+    use ruma::{
+        events::room::{EncryptedFile, EncryptedFileInit, MediaSource},
+        mxc_uri,
+    };
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use super::{AttachmentErr, fetch_attachment, save_attachment};
+    use crate::test_support::logged_in_client;
+
+    fn encrypt(plaintext: &[u8]) -> (Vec<u8>, EncryptedFile) {
+        let mut cursor = Cursor::new(plaintext.to_vec());
+        let mut encryptor = AttachmentEncryptor::new(&mut cursor);
+        let mut ciphertext = Vec::new();
+        encryptor.read_to_end(&mut ciphertext).unwrap();
+        let info = encryptor.finish();
+        let file = EncryptedFileInit {
+            url: mxc_uri!("mxc://example.org/secret").to_owned(),
+            key: info.key,
+            iv: info.iv,
+            hashes: info.hashes,
+            v: info.version,
+        }
+        .into();
+        (ciphertext, file)
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn encrypted_attachment() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        let (mut ciphertext, file) = encrypt(b"a picture of a cat");
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(ciphertext.clone()))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        let source = MediaSource::Encrypted(Box::new(file));
+
+        assert_eq!(
+            fetch_attachment(&client, &source).await.unwrap(),
+            b"a picture of a cat"
+        );
+
+        ciphertext[0] ^= 1;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(ciphertext))
+            .mount(&server)
+            .await;
+        assert!(matches!(
+            fetch_attachment(&client, &source).await,
+            Err(AttachmentErr::Download(_))
+        ));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn save_asks_before_overwriting() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/cat"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"new cat".to_vec()))
+            .mount(&server)
+            .await;
+        let dir = std::env::temp_dir().join(format!("swift-wind-save-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("cat.png");
+        std::fs::write(&target, b"old cat").unwrap();
+
+        let source = MediaSource::Plain(mxc_uri!("mxc://example.org/cat").to_owned());
+        assert!(matches!(
+            save_attachment(&client, &source, &target, false).await,
+            Err(AttachmentErr::Exists(_))
+        ));
+        assert_eq!(std::fs::read(&target).unwrap(), b"old cat");
+
+        let missing = MediaSource::Plain(mxc_uri!("mxc://example.org/missing").to_owned());
+        assert!(matches!(
+            save_attachment(&client, &missing, &target, true).await,
+            Err(AttachmentErr::Save(_))
+        ));
+        assert_eq!(std::fs::read(&target).unwrap(), b"old cat");
+        let invalid = MediaSource::Plain("not-an-mxc-uri".into());
+        assert!(matches!(
+            save_attachment(&client, &invalid, &dir.join("dog.png"), false).await,
+            Err(AttachmentErr::Save(_))
+        ));
+
+        save_attachment(&client, &source, &target, true)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"new cat");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
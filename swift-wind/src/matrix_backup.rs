@@ -0,0 +1,305 @@
+use futures::StreamExt;
+use matrix_sdk::{
+    Client,
+    encryption::{
+        backups::{BackupState, UploadState},
+        recovery::RecoveryError,
+    },
+};
+use ruma::api::client::{
+    backup::{delete_backup_version, get_latest_backup_info},
+    error::ErrorKind,
+};
+use thiserror::Error;
+use tracing::{trace, warn};
+
+#[derive(Debug, Error)]
+pub enum BackupErr {
+    #[error("couldn't reach the key backup: {0}")]
+    Server(#[source] matrix_sdk::Error),
+
+    #[error("couldn't create the key backup: {0}")]
+    Create(#[source] RecoveryError),
+
+    #[error("couldn't delete the key backup: {0}")]
+    Delete(#[source] matrix_sdk::Error),
+
+    #[error("the recovery key or passphrase is wrong, or secret storage couldn't be read: {0}")]
+    Unlock(#[source] RecoveryError),
+
+    #[error("couldn't store the new backup key in secret storage: {0}")]
+    ResetKey(#[source] RecoveryError),
+}
+
+/// The room key backup as the settings page shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupStatus {
+    pub state: BackupState,
+    pub exists_on_server: bool,
+    /// This session uploads its room keys to the backup.
+    pub enabled_here: bool,
+}
+
+/// How far a restore from backup got, counted in rooms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RestoreProgress {
+    pub rooms_done: usize,
+    pub rooms_total: usize,
+}
+
+pub async fn backup_status(client: &Client) -> Result<BackupStatus, BackupErr> {
+    let backups = client.encryption().backups();
+    let exists_on_server = backups
+        .fetch_exists_on_server()
+        .await
+        .map_err(BackupErr::Server)?;
+    Ok(BackupStatus {
+        state: backups.state(),
+        exists_on_server,
+        enabled_here: backups.are_enabled().await,
+    })
+}
+
+/// Creates a backup when the account has none, room keys start uploading right away.
+pub async fn enable_backup(client: &Client) -> Result<(), BackupErr> {
+    trace!("creating key backup");
+    client
+        .encryption()
+        .recovery()
+        .enable_backup()
+        .await
+        .map_err(BackupErr::Create)
+}
+
+/// Deletes the backup from the server, keys already on this session stay readable here.
+pub async fn delete_backup(client: &Client) -> Result<(), BackupErr> {
+    trace!("deleting key backup");
+    client
+        .encryption()
+        .backups()
+        .disable_and_delete()
+        .await
+        .map_err(BackupErr::Delete)
+}
+
+/// Replaces the backup with a new one holding this session's keys.
+///
+/// The new backup key only reaches secret storage with a new recovery key, which is returned.
+/// The old backup is deleted last, a reset failing halfway leaves the account with a backup.
+pub async fn reset_backup(
+    client: &Client,
+    passphrase: Option<&str>,
+) -> Result<Option<String>, BackupErr> {
+    let previous = latest_version(client).await?;
+    trace!("creating replacement key backup");
+    let encryption = client.encryption();
+    encryption
+        .backups()
+        .create()
+        .await
+        .map_err(|err| BackupErr::Create(err.into()))?;
+
+    let has_secret_storage = encryption
+        .secret_storage()
+        .is_enabled()
+        .await
+        .map_err(BackupErr::Server)?;
+    let recovery_key = if has_secret_storage {
+        trace!("storing the new backup key under a new recovery key");
+        let recovery = encryption.recovery();
+        let reset = recovery.reset_key();
+        let reset = match passphrase.filter(|passphrase| !passphrase.is_empty()) {
+            Some(passphrase) => reset.with_passphrase(passphrase),
+            None => reset,
+        };
+        Some(reset.await.map_err(BackupErr::ResetKey)?)
+    } else {
+        None
+    };
+
+    // The new backup is the latest version now, a leftover old one is only clutter.
+    if let Some(previous) = previous {
+        trace!("deleting replaced key backup {previous}");
+        let request = delete_backup_version::v3::Request::new(previous);
+        if let Err(err) = client.send(request).await {
+            warn!("failed to delete replaced key backup {err}");
+        }
+    }
+    Ok(recovery_key)
+}
+
+/// Version of the backup new room keys go to, `None` when the account has none.
+async fn latest_version(client: &Client) -> Result<Option<String>, BackupErr> {
+    match client
+        .send(get_latest_backup_info::v3::Request::new())
+        .await
+    {
+        Ok(info) => Ok(Some(info.version)),
+        Err(err) if err.client_api_error_kind() == Some(&ErrorKind::NotFound) => Ok(None),
+        Err(err) => Err(BackupErr::Server(err.into())),
+    }
+}
+
+/// Unlocks the backup with the recovery key or passphrase and downloads the keys of every
+/// joined room, rooms that fail are skipped and fetched again once a message needs them.
+pub async fn restore_backup(
+    client: &Client,
+    key_or_passphrase: &str,
+    mut on_progress: impl FnMut(RestoreProgress),
+) -> Result<(), BackupErr> {
+    let encryption = client.encryption();
+    encryption
+        .recovery()
+        .recover(key_or_passphrase.trim())
+        .await
+        .map_err(BackupErr::Unlock)?;
+
+    let rooms = client.joined_rooms();
+    let mut progress = RestoreProgress {
+        rooms_done: 0,
+        rooms_total: rooms.len(),
+    };
+    on_progress(progress);
+    let backups = encryption.backups();
+    for room in rooms {
+        if let Err(err) = backups.download_room_keys_for_room(room.room_id()).await {
+            warn!("restoring keys of {} failed {err}", room.room_id());
+        }
+        progress.rooms_done += 1;
+        on_progress(progress);
+    }
+    trace!("restored keys of {} rooms", progress.rooms_total);
+    Ok(())
+}
+
+/// Reports how many room keys are backed up until this session has uploaded all of them.
+pub async fn watch_upload(client: &Client, mut on_progress: impl FnMut(usize, usize)) {
+    let backups = client.encryption().backups();
+    let wait = backups.wait_for_steady_state();
+    let mut progress = wait.subscribe_to_progress();
+    let report = async {
+        while let Some(state) = progress.next().await {
+            if let Ok(UploadState::Uploading(counts)) = state {
+                on_progress(counts.backed_up, counts.total);
+            }
+        }
+    };
+    tokio::select! {
+        res = wait => {
+            if let Err(err) = res {
+                warn!("room key upload stopped {err}");
+            }
+        }
+        () = report => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::encryption::backups::BackupState;
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use super::{BackupStatus, backup_status, reset_backup};
+    use crate::test_support::logged_in_client;
+
+    #[test_log::test(tokio::test)]
+    async fn status() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v3/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+                "auth_data": {
+                    "public_key": "hdx|5N+fCkSEzIv9ktx5u6nsuUxZFq7kn0M8Y4ruJXiQ",
+                    "signatures": {}
+                },
+                "count": 12,
+                "etag": "1",
+                "version": "1"
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v3/room_keys/version"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No current backup version"
+            })))
+            .mount(&server)
+            .await;
+
+        let expected = BackupStatus {
+            state: BackupState::Unknown,
+            exists_on_server: true,
+            enabled_here: false,
+        };
+        assert_eq!(backup_status(&client).await.unwrap(), expected);
+        let expected = BackupStatus {
+            exists_on_server: false,
+            ..expected
+        };
+        assert_eq!(backup_status(&client).await.unwrap(), expected);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn reset_creates_before_deleting() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v3/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+                "auth_data": {
+                    "public_key": "hdx|5N+fCkSEzIv9ktx5u6nsuUxZFq7kn0M8Y4ruJXiQ",
+                    "signatures": {}
+                },
+                "count": 12,
+                "etag": "1",
+                "version": "1"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "version": "2" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/_matrix/client/v3/room_keys/version/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/_matrix/client/v3/user/@alice:example.org/account_data/m.secret_storage.default_key",
+            ))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "Account data not found"
+            })))
+            .mount(&server)
+            .await;
+
+        assert_eq!(reset_backup(&client, None).await.unwrap(), None);
+
+        let requests = server.received_requests().await.unwrap();
+        let position = |method: &str, path: &str| {
+            requests
+                .iter()
+                .position(|request| request.method.as_str() == method && request.url.path() == path)
+                .unwrap()
+        };
+        assert!(
+            position("POST", "/_matrix/client/v3/room_keys/version")
+                < position("DELETE", "/_matrix/client/v3/room_keys/version/1")
+        );
+    }
+}
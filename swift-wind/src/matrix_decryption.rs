@@ -0,0 +1,205 @@
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use matrix_sdk::{
+    Client, Room,
+    deserialized_responses::{TimelineEventKind, UnableToDecryptReason, WithheldCode},
+};
+use ruma::{
+    events::{
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent,
+        room::{encrypted::OriginalSyncRoomEncryptedEvent, message::OriginalSyncRoomMessageEvent},
+    },
+    serde::Raw,
+};
+use thiserror::Error;
+use tracing::trace;
+
+use crate::matrix_trust::MessageShield;
+
+#[derive(Debug, Error)]
+pub enum DecryptionErr {
+    #[error("couldn't decrypt the message: {0}")]
+    Decrypt(#[source] matrix_sdk::Error),
+
+    #[error("couldn't read the decrypted message: {0}")]
+    Deserialize(#[source] serde_json::Error),
+
+    #[error("couldn't fetch the message's key from the backup: {0}")]
+    Backup(#[source] matrix_sdk::Error),
+}
+
+/// Why an encrypted message can't be read, as the timeline explains it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtdReason {
+    /// The key hasn't reached this session, it might still arrive.
+    MissingKeys,
+    /// The sender's session refused to share the key.
+    Withheld(WithheldCode),
+    /// The key was withheld or dropped because a device isn't verified.
+    UnverifiedDevice,
+    /// The message is broken or was encrypted with a key that doesn't match.
+    Unreadable,
+}
+
+impl From<&UnableToDecryptReason> for UtdReason {
+    fn from(reason: &UnableToDecryptReason) -> Self {
+        match reason {
+            UnableToDecryptReason::MissingMegolmSession {
+                withheld_code: Some(WithheldCode::Unverified),
+            }
+            | UnableToDecryptReason::SenderIdentityNotTrusted(_) => Self::UnverifiedDevice,
+            UnableToDecryptReason::MissingMegolmSession {
+                withheld_code: Some(code),
+            } => Self::Withheld(code.clone()),
+            UnableToDecryptReason::MissingMegolmSession {
+                withheld_code: None,
+            }
+            | UnableToDecryptReason::UnknownMegolmMessageIndex => Self::MissingKeys,
+            _ => Self::Unreadable,
+        }
+    }
+}
+
+/// An `m.room.encrypted` event after trying to decrypt it.
+#[derive(Debug, Clone)]
+pub enum DecryptedMessage {
+    Message {
+        message: Box<OriginalSyncRoomMessageEvent>,
+        shield: MessageShield,
+    },
+    /// Decrypted into something other than an `m.room.message`.
+    Other,
+    Undecryptable {
+        session_id: Option<String>,
+        reason: UtdReason,
+    },
+}
+
+/// Decrypts with the keys this session has, missing keys are requested from the backup and
+/// the sender's other sessions in the background.
+pub async fn decrypt_message(
+    room: &Room,
+    event: &Raw<OriginalSyncRoomEncryptedEvent>,
+) -> Result<DecryptedMessage, DecryptionErr> {
+    let event = room
+        .decrypt_event(event)
+        .await
+        .map_err(DecryptionErr::Decrypt)?;
+    if let TimelineEventKind::UnableToDecrypt { utd_info, .. } = &event.kind {
+        trace!("couldn't decrypt event {:?}", utd_info);
+        return Ok(DecryptedMessage::Undecryptable {
+            session_id: utd_info.session_id.clone(),
+            reason: (&utd_info.reason).into(),
+        });
+    }
+    let shield = event
+        .encryption_info()
+        .map(|info| MessageShield::from(&info.verification_state))
+        .unwrap_or_default();
+    let event = event
+        .raw()
+        .deserialize()
+        .map_err(DecryptionErr::Deserialize)?;
+    Ok(match event {
+        AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+            SyncMessageLikeEvent::Original(message),
+        )) => DecryptedMessage::Message {
+            message: Box::new(message),
+            shield,
+        },
+        _ => DecryptedMessage::Other,
+    })
+}
+
+/// Asks the backup for the message's key right away, then decrypts again which also repeats
+/// the key request to the sender's sessions.
+pub async fn request_keys(
+    room: &Room,
+    event: &Raw<OriginalSyncRoomEncryptedEvent>,
+    session_id: &str,
+) -> Result<DecryptedMessage, DecryptionErr> {
+    let backups = room.client().encryption().backups();
+    if backups.are_enabled().await {
+        let found = backups
+            .download_room_key(room.room_id(), session_id)
+            .await
+            .map_err(DecryptionErr::Backup)?;
+        trace!("key {session_id} in backup: {found}");
+    }
+    decrypt_message(room, event).await
+}
+
+/// Room keys arriving at this session. Subscribe before decrypting, a key landing in between
+/// would be missed otherwise.
+pub struct KeyArrivals(Pin<Box<dyn Stream<Item = Option<Vec<String>>> + Send>>);
+
+impl KeyArrivals {
+    pub async fn subscribe(client: &Client) -> Option<Self> {
+        let received = client.encryption().room_keys_received_stream().await?;
+        // Lagging behind skips keys, `None` says the session ids are unknown
+        let session_ids = received.map(|keys| {
+            keys.ok()
+                .map(|keys| keys.into_iter().map(|key| key.session_id).collect())
+        });
+        Some(Self(Box::pin(session_ids)))
+    }
+
+    /// Resolves once the key of the session arrives, from the backup, a forward, a key file
+    /// or to-device.
+    pub async fn wait_for(mut self, session_id: &str) {
+        while let Some(keys) = self.0.next().await {
+            match keys {
+                Some(keys) if keys.iter().any(|key| key == session_id) => return,
+                Some(_) => {}
+                // Some keys were skipped, the one we wait for might be among them
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::deserialized_responses::{
+        UnableToDecryptReason, VerificationLevel, WithheldCode,
+    };
+
+    use super::UtdReason;
+
+    #[test]
+    fn utd_reasons() {
+        let missing = UnableToDecryptReason::MissingMegolmSession {
+            withheld_code: None,
+        };
+        assert_eq!(UtdReason::from(&missing), UtdReason::MissingKeys);
+        assert_eq!(
+            UtdReason::from(&UnableToDecryptReason::UnknownMegolmMessageIndex),
+            UtdReason::MissingKeys
+        );
+
+        let blacklisted = UnableToDecryptReason::MissingMegolmSession {
+            withheld_code: Some(WithheldCode::Blacklisted),
+        };
+        assert_eq!(
+            UtdReason::from(&blacklisted),
+            UtdReason::Withheld(WithheldCode::Blacklisted)
+        );
+
+        let unverified = UnableToDecryptReason::MissingMegolmSession {
+            withheld_code: Some(WithheldCode::Unverified),
+        };
+        assert_eq!(UtdReason::from(&unverified), UtdReason::UnverifiedDevice);
+        assert_eq!(
+            UtdReason::from(&UnableToDecryptReason::SenderIdentityNotTrusted(
+                VerificationLevel::UnsignedDevice
+            )),
+            UtdReason::UnverifiedDevice
+        );
+
+        assert_eq!(
+            UtdReason::from(&UnableToDecryptReason::MismatchedIdentityKeys),
+            UtdReason::Unreadable
+        );
+    }
+}
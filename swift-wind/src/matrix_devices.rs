@@ -0,0 +1,148 @@
+use matrix_sdk::{Client, HttpError, encryption::CryptoStoreError};
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedDeviceId};
+use thiserror::Error;
+use tracing::{trace, warn};
+
+#[derive(Debug, Error)]
+pub enum DevicesErr {
+    #[error("the account isn't logged in")]
+    NotLoggedIn,
+
+    #[error("couldn't list the sessions: {0}")]
+    List(#[source] HttpError),
+
+    #[error("couldn't read the session's verification: {0}")]
+    Verification(#[source] CryptoStoreError),
+
+    #[error("couldn't rename the session: {0}")]
+    Rename(#[source] HttpError),
+}
+
+/// One session of the account as the sessions list shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub device_id: OwnedDeviceId,
+    pub display_name: Option<String>,
+    pub last_seen_ip: Option<String>,
+    pub last_seen: Option<MilliSecondsSinceUnixEpoch>,
+    /// The session swift-wind is signed in as.
+    pub is_current: bool,
+    /// `None` when the session has no encryption keys, like one that never synced.
+    pub verified: Option<bool>,
+}
+
+/// Sessions of the account, the current one first and the rest by when they were last seen.
+pub async fn list_devices(client: &Client) -> Result<Vec<DeviceInfo>, DevicesErr> {
+    let user_id = client.user_id().ok_or(DevicesErr::NotLoggedIn)?;
+    let current = client.device_id();
+    trace!("listing sessions of {user_id}");
+    let res = client.devices().await.map_err(DevicesErr::List)?;
+
+    let mut devices = Vec::with_capacity(res.devices.len());
+    for device in res.devices {
+        let verified = client
+            .encryption()
+            .get_device(user_id, &device.device_id)
+            .await
+            .map_err(DevicesErr::Verification)?
+            .map(|keys| keys.is_verified());
+        devices.push(DeviceInfo {
+            is_current: current == Some(&*device.device_id),
+            device_id: device.device_id,
+            display_name: device.display_name,
+            last_seen_ip: device.last_seen_ip,
+            last_seen: device.last_seen_ts,
+            verified,
+        });
+    }
+    devices.sort_by(|a, b| {
+        b.is_current
+            .cmp(&a.is_current)
+            .then(b.last_seen.cmp(&a.last_seen))
+    });
+    Ok(devices)
+}
+
+pub async fn rename_device(
+    client: &Client,
+    device_id: &OwnedDeviceId,
+    name: &str,
+) -> Result<(), DevicesErr> {
+    let name = name.trim();
+    if name.is_empty() {
+        warn!("not renaming {device_id} to a blank name");
+        return Ok(());
+    }
+    trace!("renaming {device_id} to {name}");
+    client
+        .rename_device(device_id, name)
+        .await
+        .map_err(DevicesErr::Rename)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{device_id, owned_device_id};
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_json, method, path},
+    };
+
+    use super::{list_devices, rename_device};
+    use crate::test_support::logged_in_client_on;
+
+    #[test_log::test(tokio::test)]
+    async fn sessions() {
+        let server = MockServer::start().await;
+        let client = logged_in_client_on(&server, device_id!("CURRENT")).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v3/devices"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "devices": [
+                    { "device_id": "OLD", "display_name": "Old laptop", "last_seen_ts": 1000 },
+                    { "device_id": "NEVER" },
+                    {
+                        "device_id": "CURRENT",
+                        "display_name": "swift-wind on Linux",
+                        "last_seen_ip": "127.0.0.1",
+                        "last_seen_ts": 2000,
+                    },
+                    { "device_id": "PHONE", "display_name": "Phone", "last_seen_ts": 3000 },
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/_matrix/client/v3/devices/PHONE"))
+            .and(body_json(json!({ "display_name": "Work phone" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let devices = list_devices(&client).await.unwrap();
+        let ids: Vec<_> = devices
+            .iter()
+            .map(|device| device.device_id.as_str())
+            .collect();
+        assert_eq!(ids, ["CURRENT", "PHONE", "OLD", "NEVER"]);
+        assert!(devices[0].is_current);
+        assert_eq!(devices[0].last_seen_ip.as_deref(), Some("127.0.0.1"));
+        // Only the current session has keys swift-wind knows about
+        assert_eq!(devices[0].verified, Some(true));
+        assert!(
+            devices[1..]
+                .iter()
+                .all(|device| !device.is_current && device.verified.is_none())
+        );
+
+        rename_device(&client, &owned_device_id!("PHONE"), " Work phone ")
+            .await
+            .unwrap();
+        rename_device(&client, &owned_device_id!("PHONE"), "  ")
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,449 @@
+use reqwest::StatusCode;
+use ruma::{
+    OwnedServerName, ServerName, UserId,
+    api::client::session::get_login_types::v3::{IdentityProvider, LoginType},
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::trace;
+use url::Url;
+
+use crate::matrix_oidc::AuthMetadata;
+
+/// What the user typed in the server field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerInput {
+    /// A bare server name, or the server part of a user ID, resolved through `.well-known`.
+    ServerName(OwnedServerName),
+
+    /// A base URL used as is.
+    Url(Url),
+}
+
+#[derive(Debug, Error)]
+pub enum DiscoveryErr {
+    #[error("enter a server name like example.org, a user ID like @you:example.org or a URL")]
+    Empty,
+
+    #[error("\"{0}\" is not a server name, user ID or URL")]
+    InvalidInput(String),
+
+    #[error("couldn't reach {url}: {source}")]
+    Unreachable {
+        url: Url,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("{url} answered with {status}")]
+    Status { url: Url, status: StatusCode },
+
+    #[error("{url} is not a valid client well-known file: {source}")]
+    InvalidWellKnown {
+        url: Url,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("the well-known file of {server} points to an invalid homeserver URL \"{base_url}\"")]
+    InvalidBaseUrl {
+        server: OwnedServerName,
+        base_url: String,
+    },
+
+    #[error("{url} is not a Matrix homeserver, its versions can't be read: {source}")]
+    NotAHomeserver {
+        url: Url,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Validated homeserver behind a [`ServerInput`].
+#[derive(Debug, Clone)]
+pub struct Discovered {
+    pub homeserver: Url,
+    pub server_name: Option<OwnedServerName>,
+    pub versions: Vec<String>,
+}
+
+/// Login types of a homeserver sorted into what the login page can offer.
+#[derive(Debug, Clone, Default)]
+pub struct LoginFlows {
+    pub password: bool,
+    pub token: bool,
+
+    /// `None` when SSO isn't offered, an empty list when it is but without a choice of provider.
+    pub sso: Option<Vec<IdentityProvider>>,
+
+    /// Names of the offered login types swift-wind can't use.
+    pub unsupported: Vec<String>,
+
+    /// Set by the caller when the homeserver delegates auth, see [`crate::matrix_oidc::discover_auth_metadata`].
+    pub oidc: Option<AuthMetadata>,
+}
+
+impl LoginFlows {
+    pub fn new(login_types: Vec<LoginType>) -> Self {
+        let mut flows = Self::default();
+        for login_type in login_types {
+            match login_type {
+                LoginType::Password(_) => flows.password = true,
+                LoginType::Token(_) => flows.token = true,
+                LoginType::Sso(sso) => flows
+                    .sso
+                    .get_or_insert_with(Vec::new)
+                    .extend(sso.identity_providers),
+                login_type => flows.unsupported.push(login_type.login_type().to_owned()),
+            }
+        }
+        flows
+    }
+
+    /// Whether none of the offered login types can be used.
+    pub fn is_empty(&self) -> bool {
+        !self.password && !self.token && self.sso.is_none() && self.oidc.is_none()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WellKnown {
+    #[serde(rename = "m.homeserver")]
+    homeserver: WellKnownHomeserver,
+}
+
+#[derive(Debug, Deserialize)]
+struct WellKnownHomeserver {
+    base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Versions {
+    versions: Vec<String>,
+}
+
+pub fn parse_server_input(input: &str) -> Result<ServerInput, DiscoveryErr> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(DiscoveryErr::Empty);
+    }
+    if input.starts_with('@') {
+        let user_id =
+            UserId::parse(input).map_err(|_| DiscoveryErr::InvalidInput(input.to_owned()))?;
+        return Ok(ServerInput::ServerName(user_id.server_name().to_owned()));
+    }
+    if input.contains("://") {
+        let url = Url::parse(input).map_err(|_| DiscoveryErr::InvalidInput(input.to_owned()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(DiscoveryErr::InvalidInput(input.to_owned()));
+        }
+        return Ok(ServerInput::Url(url));
+    }
+    ServerName::parse(input.trim_end_matches('/'))
+        .map(ServerInput::ServerName)
+        .map_err(|_| DiscoveryErr::InvalidInput(input.to_owned()))
+}
+
+/// Resolves the input like the client-server spec asks: `.well-known` first, a missing file
+/// means the server name is the homeserver, and whatever comes out has to answer `/versions`.
+pub async fn discover_homeserver(input: &str) -> Result<Discovered, DiscoveryErr> {
+    let (homeserver, server_name) = match parse_server_input(input)? {
+        ServerInput::Url(url) => (url, None),
+        ServerInput::ServerName(server_name) => {
+            let homeserver = well_known(&server_name).await?;
+            (homeserver, Some(server_name))
+        }
+    };
+    let versions = versions(&homeserver).await?;
+    Ok(Discovered {
+        homeserver,
+        server_name,
+        versions,
+    })
+}
+
+async fn well_known(server_name: &ServerName) -> Result<Url, DiscoveryErr> {
+    let server = Url::parse(&format!("https://{server_name}"))
+        .map_err(|_| DiscoveryErr::InvalidInput(server_name.to_string()))?;
+    well_known_at(server, server_name).await
+}
+
+/// Reads the `.well-known` file `server` hosts for `server_name`.
+async fn well_known_at(server: Url, server_name: &ServerName) -> Result<Url, DiscoveryErr> {
+    let url = server
+        .join(".well-known/matrix/client")
+        .expect("well-known path is a valid relative URL");
+
+    trace!("looking up {url}");
+    let res = reqwest::get(url.clone())
+        .await
+        .map_err(|source| DiscoveryErr::Unreachable {
+            url: url.clone(),
+            source,
+        })?;
+    if res.status() == StatusCode::NOT_FOUND {
+        trace!("{server_name} has no well-known file, using it as the homeserver");
+        return Ok(server);
+    }
+    if !res.status().is_success() {
+        return Err(DiscoveryErr::Status {
+            url,
+            status: res.status(),
+        });
+    }
+    let body = res
+        .bytes()
+        .await
+        .map_err(|source| DiscoveryErr::Unreachable {
+            url: url.clone(),
+            source,
+        })?;
+    let well_known = serde_json::from_slice::<WellKnown>(&body)
+        .map_err(|source| DiscoveryErr::InvalidWellKnown { url, source })?;
+
+    let base_url = well_known.homeserver.base_url;
+    Url::parse(&base_url).map_err(|_| DiscoveryErr::InvalidBaseUrl {
+        server: server_name.to_owned(),
+        base_url,
+    })
+}
+
+/// Joins a path onto a base URL that may live under a path, which `Url::join` would replace.
+pub(crate) fn join_base(base: &Url, path: &str) -> Option<Url> {
+    let mut base = base.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join(path).ok()
+}
+
+async fn versions(homeserver: &Url) -> Result<Vec<String>, DiscoveryErr> {
+    let url = join_base(homeserver, "_matrix/client/versions")
+        .ok_or_else(|| DiscoveryErr::InvalidInput(homeserver.to_string()))?;
+
+    trace!("checking {url}");
+    let res = reqwest::get(url.clone())
+        .await
+        .map_err(|source| DiscoveryErr::Unreachable {
+            url: url.clone(),
+            source,
+        })?;
+    if !res.status().is_success() {
+        return Err(DiscoveryErr::Status {
+            url,
+            status: res.status(),
+        });
+    }
+    let body = res
+        .bytes()
+        .await
+        .map_err(|source| DiscoveryErr::Unreachable {
+            url: url.clone(),
+            source,
+        })?;
+    serde_json::from_slice::<Versions>(&body)
+        .map(|versions| versions.versions)
+        .map_err(|source| DiscoveryErr::NotAHomeserver { url, source })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use reqwest::StatusCode;
+    use ruma::server_name;
+    use url::Url;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use ruma::api::client::session::get_login_types::v3::LoginType;
+    use serde_json::json;
+
+    use super::{
+        DiscoveryErr, LoginFlows, ServerInput, WellKnown, parse_server_input, versions,
+        well_known_at,
+    };
+
+    #[test]
+    fn server_input() {
+        assert_eq!(
+            parse_server_input(" example.org ").unwrap(),
+            ServerInput::ServerName(server_name!("example.org").to_owned())
+        );
+        assert_eq!(
+            parse_server_input("example.org:8448").unwrap(),
+            ServerInput::ServerName(server_name!("example.org:8448").to_owned())
+        );
+        assert_eq!(
+            parse_server_input("@alice:example.org").unwrap(),
+            ServerInput::ServerName(server_name!("example.org").to_owned())
+        );
+        assert_eq!(
+            parse_server_input("http://127.0.0.1:8008").unwrap(),
+            ServerInput::Url(Url::parse("http://127.0.0.1:8008").unwrap())
+        );
+
+        assert_matches!(parse_server_input(""), Err(DiscoveryErr::Empty));
+        assert_matches!(
+            parse_server_input("@alice"),
+            Err(DiscoveryErr::InvalidInput(_))
+        );
+        assert_matches!(
+            parse_server_input("ftp://example.org"),
+            Err(DiscoveryErr::InvalidInput(_))
+        );
+        assert_matches!(
+            parse_server_input("not a server"),
+            Err(DiscoveryErr::InvalidInput(_))
+        );
+    }
+
+    #[test]
+    fn well_known_body() {
+        let well_known: WellKnown = serde_json::from_str(
+            r#"{"m.homeserver": {"base_url": "https://matrix.example.org"}, "m.identity_server": {}}"#,
+        )
+        .unwrap();
+        assert_eq!(well_known.homeserver.base_url, "https://matrix.example.org");
+
+        assert!(serde_json::from_str::<WellKnown>(r#"{"m.identity_server": {}}"#).is_err());
+    }
+
+    fn login_types(flows: serde_json::Value) -> Vec<LoginType> {
+        serde_json::from_value(flows).unwrap()
+    }
+
+    #[test]
+    fn login_flows() {
+        let flows = LoginFlows::new(login_types(json!([
+            { "type": "m.login.password" },
+            { "type": "m.login.token" },
+            { "type": "m.login.sso", "identity_providers": [
+                { "id": "oidc-github", "name": "GitHub", "brand": "github" },
+                { "id": "oidc-gitlab", "name": "GitLab" },
+            ] },
+            { "type": "m.login.application_service" },
+            { "type": "org.example.custom" },
+        ])));
+        assert!(flows.password);
+        assert!(flows.token);
+        let providers = flows.sso.unwrap();
+        assert_eq!(providers.len(), 2);
+        assert_eq!(providers[0].name, "GitHub");
+        assert_eq!(
+            flows.unsupported,
+            vec!["m.login.application_service", "org.example.custom"]
+        );
+
+        let sso_only = LoginFlows::new(login_types(json!([{ "type": "m.login.sso" }])));
+        assert!(!sso_only.is_empty());
+        assert_eq!(sso_only.sso.map(|providers| providers.len()), Some(0));
+
+        let custom = LoginFlows::new(login_types(json!([{ "type": "org.example.custom" }])));
+        assert!(custom.is_empty());
+    }
+
+    async fn mock_well_known(server: &MockServer, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(response)
+            .mount(server)
+            .await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn well_known_lookup() {
+        let server = MockServer::start().await;
+        let uri = Url::parse(&server.uri()).unwrap();
+        mock_well_known(
+            &server,
+            ResponseTemplate::new(200).set_body_json(json!({
+                "m.homeserver": { "base_url": "https://matrix.example.org" }
+            })),
+        )
+        .await;
+        assert_eq!(
+            well_known_at(uri, server_name!("example.org"))
+                .await
+                .unwrap(),
+            Url::parse("https://matrix.example.org").unwrap()
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn well_known_missing() {
+        let server = MockServer::start().await;
+        let uri = Url::parse(&server.uri()).unwrap();
+        mock_well_known(&server, ResponseTemplate::new(404)).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "versions": ["v1.1", "v1.11"]
+            })))
+            .mount(&server)
+            .await;
+
+        // Without a well-known file the server itself is the homeserver
+        let homeserver = well_known_at(uri.clone(), server_name!("example.org"))
+            .await
+            .unwrap();
+        assert_eq!(homeserver, uri);
+        assert_eq!(
+            versions(&homeserver).await.unwrap(),
+            vec!["v1.1".to_owned(), "v1.11".to_owned()]
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn well_known_errors() {
+        let server = MockServer::start().await;
+        let uri = Url::parse(&server.uri()).unwrap();
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "m.homeserver": { "base_url": "not a url" }
+            })))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        mock_well_known(&server, ResponseTemplate::new(500)).await;
+
+        assert_matches!(
+            well_known_at(uri.clone(), server_name!("example.org")).await,
+            Err(DiscoveryErr::InvalidBaseUrl { base_url, .. }) if base_url == "not a url"
+        );
+        assert_matches!(
+            well_known_at(uri, server_name!("example.org")).await,
+            Err(DiscoveryErr::Status { status, .. }) if status == StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn versions_errors() {
+        let server = MockServer::start().await;
+        let uri = Url::parse(&server.uri()).unwrap();
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html>not matrix</html>"))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/versions"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        assert_matches!(
+            versions(&uri).await,
+            Err(DiscoveryErr::NotAHomeserver { .. })
+        );
+        assert_matches!(
+            versions(&uri).await,
+            Err(DiscoveryErr::Status { status, .. }) if status == StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use std::path::{Path, PathBuf};
+
+use matrix_sdk::Client;
+use reqwest::{
+    StatusCode,
+    header::{HeaderValue, RANGE},
+};
+use ruma::events::room::MediaSource;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+use tracing::{trace, warn};
+
+use crate::matrix_attachment::{AttachmentErr, decrypt_file};
+use crate::matrix_media::{MediaErr, media_download_request};
+use crate::matrix_store::data_dir;
+
+#[derive(Debug, Error)]
+pub enum DownloadErr {
+    #[error(transparent)]
+    Media(#[from] MediaErr),
+
+    #[error("the download failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Attachment(#[from] AttachmentErr),
+
+    #[error("couldn't write the file: {0}")]
+    Write(#[from] std::io::Error),
+
+    #[error("the download was cancelled")]
+    Cancelled,
+}
+
+/// What the download manager wants a running download to do, sent through a `watch` channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownloadState {
+    #[default]
+    Running,
+    /// Drops the connection, resuming asks the server for the rest with a `Range` header.
+    Paused,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DownloadProgress {
+    pub received: u64,
+    pub total: Option<u64>,
+}
+
+/// `$XDG_DOWNLOAD_DIR`, `~/Downloads` or the data directory as a last resort.
+pub fn default_download_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DOWNLOAD_DIR") {
+        return PathBuf::from(dir);
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        return PathBuf::from(home).join("Downloads");
+    }
+    data_dir().join("downloads")
+}
+
+/// Reserves a path in `dir` for a file named by the sender, numbered instead of overwriting.
+///
+/// The file is created empty right away, so downloads of the same name running at the
+/// same time each get their own. Only the final component of `name` is kept so a crafted
+/// name can't leave `dir`.
+pub fn reserve_path(dir: &Path, name: &str) -> std::io::Result<PathBuf> {
+    let name = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "download".to_owned());
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name.as_str(), String::new()),
+    };
+    let numbered = (1..).map(|index| format!("{stem} ({index}){extension}"));
+    for candidate in std::iter::once(name.clone()).chain(numbered) {
+        let path = dir.join(candidate);
+        if partial_path(&path).exists() {
+            continue;
+        }
+        match std::fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("some index is free")
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Waits out a pause, `Err` once the download is cancelled.
+async fn wait_while_paused(
+    control: &mut watch::Receiver<DownloadState>,
+) -> Result<(), DownloadErr> {
+    loop {
+        match *control.borrow_and_update() {
+            DownloadState::Running => return Ok(()),
+            DownloadState::Cancelled => return Err(DownloadErr::Cancelled),
+            DownloadState::Paused => {}
+        }
+        if control.changed().await.is_err() {
+            return Err(DownloadErr::Cancelled);
+        }
+    }
+}
+
+/// Streams the media into `path` through a `.part` file, following `control` to pause,
+/// resume or cancel.
+///
+/// `path` should come from [`reserve_path`], the download replaces the empty reservation.
+/// Encrypted files are decrypted and checked against their SHA-256 once complete, nothing
+/// is left at `path` unless that succeeds.
+pub async fn download_file(
+    client: &Client,
+    source: &MediaSource,
+    path: &Path,
+    mut control: watch::Receiver<DownloadState>,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<(), DownloadErr> {
+    let uri = match source {
+        MediaSource::Plain(uri) => uri,
+        MediaSource::Encrypted(file) => &file.url,
+    };
+    let partial = partial_path(path);
+
+    let res = async {
+        let request = media_download_request(client, uri).await?;
+        let url = request.url().clone();
+        let mut file = tokio::fs::File::create(&partial).await?;
+        let mut progress = DownloadProgress::default();
+        'download: loop {
+            wait_while_paused(&mut control).await?;
+            let mut request = request
+                .try_clone()
+                .expect("media requests have no streaming body");
+            if progress.received > 0 {
+                let range = HeaderValue::from_str(&format!("bytes={}-", progress.received))
+                    .expect("byte range is a valid header");
+                request.headers_mut().insert(RANGE, range);
+            }
+            let mut response = tokio::select! {
+                response = client.http_client().execute(request) => response?.error_for_status()?,
+                changed = control.changed() => {
+                    changed.map_err(|_| DownloadErr::Cancelled)?;
+                    continue 'download;
+                }
+            };
+            if progress.received > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+                trace!("{url} doesn't support ranges, starting over");
+                file = tokio::fs::File::create(&partial).await?;
+                progress.received = 0;
+            }
+            progress.total = response.content_length().map(|len| len + progress.received);
+            on_progress(progress);
+
+            loop {
+                tokio::select! {
+                    chunk = response.chunk() => match chunk? {
+                        Some(chunk) => {
+                            file.write_all(&chunk).await?;
+                            progress.received += chunk.len() as u64;
+                            on_progress(progress);
+                        }
+                        None => break 'download,
+                    },
+                    changed = control.changed() => {
+                        changed.map_err(|_| DownloadErr::Cancelled)?;
+                        continue 'download;
+                    }
+                }
+            }
+        }
+        file.flush().await?;
+        drop(file);
+
+        match source {
+            MediaSource::Plain(_) => tokio::fs::rename(&partial, path).await?,
+            MediaSource::Encrypted(file) => {
+                let file = file.as_ref().clone();
+                let (ciphertext, plaintext) = (partial.clone(), path.to_owned());
+                tokio::task::spawn_blocking(move || decrypt_file(&ciphertext, &plaintext, &file))
+                    .await
+                    .map_err(std::io::Error::other)??;
+                tokio::fs::remove_file(&partial).await?;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    if res.is_err() {
+        for leftover in [&partial, path] {
+            if leftover.exists()
+                && let Err(err) = tokio::fs::remove_file(leftover).await
+            {
+                warn!("couldn't remove {} {err}", leftover.display());
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use matrix_sdk::crypto::AttachmentEncryptor;
+    use ruma::{
+        events::room::{EncryptedFileInit, MediaSource},
+        mxc_uri,
+    };
+    use tokio::sync::watch;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path},
+    };
+
+    use super::{DownloadErr, DownloadProgress, DownloadState, download_file, reserve_path};
+    use crate::matrix_attachment::AttachmentErr;
+    use crate::test_support::logged_in_client;
+
+    fn download_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("swift-wind-download-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reserved_paths() {
+        let dir = download_dir("reserve");
+        assert_eq!(reserve_path(&dir, "cat.png").unwrap(), dir.join("cat.png"));
+        assert!(dir.join("cat.png").exists());
+        // A second download of the same name before the first one finished
+        assert_eq!(
+            reserve_path(&dir, "cat.png").unwrap(),
+            dir.join("cat (1).png")
+        );
+        std::fs::write(dir.join("dog.png.part"), b"").unwrap();
+        assert_eq!(
+            reserve_path(&dir, "dog.png").unwrap(),
+            dir.join("dog (1).png")
+        );
+        assert_eq!(
+            reserve_path(&dir, "../../etc/passwd").unwrap(),
+            dir.join("passwd")
+        );
+        assert_eq!(reserve_path(&dir, "..").unwrap(), dir.join("download"));
+        std::fs::write(dir.join(".bashrc"), b"").unwrap();
+        assert_eq!(
+            reserve_path(&dir, ".bashrc").unwrap(),
+            dir.join(".bashrc (1)")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn pause_and_resume() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/notes"))
+            .and(header("authorization", "Bearer access"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_bytes(b"meeting notes".to_vec())
+                    .set_delay(Duration::from_millis(200)),
+            )
+            .mount(&server)
+            .await;
+        let dir = download_dir("pause");
+        let target = dir.join("notes.txt");
+        let (control, receiver) = watch::channel(DownloadState::Running);
+        let progress = Arc::new(Mutex::new(DownloadProgress::default()));
+
+        let download = tokio::spawn({
+            let (client, target, progress) = (client.clone(), target.clone(), progress.clone());
+            async move {
+                let source = MediaSource::Plain(mxc_uri!("mxc://example.org/notes").to_owned());
+                download_file(&client, &source, &target, receiver, |update| {
+                    *progress.lock().unwrap() = update;
+                })
+                .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        control.send(DownloadState::Paused).unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*progress.lock().unwrap(), DownloadProgress::default());
+        control.send(DownloadState::Running).unwrap();
+
+        download.await.unwrap().unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"meeting notes");
+        assert_eq!(
+            *progress.lock().unwrap(),
+            DownloadProgress {
+                received: 13,
+                total: Some(13)
+            }
+        );
+        assert!(!dir.join("notes.txt.part").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn cancel_leaves_nothing() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/notes"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_bytes(b"meeting notes".to_vec())
+                    .set_delay(Duration::from_millis(200)),
+            )
+            .mount(&server)
+            .await;
+        let dir = download_dir("cancel");
+        let target = dir.join("notes.txt");
+        let (control, receiver) = watch::channel(DownloadState::Running);
+
+        let download = tokio::spawn({
+            let (client, target) = (client.clone(), target.clone());
+            async move {
+                let source = MediaSource::Plain(mxc_uri!("mxc://example.org/notes").to_owned());
+                download_file(&client, &source, &target, receiver, |_| {}).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        control.send(DownloadState::Cancelled).unwrap();
+
+        assert!(matches!(
+            download.await.unwrap(),
+            Err(DownloadErr::Cancelled)
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn failed_request_leaves_nothing() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        let dir = download_dir("invalid");
+        let target = reserve_path(&dir, "notes.txt").unwrap();
+        let (_control, receiver) = watch::channel(DownloadState::Running);
+
+        let source = MediaSource::Plain("not-an-mxc-uri".into());
+        assert!(matches!(
+            download_file(&client, &source, &target, receiver, |_| {}).await,
+            Err(DownloadErr::Media(_))
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn encrypted_download() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        let mut cursor = Cursor::new(b"quarterly report".to_vec());
+        let mut encryptor = AttachmentEncryptor::new(&mut cursor);
+        let mut ciphertext = Vec::new();
+        encryptor.read_to_end(&mut ciphertext).unwrap();
+        let info = encryptor.finish();
+        let source = MediaSource::Encrypted(Box::new(
+            EncryptedFileInit {
+                url: mxc_uri!("mxc://example.org/report").to_owned(),
+                key: info.key,
+                iv: info.iv,
+                hashes: info.hashes,
+                v: info.version,
+            }
+            .into(),
+        ));
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/report"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(ciphertext.clone()))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        let dir = download_dir("encrypted");
+        let target = dir.join("report.txt");
+
+        let (_control, receiver) = watch::channel(DownloadState::Running);
+        download_file(&client, &source, &target, receiver, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"quarterly report");
+        std::fs::remove_file(&target).unwrap();
+
+        ciphertext[0] ^= 1;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/report"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(ciphertext))
+            .mount(&server)
+            .await;
+        let (_control, receiver) = watch::channel(DownloadState::Running);
+        assert!(matches!(
+            download_file(&client, &source, &target, receiver, |_| {}).await,
+            Err(DownloadErr::Attachment(AttachmentErr::Verify(_)))
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use std::f32::consts::PI;
+use std::io::Cursor;
+use std::time::Duration;
+
+use image::{
+    AnimationDecoder, ImageError, ImageFormat, RgbImage, codecs::gif::GifDecoder,
+    codecs::webp::WebPDecoder,
+};
+use thiserror::Error;
+
+const BASE83: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+#[derive(Debug, Error)]
+pub enum ImageErr {
+    #[error("invalid blurhash: {0}")]
+    Blurhash(&'static str),
+
+    #[error("couldn't decode the image: {0}")]
+    Decode(#[from] ImageError),
+}
+
+/// Frames asking for less than this play at [`DEFAULT_FRAME_DELAY`], like browsers do.
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// One frame of a GIF or WebP animation, re-encoded as PNG for the renderer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationFrame {
+    pub png: Vec<u8>,
+    pub delay: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedImage {
+    Still(Vec<u8>),
+    Animated(Vec<AnimationFrame>),
+}
+
+/// Keeps still images as they are, animations are split into frames.
+pub fn decode_image(data: Vec<u8>) -> Result<DecodedImage, ImageErr> {
+    Ok(match animation_frames(&data)? {
+        Some(frames) => DecodedImage::Animated(frames),
+        None => DecodedImage::Still(data),
+    })
+}
+
+/// Largest size inside the box that keeps the aspect ratio, images are never scaled up.
+///
+/// Without known dimensions the whole box is used.
+pub fn fit_size(dimensions: Option<(u32, u32)>, max_width: u32, max_height: u32) -> (u32, u32) {
+    let Some((width, height)) = dimensions.filter(|(width, height)| *width > 0 && *height > 0)
+    else {
+        return (max_width, max_height);
+    };
+    let scale = (max_width as f32 / width as f32)
+        .min(max_height as f32 / height as f32)
+        .min(1.0);
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+fn decode83(chars: &[u8]) -> Result<u32, ImageErr> {
+    chars.iter().try_fold(0, |value, char| {
+        let digit = BASE83
+            .iter()
+            .position(|digit| digit == char)
+            .ok_or(ImageErr::Blurhash("unexpected character"))?;
+        Ok(value * 83 + digit as u32)
+    })
+}
+
+fn srgb_to_linear(value: u32) -> f32 {
+    let value = (value & 255) as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u8
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    value.abs().powf(exp).copysign(value)
+}
+
+/// Renders an `xyz.amorgan.blurhash` placeholder as a PNG of the given size.
+pub fn blurhash_png(hash: &str, width: u32, height: u32) -> Result<Vec<u8>, ImageErr> {
+    let hash = hash.as_bytes();
+    if hash.len() < 6 {
+        return Err(ImageErr::Blurhash("too short"));
+    }
+    let size_flag = decode83(&hash[..1])?;
+    let (components_x, components_y) = (size_flag % 9 + 1, size_flag / 9 + 1);
+    if hash.len() != 4 + 2 * (components_x * components_y) as usize {
+        return Err(ImageErr::Blurhash("length doesn't match the components"));
+    }
+    let max_value = (decode83(&hash[1..2])? + 1) as f32 / 166.0;
+
+    let dc = decode83(&hash[2..6])?;
+    let mut colors = vec![[
+        srgb_to_linear(dc >> 16),
+        srgb_to_linear(dc >> 8),
+        srgb_to_linear(dc),
+    ]];
+    for ac in hash[6..].chunks(2) {
+        let ac = decode83(ac)?;
+        let quantised = [ac / (19 * 19), (ac / 19) % 19, ac % 19];
+        colors.push(quantised.map(|value| sign_pow((value as f32 - 9.0) / 9.0, 2.0) * max_value));
+    }
+
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        let mut pixel = [0.0; 3];
+        for j in 0..components_y {
+            for i in 0..components_x {
+                let basis = (PI * x as f32 * i as f32 / width as f32).cos()
+                    * (PI * y as f32 * j as f32 / height as f32).cos();
+                let color = colors[(i + j * components_x) as usize];
+                for channel in 0..3 {
+                    pixel[channel] += color[channel] * basis;
+                }
+            }
+        }
+        image::Rgb(pixel.map(linear_to_srgb))
+    });
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Splits an animated GIF or WebP into frames, `None` for still images and other formats.
+pub fn animation_frames(data: &[u8]) -> Result<Option<Vec<AnimationFrame>>, ImageErr> {
+    let frames = match image::guess_format(data) {
+        Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(data))?
+            .into_frames()
+            .collect_frames()?,
+        Ok(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(data))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames().collect_frames()?
+        }
+        _ => return Ok(None),
+    };
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    frames
+        .into_iter()
+        .map(|frame| {
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let delay = Duration::from_millis(u64::from(numerator / denominator.max(1)));
+            let delay = if delay < MIN_FRAME_DELAY {
+                DEFAULT_FRAME_DELAY
+            } else {
+                delay
+            };
+            let mut png = Vec::new();
+            frame
+                .into_buffer()
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+            Ok(AnimationFrame { png, delay })
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use image::{Delay, Frame, ImageFormat, RgbaImage, codecs::gif::GifEncoder, load_from_memory};
+
+    use super::{DecodedImage, ImageErr, animation_frames, blurhash_png, decode_image, fit_size};
+
+    #[test]
+    fn fitting() {
+        assert_eq!(fit_size(Some((1600, 900)), 320, 240), (320, 180));
+        assert_eq!(fit_size(Some((900, 1600)), 320, 240), (135, 240));
+        assert_eq!(fit_size(Some((100, 50)), 320, 240), (100, 50));
+        assert_eq!(fit_size(None, 320, 240), (320, 240));
+        assert_eq!(fit_size(Some((0, 50)), 320, 240), (320, 240));
+    }
+
+    #[test]
+    fn blurhash() {
+        // Only the DC component, plain red
+        let png = blurhash_png("00TI:j", 4, 3).unwrap();
+        let image = load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (4, 3));
+        assert!(image.pixels().all(|pixel| pixel.0 == [255, 0, 0]));
+
+        let png = blurhash_png("LEHV6nWB2yk8pyo0adR*.7kCMdnj", 32, 32).unwrap();
+        assert_eq!(
+            load_from_memory(&png).unwrap().to_rgb8().dimensions(),
+            (32, 32)
+        );
+
+        assert!(matches!(
+            blurhash_png("LEHV6nWB2yk8", 32, 32),
+            Err(ImageErr::Blurhash(_))
+        ));
+        assert!(matches!(
+            blurhash_png("00TI\"j", 32, 32),
+            Err(ImageErr::Blurhash(_))
+        ));
+    }
+
+    #[test]
+    fn gif_frames() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for (shade, delay) in [(0, 250), (255, 0)] {
+                let frame = Frame::from_parts(
+                    RgbaImage::from_pixel(2, 2, image::Rgba([shade, shade, shade, 255])),
+                    0,
+                    0,
+                    Delay::from_saturating_duration(Duration::from_millis(delay)),
+                );
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        let frames = animation_frames(&gif).unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].delay, Duration::from_millis(250));
+        // Zero delays play at the browsers' default rather than as fast as possible
+        assert_eq!(frames[1].delay, Duration::from_millis(100));
+        assert_eq!(
+            image::guess_format(&frames[1].png).unwrap(),
+            ImageFormat::Png
+        );
+
+        let mut png = Vec::new();
+        RgbaImage::new(2, 2)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert_eq!(animation_frames(&png).unwrap(), None);
+        assert_eq!(decode_image(png.clone()).unwrap(), DecodedImage::Still(png));
+        assert!(
+            matches!(decode_image(gif).unwrap(), DecodedImage::Animated(frames) if frames.len() == 2)
+        );
+    }
+}
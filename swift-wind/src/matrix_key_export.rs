@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use futures::StreamExt;
+use matrix_sdk::{Client, encryption::RoomKeyImportError};
+use thiserror::Error;
+use tracing::trace;
+
+#[derive(Debug, Error)]
+pub enum KeyExportErr {
+    #[error("the passphrase can't be empty")]
+    EmptyPassphrase,
+
+    #[error("couldn't export the room keys: {0}")]
+    Export(#[source] matrix_sdk::Error),
+
+    #[error("couldn't import the room keys, check the file and passphrase: {0}")]
+    Import(#[source] RoomKeyImportError),
+}
+
+/// How many sessions of a key file made it into the crypto store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyImport {
+    pub imported: usize,
+    pub total: usize,
+}
+
+/// Writes every room key in the crypto store to a passphrase-encrypted megolm key export,
+/// returns how many sessions it holds.
+pub async fn export_keys(
+    client: &Client,
+    path: PathBuf,
+    passphrase: &str,
+) -> Result<usize, KeyExportErr> {
+    if passphrase.is_empty() {
+        return Err(KeyExportErr::EmptyPassphrase);
+    }
+    let mut exported = 0;
+    client
+        .encryption()
+        .export_room_keys(path, passphrase, |_| {
+            exported += 1;
+            true
+        })
+        .await
+        .map_err(KeyExportErr::Export)?;
+    trace!("exported {exported} room keys");
+    Ok(exported)
+}
+
+/// Reads a megolm key export like the ones Element writes, reporting the sessions stored so far.
+///
+/// Sessions the store already has in a better version are counted in the total only.
+pub async fn import_keys(
+    client: &Client,
+    path: PathBuf,
+    passphrase: &str,
+    mut on_progress: impl FnMut(usize),
+) -> Result<KeyImport, KeyExportErr> {
+    let encryption = client.encryption();
+    let received = encryption.room_keys_received_stream().await;
+    let import = encryption.import_room_keys(path, passphrase);
+    let report = async {
+        if let Some(mut received) = received {
+            let mut imported = 0;
+            while let Some(keys) = received.next().await {
+                if let Ok(keys) = keys {
+                    imported += keys.len();
+                    on_progress(imported);
+                }
+            }
+        }
+        std::future::pending::<()>().await
+    };
+
+    let res = tokio::select! {
+        res = import => res,
+        () = report => unreachable!("reporting never finishes on its own"),
+    };
+    let res = res.map_err(KeyExportErr::Import)?;
+    trace!(
+        "imported {} of {} room keys",
+        res.imported_count, res.total_count
+    );
+    Ok(KeyImport {
+        imported: res.imported_count,
+        total: res.total_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::MockServer;
+
+    use super::{KeyExportErr, KeyImport, export_keys, import_keys};
+    use crate::test_support::logged_in_client;
+
+    #[test_log::test(tokio::test)]
+    async fn round_trip() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        let file_name = format!("swift-wind-keys-{}.txt", std::process::id());
+        let path = std::env::temp_dir().join(file_name);
+
+        assert!(matches!(
+            export_keys(&client, path.clone(), "").await,
+            Err(KeyExportErr::EmptyPassphrase)
+        ));
+        assert_eq!(
+            export_keys(&client, path.clone(), "hunter2").await.unwrap(),
+            0
+        );
+        let file = std::fs::read_to_string(&path).unwrap();
+        assert!(file.starts_with("-----BEGIN MEGOLM SESSION DATA-----"));
+
+        assert!(matches!(
+            import_keys(&client, path.clone(), "wrong", |_| {}).await,
+            Err(KeyExportErr::Import(_))
+        ));
+        assert_eq!(
+            import_keys(&client, path.clone(), "hunter2", |_| {})
+                .await
+                .unwrap(),
+            KeyImport::default()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::{io, net::Ipv4Addr, time::Duration};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{trace, warn};
+use url::Url;
+
+/// How long the browser has to come back from signing in.
+pub const REDIRECT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Biggest request head read from the browser, the redirect only needs the request line.
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+const SUCCESS_PAGE: &str = "<html><body><h1>Signed in</h1><p>You can close this window and go back to swift-wind.</p></body></html>";
+
+const FAILURE_PAGE: &str =
+    "<html><body><h1>Sign-in failed</h1><p>Go back to swift-wind and try again.</p></body></html>";
+
+#[derive(Debug, Error)]
+pub enum LoopbackErr {
+    #[error("couldn't listen for the sign-in redirect: {0}")]
+    Listen(#[source] io::Error),
+
+    #[error("sign-in wasn't finished within {} seconds", .0.as_secs())]
+    Timeout(Duration),
+}
+
+/// Short-lived HTTP listener on 127.0.0.1 the browser is sent back to after signing in.
+///
+/// The redirect URL has a random path, so other pages can't complete the sign-in
+/// with a token of their own by guessing the port.
+#[derive(Debug)]
+pub struct LoopbackListener {
+    listener: TcpListener,
+    redirect_url: Url,
+}
+
+impl LoopbackListener {
+    pub async fn bind() -> Result<Self, LoopbackErr> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(LoopbackErr::Listen)?;
+        let addr = listener.local_addr().map_err(LoopbackErr::Listen)?;
+        let secret = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>());
+        let redirect_url = Url::parse(&format!("http://{addr}/{secret}/"))
+            .expect("socket address is a valid URL host");
+        Ok(Self {
+            listener,
+            redirect_url,
+        })
+    }
+
+    /// URL the server has to send the browser back to.
+    pub fn redirect_url(&self) -> &Url {
+        &self.redirect_url
+    }
+
+    /// Waits for the browser to come back, `is_success` picks the page it is answered with.
+    pub async fn redirect<F>(self, timeout: Duration, is_success: F) -> Result<Url, LoopbackErr>
+    where
+        F: Fn(&Url) -> bool,
+    {
+        tokio::time::timeout(timeout, self.accept_redirect(is_success))
+            .await
+            .map_err(|_| LoopbackErr::Timeout(timeout))?
+    }
+
+    async fn accept_redirect<F>(&self, is_success: F) -> Result<Url, LoopbackErr>
+    where
+        F: Fn(&Url) -> bool,
+    {
+        loop {
+            let (mut stream, addr) = self.listener.accept().await.map_err(LoopbackErr::Listen)?;
+            trace!("loopback listener got a connection from {addr}");
+            let redirect = match read_redirect(&mut stream, &self.redirect_url).await {
+                Ok(redirect) => redirect,
+                Err(err) => {
+                    warn!("failed to read sign-in redirect {:?}", err);
+                    continue;
+                }
+            };
+            let (status, page) = match &redirect {
+                Some(url) if is_success(url) => ("200 OK", SUCCESS_PAGE),
+                Some(_) => ("400 Bad Request", FAILURE_PAGE),
+                None => ("404 Not Found", ""),
+            };
+            if let Err(err) = respond(&mut stream, status, page).await {
+                warn!("failed to answer sign-in redirect {:?}", err);
+            }
+            if let Some(url) = redirect {
+                return Ok(url);
+            }
+        }
+    }
+}
+
+async fn read_redirect(stream: &mut TcpStream, base: &Url) -> io::Result<Option<Url>> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too long",
+            ));
+        }
+    }
+    Ok(parse_redirect(&String::from_utf8_lossy(&head), base))
+}
+
+/// The redirect URL the browser asked for, `None` for anything else like a favicon
+/// or a request that doesn't know the secret path.
+fn parse_redirect(head: &str, base: &Url) -> Option<Url> {
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (Some("GET"), Some(target)) = (request_line.next(), request_line.next()) else {
+        return None;
+    };
+    base.join(target)
+        .ok()
+        .filter(|url| url.path() == base.path())
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, page: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\ncontent-type: text/html; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{page}",
+        page.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::parse_redirect;
+
+    #[test]
+    fn redirect_request() {
+        let base = Url::parse("http://127.0.0.1:4000/secret/").unwrap();
+        let url = parse_redirect(
+            "GET /secret/?loginToken=abc%2B1 HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+            &base,
+        )
+        .unwrap();
+        assert_eq!(url.query(), Some("loginToken=abc%2B1"));
+
+        assert!(parse_redirect("GET /?loginToken=forged HTTP/1.1\r\n\r\n", &base).is_none());
+        assert!(parse_redirect("GET /secret HTTP/1.1\r\n\r\n", &base).is_none());
+        assert!(parse_redirect("GET /favicon.ico HTTP/1.1\r\n\r\n", &base).is_none());
+        assert!(parse_redirect("POST /secret/ HTTP/1.1\r\n\r\n", &base).is_none());
+        assert!(parse_redirect("", &base).is_none());
+    }
+}
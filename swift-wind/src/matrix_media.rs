@@ -0,0 +1,339 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use matrix_sdk::{
+    Client,
+    media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings},
+};
+use ruma::{
+    MxcUri, UInt,
+    api::{
+        MatrixVersion, OutgoingRequest, SendAccessToken,
+        client::{
+            authenticated_media, media::get_content, media::get_content_thumbnail::v3::Method,
+        },
+        error::IntoHttpError,
+    },
+    events::room::MediaSource,
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{trace, warn};
+
+use crate::matrix_store::data_dir;
+
+/// Disk space the media cache may take before the least recently used files go.
+pub const MEDIA_CACHE_BUDGET: u64 = 256 * 1024 * 1024;
+
+const AUTHENTICATED_MEDIA_STABLE_FEATURE: &str = "org.matrix.msc3916.stable";
+
+#[derive(Debug, Error)]
+pub enum MediaErr {
+    #[error("not a valid mxc URI: {0}")]
+    InvalidUri(String),
+
+    #[error("couldn't download the media: {0}")]
+    Download(#[source] matrix_sdk::Error),
+
+    #[error("couldn't check what the homeserver supports: {0}")]
+    Server(#[source] Box<matrix_sdk::HttpError>),
+
+    #[error("couldn't build the media request: {0}")]
+    Request(#[from] IntoHttpError),
+
+    #[error("couldn't build the media request: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaSize {
+    /// Scaled down by the server, cropped to fill the box.
+    Thumbnail {
+        width: u32,
+        height: u32,
+    },
+    Full,
+}
+
+/// Unencrypted media on disk, evicted least recently used first once over budget.
+///
+/// Decrypted attachments never go here, see [`crate::matrix_attachment`].
+#[derive(Debug)]
+pub struct MediaCache {
+    dir: PathBuf,
+    budget: u64,
+    eviction: Mutex<()>,
+}
+
+impl MediaCache {
+    pub fn new(dir: impl Into<PathBuf>, budget: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            budget,
+            eviction: Mutex::new(()),
+        }
+    }
+
+    /// The cache every account shares, under the data directory.
+    ///
+    /// [`fetch_media`] keys it by account, so one account never gets media another fetched.
+    pub fn shared() -> &'static MediaCache {
+        static SHARED: OnceLock<MediaCache> = OnceLock::new();
+        SHARED.get_or_init(|| MediaCache::new(data_dir().join("media"), MEDIA_CACHE_BUDGET))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes())))
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(key);
+        let data = tokio::fs::read(&path).await.ok()?;
+        // The modification time doubles as the last use for eviction
+        let touched = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || {
+                std::fs::File::options()
+                    .write(true)
+                    .open(&path)?
+                    .set_modified(SystemTime::now())
+            }
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .flatten();
+        if let Err(err) = touched {
+            warn!("couldn't mark {} as used {err}", path.display());
+        }
+        Some(data)
+    }
+
+    pub async fn put(&self, key: &str, data: &[u8]) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.path(key), data).await?;
+        self.evict().await
+    }
+
+    async fn evict(&self) -> std::io::Result<()> {
+        let _guard = self.eviction.lock().await;
+        let mut files = Vec::new();
+        let mut total = 0;
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            total += metadata.len();
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+        files.sort();
+        for (_, len, path) in files {
+            if total <= self.budget {
+                break;
+            }
+            trace!("evicting {} from the media cache", path.display());
+            tokio::fs::remove_file(&path).await?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Resolves an `mxc` URI through the homeserver, authenticated media when it supports it,
+/// going through the disk cache first.
+pub async fn fetch_media(
+    client: &Client,
+    cache: &MediaCache,
+    uri: &MxcUri,
+    size: MediaSize,
+) -> Result<Vec<u8>, MediaErr> {
+    if !uri.is_valid() {
+        return Err(MediaErr::InvalidUri(uri.to_string()));
+    }
+    let user_id = client
+        .user_id()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let key = match size {
+        MediaSize::Thumbnail { width, height } => format!("{user_id} {uri}?{width}x{height}"),
+        MediaSize::Full => format!("{user_id} {uri}"),
+    };
+    if let Some(data) = cache.get(&key).await {
+        return Ok(data);
+    }
+
+    let format = match size {
+        MediaSize::Thumbnail { width, height } => {
+            MediaFormat::Thumbnail(MediaThumbnailSettings::with_method(
+                Method::Crop,
+                UInt::from(width),
+                UInt::from(height),
+            ))
+        }
+        MediaSize::Full => MediaFormat::File,
+    };
+    let request = MediaRequestParameters {
+        source: MediaSource::Plain(uri.to_owned()),
+        format,
+    };
+    let data = client
+        .media()
+        .get_media_content(&request, false)
+        .await
+        .map_err(MediaErr::Download)?;
+    if let Err(err) = cache.put(&key, &data).await {
+        warn!("couldn't cache {key} {err}");
+    }
+    Ok(data)
+}
+
+/// Request for the whole file behind `uri`, on the endpoint [`Client::media`] would use:
+/// authenticated media with the access token when the homeserver supports it.
+///
+/// For downloads that stream the response instead of holding it in memory.
+pub async fn media_download_request(
+    client: &Client,
+    uri: &MxcUri,
+) -> Result<reqwest::Request, MediaErr> {
+    let invalid_uri = |_| MediaErr::InvalidUri(uri.to_string());
+    let versions = client
+        .server_versions()
+        .await
+        .map_err(|err| MediaErr::Server(Box::new(err)))?;
+    let authenticated = versions.contains(&MatrixVersion::V1_11)
+        || client
+            .unstable_features()
+            .await
+            .map_err(|err| MediaErr::Server(Box::new(err)))?
+            .get(AUTHENTICATED_MEDIA_STABLE_FEATURE)
+            .is_some_and(|supported| *supported);
+
+    let homeserver = client.homeserver();
+    let request = if authenticated {
+        let access_token = client.access_token();
+        let access_token = match &access_token {
+            Some(token) => SendAccessToken::IfRequired(token),
+            None => SendAccessToken::None,
+        };
+        authenticated_media::get_content::v1::Request::from_uri(uri)
+            .map_err(invalid_uri)?
+            .try_into_http_request::<Vec<u8>>(
+                homeserver.as_str(),
+                access_token,
+                &[MatrixVersion::V1_11],
+            )?
+    } else {
+        #[allow(deprecated)]
+        get_content::v3::Request::from_url(uri)
+            .map_err(invalid_uri)?
+            .try_into_http_request::<Vec<u8>>(
+                homeserver.as_str(),
+                SendAccessToken::None,
+                &versions,
+            )?
+    };
+    Ok(reqwest::Request::try_from(request)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use ruma::{mxc_uri, user_id};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path},
+    };
+
+    use super::{MediaCache, MediaErr, MediaSize, fetch_media};
+    use crate::test_support::{logged_in_client, logged_in_client_as};
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("swift-wind-media-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn authenticated_thumbnail_is_cached() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/_matrix/client/v1/media/thumbnail/example.org/avatar",
+            ))
+            .and(header("authorization", "Bearer access"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"png".to_vec()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let dir = cache_dir("thumbnail");
+        let cache = MediaCache::new(&dir, 1024);
+        let size = MediaSize::Thumbnail {
+            width: 32,
+            height: 32,
+        };
+        let uri = mxc_uri!("mxc://example.org/avatar");
+
+        for _ in 0..2 {
+            assert_eq!(
+                fetch_media(&client, &cache, uri, size).await.unwrap(),
+                b"png"
+            );
+        }
+        assert!(matches!(
+            fetch_media(&client, &cache, "https://example.org/avatar".into(), size).await,
+            Err(MediaErr::InvalidUri(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn accounts_dont_share_media() {
+        let server = MockServer::start().await;
+        let alice = logged_in_client(&server).await;
+        let bob = logged_in_client_as(&server, user_id!("@bob:example.org")).await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v1/media/download/example.org/photo"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpg".to_vec()))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let dir = cache_dir("accounts");
+        let cache = MediaCache::new(&dir, 1024);
+        let uri = mxc_uri!("mxc://example.org/photo");
+
+        for client in [&alice, &bob, &alice] {
+            assert_eq!(
+                fetch_media(client, &cache, uri, MediaSize::Full)
+                    .await
+                    .unwrap(),
+                b"jpg"
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn least_recently_used_is_evicted() {
+        let dir = cache_dir("eviction");
+        let cache = MediaCache::new(&dir, 10);
+
+        cache.put("a", b"aaaa").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        cache.put("b", b"bbbb").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(cache.get("a").await.is_some());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        cache.put("c", b"cccc").await.unwrap();
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,709 @@
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use matrix_sdk::{
+    Client, SessionMeta,
+    authentication::matrix::{MatrixSession, MatrixSessionTokens},
+};
+use rand::{Rng, distr::Alphanumeric};
+use reqwest::{StatusCode, header::CONTENT_TYPE};
+use ruma::{DeviceId, OwnedDeviceId, OwnedUserId};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::trace;
+use url::Url;
+
+use crate::matrix_discovery::join_base;
+use crate::matrix_loopback::{LoopbackErr, LoopbackListener, REDIRECT_TIMEOUT};
+
+const CLIENT_NAME: &str = "swift-wind";
+const CLIENT_URI: &str = "https://github.com/hey-adora/swift-wind";
+
+const API_SCOPE: &str = "urn:matrix:org.matrix.msc2967.client:api:*";
+const DEVICE_SCOPE: &str = "urn:matrix:org.matrix.msc2967.client:device:";
+
+/// Access tokens are refreshed this long before they expire.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+const AUTH_METADATA_PATH: &str = "_matrix/client/unstable/org.matrix.msc2965/auth_metadata";
+const AUTH_ISSUER_PATH: &str = "_matrix/client/unstable/org.matrix.msc2965/auth_issuer";
+const WHOAMI_PATH: &str = "_matrix/client/v3/account/whoami";
+
+#[derive(Debug, Error)]
+pub enum OidcErr {
+    #[error("couldn't reach {url}: {source}")]
+    Unreachable {
+        url: Url,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("{url} answered with {status}: {body}")]
+    Status {
+        url: Url,
+        status: StatusCode,
+        body: String,
+    },
+
+    #[error("{url} sent an invalid response: {source}")]
+    InvalidResponse {
+        url: Url,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("the authentication server doesn't let new apps register")]
+    NoRegistration,
+
+    #[error("the authentication server doesn't support PKCE with S256")]
+    NoPkce,
+
+    #[error(transparent)]
+    Loopback(#[from] LoopbackErr),
+
+    #[error("couldn't open the browser: {0}")]
+    Browser(#[source] io::Error),
+
+    #[error("sign-in was denied: {0}")]
+    Denied(String),
+
+    #[error("the authentication server redirected back with an unexpected response")]
+    InvalidRedirect,
+
+    #[error("the session can't be refreshed, sign in again")]
+    NoRefreshToken,
+
+    #[error("couldn't start the session: {0}")]
+    Session(#[source] Box<matrix_sdk::Error>),
+}
+
+/// Authorization server metadata of a homeserver delegating auth (MSC2965).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthMetadata {
+    pub issuer: Url,
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    #[serde(default)]
+    pub registration_endpoint: Option<Url>,
+    #[serde(default)]
+    pub revocation_endpoint: Option<Url>,
+    #[serde(default)]
+    pub code_challenge_methods_supported: Vec<String>,
+    #[serde(default)]
+    pub account_management_uri: Option<Url>,
+    #[serde(default)]
+    pub account_management_actions_supported: Vec<String>,
+}
+
+/// What's needed on top of the Matrix session to refresh and end an OIDC login.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcSession {
+    pub metadata: AuthMetadata,
+    pub client_id: String,
+    pub refresh_token: Option<String>,
+    /// When the access token expires, `None` if the server didn't say.
+    #[serde(default)]
+    pub expires_at: Option<SystemTime>,
+}
+
+/// Page of the account management web UI to deep link into (MSC4191).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountManagementAction {
+    Profile,
+    SessionsList,
+    SessionView(OwnedDeviceId),
+    SessionEnd(OwnedDeviceId),
+    CrossSigningReset,
+}
+
+impl AccountManagementAction {
+    fn action(&self) -> &'static str {
+        match self {
+            Self::Profile => "org.matrix.profile",
+            Self::SessionsList => "org.matrix.sessions_list",
+            Self::SessionView(_) => "org.matrix.session_view",
+            Self::SessionEnd(_) => "org.matrix.session_end",
+            Self::CrossSigningReset => "org.matrix.cross_signing_reset",
+        }
+    }
+
+    fn device_id(&self) -> Option<&DeviceId> {
+        match self {
+            Self::SessionView(device_id) | Self::SessionEnd(device_id) => Some(device_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthIssuer {
+    issuer: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientRegistration {
+    client_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+impl TokenResponse {
+    fn expires_at(&self) -> Option<SystemTime> {
+        SystemTime::now().checked_add(Duration::from_secs(self.expires_in?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Whoami {
+    user_id: OwnedUserId,
+    #[serde(default)]
+    device_id: Option<OwnedDeviceId>,
+}
+
+/// Verifier and challenge of a PKCE authorization (RFC 7636).
+#[derive(Debug)]
+struct Pkce {
+    verifier: String,
+    challenge: String,
+}
+
+impl Pkce {
+    fn new() -> Self {
+        let verifier = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        let challenge = code_challenge(&verifier);
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Auth metadata of a homeserver, `None` when it handles logins itself.
+pub async fn discover_auth_metadata(homeserver: &Url) -> Result<Option<AuthMetadata>, OidcErr> {
+    let http = reqwest::Client::new();
+    let url = endpoint(homeserver, AUTH_METADATA_PATH);
+    match send_json(http.get(url.clone()), &url).await {
+        Ok(metadata) => return Ok(Some(metadata)),
+        Err(OidcErr::Status { status, .. }) if unsupported_endpoint(status) => {}
+        Err(err) => return Err(err),
+    }
+
+    // Older servers only name the issuer, which publishes its own metadata.
+    let url = endpoint(homeserver, AUTH_ISSUER_PATH);
+    let issuer: AuthIssuer = match send_json(http.get(url.clone()), &url).await {
+        Ok(issuer) => issuer,
+        Err(OidcErr::Status { status, .. }) if unsupported_endpoint(status) => {
+            trace!("{homeserver} doesn't delegate auth");
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let url = endpoint(&issuer.issuer, ".well-known/openid-configuration");
+    send_json(http.get(url.clone()), &url).await.map(Some)
+}
+
+/// Registers swift-wind, signs in through the browser and logs the client in with the tokens.
+pub async fn login_oidc<F>(
+    client: &Client,
+    metadata: AuthMetadata,
+    open_browser: F,
+) -> Result<OidcSession, OidcErr>
+where
+    F: FnOnce(&str) -> io::Result<()>,
+{
+    let supports_s256 = metadata.code_challenge_methods_supported.is_empty()
+        || metadata
+            .code_challenge_methods_supported
+            .iter()
+            .any(|method| method == "S256");
+    if !supports_s256 {
+        return Err(OidcErr::NoPkce);
+    }
+
+    let listener = LoopbackListener::bind().await?;
+    let redirect_uri = listener.redirect_url().clone();
+    let client_id = register_client(&metadata, &redirect_uri).await?;
+    let device_id = generate_device_id();
+    let pkce = Pkce::new();
+    let state = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>());
+
+    let mut auth_url = metadata.authorization_endpoint.clone();
+    auth_url
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &client_id)
+        .append_pair("redirect_uri", redirect_uri.as_str())
+        .append_pair(
+            "scope",
+            &format!("openid {API_SCOPE} {DEVICE_SCOPE}{device_id}"),
+        )
+        .append_pair("state", &state)
+        .append_pair("code_challenge", &pkce.challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    trace!("opening {auth_url}");
+    open_browser(auth_url.as_str()).map_err(OidcErr::Browser)?;
+    let redirect = listener
+        .redirect(REDIRECT_TIMEOUT, |url| query_param(url, "code").is_some())
+        .await?;
+    let code = authorization_code(&redirect, &state)?;
+
+    trace!("exchanging authorization code");
+    let tokens = token_request(
+        &metadata.token_endpoint,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("client_id", &client_id),
+            ("code_verifier", &pkce.verifier),
+        ],
+    )
+    .await?;
+
+    let expires_at = tokens.expires_at();
+    let whoami = whoami(&client.homeserver(), &tokens.access_token).await?;
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: whoami.user_id,
+            device_id: whoami.device_id.unwrap_or(device_id),
+        },
+        // The Matrix refresh endpoint doesn't know OIDC tokens, refreshing goes through `OidcSession`.
+        tokens: MatrixSessionTokens {
+            access_token: tokens.access_token,
+            refresh_token: None,
+        },
+    };
+    client
+        .matrix_auth()
+        .restore_session(session)
+        .await
+        .map_err(|err| OidcErr::Session(Box::new(err)))?;
+
+    Ok(OidcSession {
+        metadata,
+        client_id,
+        refresh_token: tokens.refresh_token,
+        expires_at,
+    })
+}
+
+impl OidcSession {
+    /// Trades the refresh token for a new access token, keeping the refresh token if it rotated.
+    pub async fn refresh(&mut self) -> Result<String, OidcErr> {
+        let refresh_token = self
+            .refresh_token
+            .as_deref()
+            .ok_or(OidcErr::NoRefreshToken)?;
+        trace!("refreshing oidc access token");
+        let tokens = token_request(
+            &self.metadata.token_endpoint,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                ("client_id", &self.client_id),
+            ],
+        )
+        .await?;
+        self.expires_at = tokens.expires_at();
+        if let Some(refresh_token) = tokens.refresh_token {
+            self.refresh_token = Some(refresh_token);
+        }
+        Ok(tokens.access_token)
+    }
+
+    /// Time left until the access token should be refreshed, `None` when its expiry is unknown.
+    pub fn refresh_in(&self) -> Option<Duration> {
+        let refresh_at = self.expires_at?.checked_sub(REFRESH_MARGIN)?;
+        Some(
+            refresh_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Ends the session at the authentication server, revoking the refresh token ends it fully.
+    pub async fn revoke(&self, access_token: &str) -> Result<(), OidcErr> {
+        let Some(url) = &self.metadata.revocation_endpoint else {
+            trace!("authentication server has no revocation endpoint");
+            return Ok(());
+        };
+        let (token, hint) = match &self.refresh_token {
+            Some(refresh_token) => (refresh_token.as_str(), "refresh_token"),
+            None => (access_token, "access_token"),
+        };
+        let res = reqwest::Client::new()
+            .post(url.clone())
+            .form(&[
+                ("token", token),
+                ("token_type_hint", hint),
+                ("client_id", &self.client_id),
+            ])
+            .send()
+            .await
+            .map_err(|source| OidcErr::Unreachable {
+                url: url.clone(),
+                source,
+            })?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(OidcErr::Status {
+                url: url.clone(),
+                status,
+                body,
+            });
+        }
+        Ok(())
+    }
+
+    /// Link into the account management web UI, its front page when the action isn't supported.
+    pub fn account_management_url(&self, action: &AccountManagementAction) -> Option<Url> {
+        let mut url = self.metadata.account_management_uri.clone()?;
+        let supported = &self.metadata.account_management_actions_supported;
+        if !supported.is_empty() && !supported.iter().any(|name| name == action.action()) {
+            return Some(url);
+        }
+        let mut query = url.query_pairs_mut();
+        query.append_pair("action", action.action());
+        if let Some(device_id) = action.device_id() {
+            query.append_pair("device_id", device_id.as_str());
+        }
+        drop(query);
+        Some(url)
+    }
+}
+
+async fn register_client(metadata: &AuthMetadata, redirect_uri: &Url) -> Result<String, OidcErr> {
+    let url = metadata
+        .registration_endpoint
+        .clone()
+        .ok_or(OidcErr::NoRegistration)?;
+    trace!("registering client at {url}");
+    let body = json!({
+        "client_name": CLIENT_NAME,
+        "client_uri": CLIENT_URI,
+        "application_type": "native",
+        "redirect_uris": [redirect_uri],
+        "response_types": ["code"],
+        "grant_types": ["authorization_code", "refresh_token"],
+        "token_endpoint_auth_method": "none",
+    });
+    let request = reqwest::Client::new()
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string());
+    let registration: ClientRegistration = send_json(request, &url).await?;
+    Ok(registration.client_id)
+}
+
+async fn token_request(url: &Url, form: &[(&str, &str)]) -> Result<TokenResponse, OidcErr> {
+    let request = reqwest::Client::new().post(url.clone()).form(form);
+    send_json(request, url).await
+}
+
+async fn whoami(homeserver: &Url, access_token: &str) -> Result<Whoami, OidcErr> {
+    let url = endpoint(homeserver, WHOAMI_PATH);
+    let request = reqwest::Client::new()
+        .get(url.clone())
+        .bearer_auth(access_token);
+    send_json(request, &url).await
+}
+
+fn authorization_code(redirect: &Url, state: &str) -> Result<String, OidcErr> {
+    if let Some(error) = query_param(redirect, "error") {
+        let description = query_param(redirect, "error_description").unwrap_or(error);
+        return Err(OidcErr::Denied(description));
+    }
+    if query_param(redirect, "state").as_deref() != Some(state) {
+        return Err(OidcErr::InvalidRedirect);
+    }
+    query_param(redirect, "code").ok_or(OidcErr::InvalidRedirect)
+}
+
+fn query_param(url: &Url, key: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn generate_device_id() -> OwnedDeviceId {
+    let device_id: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(10)
+        .map(|c| char::from(c).to_ascii_uppercase())
+        .collect();
+    device_id.into()
+}
+
+fn endpoint(base: &Url, path: &str) -> Url {
+    join_base(base, path).expect("endpoint path is a valid relative URL")
+}
+
+/// Unknown endpoints are answered with 404, or 400 by older servers.
+fn unsupported_endpoint(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST | StatusCode::METHOD_NOT_ALLOWED
+    )
+}
+
+async fn send_json<T: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    url: &Url,
+) -> Result<T, OidcErr> {
+    let res = request
+        .send()
+        .await
+        .map_err(|source| OidcErr::Unreachable {
+            url: url.clone(),
+            source,
+        })?;
+    let status = res.status();
+    let body = res.bytes().await.map_err(|source| OidcErr::Unreachable {
+        url: url.clone(),
+        source,
+    })?;
+    if !status.is_success() {
+        return Err(OidcErr::Status {
+            url: url.clone(),
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        });
+    }
+    serde_json::from_slice(&body).map_err(|source| OidcErr::InvalidResponse {
+        url: url.clone(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use matrix_sdk::Client;
+    use ruma::owned_device_id;
+    use serde_json::json;
+    use url::Url;
+    use wiremock::{
+        Mock, MockServer, Request, ResponseTemplate,
+        matchers::{body_string_contains, header, method, path},
+    };
+
+    use super::{
+        AccountManagementAction, AuthMetadata, OidcErr, OidcSession, authorization_code,
+        code_challenge, discover_auth_metadata, login_oidc,
+    };
+
+    fn metadata(issuer: &str) -> AuthMetadata {
+        serde_json::from_value(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}authorize"),
+            "token_endpoint": format!("{issuer}oauth2/token"),
+            "registration_endpoint": format!("{issuer}oauth2/registration"),
+            "revocation_endpoint": format!("{issuer}oauth2/revoke"),
+            "code_challenge_methods_supported": ["plain", "S256"],
+            "account_management_uri": format!("{issuer}account/"),
+            "account_management_actions_supported": ["org.matrix.profile", "org.matrix.session_view"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn pkce_challenge() {
+        // Example from RFC 7636 appendix B.
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn redirect_code() {
+        let redirect = Url::parse("http://127.0.0.1:4000/?code=abc&state=xyz").unwrap();
+        assert_eq!(authorization_code(&redirect, "xyz").unwrap(), "abc");
+        assert_matches!(
+            authorization_code(&redirect, "other"),
+            Err(OidcErr::InvalidRedirect)
+        );
+
+        let denied = Url::parse(
+            "http://127.0.0.1:4000/?error=access_denied&error_description=Nope&state=xyz",
+        )
+        .unwrap();
+        assert_matches!(authorization_code(&denied, "xyz"), Err(OidcErr::Denied(description)) if description == "Nope");
+    }
+
+    #[test]
+    fn account_management_links() {
+        let session = OidcSession {
+            metadata: metadata("https://auth.example.org/"),
+            client_id: "client".to_owned(),
+            refresh_token: None,
+            expires_at: None,
+        };
+        assert_eq!(
+            session
+                .account_management_url(&AccountManagementAction::SessionView(owned_device_id!(
+                    "DEVICE"
+                )))
+                .unwrap()
+                .as_str(),
+            "https://auth.example.org/account/?action=org.matrix.session_view&device_id=DEVICE"
+        );
+        assert_eq!(
+            session
+                .account_management_url(&AccountManagementAction::CrossSigningReset)
+                .unwrap()
+                .as_str(),
+            "https://auth.example.org/account/"
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn discovery() {
+        let server = MockServer::start().await;
+        let issuer = format!("{}/", server.uri());
+        Mock::given(method("GET"))
+            .and(path(
+                "/_matrix/client/unstable/org.matrix.msc2965/auth_issuer",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "issuer": issuer })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(metadata(&issuer)))
+            .mount(&server)
+            .await;
+
+        let homeserver = Url::parse(&server.uri()).unwrap();
+        let metadata = discover_auth_metadata(&homeserver).await.unwrap().unwrap();
+        assert_eq!(metadata.issuer.as_str(), issuer);
+
+        let plain = MockServer::start().await;
+        let homeserver = Url::parse(&plain.uri()).unwrap();
+        assert!(discover_auth_metadata(&homeserver).await.unwrap().is_none());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn login_with_mock_provider() {
+        let server = MockServer::start().await;
+        let issuer = format!("{}/", server.uri());
+        Mock::given(method("GET"))
+            .and(path(
+                "/_matrix/client/unstable/org.matrix.msc2965/auth_metadata",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(metadata(&issuer)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/oauth2/registration"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({
+                "client_id": "client",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // Stands in for the consent page: approves right away and sends the browser back.
+        Mock::given(method("GET"))
+            .and(path("/authorize"))
+            .respond_with(|req: &Request| {
+                let param = |key: &str| {
+                    req.url
+                        .query_pairs()
+                        .find(|(name, _)| name == key)
+                        .map(|(_, value)| value.into_owned())
+                        .unwrap()
+                };
+                assert_eq!(param("client_id"), "client");
+                assert_eq!(param("code_challenge_method"), "S256");
+                assert!(param("scope").contains("urn:matrix:org.matrix.msc2967.client:api:*"));
+                let mut redirect_url = Url::parse(&param("redirect_uri")).unwrap();
+                redirect_url
+                    .query_pairs_mut()
+                    .append_pair("code", "auth-code")
+                    .append_pair("state", &param("state"));
+                ResponseTemplate::new(302).insert_header("location", redirect_url.as_str())
+            })
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/oauth2/token"))
+            .and(body_string_contains("grant_type=authorization_code"))
+            .and(body_string_contains("code=auth-code"))
+            .and(body_string_contains("code_verifier="))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access",
+                "refresh_token": "refresh",
+                "token_type": "Bearer",
+                "expires_in": 300,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/oauth2/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .and(body_string_contains("refresh_token=refresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access2",
+                "refresh_token": "refresh2",
+                "token_type": "Bearer",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v3/account/whoami"))
+            .and(header("authorization", "Bearer access"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user_id": "@alice:example.org",
+            })))
+            .mount(&server)
+            .await;
+
+        let homeserver = Url::parse(&server.uri()).unwrap();
+        let metadata = discover_auth_metadata(&homeserver).await.unwrap().unwrap();
+        let client = Client::builder()
+            .homeserver_url(homeserver)
+            .build()
+            .await
+            .unwrap();
+        let mut session = login_oidc(&client, metadata, |url| {
+            let url = url.to_owned();
+            tokio::spawn(async move {
+                let page = reqwest::get(url).await.unwrap().text().await.unwrap();
+                assert!(page.contains("Signed in"));
+            });
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(session.client_id, "client");
+        assert_matches!(client.user_id(), Some(user_id) if user_id == "@alice:example.org");
+        assert_eq!(client.access_token().as_deref(), Some("access"));
+        let refresh_in = session.refresh_in().unwrap();
+        assert!(refresh_in > Duration::from_secs(200) && refresh_in <= Duration::from_secs(240));
+
+        assert_eq!(session.refresh().await.unwrap(), "access2");
+        assert_eq!(session.refresh_token.as_deref(), Some("refresh2"));
+        assert_eq!(session.refresh_in(), None);
+    }
+}
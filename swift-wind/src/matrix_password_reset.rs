@@ -0,0 +1,209 @@
+use matrix_sdk::{Client, HttpError};
+use ruma::{
+    OwnedClientSecret, OwnedSessionId, UInt,
+    api::client::{
+        account::request_password_change_token_via_email,
+        error::ErrorKind,
+        uiaa::{AuthData, AuthType},
+    },
+};
+use thiserror::Error;
+use tracing::trace;
+
+use crate::matrix_uiaa::{UiaaErr, UiaaOperation, UiaaStep, threepid_auth_data};
+
+/// Asks the homeserver to email a password reset link, `send_attempt` has to grow to send it again.
+pub async fn request_reset_email(
+    client: &Client,
+    email: &str,
+    client_secret: OwnedClientSecret,
+    send_attempt: u32,
+) -> Result<OwnedSessionId, HttpError> {
+    trace!("requesting password reset email");
+    let request = request_password_change_token_via_email::v3::Request::new(
+        client_secret,
+        email.trim().to_owned(),
+        UInt::from(send_attempt),
+    );
+    Ok(client.send(request).await?.sid)
+}
+
+#[derive(Debug, Error)]
+pub enum PasswordResetErr {
+    #[error("the server doesn't allow resetting the password by email")]
+    EmailNotOffered,
+
+    #[error("the server refused the reset: {0}")]
+    Refused(String),
+
+    #[error(transparent)]
+    Uiaa(#[from] UiaaErr),
+}
+
+/// Password change authenticated by an emailed link, tried again until the user opened it.
+#[derive(Debug, Clone)]
+pub struct PasswordReset {
+    operation: UiaaOperation,
+    auth: AuthData,
+}
+
+impl PasswordReset {
+    pub fn new(
+        new_password: String,
+        logout_devices: bool,
+        sid: &OwnedSessionId,
+        client_secret: &OwnedClientSecret,
+    ) -> Self {
+        Self {
+            operation: UiaaOperation::change_password(new_password, logout_devices),
+            auth: threepid_auth_data(&AuthType::EmailIdentity, None, sid, client_secret)
+                .expect("email identity is a threepid stage"),
+        }
+    }
+
+    /// `true` once the password is changed, `false` while the link wasn't opened yet.
+    pub async fn try_reset(&self, client: &Client) -> Result<bool, PasswordResetErr> {
+        let info = match self.operation.send(client, Some(self.auth.clone())).await? {
+            UiaaStep::Done => return Ok(true),
+            UiaaStep::Auth(info) => info,
+        };
+        // Only the emailed link is there to authenticate with, waiting on it is pointless otherwise
+        if !info
+            .flows
+            .iter()
+            .any(|flow| flow.stages == [AuthType::EmailIdentity])
+        {
+            return Err(PasswordResetErr::EmailNotOffered);
+        }
+        match info.auth_error {
+            None => {
+                trace!("password reset not validated yet");
+                Ok(false)
+            }
+            // Servers refuse an unvalidated email as unauthorized
+            Some(err) if err.kind == ErrorKind::Unauthorized => {
+                trace!("password reset not validated yet: {}", err.message);
+                Ok(false)
+            }
+            Some(err) => Err(PasswordResetErr::Refused(err.message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{ClientSecret, SessionId};
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_partial_json, method, path},
+    };
+
+    use super::{PasswordReset, PasswordResetErr, request_reset_email};
+    use crate::test_support::client;
+
+    #[test_log::test(tokio::test)]
+    async fn reset_after_link_opened() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/_matrix/client/v3/account/password/email/requestToken",
+            ))
+            .and(body_partial_json(json!({
+                "email": "alice@example.org",
+                "send_attempt": 1,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sid": "sid" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let password_change = json!({
+            "new_password": "new",
+            "logout_devices": false,
+            "auth": {
+                "type": "m.login.email.identity",
+                "threepid_creds": { "sid": "sid" },
+            },
+        });
+        // The first try happens before the link is opened
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/account/password"))
+            .and(body_partial_json(password_change.clone()))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "flows": [{ "stages": ["m.login.email.identity"] }],
+                "params": {},
+                "session": "s1",
+                "errcode": "M_UNAUTHORIZED",
+                "error": "Unable to get validated threepid",
+            })))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/account/password"))
+            .and(body_partial_json(json!({ "auth": { "session": "s1" } })))
+            .and(body_partial_json(password_change))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .with_priority(2)
+            .mount(&server)
+            .await;
+
+        let client = client(&server).await;
+        let client_secret = ClientSecret::new();
+        let sid = request_reset_email(&client, " alice@example.org ", client_secret.clone(), 1)
+            .await
+            .unwrap();
+        assert_eq!(sid.as_str(), "sid");
+
+        let reset = PasswordReset::new("new".to_owned(), false, &sid, &client_secret);
+        assert!(!reset.try_reset(&client).await.unwrap());
+        assert!(reset.try_reset(&client).await.unwrap());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn reset_refused() {
+        let server = MockServer::start().await;
+        // Only a password is accepted, the emailed link would never do
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/account/password"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "flows": [{ "stages": ["m.login.password"] }],
+                "params": {},
+                "session": "s1",
+            })))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/account/password"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "flows": [{ "stages": ["m.login.email.identity"] }],
+                "params": {},
+                "session": "s1",
+                "errcode": "M_THREEPID_AUTH_FAILED",
+                "error": "Wrong client secret",
+            })))
+            .with_priority(2)
+            .mount(&server)
+            .await;
+
+        let client = client(&server).await;
+        let reset = PasswordReset::new(
+            "new".to_owned(),
+            false,
+            &SessionId::parse("sid").unwrap(),
+            &ClientSecret::new(),
+        );
+        assert!(matches!(
+            reset.try_reset(&client).await,
+            Err(PasswordResetErr::EmailNotOffered)
+        ));
+        assert!(matches!(
+            reset.try_reset(&client).await,
+            Err(PasswordResetErr::Refused(message)) if message == "Wrong client secret"
+        ));
+    }
+}
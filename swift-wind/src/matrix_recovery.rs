@@ -0,0 +1,113 @@
+use matrix_sdk::{
+    Client,
+    encryption::recovery::{RecoveryError, RecoveryState},
+};
+use thiserror::Error;
+use tracing::trace;
+
+#[derive(Debug, Error)]
+pub enum RecoveryErr {
+    #[error("the account isn't logged in")]
+    NotLoggedIn,
+
+    #[error("couldn't look up the account's cross-signing keys: {0}")]
+    Identity(#[source] matrix_sdk::Error),
+
+    #[error("couldn't set up secret storage: {0}")]
+    Enable(#[source] RecoveryError),
+}
+
+/// What the encryption setup wizard still has to do for this session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionSetup {
+    /// The account has no cross-signing keys, they and a recovery key have to be created.
+    Bootstrap,
+    /// Cross-signing is set up here but there's no secret storage to recover it elsewhere.
+    EnableRecovery,
+    /// Another session set things up, this one needs the recovery key to get the secrets.
+    ///
+    /// [`crate::matrix_backup::restore_backup`] unlocks them along with the backed up room keys.
+    Unlock,
+    /// Another session set up cross-signing without secret storage, only verifying with it helps.
+    VerifyWithOtherSession,
+    Done,
+}
+
+/// Works out what's missing, asking the homeserver for the account's cross-signing keys.
+pub async fn encryption_setup(client: &Client) -> Result<EncryptionSetup, RecoveryErr> {
+    let user_id = client.user_id().ok_or(RecoveryErr::NotLoggedIn)?;
+    let encryption = client.encryption();
+    encryption.wait_for_e2ee_initialization_tasks().await;
+
+    let identity = encryption
+        .request_user_identity(user_id)
+        .await
+        .map_err(RecoveryErr::Identity)?;
+    let has_private_keys = encryption
+        .cross_signing_status()
+        .await
+        .is_some_and(|status| status.is_complete());
+    let recovery = encryption.recovery().state();
+    trace!(
+        "encryption setup of {user_id}: identity {}, private keys {has_private_keys}, recovery {recovery:?}",
+        identity.is_some()
+    );
+
+    Ok(match (identity.is_some(), has_private_keys, recovery) {
+        (false, _, _) => EncryptionSetup::Bootstrap,
+        (true, false, RecoveryState::Disabled) => EncryptionSetup::VerifyWithOtherSession,
+        (true, false, _) | (true, true, RecoveryState::Incomplete) => EncryptionSetup::Unlock,
+        (true, true, RecoveryState::Disabled) => EncryptionSetup::EnableRecovery,
+        (true, true, RecoveryState::Enabled | RecoveryState::Unknown) => EncryptionSetup::Done,
+    })
+}
+
+/// Creates secret storage and a key backup, returns the recovery key the user has to keep.
+///
+/// The cross-signing keys have to exist already, bootstrapping them needs user-interactive auth.
+pub async fn enable_recovery(
+    client: &Client,
+    passphrase: Option<&str>,
+) -> Result<String, RecoveryErr> {
+    let recovery = client.encryption().recovery();
+    let enable = recovery.enable();
+    let enable = match passphrase.filter(|passphrase| !passphrase.is_empty()) {
+        Some(passphrase) => enable.with_passphrase(passphrase),
+        None => enable,
+    };
+    trace!("enabling recovery");
+    enable.await.map_err(RecoveryErr::Enable)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use super::{EncryptionSetup, encryption_setup};
+    use crate::test_support::logged_in_client;
+
+    #[test_log::test(tokio::test)]
+    async fn fresh_account_bootstraps() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/keys/query"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_keys": { "@alice:example.org": {} },
+                "master_keys": {},
+                "self_signing_keys": {},
+                "user_signing_keys": {},
+            })))
+            .mount(&server)
+            .await;
+
+        assert_eq!(
+            encryption_setup(&client).await.unwrap(),
+            EncryptionSetup::Bootstrap
+        );
+    }
+}
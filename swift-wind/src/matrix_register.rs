@@ -0,0 +1,305 @@
+use std::env::consts::OS;
+
+use matrix_sdk::{
+    Client, HttpError, SessionMeta,
+    authentication::matrix::{MatrixSession, MatrixSessionTokens},
+};
+use reqwest::StatusCode;
+use ruma::api::{
+    OutgoingRequest, SendAccessToken,
+    client::{
+        account::{
+            check_registration_token_validity, get_username_availability, register,
+            register::RegistrationKind,
+        },
+        error::ErrorKind,
+        session::logout,
+    },
+};
+use tracing::trace;
+
+/// What the homeserver says about a username someone wants to register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsernameAvailability {
+    Available,
+    Taken,
+    /// Has characters the server doesn't allow in user IDs.
+    Invalid,
+    /// Belongs to an application service, like a bridge.
+    Reserved,
+}
+
+/// Name the homeserver shows for the device swift-wind signs in as.
+pub fn device_display_name() -> String {
+    let os = match OS {
+        "linux" => "Linux",
+        "macos" => "macOS",
+        "windows" => "Windows",
+        "freebsd" => "FreeBSD",
+        "android" => "Android",
+        "ios" => "iOS",
+        os => os,
+    };
+    format!("swift-wind on {os}")
+}
+
+pub async fn username_availability(
+    client: &Client,
+    username: &str,
+) -> Result<UsernameAvailability, HttpError> {
+    trace!("checking if {username} is available");
+    let request = get_username_availability::v3::Request::new(username.to_owned());
+    match client.send(request).await {
+        Ok(res) if res.available => Ok(UsernameAvailability::Available),
+        Ok(_) => Ok(UsernameAvailability::Taken),
+        Err(err) => match err.client_api_error_kind() {
+            Some(ErrorKind::UserInUse) => Ok(UsernameAvailability::Taken),
+            Some(ErrorKind::InvalidUsername) => Ok(UsernameAvailability::Invalid),
+            Some(ErrorKind::Exclusive) => Ok(UsernameAvailability::Reserved),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Whether a registration token can still be used, `None` when the server doesn't say.
+pub async fn registration_token_validity(
+    client: &Client,
+    token: &str,
+) -> Result<Option<bool>, HttpError> {
+    trace!("checking registration token validity");
+    let request = check_registration_token_validity::v1::Request::new(token.to_owned());
+    match client.send(request).await {
+        Ok(res) => Ok(Some(res.valid)),
+        Err(err) if unsupported(&err) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Registers a guest without logging the client in, `None` when the server doesn't allow guests.
+///
+/// Servers can't be asked whether they let guests in, so the login page registers one up front
+/// and only offers guest access once it has a session to hand over.
+pub async fn register_guest(client: &Client) -> Result<Option<MatrixSession>, HttpError> {
+    trace!("registering a guest");
+    let mut request = register::v3::Request::new();
+    request.kind = RegistrationKind::Guest;
+    request.initial_device_display_name = Some(device_display_name());
+    request.refresh_token = true;
+    let res = match client.send(request).await {
+        Ok(res) => res,
+        Err(err)
+            if err.client_api_error_kind() == Some(&ErrorKind::GuestAccessForbidden)
+                || unsupported(&err) =>
+        {
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let (Some(access_token), Some(device_id)) = (res.access_token, res.device_id) else {
+        return Ok(None);
+    };
+    Ok(Some(MatrixSession {
+        meta: SessionMeta {
+            user_id: res.user_id,
+            device_id,
+        },
+        tokens: MatrixSessionTokens {
+            access_token,
+            refresh_token: res.refresh_token,
+        },
+    }))
+}
+
+/// Logs out a guest from [`register_guest`] that nobody continued as.
+pub async fn discard_guest(client: &Client, session: &MatrixSession) -> Result<(), HttpError> {
+    trace!("discarding unused guest {}", session.meta.user_id);
+    let versions = client.server_versions().await?;
+    let request = logout::v3::Request::new()
+        .try_into_http_request::<Vec<u8>>(
+            client.homeserver().as_str(),
+            SendAccessToken::IfRequired(&session.tokens.access_token),
+            &versions,
+        )
+        .map_err(HttpError::IntoHttp)?;
+    let request = reqwest::Request::try_from(request)?;
+    client
+        .http_client()
+        .execute(request)
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Servers answer endpoints they don't have or turned off with one of these.
+fn unsupported(err: &HttpError) -> bool {
+    if matches!(
+        err.client_api_error_kind(),
+        Some(ErrorKind::Unrecognized | ErrorKind::Forbidden { .. })
+    ) {
+        return true;
+    }
+    err.as_client_api_error().is_some_and(|err| {
+        [StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED].contains(&err.status_code)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path, query_param},
+    };
+
+    use super::{
+        UsernameAvailability, device_display_name, discard_guest, register_guest,
+        registration_token_validity, username_availability,
+    };
+    use crate::test_support::client;
+
+    #[test]
+    fn device_name() {
+        assert!(device_display_name().starts_with("swift-wind on "));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn availability() {
+        let server = MockServer::start().await;
+        let client = client(&server).await;
+        let available = "/_matrix/client/v3/register/available";
+        Mock::given(method("GET"))
+            .and(path(available))
+            .and(query_param("username", "alice"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "available": true })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(available))
+            .and(query_param("username", "bob"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "errcode": "M_USER_IN_USE",
+                "error": "User ID already taken.",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(available))
+            .and(query_param("username", "B@d"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "errcode": "M_INVALID_USERNAME",
+                "error": "User ID can only contain characters a-z, 0-9, or '=_-./'",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(available))
+            .and(query_param("username", "_bridge_carol"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "errcode": "M_EXCLUSIVE",
+                "error": "This user ID is reserved by an application service.",
+            })))
+            .mount(&server)
+            .await;
+
+        for (username, availability) in [
+            ("alice", UsernameAvailability::Available),
+            ("bob", UsernameAvailability::Taken),
+            ("B@d", UsernameAvailability::Invalid),
+            ("_bridge_carol", UsernameAvailability::Reserved),
+        ] {
+            assert_eq!(
+                username_availability(&client, username).await.unwrap(),
+                availability
+            );
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn token_validity() {
+        let server = MockServer::start().await;
+        let client = client(&server).await;
+        let validity = "/_matrix/client/v1/register/m.login.registration_token/validity";
+        Mock::given(method("GET"))
+            .and(path(validity))
+            .and(query_param("token", "good"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "valid": true })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(validity))
+            .and(query_param("token", "used"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "valid": false })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(validity))
+            .and(query_param("token", "disabled"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+                "errcode": "M_FORBIDDEN",
+                "error": "Registration is disabled",
+            })))
+            .mount(&server)
+            .await;
+
+        assert_matches!(
+            registration_token_validity(&client, "good").await,
+            Ok(Some(true))
+        );
+        assert_matches!(
+            registration_token_validity(&client, "used").await,
+            Ok(Some(false))
+        );
+        assert_matches!(
+            registration_token_validity(&client, "disabled").await,
+            Ok(None)
+        );
+        // Anything the server doesn't know, wiremock answers with a bare 404
+        assert_matches!(
+            registration_token_validity(&client, "unknown").await,
+            Ok(None)
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn guest_up_front() {
+        let server = MockServer::start().await;
+        let client = client(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/register"))
+            .and(query_param("kind", "guest"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user_id": "@1:example.org",
+                "access_token": "guest",
+                "device_id": "GUEST",
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/register"))
+            .and(query_param("kind", "guest"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+                "errcode": "M_GUEST_ACCESS_FORBIDDEN",
+                "error": "Guest access is disabled"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/logout"))
+            .and(header("authorization", "Bearer guest"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let guest = register_guest(&client).await.unwrap().unwrap();
+        assert_eq!(guest.meta.user_id, "@1:example.org");
+        assert_eq!(guest.tokens.access_token, "guest");
+        // The client itself stays logged out until someone continues as the guest
+        assert!(!client.matrix_auth().logged_in());
+        discard_guest(&client, &guest).await.unwrap();
+
+        assert_matches!(register_guest(&client).await, Ok(None));
+    }
+}
@@ -0,0 +1,145 @@
+use std::io;
+
+use matrix_sdk::Client;
+use ruma::api::client::session::login;
+use thiserror::Error;
+use tracing::trace;
+use url::Url;
+
+use crate::matrix_loopback::{LoopbackErr, LoopbackListener, REDIRECT_TIMEOUT};
+
+#[derive(Debug, Error)]
+pub enum SsoErr {
+    #[error(transparent)]
+    Loopback(#[from] LoopbackErr),
+
+    #[error("couldn't build the single sign-on URL: {0}")]
+    LoginUrl(#[source] matrix_sdk::Error),
+
+    #[error("couldn't open the browser: {0}")]
+    Browser(#[source] io::Error),
+
+    #[error("the server redirected back without a login token")]
+    MissingToken,
+
+    #[error("the login token was refused: {0}")]
+    Login(#[source] matrix_sdk::Error),
+}
+
+/// Logs in through the homeserver's SSO page, `open_browser` gets the URL to show the user.
+pub async fn login_sso<F>(
+    client: &Client,
+    idp_id: Option<&str>,
+    open_browser: F,
+) -> Result<login::v3::Response, SsoErr>
+where
+    F: FnOnce(&str) -> io::Result<()>,
+{
+    let listener = LoopbackListener::bind().await?;
+    let sso_url = client
+        .matrix_auth()
+        .get_sso_login_url(listener.redirect_url().as_str(), idp_id)
+        .await
+        .map_err(SsoErr::LoginUrl)?;
+
+    trace!("opening {sso_url}");
+    open_browser(&sso_url).map_err(SsoErr::Browser)?;
+    let redirect = listener
+        .redirect(REDIRECT_TIMEOUT, |url| login_token(url).is_some())
+        .await?;
+    let token = login_token(&redirect).ok_or(SsoErr::MissingToken)?;
+
+    trace!("exchanging sso login token");
+    client
+        .matrix_auth()
+        .login_token(&token)
+        .request_refresh_token()
+        .await
+        .map_err(SsoErr::Login)
+}
+
+fn login_token(redirect: &Url) -> Option<String> {
+    redirect
+        .query_pairs()
+        .find(|(key, _)| key == "loginToken")
+        .map(|(_, token)| token.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use serde_json::json;
+    use url::Url;
+    use wiremock::{
+        Mock, MockServer, Request, ResponseTemplate,
+        matchers::{body_partial_json, method, path},
+    };
+
+    use super::{login_sso, login_token};
+    use crate::test_support::client;
+
+    #[test]
+    fn redirect_token() {
+        let redirect = Url::parse("http://127.0.0.1:4000/?loginToken=abc%2B1").unwrap();
+        assert_eq!(login_token(&redirect).as_deref(), Some("abc+1"));
+
+        let denied = Url::parse("http://127.0.0.1:4000/?error=denied").unwrap();
+        assert_eq!(login_token(&denied), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn login_through_redirect() {
+        let server = MockServer::start().await;
+        // Stands in for the identity provider: sends the browser straight back with a token.
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/v3/login/sso/redirect"))
+            .respond_with(|req: &Request| {
+                let redirect_url = req
+                    .url
+                    .query_pairs()
+                    .find(|(key, _)| key == "redirectUrl")
+                    .map(|(_, url)| url.into_owned())
+                    .unwrap();
+                let mut redirect_url = Url::parse(&redirect_url).unwrap();
+                redirect_url
+                    .query_pairs_mut()
+                    .append_pair("loginToken", "sso-token");
+                ResponseTemplate::new(302).insert_header("location", redirect_url.as_str())
+            })
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/login"))
+            .and(body_partial_json(json!({
+                "type": "m.login.token",
+                "token": "sso-token",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user_id": "@alice:example.org",
+                "access_token": "access",
+                "refresh_token": "refresh",
+                "device_id": "DEVICE",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = client(&server).await;
+        let mut browser = None;
+        let res = login_sso(&client, None, |url| {
+            let url = url.to_owned();
+            browser = Some(tokio::spawn(async move {
+                reqwest::get(url).await.unwrap().text().await.unwrap()
+            }));
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let page = browser.unwrap().await.unwrap();
+        assert!(page.contains("Signed in"));
+
+        assert_eq!(res.user_id, "@alice:example.org");
+        assert_matches!(client.user_id(), Some(user_id) if user_id == "@alice:example.org");
+    }
+}
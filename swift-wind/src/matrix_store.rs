@@ -0,0 +1,748 @@
+pub mod crypto_store;
+pub mod downloads;
+pub mod event_cache_store;
+pub mod recent_servers;
+pub mod session;
+pub mod state_store;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use matrix_sdk::config::StoreConfig;
+use matrix_sdk_base::{
+    StoreError, crypto::CryptoStoreError, event_cache::store::EventCacheStoreError,
+};
+use matrix_sdk_store_encryption::{EncryptedValueBase64, StoreCipher};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use surrealdb::{
+    Surreal,
+    engine::local::{Db, Mem, SurrealKv},
+};
+use thiserror::Error;
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+pub use crypto_store::SurrealCryptoStore;
+pub use downloads::{DownloadOutcome, DownloadRecord};
+pub use event_cache_store::SurrealEventCacheStore;
+pub use recent_servers::{add_recent_server, recent_servers};
+pub use session::{StoredSession, stored_sessions};
+pub use state_store::SurrealStateStore;
+
+const LOCK_HOLDER: &str = "swift-wind";
+const KEYRING_APPLICATION: &str = "swift-wind";
+/// Holds the store passphrase inside the store directory when there's no OS keyring.
+const PASSPHRASE_FILE: &str = "passphrase";
+
+#[derive(Debug, Error)]
+pub enum SurrealStoreErr {
+    #[error("store database error: {0}")]
+    Db(Box<surrealdb::Error>),
+
+    #[error("store io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("store serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("store identifier error: {0}")]
+    Identifier(#[from] ruma::IdParseError),
+
+    #[error("redaction failed: {0}")]
+    Redaction(#[source] ruma::canonical_json::RedactionError),
+
+    #[error("sessions can't be loaded or saved before an account is stored")]
+    AccountUnset,
+
+    #[error("failed to unpickle an object from the store")]
+    Unpickle,
+
+    #[error("store contains invalid data: {0}")]
+    InvalidData(String),
+
+    #[error("store encryption error: {0}")]
+    Encryption(#[from] matrix_sdk_store_encryption::Error),
+
+    #[error(
+        "couldn't read the passphrase of the account store from the system keyring, unlock it and try again: {0}"
+    )]
+    Keyring(#[from] oo7::Error),
+}
+
+impl From<surrealdb::Error> for SurrealStoreErr {
+    fn from(err: surrealdb::Error) -> Self {
+        SurrealStoreErr::Db(Box::new(err))
+    }
+}
+
+impl From<SurrealStoreErr> for StoreError {
+    fn from(err: SurrealStoreErr) -> Self {
+        match err {
+            SurrealStoreErr::Json(err) => StoreError::Json(err),
+            SurrealStoreErr::Identifier(err) => StoreError::Identifier(err),
+            SurrealStoreErr::Redaction(err) => StoreError::Redaction(err),
+            err => StoreError::backend(err),
+        }
+    }
+}
+
+impl From<SurrealStoreErr> for CryptoStoreError {
+    fn from(err: SurrealStoreErr) -> Self {
+        match err {
+            SurrealStoreErr::Json(err) => CryptoStoreError::Serialization(err),
+            SurrealStoreErr::AccountUnset => CryptoStoreError::AccountUnset,
+            err => CryptoStoreError::backend(err),
+        }
+    }
+}
+
+impl From<SurrealStoreErr> for EventCacheStoreError {
+    fn from(err: SurrealStoreErr) -> Self {
+        match err {
+            SurrealStoreErr::Json(err) => EventCacheStoreError::Serialization(err),
+            SurrealStoreErr::InvalidData(details) => EventCacheStoreError::InvalidData { details },
+            err => EventCacheStoreError::backend(err),
+        }
+    }
+}
+
+/// Root directory for everything swift-wind keeps on disk.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("swift-wind");
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        return PathBuf::from(home).join(".local/share/swift-wind");
+    }
+    PathBuf::from("swift-wind")
+}
+
+/// Fresh database location for an account that is about to log in.
+pub fn new_account_path() -> PathBuf {
+    accounts_dir().join(format!("{:016x}", rand::random::<u64>()))
+}
+
+fn accounts_dir() -> PathBuf {
+    data_dir().join("accounts")
+}
+
+fn keyring_attributes(path: &Path) -> HashMap<&'static str, String> {
+    HashMap::from([
+        ("application", KEYRING_APPLICATION.to_owned()),
+        ("store", path.to_string_lossy().into_owned()),
+    ])
+}
+
+/// Passphrase of the crypto store in `path`, generated and saved in the OS keyring the first time.
+///
+/// Without a usable keyring a new store keeps it in a file only the user can read. A store that
+/// already has its passphrase in the keyring keeps failing until the keyring is back, a new
+/// passphrase couldn't decrypt it.
+async fn store_passphrase(path: &Path, new_store: bool) -> Result<String, SurrealStoreErr> {
+    let file = path.join(PASSPHRASE_FILE);
+    if file.exists() {
+        return Ok(std::fs::read_to_string(file)?);
+    }
+    match keyring_passphrase(path).await {
+        Err(err) if new_store => {
+            warn!(
+                "system keyring unavailable, keeping the store passphrase in {}: {err}",
+                file.display()
+            );
+            let passphrase = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+            write_private(&file, &passphrase)?;
+            Ok(passphrase)
+        }
+        res => res,
+    }
+}
+
+fn write_private(path: &Path, contents: &str) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+async fn keyring_passphrase(path: &Path) -> Result<String, SurrealStoreErr> {
+    let keyring = oo7::Keyring::new().await?;
+    keyring.unlock().await?;
+    let attributes = keyring_attributes(path);
+    if let Some(item) = keyring.search_items(&attributes).await?.first() {
+        item.unlock().await?;
+        let secret = item.secret().await?;
+        return String::from_utf8(secret.to_vec())
+            .map_err(|_| SurrealStoreErr::InvalidData("store passphrase isn't utf-8".to_owned()));
+    }
+
+    let passphrase = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    keyring
+        .create_item(
+            &format!("swift-wind store {}", path.display()),
+            &attributes,
+            &passphrase,
+            true,
+        )
+        .await?;
+    Ok(passphrase)
+}
+
+/// Drops the keyring entry of a store whose directory is being deleted.
+async fn forget_store_passphrase(path: &Path) -> Result<(), SurrealStoreErr> {
+    let keyring = oo7::Keyring::new().await?;
+    keyring.delete(&keyring_attributes(path)).await?;
+    Ok(())
+}
+
+/// Row shared by every store table.
+///
+/// `room` is empty for data that doesn't belong to a room, `scope` groups rows
+/// inside a room (event type, receipt type...) and `value` is the JSON encoded payload.
+#[derive(Debug, Serialize, Deserialize)]
+struct Row {
+    room: String,
+    scope: String,
+    key: String,
+    value: String,
+}
+
+impl Row {
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, SurrealStoreErr> {
+        Ok(serde_json::from_str(&self.value)?)
+    }
+}
+
+/// Writes collected by a store and applied in one transaction by [`AccountDb::commit`].
+#[derive(Debug, Default)]
+struct Batch {
+    query: String,
+    params: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Batch {
+    fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Binds the parameters of the next statement, returning their name.
+    fn bind(&mut self, params: serde_json::Value) -> String {
+        let name = format!("p{}", self.params.len());
+        self.params.insert(name.clone(), params);
+        format!("${name}")
+    }
+
+    fn set<T: Serialize + ?Sized>(
+        &mut self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), SurrealStoreErr> {
+        let row = Row {
+            room: room.to_owned(),
+            scope: scope.to_owned(),
+            key: key.to_owned(),
+            value: serde_json::to_string(value)?,
+        };
+        self.set_row(table, &row)
+    }
+
+    /// Upserts a row that carries extra fields next to the [`Row`] ones.
+    ///
+    /// With `merge` fields missing from `row` keep their stored value.
+    fn upsert<R: Serialize>(
+        &mut self,
+        table: &str,
+        id: [&str; 3],
+        row: &R,
+        merge: bool,
+    ) -> Result<(), SurrealStoreErr> {
+        let p = self.bind(serde_json::json!({
+            "table": table,
+            "id": id,
+            "row": serde_json::to_value(row)?,
+        }));
+        let mode = if merge { "MERGE" } else { "CONTENT" };
+        self.query.push_str(&format!(
+            "UPSERT type::thing({p}.table, {p}.id) {mode} {p}.row RETURN NONE;"
+        ));
+        Ok(())
+    }
+
+    fn set_row(&mut self, table: &str, row: &Row) -> Result<(), SurrealStoreErr> {
+        self.upsert(table, [&row.room, &row.scope, &row.key], row, false)
+    }
+
+    fn remove(&mut self, table: &str, room: &str, scope: &str, key: &str) {
+        let p = self.bind(serde_json::json!({ "table": table, "id": [room, scope, key] }));
+        self.query.push_str(&format!(
+            "DELETE type::thing({p}.table, {p}.id) RETURN NONE;"
+        ));
+    }
+
+    fn clear_room(&mut self, table: &str, room: &str) {
+        let p = self.bind(serde_json::json!({ "table": table, "room": room }));
+        self.query.push_str(&format!(
+            "DELETE type::table({p}.table) WHERE room = {p}.room RETURN NONE;"
+        ));
+    }
+}
+
+/// Embedded database holding the state, crypto and event cache stores of one account.
+#[derive(Clone)]
+pub struct AccountDb {
+    db: Surreal<Db>,
+    path: Option<PathBuf>,
+    /// Loaded on first use, see [`AccountDb::cipher`].
+    cipher: Arc<OnceCell<Option<Arc<StoreCipher>>>>,
+}
+
+impl std::fmt::Debug for AccountDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccountDb")
+            .field("db", &self.db)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AccountDb {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, SurrealStoreErr> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        let db =
+            Surreal::new::<SurrealKv>(path.join("store").to_string_lossy().into_owned()).await?;
+        Self::init(db, Some(path.to_owned())).await
+    }
+
+    pub async fn in_memory() -> Result<Self, SurrealStoreErr> {
+        let db = Surreal::new::<Mem>(()).await?;
+        Self::init(db, None).await
+    }
+
+    async fn init(db: Surreal<Db>, path: Option<PathBuf>) -> Result<Self, SurrealStoreErr> {
+        db.use_ns("swift_wind").use_db("matrix").await?;
+        Ok(Self {
+            db,
+            path,
+            cipher: Arc::default(),
+        })
+    }
+
+    /// In-memory database whose values are encrypted like on disk.
+    #[cfg(test)]
+    pub(crate) async fn in_memory_encrypted(passphrase: &str) -> Result<Self, SurrealStoreErr> {
+        let db = Self::in_memory().await?;
+        let cipher = crypto_store::load_or_create_cipher(&db, passphrase).await?;
+        let _ = db.cipher.set(Some(Arc::new(cipher)));
+        Ok(db)
+    }
+
+    /// Directory of the database, `None` for in-memory databases.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Client store config with all three matrix-sdk stores living in this database.
+    ///
+    /// The crypto store of an on-disk database is encrypted, see [`AccountDb::cipher`].
+    pub async fn store_config(&self) -> Result<StoreConfig, SurrealStoreErr> {
+        let cipher = self.cipher().await?;
+        Ok(StoreConfig::new(LOCK_HOLDER.to_owned())
+            .state_store(SurrealStateStore::open(self.clone()).await?)
+            .crypto_store(SurrealCryptoStore::with_cipher(self.clone(), cipher).await?)
+            .event_cache_store(SurrealEventCacheStore::open(self.clone()).await?))
+    }
+
+    /// Cipher for values that mustn't be stored in plain text, `None` for in-memory databases.
+    ///
+    /// It's kept in the database itself, encrypted with the store passphrase.
+    async fn cipher(&self) -> Result<Option<Arc<StoreCipher>>, SurrealStoreErr> {
+        self.cipher
+            .get_or_try_init(|| async {
+                let Some(path) = &self.path else {
+                    return Ok(None);
+                };
+                let new_store = !crypto_store::has_cipher(self).await?;
+                let passphrase = store_passphrase(path, new_store).await?;
+                let cipher = crypto_store::load_or_create_cipher(self, &passphrase).await?;
+                Ok(Some(Arc::new(cipher)))
+            })
+            .await
+            .cloned()
+    }
+
+    /// Like [`AccountDb::set`] with the value encrypted by [`AccountDb::cipher`].
+    async fn set_encrypted<T: Serialize + ?Sized>(
+        &self,
+        table: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), SurrealStoreErr> {
+        let value = match self.cipher().await? {
+            Some(cipher) => {
+                serde_json::to_value(cipher.encrypt_value_base64_data(serde_json::to_vec(value)?)?)?
+            }
+            None => serde_json::to_value(value)?,
+        };
+        self.set(table, "", "", key, &value).await
+    }
+
+    async fn get_encrypted<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> Result<Option<T>, SurrealStoreErr> {
+        let Some(value) = self.get::<serde_json::Value>(table, "", "", key).await? else {
+            return Ok(None);
+        };
+        let cipher = self.cipher().await?;
+        // Values saved before they were encrypted are read as is, the next save encrypts them
+        match (
+            cipher,
+            serde_json::from_value::<EncryptedValueBase64>(value.clone()),
+        ) {
+            (Some(cipher), Ok(encrypted)) => Ok(Some(serde_json::from_slice(
+                &cipher.decrypt_value_base64_data(encrypted)?,
+            )?)),
+            _ => Ok(Some(serde_json::from_value(value)?)),
+        }
+    }
+
+    async fn define_tables(&self, tables: &[&str]) -> Result<(), SurrealStoreErr> {
+        let mut query = String::new();
+        for table in tables {
+            query.push_str(&format!(
+                "DEFINE INDEX IF NOT EXISTS {table}_room_scope ON TABLE {table} FIELDS room, scope;"
+            ));
+        }
+        self.db.query(query).await?.check()?;
+        Ok(())
+    }
+
+    /// Applies every write of the batch, or none of them.
+    async fn commit(&self, batch: Batch) -> Result<(), SurrealStoreErr> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.db
+            .query(format!(
+                "BEGIN TRANSACTION;{}COMMIT TRANSACTION;",
+                batch.query
+            ))
+            .bind(batch.params)
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+    ) -> Result<Option<T>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .query("SELECT * OMIT id FROM type::thing($table, [$room, $scope, $key])")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .bind(("scope", scope.to_owned()))
+            .bind(("key", key.to_owned()))
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.first().map(Row::decode).transpose()
+    }
+
+    async fn get_many<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        keys: Vec<String>,
+    ) -> Result<Vec<(String, T)>, SurrealStoreErr> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut res = self
+            .db
+            .query("SELECT * OMIT id FROM type::table($table) WHERE room = $room AND scope = $scope AND key IN $keys")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .bind(("scope", scope.to_owned()))
+            .bind(("keys", keys))
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.into_iter()
+            .map(|row| {
+                let value = row.decode()?;
+                Ok((row.key, value))
+            })
+            .collect()
+    }
+
+    async fn set<T: Serialize + ?Sized>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), SurrealStoreErr> {
+        let row = Row {
+            room: room.to_owned(),
+            scope: scope.to_owned(),
+            key: key.to_owned(),
+            value: serde_json::to_string(value)?,
+        };
+        self.db
+            .query("UPSERT type::thing($table, [$room, $scope, $key]) CONTENT $row RETURN NONE")
+            .bind(("table", table.to_owned()))
+            .bind(("room", row.room.clone()))
+            .bind(("scope", row.scope.clone()))
+            .bind(("key", row.key.clone()))
+            .bind(("row", row))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Removes one row, returning what it held.
+    async fn remove<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+    ) -> Result<Option<T>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .query("DELETE type::thing($table, [$room, $scope, $key]) RETURN BEFORE")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .bind(("scope", scope.to_owned()))
+            .bind(("key", key.to_owned()))
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.first().map(Row::decode).transpose()
+    }
+
+    /// Every row of a scope, ordered by key.
+    async fn list<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+    ) -> Result<Vec<(String, T)>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .query("SELECT * OMIT id FROM type::table($table) WHERE room = $room AND scope = $scope ORDER BY key")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .bind(("scope", scope.to_owned()))
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.into_iter()
+            .map(|row| {
+                let value = row.decode()?;
+                Ok((row.key, value))
+            })
+            .collect()
+    }
+
+    /// Every row of a room as `(scope, key, value)`.
+    async fn list_room<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+    ) -> Result<Vec<(String, String, T)>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .query("SELECT * OMIT id FROM type::table($table) WHERE room = $room")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.into_iter()
+            .map(|row| {
+                let value = row.decode()?;
+                Ok((row.scope, row.key, value))
+            })
+            .collect()
+    }
+
+    /// Every row of a table as `(room, value)`.
+    async fn list_table<T: DeserializeOwned>(
+        &self,
+        table: &str,
+    ) -> Result<Vec<(String, T)>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .query("SELECT * OMIT id FROM type::table($table)")
+            .bind(("table", table.to_owned()))
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.into_iter()
+            .map(|row| {
+                let value = row.decode()?;
+                Ok((row.room, value))
+            })
+            .collect()
+    }
+
+    /// Rooms that have at least one row in the table.
+    async fn rooms(&self, table: &str) -> Result<Vec<String>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .query("RETURN array::distinct(SELECT VALUE room FROM type::table($table))")
+            .bind(("table", table.to_owned()))
+            .await?;
+        Ok(res.take(0)?)
+    }
+
+    async fn clear_scope(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+    ) -> Result<(), SurrealStoreErr> {
+        self.db
+            .query("DELETE type::table($table) WHERE room = $room AND scope = $scope")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .bind(("scope", scope.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn clear_room(&self, table: &str, room: &str) -> Result<(), SurrealStoreErr> {
+        self.db
+            .query("DELETE type::table($table) WHERE room = $room")
+            .bind(("table", table.to_owned()))
+            .bind(("room", room.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn clear_table(&self, table: &str) -> Result<(), SurrealStoreErr> {
+        self.db
+            .query("DELETE type::table($table)")
+            .bind(("table", table.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountDb, Batch, PASSPHRASE_FILE, store_passphrase, write_private};
+
+    #[test_log::test(tokio::test)]
+    async fn rows_roundtrip() {
+        let db = AccountDb::in_memory().await.unwrap();
+        db.define_tables(&["test"]).await.unwrap();
+
+        db.set("test", "!room:a", "scope", "b", &2).await.unwrap();
+        db.set("test", "!room:a", "scope", "a", &1).await.unwrap();
+        db.set("test", "!room:b", "scope", "a", &3).await.unwrap();
+
+        assert_eq!(
+            db.get::<i32>("test", "!room:a", "scope", "a")
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            db.list::<i32>("test", "!room:a", "scope").await.unwrap(),
+            vec![("a".to_owned(), 1), ("b".to_owned(), 2)]
+        );
+        assert_eq!(
+            db.get_many::<i32>("test", "!room:a", "scope", vec!["b".to_owned()])
+                .await
+                .unwrap(),
+            vec![("b".to_owned(), 2)]
+        );
+        let mut rooms = db.rooms("test").await.unwrap();
+        rooms.sort();
+        assert_eq!(rooms, vec!["!room:a".to_owned(), "!room:b".to_owned()]);
+
+        assert_eq!(
+            db.remove::<i32>("test", "!room:a", "scope", "a")
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            db.get::<i32>("test", "!room:a", "scope", "a")
+                .await
+                .unwrap(),
+            None
+        );
+
+        db.clear_room("test", "!room:a").await.unwrap();
+        assert!(
+            db.list_room::<i32>("test", "!room:a")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(db.list_table::<i32>("test").await.unwrap().len(), 1);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn batch_applies_in_order() {
+        let db = AccountDb::in_memory().await.unwrap();
+        db.define_tables(&["test"]).await.unwrap();
+        db.set("test", "!room:a", "scope", "a", &1).await.unwrap();
+        db.set("test", "!room:b", "scope", "a", &2).await.unwrap();
+
+        let mut batch = Batch::default();
+        batch.set("test", "!room:a", "scope", "b", &3).unwrap();
+        batch.remove("test", "!room:a", "scope", "a");
+        batch.clear_room("test", "!room:b");
+        batch.set("test", "!room:b", "scope", "c", &4).unwrap();
+        db.commit(batch).await.unwrap();
+
+        assert_eq!(
+            db.list::<i32>("test", "!room:a", "scope").await.unwrap(),
+            vec![("b".to_owned(), 3)]
+        );
+        assert_eq!(
+            db.list::<i32>("test", "!room:b", "scope").await.unwrap(),
+            vec![("c".to_owned(), 4)]
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn passphrase_file() {
+        let dir =
+            std::env::temp_dir().join(format!("swift-wind-passphrase-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(PASSPHRASE_FILE);
+        write_private(&file, "secret").unwrap();
+        assert!(write_private(&file, "other").is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A store that fell back to the file keeps using it, the keyring isn't asked
+        assert_eq!(store_passphrase(&dir, false).await.unwrap(), "secret");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,918 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock},
+};
+
+use matrix_sdk::{async_trait, store_locks::memory_store_helper::try_take_leased_lock};
+use matrix_sdk_base::crypto::{
+    Account, DeviceData, GossipRequest, GossippedSecret, SecretInfo, Session, TrackedUser,
+    UserIdentityData,
+    olm::{
+        Curve25519PublicKey, InboundGroupSession, OlmMessageHash, OutboundGroupSession,
+        PickledAccount, PickledCrossSigningIdentity, PickledInboundGroupSession,
+        PickledOutboundGroupSession, PickledSession, PrivateCrossSigningIdentity, SenderDataType,
+        StaticAccountData,
+    },
+    store::{
+        BackupDecryptionKey, BackupKeys, Changes, CryptoStore, DehydratedDeviceKey, PendingChanges,
+        RoomKeyCounts, RoomSettings,
+    },
+    types::events::room_key_withheld::RoomKeyWithheldEvent,
+};
+use matrix_sdk_store_encryption::{EncryptedValueBase64, StoreCipher};
+use ruma::{
+    DeviceId, OwnedDeviceId, RoomId, TransactionId, UserId, events::secret::request::SecretName,
+    time::Instant,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use super::{AccountDb, Batch, Row, SurrealStoreErr};
+
+const KV: &str = "crypto_kv";
+const SESSION: &str = "crypto_session";
+const INBOUND_GROUP_SESSION: &str = "crypto_inbound_group_session";
+const OUTBOUND_GROUP_SESSION: &str = "crypto_outbound_group_session";
+const TRACKED_USER: &str = "crypto_tracked_user";
+const OLM_HASH: &str = "crypto_olm_hash";
+const DEVICE: &str = "crypto_device";
+const IDENTITY: &str = "crypto_identity";
+const KEY_REQUEST: &str = "crypto_key_request";
+const WITHHELD: &str = "crypto_withheld";
+const SECRET: &str = "crypto_secret_inbox";
+const ROOM_SETTINGS: &str = "crypto_room_settings";
+const CUSTOM: &str = "crypto_custom";
+
+const ACCOUNT_KEY: &str = "account";
+const IDENTITY_KEY: &str = "identity";
+const NEXT_BATCH_TOKEN_KEY: &str = "next_batch_token";
+const BACKUP_VERSION_KEY: &str = "backup_version";
+const BACKUP_DECRYPTION_KEY: &str = "backup_decryption_key";
+const DEHYDRATED_DEVICE_PICKLE_KEY: &str = "dehydrated_device_pickle_key";
+const STORE_CIPHER_KEY: &str = "store_cipher";
+
+/// Inbound group sessions keep the fields the backup and device batch queries filter on
+/// in plain text next to the pickle.
+#[derive(Serialize)]
+struct InboundGroupSessionRow {
+    room: String,
+    scope: String,
+    key: String,
+    value: String,
+    sender_key: String,
+    sender_data_type: SenderDataType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backed_up_to: Option<String>,
+}
+
+/// matrix-sdk [`CryptoStore`] keeping the olm account, sessions, devices and room keys.
+///
+/// When opened with a passphrase every value is encrypted with a [`StoreCipher`], which is
+/// itself kept in the store encrypted with the passphrase. Keys stay in plain text so rows
+/// can still be looked up by room, user and session id.
+/// Leases only guard against other clients in this process, the embedded database
+/// can't be opened twice anyway.
+pub struct SurrealCryptoStore {
+    db: AccountDb,
+    cipher: Option<Arc<StoreCipher>>,
+    static_account: Arc<StdRwLock<Option<StaticAccountData>>>,
+    leases: StdMutex<HashMap<String, (String, Instant)>>,
+    save_lock: Mutex<()>,
+}
+
+impl std::fmt::Debug for SurrealCryptoStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SurrealCryptoStore")
+            .field("db", &self.db)
+            .field("encrypted", &self.cipher.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SurrealCryptoStore {
+    pub async fn open(db: AccountDb, passphrase: Option<&str>) -> Result<Self, SurrealStoreErr> {
+        let cipher = match passphrase {
+            Some(passphrase) => Some(Arc::new(load_or_create_cipher(&db, passphrase).await?)),
+            None => None,
+        };
+        Self::with_cipher(db, cipher).await
+    }
+
+    /// Opens the store with a cipher loaded beforehand, see [`AccountDb::store_config`].
+    pub(crate) async fn with_cipher(
+        db: AccountDb,
+        cipher: Option<Arc<StoreCipher>>,
+    ) -> Result<Self, SurrealStoreErr> {
+        db.define_tables(&[
+            KV,
+            SESSION,
+            INBOUND_GROUP_SESSION,
+            OUTBOUND_GROUP_SESSION,
+            TRACKED_USER,
+            OLM_HASH,
+            DEVICE,
+            IDENTITY,
+            KEY_REQUEST,
+            WITHHELD,
+            SECRET,
+            ROOM_SETTINGS,
+            CUSTOM,
+        ])
+        .await?;
+        db.db
+            .query(format!(
+                "DEFINE INDEX IF NOT EXISTS {INBOUND_GROUP_SESSION}_sender ON TABLE {INBOUND_GROUP_SESSION} FIELDS sender_key;\
+                 DEFINE INDEX IF NOT EXISTS {INBOUND_GROUP_SESSION}_backup ON TABLE {INBOUND_GROUP_SESSION} FIELDS backed_up_to;"
+            ))
+            .await?
+            .check()?;
+        Ok(Self {
+            db,
+            cipher,
+            static_account: Default::default(),
+            leases: Default::default(),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn get_static_account(&self) -> Option<StaticAccountData> {
+        self.static_account.read().unwrap().clone()
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<serde_json::Value, SurrealStoreErr> {
+        match &self.cipher {
+            Some(cipher) => {
+                let encrypted = cipher.encrypt_value_base64_data(serde_json::to_vec(value)?)?;
+                Ok(serde_json::to_value(encrypted)?)
+            }
+            None => Ok(serde_json::to_value(value)?),
+        }
+    }
+
+    fn deserialize_value<T: DeserializeOwned>(
+        &self,
+        value: serde_json::Value,
+    ) -> Result<T, SurrealStoreErr> {
+        match &self.cipher {
+            Some(cipher) => {
+                let encrypted: EncryptedValueBase64 = serde_json::from_value(value)?;
+                Ok(serde_json::from_slice(
+                    &cipher.decrypt_value_base64_data(encrypted)?,
+                )?)
+            }
+            None => Ok(serde_json::from_value(value)?),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+    ) -> Result<Option<T>, SurrealStoreErr> {
+        self.db
+            .get(table, room, scope, key)
+            .await?
+            .map(|value| self.deserialize_value(value))
+            .transpose()
+    }
+
+    async fn set<T: Serialize + ?Sized>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), SurrealStoreErr> {
+        self.db
+            .set(table, room, scope, key, &self.serialize_value(value)?)
+            .await
+    }
+
+    async fn list<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        room: &str,
+        scope: &str,
+    ) -> Result<Vec<(String, T)>, SurrealStoreErr> {
+        self.db
+            .list(table, room, scope)
+            .await?
+            .into_iter()
+            .map(|(key, value)| Ok((key, self.deserialize_value(value)?)))
+            .collect()
+    }
+
+    fn batch_set<T: Serialize + ?Sized>(
+        &self,
+        batch: &mut Batch,
+        table: &str,
+        room: &str,
+        scope: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), SurrealStoreErr> {
+        batch.set(table, room, scope, key, &self.serialize_value(value)?)
+    }
+
+    /// Queues an inbound group session, keeping its stored backup version unless a new one is given.
+    async fn batch_inbound_group_session(
+        &self,
+        batch: &mut Batch,
+        session: &InboundGroupSession,
+        backed_up_to: Option<&str>,
+    ) -> Result<(), SurrealStoreErr> {
+        let row = InboundGroupSessionRow {
+            room: session.room_id().to_string(),
+            scope: String::new(),
+            key: session.session_id().to_owned(),
+            value: serde_json::to_string(&self.serialize_value(&session.pickle().await)?)?,
+            sender_key: session.sender_key().to_base64(),
+            sender_data_type: session.sender_data_type(),
+            backed_up_to: backed_up_to.map(ToOwned::to_owned),
+        };
+        batch.upsert(
+            INBOUND_GROUP_SESSION,
+            [&row.room, &row.scope, &row.key],
+            &row,
+            true,
+        )
+    }
+
+    /// Inbound group sessions matching `condition`, which may use the bound `params`.
+    async fn query_inbound_group_sessions(
+        &self,
+        condition: &str,
+        params: serde_json::Value,
+    ) -> Result<Vec<InboundGroupSession>, SurrealStoreErr> {
+        let mut res = self
+            .db
+            .db
+            .query(format!(
+                "SELECT * OMIT id FROM type::table($table) {condition}"
+            ))
+            .bind(("table", INBOUND_GROUP_SESSION))
+            .bind(params)
+            .await?;
+        let rows: Vec<Row> = res.take(0)?;
+        rows.iter()
+            .map(|row| self.unpickle_inbound_group_session(row.decode()?))
+            .collect()
+    }
+
+    /// Number of inbound group sessions, only counting those backed up to a version if one is given.
+    async fn count_inbound_group_sessions(
+        &self,
+        backed_up_to: Option<&str>,
+    ) -> Result<usize, SurrealStoreErr> {
+        let condition = match backed_up_to {
+            Some(_) => "WHERE backed_up_to = $version",
+            None => "",
+        };
+        let mut res = self
+            .db
+            .db
+            .query(format!(
+                "RETURN count(SELECT VALUE key FROM type::table($table) {condition})"
+            ))
+            .bind(("table", INBOUND_GROUP_SESSION))
+            .bind(("version", backed_up_to.map(ToOwned::to_owned)))
+            .await?;
+        let count: Option<usize> = res.take(0)?;
+        Ok(count.unwrap_or_default())
+    }
+
+    fn unpickle_inbound_group_session(
+        &self,
+        value: serde_json::Value,
+    ) -> Result<InboundGroupSession, SurrealStoreErr> {
+        let pickle: PickledInboundGroupSession = self.deserialize_value(value)?;
+        InboundGroupSession::from_pickle(pickle).map_err(|_| SurrealStoreErr::Unpickle)
+    }
+}
+
+/// Whether the store cipher was created, after that only its passphrase can open the store.
+pub(super) async fn has_cipher(db: &AccountDb) -> Result<bool, SurrealStoreErr> {
+    Ok(db
+        .get::<Vec<u8>>(KV, "", "", STORE_CIPHER_KEY)
+        .await?
+        .is_some())
+}
+
+/// Reads the store cipher saved under the passphrase, creating it on first use.
+pub(super) async fn load_or_create_cipher(
+    db: &AccountDb,
+    passphrase: &str,
+) -> Result<StoreCipher, SurrealStoreErr> {
+    if let Some(exported) = db.get::<Vec<u8>>(KV, "", "", STORE_CIPHER_KEY).await? {
+        return Ok(StoreCipher::import(passphrase, &exported)?);
+    }
+    let cipher = StoreCipher::new()?;
+    #[cfg(not(test))]
+    let exported = cipher.export(passphrase)?;
+    #[cfg(test)]
+    let exported = cipher._insecure_export_fast_for_testing(passphrase)?;
+    db.set(KV, "", "", STORE_CIPHER_KEY, &exported).await?;
+    Ok(cipher)
+}
+
+#[async_trait]
+impl CryptoStore for SurrealCryptoStore {
+    type Error = SurrealStoreErr;
+
+    async fn load_account(&self) -> Result<Option<Account>, Self::Error> {
+        let pickle: Option<PickledAccount> = self.get(KV, "", "", ACCOUNT_KEY).await?;
+        let Some(pickle) = pickle else {
+            return Ok(None);
+        };
+        let account = Account::from_pickle(pickle).map_err(|_| SurrealStoreErr::Unpickle)?;
+        *self.static_account.write().unwrap() = Some(account.static_data().clone());
+        Ok(Some(account))
+    }
+
+    async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>, Self::Error> {
+        let pickle: Option<PickledCrossSigningIdentity> =
+            self.get(KV, "", "", IDENTITY_KEY).await?;
+        pickle
+            .map(|pickle| {
+                PrivateCrossSigningIdentity::from_pickle(pickle)
+                    .map_err(|_| SurrealStoreErr::Unpickle)
+            })
+            .transpose()
+    }
+
+    async fn save_pending_changes(&self, changes: PendingChanges) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        if let Some(account) = changes.account {
+            *self.static_account.write().unwrap() = Some(account.static_data().clone());
+            self.set(KV, "", "", ACCOUNT_KEY, &account.pickle()).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_changes(&self, changes: Changes) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let mut batch = Batch::default();
+
+        if let Some(identity) = changes.private_identity {
+            self.batch_set(
+                &mut batch,
+                KV,
+                "",
+                "",
+                IDENTITY_KEY,
+                &identity.pickle().await,
+            )?;
+        }
+        if let Some(token) = &changes.next_batch_token {
+            self.batch_set(&mut batch, KV, "", "", NEXT_BATCH_TOKEN_KEY, token)?;
+        }
+        if let Some(key) = &changes.backup_decryption_key {
+            self.batch_set(&mut batch, KV, "", "", BACKUP_DECRYPTION_KEY, key)?;
+        }
+        if let Some(version) = &changes.backup_version {
+            self.batch_set(&mut batch, KV, "", "", BACKUP_VERSION_KEY, version)?;
+        }
+        if let Some(key) = &changes.dehydrated_device_pickle_key {
+            self.batch_set(&mut batch, KV, "", "", DEHYDRATED_DEVICE_PICKLE_KEY, key)?;
+        }
+
+        for session in &changes.sessions {
+            let pickle: PickledSession = session.pickle().await;
+            self.batch_set(
+                &mut batch,
+                SESSION,
+                "",
+                &session.sender_key().to_base64(),
+                session.session_id(),
+                &pickle,
+            )?;
+        }
+        for session in &changes.inbound_group_sessions {
+            self.batch_inbound_group_session(&mut batch, session, None)
+                .await?;
+        }
+        for session in &changes.outbound_group_sessions {
+            let pickle: PickledOutboundGroupSession = session.pickle().await;
+            self.batch_set(
+                &mut batch,
+                OUTBOUND_GROUP_SESSION,
+                session.room_id().as_str(),
+                "",
+                "",
+                &pickle,
+            )?;
+        }
+
+        for device in changes.devices.new.iter().chain(&changes.devices.changed) {
+            self.batch_set(
+                &mut batch,
+                DEVICE,
+                "",
+                device.user_id().as_str(),
+                device.device_id().as_str(),
+                device,
+            )?;
+        }
+        for device in &changes.devices.deleted {
+            batch.remove(
+                DEVICE,
+                "",
+                device.user_id().as_str(),
+                device.device_id().as_str(),
+            );
+        }
+        for identity in changes
+            .identities
+            .new
+            .iter()
+            .chain(&changes.identities.changed)
+        {
+            self.batch_set(
+                &mut batch,
+                IDENTITY,
+                "",
+                "",
+                identity.user_id().as_str(),
+                identity,
+            )?;
+        }
+
+        for hash in &changes.message_hashes {
+            self.batch_set(
+                &mut batch,
+                OLM_HASH,
+                "",
+                &hash.sender_key,
+                &hash.hash,
+                &true,
+            )?;
+        }
+        for request in &changes.key_requests {
+            self.batch_set(
+                &mut batch,
+                KEY_REQUEST,
+                "",
+                "",
+                request.request_id.as_str(),
+                request,
+            )?;
+        }
+        for (room_id, sessions) in &changes.withheld_session_info {
+            for (session_id, event) in sessions {
+                self.batch_set(
+                    &mut batch,
+                    WITHHELD,
+                    room_id.as_str(),
+                    "",
+                    session_id,
+                    event,
+                )?;
+            }
+        }
+        for (room_id, settings) in &changes.room_settings {
+            self.batch_set(
+                &mut batch,
+                ROOM_SETTINGS,
+                room_id.as_str(),
+                "",
+                "",
+                settings,
+            )?;
+        }
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for secret in &changes.secrets {
+            let name = secret.secret_name.to_string();
+            let position = match positions.get_mut(&name) {
+                Some(position) => position,
+                None => {
+                    let stored = self
+                        .db
+                        .list::<serde_json::Value>(SECRET, "", &name)
+                        .await?
+                        .len();
+                    positions.entry(name.clone()).or_insert(stored)
+                }
+            };
+            self.batch_set(
+                &mut batch,
+                SECRET,
+                "",
+                &name,
+                &format!("{position:08}"),
+                secret,
+            )?;
+            *position += 1;
+        }
+
+        self.db.commit(batch).await
+    }
+
+    async fn save_inbound_group_sessions(
+        &self,
+        sessions: Vec<InboundGroupSession>,
+        backed_up_to_version: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let mut batch = Batch::default();
+        for session in sessions {
+            let backed_up = session.backed_up();
+            if backed_up != backed_up_to_version.is_some() {
+                warn!(
+                    backed_up,
+                    backed_up_to_version,
+                    "Session backed-up flag does not correspond to backup version setting",
+                );
+            }
+            self.batch_inbound_group_session(&mut batch, &session, backed_up_to_version)
+                .await?;
+        }
+        self.db.commit(batch).await
+    }
+
+    async fn get_sessions(&self, sender_key: &str) -> Result<Option<Vec<Session>>, Self::Error> {
+        let pickles: Vec<(String, PickledSession)> = self.list(SESSION, "", sender_key).await?;
+        if pickles.is_empty() {
+            return Ok(None);
+        }
+        let device_keys = self.get_own_device().await?.as_device_keys().clone();
+        pickles
+            .into_iter()
+            .map(|(_, pickle)| {
+                Session::from_pickle(device_keys.clone(), pickle)
+                    .map_err(|_| SurrealStoreErr::AccountUnset)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<InboundGroupSession>, Self::Error> {
+        self.db
+            .get(INBOUND_GROUP_SESSION, room_id.as_str(), "", session_id)
+            .await?
+            .map(|value| self.unpickle_inbound_group_session(value))
+            .transpose()
+    }
+
+    async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<RoomKeyWithheldEvent>, Self::Error> {
+        self.get(WITHHELD, room_id.as_str(), "", session_id).await
+    }
+
+    async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.query_inbound_group_sessions("", serde_json::json!({}))
+            .await
+    }
+
+    async fn inbound_group_session_counts(
+        &self,
+        backup_version: Option<&str>,
+    ) -> Result<RoomKeyCounts, Self::Error> {
+        let backed_up = match backup_version {
+            Some(version) => self.count_inbound_group_sessions(Some(version)).await?,
+            None => 0,
+        };
+        Ok(RoomKeyCounts {
+            total: self.count_inbound_group_sessions(None).await?,
+            backed_up,
+        })
+    }
+
+    async fn get_inbound_group_sessions_for_device_batch(
+        &self,
+        sender_key: Curve25519PublicKey,
+        sender_data_type: SenderDataType,
+        after_session_id: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.query_inbound_group_sessions(
+            "WHERE sender_key = $sender_key AND sender_data_type = $sender_data_type \
+             AND key > $after ORDER BY key LIMIT $limit",
+            serde_json::json!({
+                "sender_key": sender_key.to_base64(),
+                "sender_data_type": sender_data_type,
+                "after": after_session_id.unwrap_or_default(),
+                "limit": limit,
+            }),
+        )
+        .await
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        backup_version: &str,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.query_inbound_group_sessions(
+            "WHERE backed_up_to != $version LIMIT $limit",
+            serde_json::json!({ "version": backup_version, "limit": limit }),
+        )
+        .await
+    }
+
+    async fn mark_inbound_group_sessions_as_backed_up(
+        &self,
+        backup_version: &str,
+        room_and_session_ids: &[(&RoomId, &str)],
+    ) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let mut batch = Batch::default();
+        for &(room_id, session_id) in room_and_session_ids {
+            let Some(session) = self.get_inbound_group_session(room_id, session_id).await? else {
+                continue;
+            };
+            session.mark_as_backed_up();
+            self.batch_inbound_group_session(&mut batch, &session, Some(backup_version))
+                .await?;
+        }
+        self.db.commit(batch).await
+    }
+
+    async fn reset_backup_state(&self) -> Result<(), Self::Error> {
+        // Sessions remember the backup version they were uploaded to, a new
+        // backup version makes them eligible again without touching them.
+        Ok(())
+    }
+
+    async fn load_backup_keys(&self) -> Result<BackupKeys, Self::Error> {
+        let decryption_key: Option<BackupDecryptionKey> =
+            self.get(KV, "", "", BACKUP_DECRYPTION_KEY).await?;
+        let backup_version: Option<String> = self.get(KV, "", "", BACKUP_VERSION_KEY).await?;
+        Ok(BackupKeys {
+            decryption_key,
+            backup_version,
+        })
+    }
+
+    async fn load_dehydrated_device_pickle_key(
+        &self,
+    ) -> Result<Option<DehydratedDeviceKey>, Self::Error> {
+        self.get(KV, "", "", DEHYDRATED_DEVICE_PICKLE_KEY).await
+    }
+
+    async fn delete_dehydrated_device_pickle_key(&self) -> Result<(), Self::Error> {
+        let _: Option<serde_json::Value> = self
+            .db
+            .remove(KV, "", "", DEHYDRATED_DEVICE_PICKLE_KEY)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_outbound_group_session(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OutboundGroupSession>, Self::Error> {
+        let pickle: Option<PickledOutboundGroupSession> = self
+            .get(OUTBOUND_GROUP_SESSION, room_id.as_str(), "", "")
+            .await?;
+        let Some(pickle) = pickle else {
+            return Ok(None);
+        };
+        let account = self
+            .get_static_account()
+            .ok_or(SurrealStoreErr::AccountUnset)?;
+        OutboundGroupSession::from_pickle(account.device_id, account.identity_keys, pickle)
+            .map(Some)
+            .map_err(|_| SurrealStoreErr::Unpickle)
+    }
+
+    async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>, Self::Error> {
+        Ok(self
+            .list(TRACKED_USER, "", "")
+            .await?
+            .into_iter()
+            .map(|(_, user)| user)
+            .collect())
+    }
+
+    async fn save_tracked_users(&self, users: &[(&UserId, bool)]) -> Result<(), Self::Error> {
+        let mut batch = Batch::default();
+        for (user_id, dirty) in users {
+            let user = TrackedUser {
+                user_id: (*user_id).to_owned(),
+                dirty: *dirty,
+            };
+            self.batch_set(&mut batch, TRACKED_USER, "", "", user_id.as_str(), &user)?;
+        }
+        self.db.commit(batch).await
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceData>, Self::Error> {
+        self.get(DEVICE, "", user_id.as_str(), device_id.as_str())
+            .await
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> Result<HashMap<OwnedDeviceId, DeviceData>, Self::Error> {
+        Ok(self
+            .list::<DeviceData>(DEVICE, "", user_id.as_str())
+            .await?
+            .into_iter()
+            .map(|(_, device)| (device.device_id().to_owned(), device))
+            .collect())
+    }
+
+    async fn get_own_device(&self) -> Result<DeviceData, Self::Error> {
+        let account = self
+            .get_static_account()
+            .ok_or(SurrealStoreErr::AccountUnset)?;
+        Ok(self
+            .get_device(&account.user_id, &account.device_id)
+            .await?
+            .expect("Invalid state: Should always have our own device"))
+    }
+
+    async fn get_user_identity(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<UserIdentityData>, Self::Error> {
+        self.get(IDENTITY, "", "", user_id.as_str()).await
+    }
+
+    async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool, Self::Error> {
+        let known: Option<bool> = self
+            .get(OLM_HASH, "", &message_hash.sender_key, &message_hash.hash)
+            .await?;
+        Ok(known.is_some())
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<Option<GossipRequest>, Self::Error> {
+        self.get(KEY_REQUEST, "", "", request_id.as_str()).await
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        secret_info: &SecretInfo,
+    ) -> Result<Option<GossipRequest>, Self::Error> {
+        Ok(self
+            .list::<GossipRequest>(KEY_REQUEST, "", "")
+            .await?
+            .into_iter()
+            .map(|(_, request)| request)
+            .find(|request| request.info == *secret_info))
+    }
+
+    async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>, Self::Error> {
+        Ok(self
+            .list::<GossipRequest>(KEY_REQUEST, "", "")
+            .await?
+            .into_iter()
+            .map(|(_, request)| request)
+            .filter(|request| !request.sent_out)
+            .collect())
+    }
+
+    async fn delete_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<(), Self::Error> {
+        let _: Option<serde_json::Value> = self
+            .db
+            .remove(KEY_REQUEST, "", "", request_id.as_str())
+            .await?;
+        Ok(())
+    }
+
+    async fn get_secrets_from_inbox(
+        &self,
+        secret_name: &SecretName,
+    ) -> Result<Vec<GossippedSecret>, Self::Error> {
+        Ok(self
+            .list(SECRET, "", secret_name.as_str())
+            .await?
+            .into_iter()
+            .map(|(_, secret)| secret)
+            .collect())
+    }
+
+    async fn delete_secrets_from_inbox(&self, secret_name: &SecretName) -> Result<(), Self::Error> {
+        self.db.clear_scope(SECRET, "", secret_name.as_str()).await
+    }
+
+    async fn get_room_settings(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RoomSettings>, Self::Error> {
+        self.get(ROOM_SETTINGS, room_id.as_str(), "", "").await
+    }
+
+    async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.get(CUSTOM, "", "", key).await
+    }
+
+    async fn set_custom_value(&self, key: &str, value: Vec<u8>) -> Result<(), Self::Error> {
+        self.set(CUSTOM, "", "", key, &value).await
+    }
+
+    async fn remove_custom_value(&self, key: &str) -> Result<(), Self::Error> {
+        let _: Option<serde_json::Value> = self.db.remove(CUSTOM, "", "", key).await?;
+        Ok(())
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error> {
+        Ok(try_take_leased_lock(
+            &mut self.leases.lock().unwrap(),
+            lease_duration_ms,
+            key,
+            holder,
+        ))
+    }
+
+    async fn next_batch_token(&self) -> Result<Option<String>, Self::Error> {
+        self.get(KV, "", "", NEXT_BATCH_TOKEN_KEY).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use matrix_sdk_crypto::{cryptostore_integration_tests, cryptostore_integration_tests_time};
+
+    use super::SurrealCryptoStore;
+    use crate::matrix_store::AccountDb;
+
+    static DATABASES: Mutex<Option<HashMap<String, AccountDb>>> = Mutex::new(None);
+
+    /// Stores opened under the same name share one in-memory database, so the
+    /// suite can check what survives a reopen.
+    async fn get_store(
+        name: &str,
+        passphrase: Option<&str>,
+        clear_data: bool,
+    ) -> SurrealCryptoStore {
+        let existing = DATABASES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .get(name)
+            .cloned();
+        let db = match existing {
+            Some(db) if !clear_data => db,
+            _ => {
+                let db = AccountDb::in_memory().await.unwrap();
+                DATABASES
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(HashMap::new)
+                    .insert(name.to_owned(), db.clone());
+                db
+            }
+        };
+        SurrealCryptoStore::open(db, passphrase).await.unwrap()
+    }
+
+    cryptostore_integration_tests!();
+    cryptostore_integration_tests_time!();
+
+    mod encrypted {
+        use matrix_sdk_crypto::{
+            cryptostore_integration_tests, cryptostore_integration_tests_time,
+        };
+
+        use super::SurrealCryptoStore;
+
+        async fn get_store(
+            name: &str,
+            _passphrase: Option<&str>,
+            clear_data: bool,
+        ) -> SurrealCryptoStore {
+            super::get_store(
+                &format!("encrypted_{name}"),
+                Some("default_test_password"),
+                clear_data,
+            )
+            .await
+        }
+
+        cryptostore_integration_tests!();
+        cryptostore_integration_tests_time!();
+    }
+}
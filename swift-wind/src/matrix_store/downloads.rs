@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use ruma::MilliSecondsSinceUnixEpoch;
+use serde::{Deserialize, Serialize};
+
+use super::{AccountDb, SurrealStoreErr};
+
+const DOWNLOAD: &str = "download";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DownloadOutcome {
+    Done,
+    Cancelled,
+    Failed(String),
+}
+
+/// A download that ended, kept so the download manager can list it later.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadRecord {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: Option<u64>,
+    pub outcome: DownloadOutcome,
+    pub finished: MilliSecondsSinceUnixEpoch,
+}
+
+impl AccountDb {
+    pub async fn add_download(&self, record: &DownloadRecord) -> Result<(), SurrealStoreErr> {
+        let key = format!("{}-{}", record.finished.get(), record.path.display());
+        self.set(DOWNLOAD, "", "", &key, record).await
+    }
+
+    /// Past downloads, most recent first.
+    pub async fn downloads(&self) -> Result<Vec<DownloadRecord>, SurrealStoreErr> {
+        let downloads: Vec<(String, DownloadRecord)> = self.list(DOWNLOAD, "", "").await?;
+        let mut downloads: Vec<_> = downloads.into_iter().map(|(_, record)| record).collect();
+        downloads.sort_by_key(|record| std::cmp::Reverse(record.finished));
+        Ok(downloads)
+    }
+
+    pub async fn clear_downloads(&self) -> Result<(), SurrealStoreErr> {
+        self.clear_table(DOWNLOAD).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ruma::{MilliSecondsSinceUnixEpoch, UInt};
+
+    use super::{DownloadOutcome, DownloadRecord};
+    use crate::matrix_store::AccountDb;
+
+    #[test_log::test(tokio::test)]
+    async fn history_roundtrip() {
+        let db = AccountDb::in_memory().await.unwrap();
+        let record = |name: &str, finished: u32, outcome| DownloadRecord {
+            name: name.to_owned(),
+            path: PathBuf::from("/downloads").join(name),
+            size: Some(42),
+            outcome,
+            finished: MilliSecondsSinceUnixEpoch(UInt::from(finished)),
+        };
+        let older = record("notes.txt", 9, DownloadOutcome::Done);
+        let newer = record(
+            "report.pdf",
+            10,
+            DownloadOutcome::Failed("server went away".to_owned()),
+        );
+
+        db.add_download(&newer).await.unwrap();
+        db.add_download(&older).await.unwrap();
+        assert_eq!(db.downloads().await.unwrap(), vec![newer, older]);
+
+        db.clear_downloads().await.unwrap();
+        assert!(db.downloads().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,579 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex as StdMutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use matrix_sdk::{
+    async_trait,
+    linked_chunk::{ChunkContent, ChunkIdentifier, RawChunk, Update},
+    store_locks::memory_store_helper::try_take_leased_lock,
+};
+use matrix_sdk_base::{
+    event_cache::{
+        Event, Gap,
+        store::{
+            EventCacheStore,
+            media::{
+                EventCacheStoreMedia, IgnoreMediaRetentionPolicy, MediaRetentionPolicy,
+                MediaService,
+            },
+        },
+    },
+    media::{MediaRequestParameters, UniqueKey},
+};
+use ruma::{MxcUri, RoomId, serde::Base64, time::Instant};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{AccountDb, SurrealStoreErr};
+
+const KV: &str = "event_cache_kv";
+const CHUNK: &str = "event_cache_chunk";
+const MEDIA: &str = "event_cache_media";
+const MEDIA_CONTENT: &str = "event_cache_media_content";
+
+const MEDIA_RETENTION_POLICY_KEY: &str = "media_retention_policy";
+
+#[derive(Debug, Serialize, Deserialize)]
+enum StoredChunkContent {
+    Items(Vec<Event>),
+    Gap { prev_token: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredChunk {
+    previous: Option<u64>,
+    next: Option<u64>,
+    content: StoredChunkContent,
+}
+
+/// Media bookkeeping, kept apart from the bytes so cleanups don't load every file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredMedia {
+    size: usize,
+    ignore_policy: bool,
+    last_access_ms: u64,
+}
+
+fn to_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn from_ms(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+/// matrix-sdk [`EventCacheStore`] keeping room timelines as linked chunks, plus the media cache.
+#[derive(Debug)]
+pub struct SurrealEventCacheStore {
+    db: AccountDb,
+    media_service: MediaService,
+    leases: StdMutex<HashMap<String, (String, Instant)>>,
+    update_lock: Mutex<()>,
+}
+
+impl SurrealEventCacheStore {
+    pub async fn open(db: AccountDb) -> Result<Self, SurrealStoreErr> {
+        db.define_tables(&[KV, CHUNK, MEDIA, MEDIA_CONTENT]).await?;
+        let media_service = MediaService::new();
+        media_service.restore(db.get(KV, "", "", MEDIA_RETENTION_POLICY_KEY).await?);
+        Ok(Self {
+            db,
+            media_service,
+            leases: Default::default(),
+            update_lock: Mutex::new(()),
+        })
+    }
+
+    fn chunk_key(id: ChunkIdentifier) -> String {
+        format!("{:020}", id.index())
+    }
+
+    async fn chunk(&self, room: &str, id: ChunkIdentifier) -> Result<StoredChunk, SurrealStoreErr> {
+        self.db
+            .get(CHUNK, room, "", &Self::chunk_key(id))
+            .await?
+            .ok_or_else(|| SurrealStoreErr::InvalidData(format!("unknown chunk {}", id.index())))
+    }
+
+    async fn set_chunk(
+        &self,
+        room: &str,
+        id: ChunkIdentifier,
+        chunk: &StoredChunk,
+    ) -> Result<(), SurrealStoreErr> {
+        self.db
+            .set(CHUNK, room, "", &Self::chunk_key(id), chunk)
+            .await
+    }
+
+    async fn items_chunk(
+        &self,
+        room: &str,
+        id: ChunkIdentifier,
+    ) -> Result<(StoredChunk, Vec<Event>), SurrealStoreErr> {
+        let mut chunk = self.chunk(room, id).await?;
+        let content = std::mem::replace(&mut chunk.content, StoredChunkContent::Items(Vec::new()));
+        match content {
+            StoredChunkContent::Items(items) => Ok((chunk, items)),
+            StoredChunkContent::Gap { .. } => Err(SurrealStoreErr::InvalidData(format!(
+                "chunk {} is a gap",
+                id.index()
+            ))),
+        }
+    }
+
+    async fn insert_chunk(
+        &self,
+        room: &str,
+        previous: Option<ChunkIdentifier>,
+        new: ChunkIdentifier,
+        next: Option<ChunkIdentifier>,
+        content: StoredChunkContent,
+    ) -> Result<(), SurrealStoreErr> {
+        if let Some(previous) = previous {
+            let mut chunk = self.chunk(room, previous).await?;
+            chunk.next = Some(new.index());
+            self.set_chunk(room, previous, &chunk).await?;
+        }
+        if let Some(next) = next {
+            let mut chunk = self.chunk(room, next).await?;
+            chunk.previous = Some(new.index());
+            self.set_chunk(room, next, &chunk).await?;
+        }
+        let chunk = StoredChunk {
+            previous: previous.map(|id| id.index()),
+            next: next.map(|id| id.index()),
+            content,
+        };
+        self.set_chunk(room, new, &chunk).await
+    }
+
+    async fn remove_chunk(&self, room: &str, id: ChunkIdentifier) -> Result<(), SurrealStoreErr> {
+        let removed: Option<StoredChunk> = self
+            .db
+            .remove(CHUNK, room, "", &Self::chunk_key(id))
+            .await?;
+        let Some(removed) = removed else {
+            return Err(SurrealStoreErr::InvalidData(format!(
+                "unknown chunk {}",
+                id.index()
+            )));
+        };
+        if let Some(previous) = removed.previous.map(ChunkIdentifier::new) {
+            let mut chunk = self.chunk(room, previous).await?;
+            chunk.next = removed.next;
+            self.set_chunk(room, previous, &chunk).await?;
+        }
+        if let Some(next) = removed.next.map(ChunkIdentifier::new) {
+            let mut chunk = self.chunk(room, next).await?;
+            chunk.previous = removed.previous;
+            self.set_chunk(room, next, &chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn media(&self, uri: &MxcUri) -> Result<Vec<(String, StoredMedia)>, SurrealStoreErr> {
+        self.db.list(MEDIA, "", uri.as_str()).await
+    }
+
+    async fn remove_media(&self, uri: &str, key: &str) -> Result<(), SurrealStoreErr> {
+        let _: Option<StoredMedia> = self.db.remove(MEDIA, "", uri, key).await?;
+        let _: Option<Base64> = self.db.remove(MEDIA_CONTENT, "", uri, key).await?;
+        Ok(())
+    }
+
+    /// Reads a media file and bumps its last access time.
+    async fn touch_media(
+        &self,
+        uri: &str,
+        key: &str,
+        mut media: StoredMedia,
+        current_time: SystemTime,
+    ) -> Result<Option<Vec<u8>>, SurrealStoreErr> {
+        let data: Option<Base64> = self.db.get(MEDIA_CONTENT, "", uri, key).await?;
+        let Some(data) = data else {
+            return Ok(None);
+        };
+        media.last_access_ms = to_ms(current_time);
+        self.db.set(MEDIA, "", uri, key, &media).await?;
+        Ok(Some(data.into_inner()))
+    }
+}
+
+#[async_trait]
+impl EventCacheStore for SurrealEventCacheStore {
+    type Error = SurrealStoreErr;
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error> {
+        Ok(try_take_leased_lock(
+            &mut self.leases.lock().unwrap(),
+            lease_duration_ms,
+            key,
+            holder,
+        ))
+    }
+
+    async fn handle_linked_chunk_updates(
+        &self,
+        room_id: &RoomId,
+        updates: Vec<Update<Event, Gap>>,
+    ) -> Result<(), Self::Error> {
+        let _guard = self.update_lock.lock().await;
+        let room = room_id.as_str();
+
+        for update in updates {
+            match update {
+                Update::NewItemsChunk {
+                    previous,
+                    new,
+                    next,
+                } => {
+                    let content = StoredChunkContent::Items(Vec::new());
+                    self.insert_chunk(room, previous, new, next, content)
+                        .await?;
+                }
+                Update::NewGapChunk {
+                    previous,
+                    new,
+                    next,
+                    gap,
+                } => {
+                    let content = StoredChunkContent::Gap {
+                        prev_token: gap.prev_token,
+                    };
+                    self.insert_chunk(room, previous, new, next, content)
+                        .await?;
+                }
+                Update::RemoveChunk(id) => {
+                    self.remove_chunk(room, id).await?;
+                }
+                Update::PushItems { at, items: new } => {
+                    let id = at.chunk_identifier();
+                    let (mut chunk, mut items) = self.items_chunk(room, id).await?;
+                    let index = at.index().min(items.len());
+                    items.splice(index..index, new);
+                    chunk.content = StoredChunkContent::Items(items);
+                    self.set_chunk(room, id, &chunk).await?;
+                }
+                Update::ReplaceItem { at, item } => {
+                    let id = at.chunk_identifier();
+                    let (mut chunk, mut items) = self.items_chunk(room, id).await?;
+                    let Some(slot) = items.get_mut(at.index()) else {
+                        return Err(SurrealStoreErr::InvalidData(format!(
+                            "no item {} in chunk {}",
+                            at.index(),
+                            id.index()
+                        )));
+                    };
+                    *slot = item;
+                    chunk.content = StoredChunkContent::Items(items);
+                    self.set_chunk(room, id, &chunk).await?;
+                }
+                Update::RemoveItem { at } => {
+                    let id = at.chunk_identifier();
+                    let (mut chunk, mut items) = self.items_chunk(room, id).await?;
+                    if at.index() >= items.len() {
+                        return Err(SurrealStoreErr::InvalidData(format!(
+                            "no item {} in chunk {}",
+                            at.index(),
+                            id.index()
+                        )));
+                    }
+                    items.remove(at.index());
+                    chunk.content = StoredChunkContent::Items(items);
+                    self.set_chunk(room, id, &chunk).await?;
+                }
+                Update::DetachLastItems { at } => {
+                    let id = at.chunk_identifier();
+                    let (mut chunk, mut items) = self.items_chunk(room, id).await?;
+                    items.truncate(at.index());
+                    chunk.content = StoredChunkContent::Items(items);
+                    self.set_chunk(room, id, &chunk).await?;
+                }
+                Update::StartReattachItems | Update::EndReattachItems => {}
+                Update::Clear => {
+                    self.db.clear_room(CHUNK, room).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reload_linked_chunk(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawChunk<Event, Gap>>, Self::Error> {
+        let chunks: Vec<(String, StoredChunk)> = self.db.list(CHUNK, room_id.as_str(), "").await?;
+        chunks
+            .into_iter()
+            .map(|(key, chunk)| {
+                let Ok(id) = key.parse::<u64>() else {
+                    return Err(SurrealStoreErr::InvalidData(format!("bad chunk key {key}")));
+                };
+                let content = match chunk.content {
+                    StoredChunkContent::Items(items) => ChunkContent::Items(items),
+                    StoredChunkContent::Gap { prev_token } => ChunkContent::Gap(Gap { prev_token }),
+                };
+                Ok(RawChunk {
+                    content,
+                    previous: chunk.previous.map(ChunkIdentifier::new),
+                    identifier: ChunkIdentifier::new(id),
+                    next: chunk.next.map(ChunkIdentifier::new),
+                })
+            })
+            .collect()
+    }
+
+    async fn clear_all_rooms_chunks(&self) -> Result<(), Self::Error> {
+        let _guard = self.update_lock.lock().await;
+        self.db.clear_table(CHUNK).await
+    }
+
+    async fn add_media_content(
+        &self,
+        request: &MediaRequestParameters,
+        content: Vec<u8>,
+        ignore_policy: IgnoreMediaRetentionPolicy,
+    ) -> Result<(), Self::Error> {
+        self.media_service
+            .add_media_content(self, request, content, ignore_policy)
+            .await
+    }
+
+    async fn replace_media_key(
+        &self,
+        from: &MediaRequestParameters,
+        to: &MediaRequestParameters,
+    ) -> Result<(), Self::Error> {
+        let (from_uri, from_key) = (from.uri().as_str(), from.unique_key());
+        let media: Option<StoredMedia> = self.db.get(MEDIA, "", from_uri, &from_key).await?;
+        let data: Option<Base64> = self.db.get(MEDIA_CONTENT, "", from_uri, &from_key).await?;
+        let (Some(media), Some(data)) = (media, data) else {
+            return Ok(());
+        };
+        self.remove_media(from_uri, &from_key).await?;
+
+        let (to_uri, to_key) = (to.uri().as_str(), to.unique_key());
+        self.db
+            .set(MEDIA_CONTENT, "", to_uri, &to_key, &data)
+            .await?;
+        self.db.set(MEDIA, "", to_uri, &to_key, &media).await
+    }
+
+    async fn get_media_content(
+        &self,
+        request: &MediaRequestParameters,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.media_service.get_media_content(self, request).await
+    }
+
+    async fn remove_media_content(
+        &self,
+        request: &MediaRequestParameters,
+    ) -> Result<(), Self::Error> {
+        self.remove_media(request.uri().as_str(), &request.unique_key())
+            .await
+    }
+
+    async fn get_media_content_for_uri(
+        &self,
+        uri: &MxcUri,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.media_service
+            .get_media_content_for_uri(self, uri)
+            .await
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<(), Self::Error> {
+        self.db.clear_scope(MEDIA, "", uri.as_str()).await?;
+        self.db.clear_scope(MEDIA_CONTENT, "", uri.as_str()).await
+    }
+
+    async fn set_media_retention_policy(
+        &self,
+        policy: MediaRetentionPolicy,
+    ) -> Result<(), Self::Error> {
+        self.media_service
+            .set_media_retention_policy(self, policy)
+            .await
+    }
+
+    fn media_retention_policy(&self) -> MediaRetentionPolicy {
+        self.media_service.media_retention_policy()
+    }
+
+    async fn set_ignore_media_retention_policy(
+        &self,
+        request: &MediaRequestParameters,
+        ignore_policy: IgnoreMediaRetentionPolicy,
+    ) -> Result<(), Self::Error> {
+        self.media_service
+            .set_ignore_media_retention_policy(self, request, ignore_policy)
+            .await
+    }
+
+    async fn clean_up_media_cache(&self) -> Result<(), Self::Error> {
+        self.media_service.clean_up_media_cache(self).await
+    }
+}
+
+#[async_trait]
+impl EventCacheStoreMedia for SurrealEventCacheStore {
+    type Error = SurrealStoreErr;
+
+    async fn media_retention_policy_inner(
+        &self,
+    ) -> Result<Option<MediaRetentionPolicy>, Self::Error> {
+        self.db.get(KV, "", "", MEDIA_RETENTION_POLICY_KEY).await
+    }
+
+    async fn set_media_retention_policy_inner(
+        &self,
+        policy: MediaRetentionPolicy,
+    ) -> Result<(), Self::Error> {
+        self.db
+            .set(KV, "", "", MEDIA_RETENTION_POLICY_KEY, &policy)
+            .await
+    }
+
+    async fn add_media_content_inner(
+        &self,
+        request: &MediaRequestParameters,
+        content: Vec<u8>,
+        current_time: SystemTime,
+        policy: MediaRetentionPolicy,
+        ignore_policy: IgnoreMediaRetentionPolicy,
+    ) -> Result<(), Self::Error> {
+        let (uri, key) = (request.uri().as_str(), request.unique_key());
+        self.remove_media(uri, &key).await?;
+
+        let ignore_policy = ignore_policy.is_yes();
+        if !ignore_policy && policy.exceeds_max_file_size(content.len()) {
+            return Ok(());
+        }
+
+        let media = StoredMedia {
+            size: content.len(),
+            ignore_policy,
+            last_access_ms: to_ms(current_time),
+        };
+        let data: Base64 = Base64::new(content);
+        self.db.set(MEDIA_CONTENT, "", uri, &key, &data).await?;
+        self.db.set(MEDIA, "", uri, &key, &media).await
+    }
+
+    async fn set_ignore_media_retention_policy_inner(
+        &self,
+        request: &MediaRequestParameters,
+        ignore_policy: IgnoreMediaRetentionPolicy,
+    ) -> Result<(), Self::Error> {
+        let (uri, key) = (request.uri().as_str(), request.unique_key());
+        let media: Option<StoredMedia> = self.db.get(MEDIA, "", uri, &key).await?;
+        let Some(mut media) = media else {
+            return Ok(());
+        };
+        media.ignore_policy = ignore_policy.is_yes();
+        self.db.set(MEDIA, "", uri, &key, &media).await
+    }
+
+    async fn get_media_content_inner(
+        &self,
+        request: &MediaRequestParameters,
+        current_time: SystemTime,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let (uri, key) = (request.uri().as_str(), request.unique_key());
+        let media: Option<StoredMedia> = self.db.get(MEDIA, "", uri, &key).await?;
+        let Some(media) = media else {
+            return Ok(None);
+        };
+        self.touch_media(uri, &key, media, current_time).await
+    }
+
+    async fn get_media_content_for_uri_inner(
+        &self,
+        uri: &MxcUri,
+        current_time: SystemTime,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let Some((key, media)) = self.media(uri).await?.into_iter().next() else {
+            return Ok(None);
+        };
+        self.touch_media(uri.as_str(), &key, media, current_time)
+            .await
+    }
+
+    async fn clean_up_media_cache_inner(
+        &self,
+        policy: MediaRetentionPolicy,
+        current_time: SystemTime,
+    ) -> Result<(), Self::Error> {
+        if !policy.has_limitations() {
+            return Ok(());
+        }
+
+        let mut kept = Vec::new();
+        let mut removed = Vec::new();
+        let rows: Vec<(String, String, StoredMedia)> = self.db.list_room(MEDIA, "").await?;
+        for (uri, key, media) in rows {
+            let expired = !media.ignore_policy
+                && (policy.exceeds_max_file_size(media.size)
+                    || policy.has_content_expired(current_time, from_ms(media.last_access_ms)));
+            if expired {
+                removed.push((uri, key));
+            } else {
+                kept.push((uri, key, media));
+            }
+        }
+
+        if let Some(max_cache_size) = policy.max_cache_size {
+            kept.sort_by_key(|(_, _, media)| std::cmp::Reverse(media.last_access_ms));
+            let mut cache_size = 0usize;
+            let mut full = false;
+            for (uri, key, media) in kept {
+                if media.ignore_policy {
+                    continue;
+                }
+                if !full {
+                    cache_size = cache_size.saturating_add(media.size);
+                    full = cache_size > max_cache_size;
+                }
+                if full {
+                    removed.push((uri, key));
+                }
+            }
+        }
+
+        for (uri, key) in removed {
+            self.remove_media(&uri, &key).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_base::{
+        event_cache_store_integration_tests, event_cache_store_integration_tests_time,
+        event_cache_store_media_integration_tests,
+    };
+
+    use super::SurrealEventCacheStore;
+    use crate::matrix_store::{AccountDb, SurrealStoreErr};
+
+    async fn get_event_cache_store() -> Result<SurrealEventCacheStore, SurrealStoreErr> {
+        SurrealEventCacheStore::open(AccountDb::in_memory().await?).await
+    }
+
+    event_cache_store_integration_tests!();
+    event_cache_store_integration_tests_time!();
+    event_cache_store_media_integration_tests!(with_media_size_tests);
+}
@@ -0,0 +1,71 @@
+use ruma::MilliSecondsSinceUnixEpoch;
+use tokio::sync::OnceCell;
+
+use super::{AccountDb, SurrealStoreErr, data_dir};
+
+const RECENT_SERVER: &str = "recent_server";
+
+const MAX_RECENT_SERVERS: usize = 5;
+
+/// App wide database, opened once since SurrealKV doesn't share a directory between handles.
+static APP_DB: OnceCell<AccountDb> = OnceCell::const_new();
+
+async fn app_db() -> Result<&'static AccountDb, SurrealStoreErr> {
+    APP_DB
+        .get_or_try_init(|| AccountDb::open(data_dir().join("app")))
+        .await
+}
+
+/// Servers typed on the login and register pages, most recent first.
+pub async fn recent_servers() -> Result<Vec<String>, SurrealStoreErr> {
+    list(app_db().await?).await
+}
+
+pub async fn add_recent_server(server: &str) -> Result<(), SurrealStoreErr> {
+    add(app_db().await?, server).await
+}
+
+async fn list(db: &AccountDb) -> Result<Vec<String>, SurrealStoreErr> {
+    let mut servers: Vec<(String, MilliSecondsSinceUnixEpoch)> =
+        db.list(RECENT_SERVER, "", "").await?;
+    servers.sort_by_key(|(_, used)| std::cmp::Reverse(*used));
+    Ok(servers.into_iter().map(|(server, _)| server).collect())
+}
+
+async fn add(db: &AccountDb, server: &str) -> Result<(), SurrealStoreErr> {
+    let server = server.trim();
+    db.set(
+        RECENT_SERVER,
+        "",
+        "",
+        server,
+        &MilliSecondsSinceUnixEpoch::now(),
+    )
+    .await?;
+    for old in list(db).await?.into_iter().skip(MAX_RECENT_SERVERS) {
+        let _: Option<MilliSecondsSinceUnixEpoch> = db.remove(RECENT_SERVER, "", "", &old).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_RECENT_SERVERS, add, list};
+    use crate::matrix_store::AccountDb;
+
+    #[test_log::test(tokio::test)]
+    async fn keeps_most_recent() {
+        let db = AccountDb::in_memory().await.unwrap();
+        for index in 0..=MAX_RECENT_SERVERS {
+            add(&db, &format!("server{index}.org")).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        add(&db, " server1.org ").await.unwrap();
+
+        let servers = list(&db).await.unwrap();
+        assert_eq!(servers.len(), MAX_RECENT_SERVERS);
+        assert_eq!(servers[0], "server1.org");
+        assert_eq!(servers[1], format!("server{MAX_RECENT_SERVERS}.org"));
+        assert!(!servers.contains(&"server0.org".to_owned()));
+    }
+}
@@ -0,0 +1,323 @@
+use matrix_sdk::authentication::matrix::MatrixSession;
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId};
+use serde::{Deserialize, Serialize};
+use tracing::{trace, warn};
+use url::Url;
+
+use super::{AccountDb, SurrealStoreErr, accounts_dir, forget_store_passphrase};
+use crate::matrix_oidc::OidcSession;
+
+const ACCOUNT: &str = "account";
+
+const SESSION_KEY: &str = "session";
+const OIDC_KEY: &str = "oidc";
+const LAST_ROOM_KEY: &str = "last_room";
+const LAST_ACTIVE_KEY: &str = "last_active";
+
+/// Everything needed to bring a logged in account back after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub homeserver: Url,
+    pub session: MatrixSession,
+}
+
+impl AccountDb {
+    pub async fn save_session(
+        &self,
+        homeserver: &Url,
+        session: &MatrixSession,
+    ) -> Result<(), SurrealStoreErr> {
+        let stored = StoredSession {
+            homeserver: homeserver.clone(),
+            session: session.clone(),
+        };
+        self.set_encrypted(ACCOUNT, SESSION_KEY, &stored).await
+    }
+
+    /// The tokens are encrypted on disk, reading them takes the store passphrase.
+    pub async fn session(&self) -> Result<Option<StoredSession>, SurrealStoreErr> {
+        self.get_encrypted(ACCOUNT, SESSION_KEY).await
+    }
+
+    /// Whether a session is stored, without decrypting it.
+    pub async fn has_session(&self) -> Result<bool, SurrealStoreErr> {
+        Ok(self
+            .get::<serde_json::Value>(ACCOUNT, "", "", SESSION_KEY)
+            .await?
+            .is_some())
+    }
+
+    /// Stored next to the session of accounts that signed in through OIDC.
+    pub async fn save_oidc_session(&self, oidc: &OidcSession) -> Result<(), SurrealStoreErr> {
+        self.set_encrypted(ACCOUNT, OIDC_KEY, oidc).await
+    }
+
+    pub async fn oidc_session(&self) -> Result<Option<OidcSession>, SurrealStoreErr> {
+        self.get_encrypted(ACCOUNT, OIDC_KEY).await
+    }
+
+    /// Forgets the session, the rest of the database is swept on the next startup.
+    pub async fn remove_session(&self) -> Result<(), SurrealStoreErr> {
+        self.clear_table(ACCOUNT).await
+    }
+
+    pub async fn set_last_room(&self, room_id: Option<&RoomId>) -> Result<(), SurrealStoreErr> {
+        match room_id {
+            Some(room_id) => self.set(ACCOUNT, "", "", LAST_ROOM_KEY, &room_id).await,
+            None => {
+                let _: Option<OwnedRoomId> = self.remove(ACCOUNT, "", "", LAST_ROOM_KEY).await?;
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn last_room(&self) -> Result<Option<OwnedRoomId>, SurrealStoreErr> {
+        self.get(ACCOUNT, "", "", LAST_ROOM_KEY).await
+    }
+
+    /// Marks this account as the one in front, see [`stored_sessions`].
+    pub async fn touch_active(&self) -> Result<(), SurrealStoreErr> {
+        let now = MilliSecondsSinceUnixEpoch::now();
+        self.set(ACCOUNT, "", "", LAST_ACTIVE_KEY, &now).await
+    }
+
+    pub async fn last_active(&self) -> Result<Option<MilliSecondsSinceUnixEpoch>, SurrealStoreErr> {
+        self.get(ACCOUNT, "", "", LAST_ACTIVE_KEY).await
+    }
+
+    /// Deletes the database of an account that never finished logging in, with its passphrase.
+    ///
+    /// Nothing is deleted once a session is stored, or for in-memory databases.
+    pub async fn discard(self) -> Result<(), SurrealStoreErr> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        if self.has_session().await? {
+            warn!(
+                "not discarding logged in account database {}",
+                path.display()
+            );
+            return Ok(());
+        }
+        trace!(
+            "removing account database without session {}",
+            path.display()
+        );
+        drop(self);
+        std::fs::remove_dir_all(&path)?;
+        if let Err(err) = forget_store_passphrase(&path).await {
+            warn!(
+                "failed to remove store passphrase of {}: {err}",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Opens every account database that still holds a session, most recently active first.
+///
+/// Databases without one (logged out, or abandoned before login finished) are deleted,
+/// so this must not run while an account is being connected.
+pub async fn stored_sessions() -> Result<Vec<(AccountDb, StoredSession)>, SurrealStoreErr> {
+    let dir = accounts_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sessions = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let db = match AccountDb::open(&path).await {
+            Ok(db) => db,
+            Err(err) => {
+                warn!(
+                    "skipping account database {} that won't open: {err}",
+                    path.display()
+                );
+                continue;
+            }
+        };
+        match db.session().await {
+            Ok(Some(session)) => {
+                trace!("found stored session in {}", path.display());
+                let last_active = db.last_active().await.unwrap_or_default();
+                sessions.push((last_active, db, session));
+            }
+            Ok(None) => {
+                if let Err(err) = db.discard().await {
+                    warn!(
+                        "couldn't discard account database {}: {err}",
+                        path.display()
+                    );
+                }
+            }
+            Err(err) => warn!(
+                "skipping unreadable account database {}: {err}",
+                path.display()
+            ),
+        }
+    }
+    sessions.sort_by_key(|(last_active, _, _)| std::cmp::Reverse(*last_active));
+    Ok(sessions
+        .into_iter()
+        .map(|(_, db, session)| (db, session))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::{
+        SessionMeta,
+        authentication::matrix::{MatrixSession, MatrixSessionTokens},
+    };
+    use ruma::{device_id, room_id, user_id};
+    use url::Url;
+
+    use crate::matrix_oidc::OidcSession;
+    use crate::matrix_store::{AccountDb, Row};
+
+    #[test_log::test(tokio::test)]
+    async fn session_roundtrip() {
+        let db = AccountDb::in_memory().await.unwrap();
+        let homeserver = Url::parse("http://127.0.0.1:8008").unwrap();
+        let session = MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@alice:localhost").to_owned(),
+                device_id: device_id!("ALICEDEVICE").to_owned(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: "access".to_owned(),
+                refresh_token: Some("refresh".to_owned()),
+            },
+        };
+
+        assert!(db.session().await.unwrap().is_none());
+        db.save_session(&homeserver, &session).await.unwrap();
+        db.set_last_room(Some(room_id!("!room:localhost")))
+            .await
+            .unwrap();
+
+        let stored = db.session().await.unwrap().unwrap();
+        assert_eq!(stored.homeserver, homeserver);
+        assert_eq!(stored.session.meta.user_id, session.meta.user_id);
+        assert_eq!(
+            stored.session.tokens.refresh_token.as_deref(),
+            Some("refresh")
+        );
+        assert_eq!(
+            db.last_room().await.unwrap().as_deref(),
+            Some(room_id!("!room:localhost"))
+        );
+        assert!(db.last_active().await.unwrap().is_none());
+        db.touch_active().await.unwrap();
+        assert!(db.last_active().await.unwrap().is_some());
+
+        assert!(db.oidc_session().await.unwrap().is_none());
+        let oidc: OidcSession = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "issuer": "https://auth.localhost/",
+                "authorization_endpoint": "https://auth.localhost/authorize",
+                "token_endpoint": "https://auth.localhost/oauth2/token",
+            },
+            "client_id": "client",
+            "refresh_token": "oidc-refresh",
+        }))
+        .unwrap();
+        db.save_oidc_session(&oidc).await.unwrap();
+        let stored = db.oidc_session().await.unwrap().unwrap();
+        assert_eq!(stored.client_id, "client");
+        assert_eq!(stored.refresh_token.as_deref(), Some("oidc-refresh"));
+
+        db.remove_session().await.unwrap();
+        assert!(db.session().await.unwrap().is_none());
+        assert!(db.oidc_session().await.unwrap().is_none());
+        assert!(db.last_room().await.unwrap().is_none());
+        assert!(db.last_active().await.unwrap().is_none());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn discard_without_session() {
+        let dir = std::env::temp_dir().join(format!("swift-wind-discard-{}", std::process::id()));
+        let pending = dir.join("pending");
+        let logged_in = dir.join("logged-in");
+
+        let db = AccountDb::open(&pending).await.unwrap();
+        db.discard().await.unwrap();
+        assert!(!pending.exists());
+
+        let db = AccountDb::open(&logged_in).await.unwrap();
+        let session = MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@alice:localhost").to_owned(),
+                device_id: device_id!("ALICEDEVICE").to_owned(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: "access".to_owned(),
+                refresh_token: None,
+            },
+        };
+        db.save_session(&Url::parse("http://127.0.0.1:8008").unwrap(), &session)
+            .await
+            .unwrap();
+        db.discard().await.unwrap();
+        assert!(logged_in.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn tokens_are_encrypted() {
+        let db = AccountDb::in_memory_encrypted("passphrase").await.unwrap();
+        let session = MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@alice:localhost").to_owned(),
+                device_id: device_id!("ALICEDEVICE").to_owned(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: "secret-access".to_owned(),
+                refresh_token: Some("secret-refresh".to_owned()),
+            },
+        };
+        db.save_session(&Url::parse("http://127.0.0.1:8008").unwrap(), &session)
+            .await
+            .unwrap();
+        let oidc: OidcSession = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "issuer": "https://auth.localhost/",
+                "authorization_endpoint": "https://auth.localhost/authorize",
+                "token_endpoint": "https://auth.localhost/oauth2/token",
+            },
+            "client_id": "client",
+            "refresh_token": "secret-oidc",
+        }))
+        .unwrap();
+        db.save_oidc_session(&oidc).await.unwrap();
+
+        let mut res = db
+            .db
+            .query("SELECT * OMIT id FROM type::table($table)")
+            .bind(("table", super::ACCOUNT))
+            .await
+            .unwrap();
+        let rows: Vec<Row> = res.take(0).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| !row.value.contains("secret")));
+
+        assert!(db.has_session().await.unwrap());
+        let stored = db.session().await.unwrap().unwrap();
+        assert_eq!(stored.session.tokens.access_token, "secret-access");
+        assert_eq!(
+            db.oidc_session()
+                .await
+                .unwrap()
+                .unwrap()
+                .refresh_token
+                .as_deref(),
+            Some("secret-oidc")
+        );
+    }
+}
@@ -0,0 +1,1154 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+use matrix_sdk::async_trait;
+use matrix_sdk_base::{
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, StateChanges, StateStore, StateStoreDataKey,
+    StateStoreDataValue,
+    deserialized_responses::{DisplayName, RawAnySyncOrStrippedState},
+    store::{
+        ChildTransactionId, DependentQueuedRequest, DependentQueuedRequestKind, QueueWedgeError,
+        QueuedRequest, QueuedRequestKind, SentRequestKey,
+    },
+};
+use ruma::{
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId,
+    OwnedTransactionId, OwnedUserId, RoomId, RoomVersionId, TransactionId, UserId,
+    canonical_json::{RedactedBecause, redact},
+    events::{
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
+        AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::member::{MembershipState, StrippedRoomMemberEvent, SyncRoomMemberEvent},
+    },
+    serde::Raw,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use super::{AccountDb, Batch, SurrealStoreErr};
+
+const KV: &str = "state_kv";
+const ACCOUNT_DATA: &str = "state_account_data";
+const ROOM_ACCOUNT_DATA: &str = "state_room_account_data";
+const PRESENCE: &str = "state_presence";
+const ROOM_INFO: &str = "state_room_info";
+const STATE_EVENT: &str = "state_event";
+const STRIPPED_STATE_EVENT: &str = "state_stripped_event";
+const MEMBER: &str = "state_member";
+const STRIPPED_MEMBER: &str = "state_stripped_member";
+const PROFILE: &str = "state_profile";
+const DISPLAY_NAME: &str = "state_display_name";
+const USER_RECEIPT: &str = "state_user_receipt";
+const EVENT_RECEIPT: &str = "state_event_receipt";
+const CUSTOM: &str = "state_custom";
+const SEND_QUEUE: &str = "state_send_queue";
+const DEPENDENT_QUEUE: &str = "state_dependent_queue";
+
+/// Tables holding per-room data, cleared by [`StateStore::remove_room`].
+const ROOM_TABLES: [&str; 12] = [
+    ROOM_ACCOUNT_DATA,
+    ROOM_INFO,
+    STATE_EVENT,
+    STRIPPED_STATE_EVENT,
+    MEMBER,
+    STRIPPED_MEMBER,
+    PROFILE,
+    DISPLAY_NAME,
+    USER_RECEIPT,
+    EVENT_RECEIPT,
+    SEND_QUEUE,
+    DEPENDENT_QUEUE,
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredQueuedRequest {
+    position: u64,
+    kind: QueuedRequestKind,
+    transaction_id: OwnedTransactionId,
+    error: Option<QueueWedgeError>,
+    priority: usize,
+    created_at: MilliSecondsSinceUnixEpoch,
+}
+
+impl From<StoredQueuedRequest> for QueuedRequest {
+    fn from(stored: StoredQueuedRequest) -> Self {
+        QueuedRequest {
+            kind: stored.kind,
+            transaction_id: stored.transaction_id,
+            error: stored.error,
+            priority: stored.priority,
+            created_at: stored.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredDependentRequest {
+    position: u64,
+    request: DependentQueuedRequest,
+}
+
+/// Receipts read and updated during one `save_changes`, written out with the rest of the batch.
+#[derive(Debug, Default)]
+struct PendingReceipts {
+    users: HashMap<(OwnedRoomId, String, OwnedUserId), (OwnedEventId, Receipt)>,
+    events: HashMap<(OwnedRoomId, String, OwnedEventId), Option<BTreeMap<OwnedUserId, Receipt>>>,
+}
+
+impl PendingReceipts {
+    fn write(self, batch: &mut Batch) -> Result<(), SurrealStoreErr> {
+        for ((room_id, scope, user_id), receipt) in &self.users {
+            batch.set(
+                USER_RECEIPT,
+                room_id.as_str(),
+                scope,
+                user_id.as_str(),
+                receipt,
+            )?;
+        }
+        for ((room_id, scope, event_id), users) in &self.events {
+            if let Some(users) = users {
+                batch.set(
+                    EVENT_RECEIPT,
+                    room_id.as_str(),
+                    scope,
+                    event_id.as_str(),
+                    users,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// matrix-sdk [`StateStore`] keeping room state, members, receipts and the send queue.
+#[derive(Debug)]
+pub struct SurrealStateStore {
+    db: AccountDb,
+    save_lock: Mutex<()>,
+}
+
+impl SurrealStateStore {
+    pub async fn open(db: AccountDb) -> Result<Self, SurrealStoreErr> {
+        db.define_tables(&[
+            KV,
+            ACCOUNT_DATA,
+            PRESENCE,
+            CUSTOM,
+            ROOM_ACCOUNT_DATA,
+            ROOM_INFO,
+            STATE_EVENT,
+            STRIPPED_STATE_EVENT,
+            MEMBER,
+            STRIPPED_MEMBER,
+            PROFILE,
+            DISPLAY_NAME,
+            USER_RECEIPT,
+            EVENT_RECEIPT,
+            SEND_QUEUE,
+            DEPENDENT_QUEUE,
+        ])
+        .await?;
+        Ok(Self {
+            db,
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// `(scope, key)` of a key-value entry.
+    fn kv_key(key: StateStoreDataKey<'_>) -> (&'static str, String) {
+        match key {
+            StateStoreDataKey::SyncToken => (StateStoreDataKey::SYNC_TOKEN, String::new()),
+            StateStoreDataKey::ServerCapabilities => {
+                (StateStoreDataKey::SERVER_CAPABILITIES, String::new())
+            }
+            StateStoreDataKey::Filter(name) => (StateStoreDataKey::FILTER, name.to_owned()),
+            StateStoreDataKey::UserAvatarUrl(user_id) => {
+                (StateStoreDataKey::USER_AVATAR_URL, user_id.to_string())
+            }
+            StateStoreDataKey::RecentlyVisitedRooms(user_id) => (
+                StateStoreDataKey::RECENTLY_VISITED_ROOMS,
+                user_id.to_string(),
+            ),
+            StateStoreDataKey::UtdHookManagerData => {
+                (StateStoreDataKey::UTD_HOOK_MANAGER_DATA, String::new())
+            }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                (StateStoreDataKey::COMPOSER_DRAFT, room_id.to_string())
+            }
+            StateStoreDataKey::SeenKnockRequests(room_id) => {
+                (StateStoreDataKey::SEEN_KNOCK_REQUESTS, room_id.to_string())
+            }
+        }
+    }
+
+    fn receipt_scope(receipt_type: &ReceiptType, thread: &ReceiptThread) -> String {
+        serde_json::json!([receipt_type.to_string(), thread.as_str()]).to_string()
+    }
+
+    fn display_name_key(name: &DisplayName) -> &str {
+        name.as_normalized_str()
+            .unwrap_or_else(|| name.as_raw_str())
+    }
+
+    fn custom_key(key: &[u8]) -> String {
+        key.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    async fn room_version(&self, room_id: &RoomId) -> Result<RoomVersionId, SurrealStoreErr> {
+        let info: Option<RoomInfo> = self.db.get(ROOM_INFO, room_id.as_str(), "", "").await?;
+        Ok(info
+            .and_then(|info| info.room_version().cloned())
+            .unwrap_or_else(|| {
+                warn!(
+                    ?room_id,
+                    "Unable to find the room version, assuming version 9"
+                );
+                RoomVersionId::V9
+            }))
+    }
+
+    /// Moves a user's receipt to `event_id`, reading rows not yet touched by this batch.
+    async fn save_receipt(
+        &self,
+        pending: &mut PendingReceipts,
+        room_id: &RoomId,
+        event_id: &EventId,
+        receipt_type: &ReceiptType,
+        user_id: &UserId,
+        receipt: &Receipt,
+    ) -> Result<(), SurrealStoreErr> {
+        let scope = Self::receipt_scope(receipt_type, &receipt.thread);
+
+        let user_key = (room_id.to_owned(), scope.clone(), user_id.to_owned());
+        let old = match pending.users.get(&user_key) {
+            Some(old) => Some(old.clone()),
+            None => {
+                self.db
+                    .get::<(OwnedEventId, Receipt)>(
+                        USER_RECEIPT,
+                        room_id.as_str(),
+                        &scope,
+                        user_id.as_str(),
+                    )
+                    .await?
+            }
+        };
+        pending
+            .users
+            .insert(user_key, (event_id.to_owned(), receipt.clone()));
+
+        if let Some((old_event_id, _)) = old
+            && let Some(users) = self
+                .event_receipts(pending, room_id, &scope, &old_event_id)
+                .await?
+        {
+            users.remove(user_id);
+        }
+
+        self.event_receipts(pending, room_id, &scope, event_id)
+            .await?
+            .get_or_insert_with(Default::default)
+            .insert(user_id.to_owned(), receipt.clone());
+        Ok(())
+    }
+
+    async fn event_receipts<'a>(
+        &self,
+        pending: &'a mut PendingReceipts,
+        room_id: &RoomId,
+        scope: &str,
+        event_id: &EventId,
+    ) -> Result<&'a mut Option<BTreeMap<OwnedUserId, Receipt>>, SurrealStoreErr> {
+        let key = (room_id.to_owned(), scope.to_owned(), event_id.to_owned());
+        if !pending.events.contains_key(&key) {
+            let users = self
+                .db
+                .get(EVENT_RECEIPT, room_id.as_str(), scope, event_id.as_str())
+                .await?;
+            pending.events.insert(key.clone(), users);
+        }
+        Ok(pending
+            .events
+            .get_mut(&key)
+            .expect("receipts were just loaded"))
+    }
+
+    async fn next_position(&self, table: &str, room_id: &RoomId) -> Result<u64, SurrealStoreErr> {
+        let rows: Vec<(String, serde_json::Value)> =
+            self.db.list(table, room_id.as_str(), "").await?;
+        Ok(rows
+            .iter()
+            .filter_map(|(_, row)| row.get("position")?.as_u64())
+            .max()
+            .map_or(0, |position| position + 1))
+    }
+
+    async fn dependent_requests(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<StoredDependentRequest>, SurrealStoreErr> {
+        let mut requests: Vec<StoredDependentRequest> = self
+            .db
+            .list(DEPENDENT_QUEUE, room_id.as_str(), "")
+            .await?
+            .into_iter()
+            .map(|(_, request)| request)
+            .collect();
+        requests.sort_by_key(|request| request.position);
+        Ok(requests)
+    }
+
+    async fn set_dependent_request(
+        &self,
+        room_id: &RoomId,
+        request: &StoredDependentRequest,
+    ) -> Result<(), SurrealStoreErr> {
+        self.db
+            .set(
+                DEPENDENT_QUEUE,
+                room_id.as_str(),
+                "",
+                request.request.own_transaction_id.as_str(),
+                request,
+            )
+            .await
+    }
+}
+
+#[async_trait]
+impl StateStore for SurrealStateStore {
+    type Error = SurrealStoreErr;
+
+    async fn get_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+    ) -> Result<Option<StateStoreDataValue>, Self::Error> {
+        let (scope, id) = Self::kv_key(key);
+        let db = &self.db;
+        Ok(match key {
+            StateStoreDataKey::SyncToken => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::SyncToken),
+            StateStoreDataKey::ServerCapabilities => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::ServerCapabilities),
+            StateStoreDataKey::Filter(_) => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::Filter),
+            StateStoreDataKey::UserAvatarUrl(_) => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::UserAvatarUrl),
+            StateStoreDataKey::RecentlyVisitedRooms(_) => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::RecentlyVisitedRooms),
+            StateStoreDataKey::UtdHookManagerData => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::UtdHookManagerData),
+            StateStoreDataKey::ComposerDraft(_) => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::ComposerDraft),
+            StateStoreDataKey::SeenKnockRequests(_) => db
+                .get(KV, "", scope, &id)
+                .await?
+                .map(StateStoreDataValue::SeenKnockRequests),
+        })
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<(), Self::Error> {
+        let (scope, id) = Self::kv_key(key);
+        let db = &self.db;
+        match key {
+            StateStoreDataKey::SyncToken => {
+                let value = value
+                    .into_sync_token()
+                    .expect("Session data not a sync token");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::ServerCapabilities => {
+                let value = value
+                    .into_server_capabilities()
+                    .expect("Session data not containing server capabilities");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::Filter(_) => {
+                let value = value.into_filter().expect("Session data not a filter");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::UserAvatarUrl(_) => {
+                let value = value
+                    .into_user_avatar_url()
+                    .expect("Session data not a user avatar url");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::RecentlyVisitedRooms(_) => {
+                let value = value
+                    .into_recently_visited_rooms()
+                    .expect("Session data not a list of recently visited rooms");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::UtdHookManagerData => {
+                let value = value
+                    .into_utd_hook_manager_data()
+                    .expect("Session data not the hook manager data");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::ComposerDraft(_) => {
+                let value = value
+                    .into_composer_draft()
+                    .expect("Session data not a composer draft");
+                db.set(KV, "", scope, &id, &value).await
+            }
+            StateStoreDataKey::SeenKnockRequests(_) => {
+                let value = value
+                    .into_seen_knock_requests()
+                    .expect("Session data is not a set of seen knock request ids");
+                db.set(KV, "", scope, &id, &value).await
+            }
+        }
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<(), Self::Error> {
+        let (scope, id) = Self::kv_key(key);
+        let _: Option<serde_json::Value> = self.db.remove(KV, "", scope, &id).await?;
+        Ok(())
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let mut batch = Batch::default();
+
+        if let Some(sync_token) = &changes.sync_token {
+            let (scope, id) = Self::kv_key(StateStoreDataKey::SyncToken);
+            batch.set(KV, "", scope, &id, sync_token)?;
+        }
+
+        for (room_id, users) in &changes.profiles_to_delete {
+            for user_id in users {
+                batch.remove(PROFILE, room_id.as_str(), "", user_id.as_str());
+            }
+        }
+
+        for (room_id, users) in &changes.profiles {
+            for (user_id, profile) in users {
+                batch.set(PROFILE, room_id.as_str(), "", user_id.as_str(), profile)?;
+            }
+        }
+
+        for (room_id, names) in &changes.ambiguity_maps {
+            for (name, user_ids) in names {
+                let key = Self::display_name_key(name);
+                if user_ids.is_empty() {
+                    batch.remove(DISPLAY_NAME, room_id.as_str(), "", key);
+                } else {
+                    batch.set(DISPLAY_NAME, room_id.as_str(), "", key, user_ids)?;
+                }
+            }
+        }
+
+        for (event_type, event) in &changes.account_data {
+            batch.set(ACCOUNT_DATA, "", "", &event_type.to_string(), event)?;
+        }
+
+        for (room_id, events) in &changes.room_account_data {
+            for (event_type, event) in events {
+                batch.set(
+                    ROOM_ACCOUNT_DATA,
+                    room_id.as_str(),
+                    "",
+                    &event_type.to_string(),
+                    event,
+                )?;
+            }
+        }
+
+        for (room_id, event_types) in &changes.state {
+            let room = room_id.as_str();
+            let mut stripped_cleared = false;
+            let mut stripped_members_cleared = false;
+
+            for (event_type, events) in event_types {
+                for (state_key, raw_event) in events {
+                    batch.set(
+                        STATE_EVENT,
+                        room,
+                        &event_type.to_string(),
+                        state_key,
+                        raw_event,
+                    )?;
+                    if !stripped_cleared {
+                        batch.clear_room(STRIPPED_STATE_EVENT, room);
+                        stripped_cleared = true;
+                    }
+
+                    if *event_type != StateEventType::RoomMember {
+                        continue;
+                    }
+                    let event = match raw_event.deserialize_as::<SyncRoomMemberEvent>() {
+                        Ok(event) => event,
+                        Err(err) => {
+                            let event_id: Option<String> =
+                                raw_event.get_field("event_id").ok().flatten();
+                            debug!(event_id, "Failed to deserialize member event: {err}");
+                            continue;
+                        }
+                    };
+                    if !stripped_members_cleared {
+                        batch.clear_room(STRIPPED_MEMBER, room);
+                        stripped_members_cleared = true;
+                    }
+                    batch.set(
+                        MEMBER,
+                        room,
+                        "",
+                        event.state_key().as_str(),
+                        event.membership(),
+                    )?;
+                }
+            }
+        }
+
+        for (room_id, info) in &changes.room_infos {
+            batch.set(ROOM_INFO, room_id.as_str(), "", "", info)?;
+        }
+
+        for (user_id, event) in &changes.presence {
+            batch.set(PRESENCE, "", "", user_id.as_str(), event)?;
+        }
+
+        for (room_id, event_types) in &changes.stripped_state {
+            let room = room_id.as_str();
+            for (event_type, events) in event_types {
+                for (state_key, raw_event) in events {
+                    batch.set(
+                        STRIPPED_STATE_EVENT,
+                        room,
+                        &event_type.to_string(),
+                        state_key,
+                        raw_event,
+                    )?;
+
+                    if *event_type != StateEventType::RoomMember {
+                        continue;
+                    }
+                    let event = match raw_event.deserialize_as::<StrippedRoomMemberEvent>() {
+                        Ok(event) => event,
+                        Err(err) => {
+                            let event_id: Option<String> =
+                                raw_event.get_field("event_id").ok().flatten();
+                            debug!(
+                                event_id,
+                                "Failed to deserialize stripped member event: {err}"
+                            );
+                            continue;
+                        }
+                    };
+                    batch.set(
+                        STRIPPED_MEMBER,
+                        room,
+                        "",
+                        event.state_key.as_str(),
+                        &event.content.membership,
+                    )?;
+                }
+            }
+        }
+
+        let mut receipts = PendingReceipts::default();
+        for (room_id, content) in &changes.receipts {
+            for (event_id, by_type) in &content.0 {
+                for (receipt_type, by_user) in by_type {
+                    for (user_id, receipt) in by_user {
+                        self.save_receipt(
+                            &mut receipts,
+                            room_id,
+                            event_id,
+                            receipt_type,
+                            user_id,
+                            receipt,
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+        receipts.write(&mut batch)?;
+
+        for (room_id, redactions) in &changes.redactions {
+            let mut room_version = None;
+            // State events of this batch aren't in the database yet but can be redacted too.
+            let mut events: BTreeMap<(String, String), Raw<AnySyncStateEvent>> = self
+                .db
+                .list_room(STATE_EVENT, room_id.as_str())
+                .await?
+                .into_iter()
+                .map(|(event_type, state_key, event)| ((event_type, state_key), event))
+                .collect();
+            for (event_type, pending) in changes.state.get(room_id).into_iter().flatten() {
+                for (state_key, raw_event) in pending {
+                    events.insert(
+                        (event_type.to_string(), state_key.clone()),
+                        raw_event.clone(),
+                    );
+                }
+            }
+
+            for ((event_type, state_key), raw_event) in events {
+                let Ok(Some(event_id)) = raw_event.get_field::<OwnedEventId>("event_id") else {
+                    continue;
+                };
+                let Some(redaction) = redactions.get(&event_id) else {
+                    continue;
+                };
+                if room_version.is_none() {
+                    let pending = changes
+                        .room_infos
+                        .get(room_id)
+                        .and_then(|info| info.room_version().cloned());
+                    room_version = Some(match pending {
+                        Some(version) => version,
+                        None => self.room_version(room_id).await?,
+                    });
+                }
+                let redacted = redact(
+                    raw_event.deserialize_as::<CanonicalJsonObject>()?,
+                    room_version.as_ref().expect("room version was just loaded"),
+                    Some(RedactedBecause::from_raw_event(redaction)?),
+                )
+                .map_err(SurrealStoreErr::Redaction)?;
+                let redacted: Raw<AnySyncStateEvent> = Raw::new(&redacted)?.cast();
+                batch.set(
+                    STATE_EVENT,
+                    room_id.as_str(),
+                    &event_type,
+                    &state_key,
+                    &redacted,
+                )?;
+            }
+        }
+
+        self.db.commit(batch).await
+    }
+
+    async fn get_presence_event(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<Raw<PresenceEvent>>, Self::Error> {
+        self.db.get(PRESENCE, "", "", user_id.as_str()).await
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>, Self::Error> {
+        let keys = user_ids.iter().map(ToString::to_string).collect();
+        Ok(self
+            .db
+            .get_many(PRESENCE, "", "", keys)
+            .await?
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect())
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>, Self::Error> {
+        Ok(self
+            .get_state_events_for_keys(room_id, event_type, &[state_key])
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        let scope = event_type.to_string();
+        let stripped: Vec<(String, Raw<AnyStrippedStateEvent>)> = self
+            .db
+            .list(STRIPPED_STATE_EVENT, room_id.as_str(), &scope)
+            .await?;
+        if !stripped.is_empty() {
+            return Ok(stripped
+                .into_iter()
+                .map(|(_, event)| RawAnySyncOrStrippedState::Stripped(event))
+                .collect());
+        }
+
+        let events: Vec<(String, Raw<AnySyncStateEvent>)> =
+            self.db.list(STATE_EVENT, room_id.as_str(), &scope).await?;
+        Ok(events
+            .into_iter()
+            .map(|(_, event)| RawAnySyncOrStrippedState::Sync(event))
+            .collect())
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        let scope = event_type.to_string();
+        let stripped: HashMap<String, Raw<AnyStrippedStateEvent>> = self
+            .db
+            .list(STRIPPED_STATE_EVENT, room_id.as_str(), &scope)
+            .await?
+            .into_iter()
+            .collect();
+        if !stripped.is_empty() {
+            return Ok(state_keys
+                .iter()
+                .filter_map(|key| stripped.get(*key).cloned())
+                .map(RawAnySyncOrStrippedState::Stripped)
+                .collect());
+        }
+
+        let keys = state_keys.iter().map(|key| (*key).to_owned()).collect();
+        let events: HashMap<String, Raw<AnySyncStateEvent>> = self
+            .db
+            .get_many(STATE_EVENT, room_id.as_str(), &scope, keys)
+            .await?
+            .into_iter()
+            .collect();
+        Ok(state_keys
+            .iter()
+            .filter_map(|key| events.get(*key).cloned())
+            .map(RawAnySyncOrStrippedState::Sync)
+            .collect())
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>, Self::Error> {
+        self.db
+            .get(PROFILE, room_id.as_str(), "", user_id.as_str())
+            .await
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>, Self::Error> {
+        let keys = user_ids.iter().map(ToString::to_string).collect();
+        let mut profiles: HashMap<String, MinimalRoomMemberEvent> = self
+            .db
+            .get_many(PROFILE, room_id.as_str(), "", keys)
+            .await?
+            .into_iter()
+            .collect();
+        Ok(user_ids
+            .iter()
+            .filter_map(|user_id| {
+                let profile = profiles.remove(user_id.as_str())?;
+                Some((&**user_id, profile))
+            })
+            .collect())
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        for table in [STRIPPED_MEMBER, MEMBER] {
+            let members: Vec<(String, MembershipState)> =
+                self.db.list(table, room_id.as_str(), "").await?;
+            let user_ids = members
+                .into_iter()
+                .filter(|(_, membership)| memberships.matches(membership))
+                .map(|(user_id, _)| OwnedUserId::try_from(user_id))
+                .collect::<Result<Vec<_>, _>>()?;
+            if !user_ids.is_empty() {
+                return Ok(user_ids);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        Ok(self
+            .db
+            .list_table(ROOM_INFO)
+            .await?
+            .into_iter()
+            .map(|(_, info)| info)
+            .collect())
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &DisplayName,
+    ) -> Result<BTreeSet<OwnedUserId>, Self::Error> {
+        Ok(self
+            .db
+            .get(
+                DISPLAY_NAME,
+                room_id.as_str(),
+                "",
+                Self::display_name_key(display_name),
+            )
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [DisplayName],
+    ) -> Result<HashMap<&'a DisplayName, BTreeSet<OwnedUserId>>, Self::Error> {
+        let keys = display_names
+            .iter()
+            .map(|name| Self::display_name_key(name).to_owned())
+            .collect();
+        let found: HashMap<String, BTreeSet<OwnedUserId>> = self
+            .db
+            .get_many(DISPLAY_NAME, room_id.as_str(), "", keys)
+            .await?
+            .into_iter()
+            .collect();
+        Ok(display_names
+            .iter()
+            .filter_map(|name| {
+                let user_ids = found.get(Self::display_name_key(name))?;
+                Some((name, user_ids.clone()))
+            })
+            .collect())
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>, Self::Error> {
+        self.db
+            .get(ACCOUNT_DATA, "", "", &event_type.to_string())
+            .await
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.db
+            .get(
+                ROOM_ACCOUNT_DATA,
+                room_id.as_str(),
+                "",
+                &event_type.to_string(),
+            )
+            .await
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>, Self::Error> {
+        self.db
+            .get(
+                USER_RECEIPT,
+                room_id.as_str(),
+                &Self::receipt_scope(&receipt_type, &thread),
+                user_id.as_str(),
+            )
+            .await
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error> {
+        let users: Option<BTreeMap<OwnedUserId, Receipt>> = self
+            .db
+            .get(
+                EVENT_RECEIPT,
+                room_id.as_str(),
+                &Self::receipt_scope(&receipt_type, &thread),
+                event_id.as_str(),
+            )
+            .await?;
+        Ok(users.unwrap_or_default().into_iter().collect())
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.db.get(CUSTOM, "", "", &Self::custom_key(key)).await
+    }
+
+    async fn set_custom_value(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let key = Self::custom_key(key);
+        let old = self.db.get(CUSTOM, "", "", &key).await?;
+        self.db.set(CUSTOM, "", "", &key, &value).await?;
+        Ok(old)
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.db.remove(CUSTOM, "", "", &Self::custom_key(key)).await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        for table in ROOM_TABLES {
+            self.db.clear_room(table, room_id.as_str()).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_send_queue_request(
+        &self,
+        room_id: &RoomId,
+        transaction_id: OwnedTransactionId,
+        created_at: MilliSecondsSinceUnixEpoch,
+        kind: QueuedRequestKind,
+        priority: usize,
+    ) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let request = StoredQueuedRequest {
+            position: self.next_position(SEND_QUEUE, room_id).await?,
+            kind,
+            transaction_id,
+            error: None,
+            priority,
+            created_at,
+        };
+        self.db
+            .set(
+                SEND_QUEUE,
+                room_id.as_str(),
+                "",
+                request.transaction_id.as_str(),
+                &request,
+            )
+            .await
+    }
+
+    async fn update_send_queue_request(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        kind: QueuedRequestKind,
+    ) -> Result<bool, Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let request: Option<StoredQueuedRequest> = self
+            .db
+            .get(SEND_QUEUE, room_id.as_str(), "", transaction_id.as_str())
+            .await?;
+        let Some(mut request) = request else {
+            return Ok(false);
+        };
+        request.kind = kind;
+        request.error = None;
+        self.db
+            .set(
+                SEND_QUEUE,
+                room_id.as_str(),
+                "",
+                transaction_id.as_str(),
+                &request,
+            )
+            .await?;
+        Ok(true)
+    }
+
+    async fn remove_send_queue_request(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+    ) -> Result<bool, Self::Error> {
+        let removed: Option<StoredQueuedRequest> = self
+            .db
+            .remove(SEND_QUEUE, room_id.as_str(), "", transaction_id.as_str())
+            .await?;
+        Ok(removed.is_some())
+    }
+
+    async fn load_send_queue_requests(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<QueuedRequest>, Self::Error> {
+        let mut requests: Vec<StoredQueuedRequest> = self
+            .db
+            .list(SEND_QUEUE, room_id.as_str(), "")
+            .await?
+            .into_iter()
+            .map(|(_, request)| request)
+            .collect();
+        requests.sort_by_key(|request| (Reverse(request.priority), request.position));
+        Ok(requests.into_iter().map(QueuedRequest::from).collect())
+    }
+
+    async fn update_send_queue_request_status(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        error: Option<QueueWedgeError>,
+    ) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let request: Option<StoredQueuedRequest> = self
+            .db
+            .get(SEND_QUEUE, room_id.as_str(), "", transaction_id.as_str())
+            .await?;
+        let Some(mut request) = request else {
+            return Ok(());
+        };
+        request.error = error;
+        self.db
+            .set(
+                SEND_QUEUE,
+                room_id.as_str(),
+                "",
+                transaction_id.as_str(),
+                &request,
+            )
+            .await
+    }
+
+    async fn load_rooms_with_unsent_requests(&self) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        Ok(self
+            .db
+            .rooms(SEND_QUEUE)
+            .await?
+            .into_iter()
+            .map(OwnedRoomId::try_from)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn save_dependent_queued_request(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        own_txn_id: ChildTransactionId,
+        created_at: MilliSecondsSinceUnixEpoch,
+        content: DependentQueuedRequestKind,
+    ) -> Result<(), Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let request = StoredDependentRequest {
+            position: self.next_position(DEPENDENT_QUEUE, room_id).await?,
+            request: DependentQueuedRequest {
+                kind: content,
+                parent_transaction_id: parent_txn_id.to_owned(),
+                own_transaction_id: own_txn_id,
+                parent_key: None,
+                created_at,
+            },
+        };
+        self.set_dependent_request(room_id, &request).await
+    }
+
+    async fn mark_dependent_queued_requests_as_ready(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        sent_parent_key: SentRequestKey,
+    ) -> Result<usize, Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let mut updated = 0;
+        for mut request in self.dependent_requests(room_id).await? {
+            if request.request.parent_transaction_id != parent_txn_id {
+                continue;
+            }
+            request.request.parent_key = Some(sent_parent_key.clone());
+            self.set_dependent_request(room_id, &request).await?;
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    async fn update_dependent_queued_request(
+        &self,
+        room_id: &RoomId,
+        own_transaction_id: &ChildTransactionId,
+        new_content: DependentQueuedRequestKind,
+    ) -> Result<bool, Self::Error> {
+        let _guard = self.save_lock.lock().await;
+        let request: Option<StoredDependentRequest> = self
+            .db
+            .get(
+                DEPENDENT_QUEUE,
+                room_id.as_str(),
+                "",
+                own_transaction_id.as_str(),
+            )
+            .await?;
+        let Some(mut request) = request else {
+            return Ok(false);
+        };
+        request.request.kind = new_content;
+        self.set_dependent_request(room_id, &request).await?;
+        Ok(true)
+    }
+
+    async fn remove_dependent_queued_request(
+        &self,
+        room_id: &RoomId,
+        own_txn_id: &ChildTransactionId,
+    ) -> Result<bool, Self::Error> {
+        let removed: Option<StoredDependentRequest> = self
+            .db
+            .remove(DEPENDENT_QUEUE, room_id.as_str(), "", own_txn_id.as_str())
+            .await?;
+        Ok(removed.is_some())
+    }
+
+    async fn load_dependent_queued_requests(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<DependentQueuedRequest>, Self::Error> {
+        Ok(self
+            .dependent_requests(room_id)
+            .await?
+            .into_iter()
+            .map(|request| request.request)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_base::{StateStore, statestore_integration_tests};
+
+    use super::SurrealStateStore;
+    use crate::matrix_store::{AccountDb, SurrealStoreErr};
+
+    async fn get_store() -> Result<impl StateStore, SurrealStoreErr> {
+        SurrealStateStore::open(AccountDb::in_memory().await?).await
+    }
+
+    statestore_integration_tests!();
+}
@@ -0,0 +1,179 @@
+use matrix_sdk::{Room, deserialized_responses::EncryptionInfo, room::MessagesOptions};
+use ruma::{
+    EventId, OwnedEventId, UInt,
+    events::{
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent,
+        room::{encrypted::OriginalSyncRoomEncryptedEvent, message::OriginalSyncRoomMessageEvent},
+    },
+    serde::Raw,
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{trace, warn};
+
+use crate::matrix_trust::MessageShield;
+
+/// Messages loaded when a room is opened.
+const TIMELINE_PAGE: u32 = 50;
+
+#[derive(Debug, Error)]
+pub enum TimelineErr {
+    #[error("couldn't load the room's messages: {0}")]
+    Messages(#[source] matrix_sdk::Error),
+}
+
+/// A message in a room's timeline, events the timeline doesn't show are left out.
+#[derive(Debug, Clone)]
+pub enum TimelineItem {
+    Message {
+        message: Box<OriginalSyncRoomMessageEvent>,
+        shield: MessageShield,
+    },
+    /// Couldn't be decrypted yet, it's tried again when the key arrives.
+    Encrypted {
+        event_id: OwnedEventId,
+        event: Raw<OriginalSyncRoomEncryptedEvent>,
+    },
+}
+
+impl TimelineItem {
+    fn from_raw(
+        event: &Raw<AnySyncTimelineEvent>,
+        encryption_info: Option<&EncryptionInfo>,
+    ) -> Option<Self> {
+        let deserialized = match event.deserialize() {
+            Ok(deserialized) => deserialized,
+            Err(err) => {
+                warn!("skipping malformed timeline event: {err}");
+                return None;
+            }
+        };
+        match deserialized {
+            AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+                SyncMessageLikeEvent::Original(message),
+            )) => Some(Self::Message {
+                message: Box::new(message),
+                shield: encryption_info
+                    .map(|info| MessageShield::from(&info.verification_state))
+                    .unwrap_or_default(),
+            }),
+            AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomEncrypted(
+                SyncMessageLikeEvent::Original(encrypted),
+            )) => Some(Self::Encrypted {
+                event_id: encrypted.event_id,
+                event: event.clone().cast(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn event_id(&self) -> &EventId {
+        match self {
+            Self::Message { message, .. } => &message.event_id,
+            Self::Encrypted { event_id, .. } => event_id,
+        }
+    }
+}
+
+/// Loads the room's latest messages, oldest first, then reports the timeline again each time
+/// a message arrives through sync.
+pub async fn watch_timeline(
+    room: &Room,
+    mut on_change: impl FnMut(Vec<TimelineItem>),
+) -> Result<(), TimelineErr> {
+    // Listen before loading, a message synced in between would be missed otherwise
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let client = room.client();
+    let handle = client.add_room_event_handler(
+        room.room_id(),
+        move |event: Raw<AnySyncTimelineEvent>, encryption_info: Option<EncryptionInfo>| {
+            let tx = tx.clone();
+            async move {
+                if let Some(item) = TimelineItem::from_raw(&event, encryption_info.as_ref()) {
+                    let _ = tx.send(item);
+                }
+            }
+        },
+    );
+    let _guard = client.event_handler_drop_guard(handle);
+
+    let mut options = MessagesOptions::backward();
+    options.limit = UInt::from(TIMELINE_PAGE);
+    let page = room
+        .messages(options)
+        .await
+        .map_err(TimelineErr::Messages)?;
+    let mut timeline: Vec<TimelineItem> = page
+        .chunk
+        .iter()
+        .rev()
+        .filter_map(|event| TimelineItem::from_raw(event.raw(), event.encryption_info()))
+        .collect();
+    trace!("loaded {} messages of {}", timeline.len(), room.room_id());
+    on_change(timeline.clone());
+
+    while let Some(item) = rx.recv().await {
+        if timeline
+            .iter()
+            .any(|known| known.event_id() == item.event_id())
+        {
+            continue;
+        }
+        timeline.push(item);
+        on_change(timeline.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{event_id, events::AnySyncTimelineEvent, serde::Raw};
+    use serde_json::json;
+
+    use super::TimelineItem;
+
+    fn raw(event: serde_json::Value) -> Raw<AnySyncTimelineEvent> {
+        Raw::new(&event).unwrap().cast()
+    }
+
+    #[test]
+    fn timeline_items() {
+        let message = raw(json!({
+            "type": "m.room.message",
+            "event_id": "$message",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "content": { "msgtype": "m.text", "body": "hi" },
+        }));
+        let item = TimelineItem::from_raw(&message, None).unwrap();
+        assert!(matches!(item, TimelineItem::Message { .. }));
+        assert_eq!(item.event_id(), event_id!("$message"));
+
+        let encrypted = raw(json!({
+            "type": "m.room.encrypted",
+            "event_id": "$encrypted",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 2,
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": "AwgAEpABhetEzzZzyYrxtEVUtlJnZtJcURBlQUQJ9irVeklCTs06LwgTMQj61PMUS4Vy",
+                "device_id": "DEVICE",
+                "sender_key": "sender+key",
+                "session_id": "session",
+            },
+        }));
+        let item = TimelineItem::from_raw(&encrypted, None).unwrap();
+        assert!(matches!(item, TimelineItem::Encrypted { .. }));
+        assert_eq!(item.event_id(), event_id!("$encrypted"));
+
+        let topic = raw(json!({
+            "type": "m.room.topic",
+            "event_id": "$topic",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 3,
+            "state_key": "",
+            "content": { "topic": "news" },
+        }));
+        assert!(TimelineItem::from_raw(&topic, None).is_none());
+    }
+}
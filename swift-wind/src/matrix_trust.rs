@@ -0,0 +1,203 @@
+use std::collections::BTreeSet;
+
+use futures::StreamExt;
+use matrix_sdk::{
+    Client, Room, RoomMemberships,
+    crypto::{IdentityState, store::CryptoStoreError},
+    deserialized_responses::{ShieldState, VerificationState},
+};
+use ruma::{OwnedUserId, UserId};
+use thiserror::Error;
+use tracing::{trace, warn};
+
+#[derive(Debug, Error)]
+pub enum TrustErr {
+    #[error("couldn't load the room members: {0}")]
+    Members(#[source] matrix_sdk::Error),
+
+    #[error("couldn't read the crypto store: {0}")]
+    Store(#[from] CryptoStoreError),
+
+    #[error("no known identity for {0}")]
+    UnknownIdentity(String),
+}
+
+/// Shield next to a decrypted message, the text says what's wrong with its sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageShield {
+    #[default]
+    None,
+    /// Authenticity can't be guaranteed, e.g. the key came from a backup.
+    Warning(&'static str),
+    /// Sent from an unverified or deleted device.
+    Danger(&'static str),
+}
+
+// Senders the user never verified don't get a shield, only problems with their devices do
+impl From<&VerificationState> for MessageShield {
+    fn from(state: &VerificationState) -> Self {
+        match state.to_shield_state_lax() {
+            ShieldState::Red { message, .. } => Self::Danger(message),
+            ShieldState::Grey { message, .. } => Self::Warning(message),
+            ShieldState::None => Self::None,
+        }
+    }
+}
+
+/// A room member and whether their identity is verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberTrust {
+    pub user_id: OwnedUserId,
+    pub display_name: Option<String>,
+    pub verified: bool,
+    /// They set up cross-signing, without it there's nothing to verify.
+    pub has_identity: bool,
+}
+
+/// Summary of the room's members for the room-level shield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomShield {
+    NotEncrypted,
+    AllVerified,
+    Unverified { members: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomTrust {
+    pub shield: RoomShield,
+    pub members: Vec<MemberTrust>,
+}
+
+pub fn room_shield(encrypted: bool, members: &[MemberTrust]) -> RoomShield {
+    if !encrypted {
+        return RoomShield::NotEncrypted;
+    }
+    match members.iter().filter(|member| !member.verified).count() {
+        0 => RoomShield::AllVerified,
+        members => RoomShield::Unverified { members },
+    }
+}
+
+/// Joined and invited members with what the crypto store knows about their identity,
+/// unverified ones first.
+pub async fn room_trust(room: &Room) -> Result<RoomTrust, TrustErr> {
+    let encryption = room.client().encryption();
+    let encrypted = room.is_encrypted().await.map_err(TrustErr::Members)?;
+    let mut members = Vec::new();
+    for member in room
+        .members(RoomMemberships::ACTIVE)
+        .await
+        .map_err(TrustErr::Members)?
+    {
+        let identity = encryption.get_user_identity(member.user_id()).await?;
+        members.push(MemberTrust {
+            user_id: member.user_id().to_owned(),
+            display_name: member.display_name().map(ToOwned::to_owned),
+            verified: identity
+                .as_ref()
+                .is_some_and(|identity| identity.is_verified()),
+            has_identity: identity.is_some(),
+        });
+    }
+    members.sort_by(|a, b| a.verified.cmp(&b.verified).then(a.user_id.cmp(&b.user_id)));
+    Ok(RoomTrust {
+        shield: room_shield(encrypted, &members),
+        members,
+    })
+}
+
+/// Reports the members whose identity changed since it was seen or verified, each time that
+/// set changes.
+pub async fn watch_identity_changes(room: &Room, mut on_change: impl FnMut(Vec<OwnedUserId>)) {
+    let mut changes = match room.subscribe_to_identity_status_changes().await {
+        Ok(changes) => Box::pin(changes),
+        Err(err) => {
+            warn!("can't watch identities in {}: {err}", room.room_id());
+            return;
+        }
+    };
+    let mut changed = BTreeSet::new();
+    while let Some(updates) = changes.next().await {
+        for update in updates {
+            match update.changed_to {
+                IdentityState::PinViolation | IdentityState::VerificationViolation => {
+                    changed.insert(update.user_id)
+                }
+                IdentityState::Verified | IdentityState::Pinned => changed.remove(&update.user_id),
+            };
+        }
+        trace!(
+            "{} members changed identity in {}",
+            changed.len(),
+            room.room_id()
+        );
+        on_change(changed.iter().cloned().collect());
+    }
+}
+
+/// Accepts the new identity of a member without verifying it again.
+pub async fn accept_identity_change(client: &Client, user_id: &UserId) -> Result<(), TrustErr> {
+    let identity = client
+        .encryption()
+        .get_user_identity(user_id)
+        .await?
+        .ok_or_else(|| TrustErr::UnknownIdentity(user_id.to_string()))?;
+    identity.withdraw_verification().await?;
+    identity.pin().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::deserialized_responses::{
+        DeviceLinkProblem, VerificationLevel, VerificationState,
+    };
+    use ruma::user_id;
+
+    use super::{MemberTrust, MessageShield, RoomShield, room_shield};
+
+    #[test]
+    fn message_shields() {
+        assert_eq!(
+            MessageShield::from(&VerificationState::Verified),
+            MessageShield::None
+        );
+        assert!(matches!(
+            MessageShield::from(&VerificationState::Unverified(VerificationLevel::None(
+                DeviceLinkProblem::MissingDevice
+            ))),
+            MessageShield::Danger(_)
+        ));
+        assert!(matches!(
+            MessageShield::from(&VerificationState::Unverified(VerificationLevel::None(
+                DeviceLinkProblem::InsecureSource
+            ))),
+            MessageShield::Warning(_)
+        ));
+    }
+
+    #[test]
+    fn room_shields() {
+        let mut members = vec![
+            MemberTrust {
+                user_id: user_id!("@alice:example.org").to_owned(),
+                display_name: None,
+                verified: true,
+                has_identity: true,
+            },
+            MemberTrust {
+                user_id: user_id!("@bob:example.org").to_owned(),
+                display_name: Some("Bob".to_owned()),
+                verified: false,
+                has_identity: false,
+            },
+        ];
+        assert_eq!(room_shield(false, &members), RoomShield::NotEncrypted);
+        assert_eq!(
+            room_shield(true, &members),
+            RoomShield::Unverified { members: 1 }
+        );
+        members[1].verified = true;
+        assert_eq!(room_shield(true, &members), RoomShield::AllVerified);
+    }
+}
@@ -0,0 +1,613 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use matrix_sdk::Client;
+use reqwest::{StatusCode, header::CONTENT_TYPE};
+use ruma::{
+    ClientSecret, OwnedDeviceId, SessionId,
+    api::{
+        OutgoingRequest,
+        client::{
+            account::{add_3pid, change_password, deactivate},
+            device::{delete_device, delete_devices},
+            keys::upload_signing_keys,
+            uiaa::{AuthData, AuthType, UiaaInfo, UiaaResponse},
+        },
+    },
+};
+use serde::Deserialize;
+use serde_json::{Value as JsonValue, json, value::RawValue as RawJsonValue};
+use thiserror::Error;
+use tracing::{trace, warn};
+use url::Url;
+
+use crate::matrix_discovery::join_base;
+
+#[derive(Debug, Error)]
+pub enum UiaaErr {
+    #[error("couldn't reach {url}: {source}")]
+    Unreachable {
+        url: Url,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("{url} answered with {status}")]
+    Status { url: Url, status: StatusCode },
+
+    #[error("{url} sent an invalid response: {source}")]
+    InvalidResponse {
+        url: Url,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("the code was not accepted")]
+    Rejected,
+
+    #[error(transparent)]
+    Request(Box<matrix_sdk::Error>),
+}
+
+/// Request that takes user-interactive auth in its `auth` field.
+pub trait UiaaRequest:
+    OutgoingRequest<EndpointError = UiaaResponse, IncomingResponse: Send + Sync>
+    + Clone
+    + Debug
+    + Send
+    + Sync
+    + 'static
+{
+    fn set_auth(&mut self, auth: Option<AuthData>);
+}
+
+macro_rules! impl_uiaa_request {
+    ($($request:ty),* $(,)?) => {
+        $(
+            impl UiaaRequest for $request {
+                fn set_auth(&mut self, auth: Option<AuthData>) {
+                    self.auth = auth;
+                }
+            }
+        )*
+    };
+}
+
+impl_uiaa_request!(
+    add_3pid::v3::Request,
+    change_password::v3::Request,
+    deactivate::v3::Request,
+    delete_device::v3::Request,
+    delete_devices::v3::Request,
+    upload_signing_keys::v3::Request,
+);
+
+/// Where an operation stands after being sent.
+#[derive(Debug, Clone)]
+pub enum UiaaStep {
+    Done,
+    /// The server wants more stages, `auth_error` says why the last attempt was refused.
+    Auth(UiaaInfo),
+}
+
+type SendFuture = Pin<Box<dyn Future<Output = matrix_sdk::Result<()>> + Send>>;
+type SendFn = dyn Fn(Client, Option<AuthData>) -> SendFuture + Send + Sync;
+
+/// An operation guarded by user-interactive auth, sent again with each completed stage.
+///
+/// Remembers the session the server handed out so stages don't have to carry it.
+#[derive(Clone)]
+pub struct UiaaOperation {
+    send: Arc<SendFn>,
+    session: Arc<Mutex<Option<String>>>,
+}
+
+impl UiaaOperation {
+    pub fn new<F, Fut>(send: F) -> Self
+    where
+        F: Fn(Client, Option<AuthData>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = matrix_sdk::Result<()>> + Send + 'static,
+    {
+        Self {
+            send: Arc::new(move |client, auth| Box::pin(send(client, auth))),
+            session: Arc::default(),
+        }
+    }
+
+    /// Sends `request` with the auth of each attempt, its response is dropped.
+    pub fn request<R: UiaaRequest>(request: R) -> Self {
+        Self::new(move |client, auth| {
+            let mut request = request.clone();
+            request.set_auth(auth);
+            async move {
+                client.send(request).await?;
+                Ok(())
+            }
+        })
+    }
+
+    pub fn delete_devices(devices: Vec<OwnedDeviceId>) -> Self {
+        Self::request(delete_devices::v3::Request::new(devices))
+    }
+
+    pub fn change_password(new_password: String, logout_devices: bool) -> Self {
+        let mut request = change_password::v3::Request::new(new_password);
+        request.logout_devices = logout_devices;
+        Self::request(request)
+    }
+
+    pub fn deactivate(erase: bool) -> Self {
+        let mut request = deactivate::v3::Request::new();
+        request.erase = erase;
+        Self::request(request)
+    }
+
+    /// Creates and uploads the cross-signing keys, the SDK builds the upload request itself.
+    pub fn bootstrap_cross_signing() -> Self {
+        Self::new(
+            |client, auth| async move { client.encryption().bootstrap_cross_signing(auth).await },
+        )
+    }
+
+    /// Session of the auth in progress, `None` before the server asked for any.
+    pub fn session(&self) -> Option<String> {
+        self.session.lock().unwrap().clone()
+    }
+
+    /// Sends the operation, `auth` gets the current session if it doesn't carry one.
+    pub async fn send(&self, client: &Client, auth: Option<AuthData>) -> Result<UiaaStep, UiaaErr> {
+        let session = self.session();
+        let auth = auth.map(|auth| with_session(auth, session));
+        match (self.send)(client.clone(), auth).await {
+            Ok(()) => Ok(UiaaStep::Done),
+            Err(err) => {
+                let Some(info) = err.as_uiaa_response() else {
+                    return Err(UiaaErr::Request(Box::new(err)));
+                };
+                if let Some(auth_error) = &info.auth_error {
+                    warn!("auth stage refused: {}", auth_error.message);
+                }
+                if info.session.is_some() {
+                    self.session.lock().unwrap().clone_from(&info.session);
+                }
+                Ok(UiaaStep::Auth(info.clone()))
+            }
+        }
+    }
+}
+
+impl Debug for UiaaOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UiaaOperation")
+            .field("session", &self.session())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two handles are equal when they drive the same operation.
+impl PartialEq for UiaaOperation {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.session, &other.session)
+    }
+}
+
+impl Eq for UiaaOperation {}
+
+/// Stages swift-wind has its own UI for, the rest go through the homeserver's fallback page.
+pub fn supported_natively(stage: &AuthType) -> bool {
+    matches!(
+        stage,
+        AuthType::Dummy
+            | AuthType::Password
+            | AuthType::RegistrationToken
+            | AuthType::Terms
+            | AuthType::EmailIdentity
+            | AuthType::Msisdn
+    )
+}
+
+/// Stages left in the flow that fits the stages already completed, flows swift-wind can finish
+/// itself first and the shortest among them. Fallback pages are a last resort.
+pub fn choose_flow(info: &UiaaInfo) -> Option<VecDeque<AuthType>> {
+    info.flows
+        .iter()
+        .filter(|flow| {
+            info.completed
+                .iter()
+                .all(|stage| flow.stages.contains(stage))
+        })
+        .map(|flow| {
+            flow.stages
+                .iter()
+                .filter(|stage| !info.completed.contains(stage))
+                .cloned()
+                .collect::<VecDeque<_>>()
+        })
+        .min_by_key(|stages| {
+            let needs_fallback = !stages.iter().all(supported_natively);
+            (needs_fallback, stages.len())
+        })
+}
+
+/// Puts `session` in auth data that was built without one.
+fn with_session(auth: AuthData, session: Option<String>) -> AuthData {
+    let Some(session) = session.filter(|_| auth.session().is_none()) else {
+        return auth;
+    };
+    let Ok(JsonValue::Object(mut data)) = serde_json::to_value(&auth) else {
+        return auth;
+    };
+    data.insert("session".to_owned(), session.into());
+    serde_json::from_value(JsonValue::Object(data)).unwrap_or(auth)
+}
+
+/// Policy the user has to accept in an `m.login.terms` stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermsPolicy {
+    pub id: String,
+    pub version: String,
+    pub name: String,
+    pub url: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitTokenResponse {
+    success: bool,
+}
+
+/// Web page the homeserver serves for stages swift-wind can't do natively.
+pub fn fallback_url(homeserver: &Url, stage: &AuthType, session: &str) -> Url {
+    let path = format!("_matrix/client/v3/auth/{}/fallback/web", stage.as_str());
+    let mut url = join_base(homeserver, &path).expect("fallback path is a valid relative URL");
+    url.query_pairs_mut().append_pair("session", session);
+    url
+}
+
+/// Policies of an `m.login.terms` stage in `lang`, falling back to English and then to
+/// whatever language the server has.
+pub fn terms_policies(params: &RawJsonValue, lang: &str) -> Vec<TermsPolicy> {
+    let Ok(params) = serde_json::from_str::<JsonValue>(params.get()) else {
+        return Vec::new();
+    };
+    let Some(policies) = params
+        .get(AuthType::Terms.as_str())
+        .and_then(|terms| terms.get("policies"))
+        .and_then(JsonValue::as_object)
+    else {
+        return Vec::new();
+    };
+
+    let mut terms: Vec<TermsPolicy> = policies
+        .iter()
+        .filter_map(|(id, policy)| {
+            let policy = policy.as_object()?;
+            let version = policy.get("version")?.as_str()?.to_owned();
+            let translation = [lang, "en"]
+                .into_iter()
+                .find_map(|lang| policy.get(lang))
+                .or_else(|| policy.values().find(|value| value.is_object()))?;
+            let name = translation.get("name")?.as_str()?.to_owned();
+            let url = Url::parse(translation.get("url")?.as_str()?).ok()?;
+            Some(TermsPolicy {
+                id: id.clone(),
+                version,
+                name,
+                url,
+            })
+        })
+        .collect();
+    terms.sort_by(|a, b| a.id.cmp(&b.id));
+    terms
+}
+
+/// Auth data of a validated email or phone number, `None` for other stages.
+pub fn threepid_auth_data(
+    stage: &AuthType,
+    session: Option<String>,
+    sid: &SessionId,
+    client_secret: &ClientSecret,
+) -> Option<AuthData> {
+    if !matches!(stage, AuthType::EmailIdentity | AuthType::Msisdn) {
+        return None;
+    }
+    let data = json!({
+        "threepid_creds": {
+            "sid": sid,
+            "client_secret": client_secret,
+        }
+    });
+    let JsonValue::Object(data) = data else {
+        unreachable!("threepid data is an object");
+    };
+    AuthData::new(stage.as_str(), session, data).ok()
+}
+
+/// Validates a phone number with the code sent to it, at the `submit_url` the homeserver gave.
+pub async fn submit_msisdn_token(
+    submit_url: &Url,
+    sid: &SessionId,
+    client_secret: &ClientSecret,
+    token: &str,
+) -> Result<(), UiaaErr> {
+    let body = json!({
+        "sid": sid,
+        "client_secret": client_secret,
+        "token": token.trim(),
+    });
+    trace!("submitting msisdn token to {submit_url}");
+    let res = reqwest::Client::new()
+        .post(submit_url.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|source| UiaaErr::Unreachable {
+            url: submit_url.clone(),
+            source,
+        })?;
+    if !res.status().is_success() {
+        return Err(UiaaErr::Status {
+            url: submit_url.clone(),
+            status: res.status(),
+        });
+    }
+    let body = res.bytes().await.map_err(|source| UiaaErr::Unreachable {
+        url: submit_url.clone(),
+        source,
+    })?;
+    let res: SubmitTokenResponse =
+        serde_json::from_slice(&body).map_err(|source| UiaaErr::InvalidResponse {
+            url: submit_url.clone(),
+            source,
+        })?;
+    if !res.success {
+        return Err(UiaaErr::Rejected);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use assert_matches::assert_matches;
+    use ruma::{
+        ClientSecret, SessionId,
+        api::client::uiaa::{
+            AuthData, AuthFlow, AuthType, Dummy, Password, UiaaInfo, UserIdentifier,
+        },
+        owned_device_id,
+    };
+    use serde_json::{json, value::to_raw_value};
+    use url::Url;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_partial_json, method, path},
+    };
+
+    use super::{
+        UiaaErr, UiaaOperation, UiaaStep, choose_flow, fallback_url, submit_msisdn_token,
+        terms_policies, threepid_auth_data, with_session,
+    };
+    use crate::test_support::logged_in_client;
+
+    #[test]
+    fn fallback() {
+        let homeserver = Url::parse("https://matrix.example.org/base").unwrap();
+        assert_eq!(
+            fallback_url(&homeserver, &AuthType::ReCaptcha, "abc def").as_str(),
+            "https://matrix.example.org/base/_matrix/client/v3/auth/m.login.recaptcha/fallback/web?session=abc+def"
+        );
+    }
+
+    #[test]
+    fn terms() {
+        let params = to_raw_value(&json!({
+            "m.login.terms": {
+                "policies": {
+                    "terms_of_service": {
+                        "version": "1.2",
+                        "en": { "name": "Terms of Service", "url": "https://example.org/tos-en.html" },
+                        "fr": { "name": "Conditions d'utilisation", "url": "https://example.org/tos-fr.html" },
+                    },
+                    "privacy_policy": {
+                        "version": "2",
+                        "de": { "name": "Datenschutz", "url": "https://example.org/privacy-de.html" },
+                    },
+                    "broken": { "version": "1" },
+                }
+            }
+        }))
+        .unwrap();
+
+        let policies = terms_policies(&params, "fr");
+        assert_eq!(policies.len(), 2);
+        assert_eq!(policies[0].id, "privacy_policy");
+        assert_eq!(policies[0].name, "Datenschutz");
+        assert_eq!(policies[1].name, "Conditions d'utilisation");
+        assert_eq!(policies[1].version, "1.2");
+
+        let policies = terms_policies(&params, "nl");
+        assert_eq!(policies[1].name, "Terms of Service");
+
+        let empty = to_raw_value(&json!({})).unwrap();
+        assert!(terms_policies(&empty, "en").is_empty());
+    }
+
+    #[test]
+    fn threepid() {
+        let sid = <&SessionId>::try_from("sid").unwrap();
+        let client_secret = <&ClientSecret>::try_from("secret").unwrap();
+        let data = threepid_auth_data(
+            &AuthType::EmailIdentity,
+            Some("session".to_owned()),
+            sid,
+            client_secret,
+        )
+        .unwrap();
+        assert_matches!(&data, AuthData::EmailIdentity(email) if email.thirdparty_id_creds.sid == sid);
+        assert_eq!(data.session(), Some("session"));
+
+        assert_matches!(
+            threepid_auth_data(&AuthType::Msisdn, None, sid, client_secret),
+            Some(AuthData::Msisdn(_))
+        );
+        assert!(threepid_auth_data(&AuthType::Dummy, None, sid, client_secret).is_none());
+    }
+
+    #[test]
+    fn flow_choice() {
+        let mut info = UiaaInfo::new(
+            vec![
+                AuthFlow::new(vec![AuthType::Password, AuthType::Terms, AuthType::Dummy]),
+                AuthFlow::new(vec![AuthType::Sso]),
+                AuthFlow::new(vec![AuthType::Terms, AuthType::EmailIdentity]),
+            ],
+            to_raw_value(&json!({})).unwrap(),
+        );
+        assert_eq!(
+            choose_flow(&info),
+            Some(VecDeque::from([AuthType::Terms, AuthType::EmailIdentity]))
+        );
+
+        info.completed = vec![AuthType::Terms];
+        assert_eq!(
+            choose_flow(&info),
+            Some(VecDeque::from([AuthType::EmailIdentity]))
+        );
+
+        info.completed.clear();
+        info.flows = vec![
+            AuthFlow::new(vec![AuthType::ReCaptcha, AuthType::Terms]),
+            AuthFlow::new(vec![AuthType::Sso]),
+        ];
+        assert_eq!(choose_flow(&info), Some(VecDeque::from([AuthType::Sso])));
+
+        info.flows.clear();
+        assert_eq!(choose_flow(&info), None);
+    }
+
+    #[test]
+    fn session_filled_in() {
+        let auth = with_session(AuthData::Dummy(Dummy::new()), Some("s1".to_owned()));
+        assert_matches!(&auth, AuthData::Dummy(_));
+        assert_eq!(auth.session(), Some("s1"));
+
+        let mut dummy = Dummy::new();
+        dummy.session = Some("own".to_owned());
+        let auth = with_session(AuthData::Dummy(dummy), Some("s1".to_owned()));
+        assert_eq!(auth.session(), Some("own"));
+
+        let auth = with_session(AuthData::Dummy(Dummy::new()), None);
+        assert_eq!(auth.session(), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn operation_retries_refused_stage() {
+        let server = MockServer::start().await;
+        let flows = json!([{ "stages": ["m.login.password"] }]);
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/delete_devices"))
+            .and(body_partial_json(json!({
+                "auth": { "type": "m.login.password", "password": "right", "session": "s1" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/delete_devices"))
+            .and(body_partial_json(json!({ "auth": { "session": "s1" } })))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "flows": flows,
+                "params": {},
+                "session": "s1",
+                "errcode": "M_FORBIDDEN",
+                "error": "Invalid password",
+            })))
+            .with_priority(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/delete_devices"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "flows": flows,
+                "params": {},
+                "session": "s1",
+            })))
+            .with_priority(3)
+            .mount(&server)
+            .await;
+
+        let client = logged_in_client(&server).await;
+
+        let operation = UiaaOperation::delete_devices(vec![owned_device_id!("OLD")]);
+        let info = assert_matches!(
+            operation.send(&client, None).await,
+            Ok(UiaaStep::Auth(info)) => info
+        );
+        assert!(info.auth_error.is_none());
+        assert_eq!(
+            choose_flow(&info),
+            Some(VecDeque::from([AuthType::Password]))
+        );
+        assert_eq!(operation.session().as_deref(), Some("s1"));
+
+        let password = |password: &str| {
+            AuthData::Password(Password::new(
+                UserIdentifier::UserIdOrLocalpart("alice".to_owned()),
+                password.to_owned(),
+            ))
+        };
+        let info = assert_matches!(
+            operation.send(&client, Some(password("wrong"))).await,
+            Ok(UiaaStep::Auth(info)) => info
+        );
+        assert_eq!(info.auth_error.unwrap().message, "Invalid password");
+
+        assert_matches!(
+            operation.send(&client, Some(password("right"))).await,
+            Ok(UiaaStep::Done)
+        );
+        assert_eq!(operation.clone(), operation);
+        assert_ne!(
+            UiaaOperation::delete_devices(vec![owned_device_id!("OLD")]),
+            operation
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn msisdn_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/submit"))
+            .and(body_partial_json(
+                json!({ "sid": "sid", "token": "123456" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/submit"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": false })))
+            .mount(&server)
+            .await;
+
+        let submit_url = Url::parse(&format!("{}/submit", server.uri())).unwrap();
+        let sid = <&SessionId>::try_from("sid").unwrap();
+        let client_secret = <&ClientSecret>::try_from("secret").unwrap();
+        submit_msisdn_token(&submit_url, sid, client_secret, " 123456 ")
+            .await
+            .unwrap();
+        assert_matches!(
+            submit_msisdn_token(&submit_url, sid, client_secret, "000000").await,
+            Err(UiaaErr::Rejected)
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use futures::StreamExt;
+use matrix_sdk::{
+    Client,
+    encryption::{
+        CryptoStoreError,
+        identities::RequestVerificationError,
+        verification::{
+            Emoji, SasState, SasVerification, VerificationRequest, VerificationRequestState,
+        },
+    },
+};
+use ruma::{
+    DeviceId, UserId,
+    events::{
+        key::verification::request::ToDeviceKeyVerificationRequestEvent,
+        room::message::{MessageType, OriginalSyncRoomMessageEvent},
+    },
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{trace, warn};
+
+#[derive(Debug, Error)]
+pub enum VerificationErr {
+    #[error("couldn't read the encryption keys: {0}")]
+    Store(#[source] CryptoStoreError),
+
+    #[error("{0} has no session {1} with encryption keys")]
+    UnknownDevice(String, String),
+
+    #[error("{0} hasn't set up cross-signing")]
+    UnknownIdentity(String),
+
+    #[error("couldn't send the verification request: {0}")]
+    Request(#[source] RequestVerificationError),
+
+    #[error("the verification hasn't reached the emoji comparison")]
+    NotComparing,
+
+    #[error(transparent)]
+    Send(#[from] matrix_sdk::Error),
+}
+
+/// Where a verification is at, as the verification dialog shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationStage {
+    /// We sent the request, the other side hasn't accepted it yet.
+    Requested,
+    /// The other side sent the request, waiting on us to accept it.
+    Incoming,
+    /// Both sides accepted, the keys are being exchanged.
+    Starting,
+    /// The short auth string both sides should see.
+    Compare {
+        emojis: Option<Vec<Emoji>>,
+        decimals: (u16, u16, u16),
+    },
+    /// We confirmed the match, waiting on the other side to confirm it too.
+    Confirmed,
+    Done,
+    Cancelled {
+        reason: String,
+        by_us: bool,
+    },
+    Failed(String),
+}
+
+/// One SAS verification with another session, started by either side.
+#[derive(Debug, Clone)]
+pub struct VerificationFlow {
+    request: VerificationRequest,
+}
+
+impl PartialEq for VerificationFlow {
+    fn eq(&self, other: &Self) -> bool {
+        self.request.flow_id() == other.request.flow_id()
+            && self.request.other_user_id() == other.request.other_user_id()
+    }
+}
+
+impl Eq for VerificationFlow {}
+
+impl VerificationFlow {
+    pub fn new(request: VerificationRequest) -> Self {
+        Self { request }
+    }
+
+    /// An open request we received, `None` once it's finished or unknown to the crypto store.
+    pub async fn incoming(client: &Client, sender: &UserId, flow_id: &str) -> Option<Self> {
+        let request = client
+            .encryption()
+            .get_verification_request(sender, flow_id)
+            .await?;
+        if request.we_started() || request.is_done() || request.is_cancelled() {
+            return None;
+        }
+        Some(Self::new(request))
+    }
+
+    /// Asks one of the sessions of a user to verify, over to-device messages.
+    pub async fn request_device(
+        client: &Client,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Self, VerificationErr> {
+        let device = client
+            .encryption()
+            .get_device(user_id, device_id)
+            .await
+            .map_err(VerificationErr::Store)?
+            .ok_or_else(|| {
+                VerificationErr::UnknownDevice(user_id.to_string(), device_id.to_string())
+            })?;
+        trace!("requesting verification of {user_id} {device_id}");
+        Ok(Self::new(device.request_verification().await?))
+    }
+
+    /// Asks a user to verify, in a direct message room unless it's our own user.
+    pub async fn request_user(client: &Client, user_id: &UserId) -> Result<Self, VerificationErr> {
+        let identity = client
+            .encryption()
+            .get_user_identity(user_id)
+            .await
+            .map_err(VerificationErr::Store)?
+            .ok_or_else(|| VerificationErr::UnknownIdentity(user_id.to_string()))?;
+        trace!("requesting verification of {user_id}");
+        let request = identity
+            .request_verification()
+            .await
+            .map_err(VerificationErr::Request)?;
+        Ok(Self::new(request))
+    }
+
+    pub fn other_user_id(&self) -> &UserId {
+        self.request.other_user_id()
+    }
+
+    pub fn flow_id(&self) -> &str {
+        self.request.flow_id()
+    }
+
+    pub fn is_self_verification(&self) -> bool {
+        self.request.is_self_verification()
+    }
+
+    pub async fn accept(&self) -> Result<(), VerificationErr> {
+        Ok(self.request.accept().await?)
+    }
+
+    /// The emojis or numbers match the ones on the other side.
+    pub async fn confirm(&self) -> Result<(), VerificationErr> {
+        let sas = self.sas().ok_or(VerificationErr::NotComparing)?;
+        Ok(sas.confirm().await?)
+    }
+
+    /// The emojis or numbers differ, which cancels the verification.
+    pub async fn mismatch(&self) -> Result<(), VerificationErr> {
+        let sas = self.sas().ok_or(VerificationErr::NotComparing)?;
+        Ok(sas.mismatch().await?)
+    }
+
+    pub async fn cancel(&self) -> Result<(), VerificationErr> {
+        match self.sas() {
+            Some(sas) => Ok(sas.cancel().await?),
+            None => Ok(self.request.cancel().await?),
+        }
+    }
+
+    fn sas(&self) -> Option<SasVerification> {
+        match self.request.state() {
+            VerificationRequestState::Transitioned { verification, .. } => verification.sas(),
+            _ => None,
+        }
+    }
+
+    /// Follows the verification until it ends, reporting every stage it goes through.
+    ///
+    /// The side that sent the request starts the SAS once both accepted, the other side
+    /// accepts it when it arrives.
+    pub async fn run(&self, mut on_stage: impl FnMut(VerificationStage)) {
+        let mut changes = self.request.changes();
+        let mut state = self.request.state();
+        loop {
+            match state {
+                VerificationRequestState::Created { .. } => on_stage(VerificationStage::Requested),
+                VerificationRequestState::Requested { .. } => on_stage(VerificationStage::Incoming),
+                VerificationRequestState::Ready { .. } => {
+                    on_stage(VerificationStage::Starting);
+                    if self.request.we_started()
+                        && let Err(err) = self.request.start_sas().await
+                    {
+                        warn!("starting sas verification failed {err}");
+                        on_stage(VerificationStage::Failed(err.to_string()));
+                        return;
+                    }
+                }
+                VerificationRequestState::Transitioned { verification, .. } => {
+                    match verification.sas() {
+                        Some(sas) => follow_sas(sas, &mut on_stage).await,
+                        None => {
+                            warn!("other side started a verification method other than sas");
+                            if let Err(err) = self.request.cancel().await {
+                                warn!("cancelling verification failed {err}");
+                            }
+                            on_stage(VerificationStage::Failed(
+                                "The other side chose a verification method swift-wind doesn't support"
+                                    .to_string(),
+                            ));
+                        }
+                    }
+                    return;
+                }
+                VerificationRequestState::Done => {
+                    on_stage(VerificationStage::Done);
+                    return;
+                }
+                VerificationRequestState::Cancelled(info) => {
+                    on_stage(VerificationStage::Cancelled {
+                        reason: info.reason().to_string(),
+                        by_us: info.cancelled_by_us(),
+                    });
+                    return;
+                }
+            }
+            let Some(next) = changes.next().await else {
+                return;
+            };
+            state = next;
+        }
+    }
+}
+
+async fn follow_sas(sas: SasVerification, on_stage: &mut impl FnMut(VerificationStage)) {
+    let mut changes = sas.changes();
+    let mut state = sas.state();
+    loop {
+        match state {
+            SasState::Created { .. } | SasState::Accepted { .. } => {
+                on_stage(VerificationStage::Starting)
+            }
+            SasState::Started { .. } => {
+                on_stage(VerificationStage::Starting);
+                if !sas.we_started()
+                    && let Err(err) = sas.accept().await
+                {
+                    warn!("accepting sas verification failed {err}");
+                    on_stage(VerificationStage::Failed(err.to_string()));
+                    return;
+                }
+            }
+            SasState::KeysExchanged { emojis, decimals } => on_stage(VerificationStage::Compare {
+                emojis: emojis.map(|emojis| emojis.emojis.to_vec()),
+                decimals,
+            }),
+            SasState::Confirmed => on_stage(VerificationStage::Confirmed),
+            SasState::Done { .. } => {
+                on_stage(VerificationStage::Done);
+                return;
+            }
+            SasState::Cancelled(info) => {
+                on_stage(VerificationStage::Cancelled {
+                    reason: info.reason().to_string(),
+                    by_us: info.cancelled_by_us(),
+                });
+                return;
+            }
+        }
+        let Some(next) = changes.next().await else {
+            return;
+        };
+        state = next;
+    }
+}
+
+/// Verification requests sent to us, both to-device and in rooms, as sync delivers them.
+pub fn incoming_verifications(client: &Client) -> mpsc::UnboundedReceiver<VerificationFlow> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let to_device_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: ToDeviceKeyVerificationRequestEvent, client: Client| {
+            let tx = to_device_tx.clone();
+            async move {
+                let flow_id = ev.content.transaction_id.as_str();
+                if let Some(flow) = VerificationFlow::incoming(&client, &ev.sender, flow_id).await {
+                    trace!("verification request from {} {flow_id}", ev.sender);
+                    let _ = tx.send(flow);
+                }
+            }
+        },
+    );
+
+    client.add_event_handler(move |ev: OriginalSyncRoomMessageEvent, client: Client| {
+        let tx = tx.clone();
+        async move {
+            let MessageType::VerificationRequest(content) = &ev.content.msgtype else {
+                return;
+            };
+            if client.user_id() != Some(&*content.to) {
+                return;
+            }
+            let flow_id = ev.event_id.as_str();
+            if let Some(flow) = VerificationFlow::incoming(&client, &ev.sender, flow_id).await {
+                trace!("in-room verification request from {} {flow_id}", ev.sender);
+                let _ = tx.send(flow);
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use ruma::{device_id, user_id};
+    use wiremock::MockServer;
+
+    use super::{VerificationErr, VerificationFlow};
+    use crate::test_support::logged_in_client;
+
+    #[test_log::test(tokio::test)]
+    async fn unknown_targets() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(&server).await;
+
+        assert_matches!(
+            VerificationFlow::request_device(
+                &client,
+                user_id!("@alice:example.org"),
+                device_id!("OTHER")
+            )
+            .await,
+            Err(VerificationErr::UnknownDevice(..))
+        );
+        assert_matches!(
+            VerificationFlow::request_user(&client, user_id!("@bob:example.org")).await,
+            Err(VerificationErr::UnknownIdentity(..))
+        );
+        assert!(
+            VerificationFlow::incoming(&client, user_id!("@bob:example.org"), "flow")
+                .await
+                .is_none()
+        );
+    }
+}
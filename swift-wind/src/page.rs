@@ -1,5 +1,8 @@
 pub mod connect;
+pub mod downloads;
 pub mod login;
 pub mod main_interface;
 pub mod register;
+pub mod reset_password;
 pub mod settings;
+pub mod setup_encryption;
@@ -1,5 +1,10 @@
 use crate::{
-    CLIENT, MatrixClientState, Route, components::ICON, hook::connect::use_matrix_connect,
+    ACCOUNTS, CLIENT, MatrixClientState, Route,
+    components::ICON,
+    hook::{
+        connect::use_matrix_connect,
+        session::{switch_account, use_matrix_restore},
+    },
 };
 use dioxus_router::prelude::{Outlet, navigator};
 use freya::prelude::*;
@@ -13,6 +18,29 @@ pub struct ConnectFormError {
 
 #[component]
 pub fn Connect() -> Element {
+    let navigator = navigator();
+
+    let (_, mut run_restore) = use_matrix_restore(move || {
+        navigator.replace(Route::MainInterface);
+    });
+
+    use_hook(move || run_restore());
+
+    // Adding an account can be abandoned while other accounts are still logged in
+    let back_to_accounts = move |_| {
+        let Some(user_id) = ACCOUNTS
+            .peek()
+            .first()
+            .map(|account| account.user_id.clone())
+        else {
+            return;
+        };
+        spawn(async move {
+            switch_account(&user_id).await;
+            navigator.replace(Route::MainInterface);
+        });
+    };
+
     rsx! {
         rect {
             background: "linear-gradient(325deg, #fff97d 20%, #ffd978 0%, #ffd978 40%, #ff74d1 0%, #ff74d1 60%, #6ddbff 0%, #6ddbff 80%, #b276ff 0%, #b276ff 100%)",
@@ -81,6 +109,13 @@ pub fn Connect() -> Element {
 
 
                 rect {
+                    if !ACCOUNTS.read().is_empty() {
+                        Button {
+                            onclick: back_to_accounts,
+                            label { "Back to your accounts" }
+                        }
+                    }
+
                     label {
                         "Powered By Matrix"
                     }
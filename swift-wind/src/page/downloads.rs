@@ -0,0 +1,243 @@
+use std::path::Path;
+
+use freya::prelude::*;
+use swift_wind::matrix_download::DownloadState;
+use swift_wind::matrix_store::{DownloadOutcome, DownloadRecord};
+use tracing::warn;
+
+use crate::DOWNLOADS;
+use crate::Route;
+use crate::components::attachment::readable_size;
+use crate::hook::download::{ActiveDownload, control_download, use_matrix_download_history};
+
+/// Running downloads with their controls, and the account's past ones
+#[component]
+pub fn Downloads() -> Element {
+    let (history_err, mut run_clear_history, history) = use_matrix_download_history();
+
+    let active = DOWNLOADS.read().clone();
+
+    let history_content = match &*history.read() {
+        None => rsx!(Loader {}),
+        Some(Err(err)) => rsx!(
+            label {
+                color: "red",
+
+                "Couldn't load past downloads: {err}"
+            }
+        ),
+        Some(Ok(records)) if records.is_empty() => rsx!(
+            label {
+                color: "grey",
+
+                "No past downloads"
+            }
+        ),
+        Some(Ok(records)) => rsx!(
+            for record in records.iter().cloned() {
+                HistoryEntry {
+                    key: "{record.finished.get()}{record.path.display()}",
+                    record,
+                }
+            }
+
+            Button {
+                onclick: move |_| run_clear_history(),
+                label { "Clear history" }
+            }
+        ),
+    };
+
+    rsx!(
+        ScrollView {
+            rect {
+                width: "100%",
+                padding: "20",
+                spacing: "10",
+                direction: "vertical",
+
+                Link {
+                    to: Route::MainInterface,
+
+                    label {
+                        color: "#454545",
+                        font_size: "16",
+
+                        "Back"
+                    }
+                }
+
+                label {
+                    color: "#454545",
+                    font_size: "36",
+                    font_weight: "bold",
+
+                    "Downloads"
+                }
+
+                if active.is_empty() {
+                    label {
+                        color: "grey",
+
+                        "Nothing is downloading"
+                    }
+                }
+
+                for download in active {
+                    rect {
+                        key: "{download.id}",
+                        direction: "vertical",
+                        spacing: "5",
+                        padding: "8",
+                        corner_radius: "8",
+                        background: "#f2f2f2",
+
+                        label { "{download.name}" }
+                        label {
+                            color: "grey",
+                            font_size: "10",
+
+                            "{download.path.display()}"
+                        }
+
+                        if let Some(percent) = percent(&download) {
+                            ProgressBar {
+                                show_progress: true,
+                                progress: percent,
+                            }
+                        }
+                        label {
+                            font_size: "10",
+
+                            {progress_label(&download)}
+                        }
+
+                        rect {
+                            direction: "horizontal",
+                            spacing: "5",
+
+                            if download.state == DownloadState::Paused {
+                                Button {
+                                    onclick: move |_| control_download(download.id, DownloadState::Running),
+                                    label { "Resume" }
+                                }
+                            } else {
+                                Button {
+                                    onclick: move |_| control_download(download.id, DownloadState::Paused),
+                                    label { "Pause" }
+                                }
+                            }
+                            Button {
+                                onclick: move |_| control_download(download.id, DownloadState::Cancelled),
+                                label {
+                                    color: "red",
+
+                                    "Cancel"
+                                }
+                            }
+                        }
+                    }
+                }
+
+                label { font_size: "24", "History" }
+
+                {history_content}
+
+                label {
+                    color: "red",
+
+                    "{history_err}"
+                }
+            }
+        }
+    )
+}
+
+#[component]
+fn HistoryEntry(record: DownloadRecord) -> Element {
+    let size = record
+        .size
+        .map(|size| format!("({})", readable_size(size)))
+        .unwrap_or_default();
+    let outcome = match &record.outcome {
+        DownloadOutcome::Done => rsx!(
+            label {
+                color: "green",
+
+                "Done"
+            }
+            Button {
+                onclick: {
+                    let path = record.path.clone();
+                    move |_| open_containing_folder(&path)
+                },
+                label { "Open containing folder" }
+            }
+        ),
+        DownloadOutcome::Cancelled => rsx!(
+            label {
+                color: "grey",
+
+                "Cancelled"
+            }
+        ),
+        DownloadOutcome::Failed(reason) => rsx!(
+            label {
+                color: "red",
+
+                "Failed: {reason}"
+            }
+        ),
+    };
+
+    rsx!(
+        rect {
+            direction: "horizontal",
+            spacing: "10",
+            cross_align: "center",
+
+            rect {
+                direction: "vertical",
+
+                label { "{record.name}" }
+                label {
+                    color: "grey",
+                    font_size: "10",
+
+                    "{record.path.display()} {size}"
+                }
+            }
+
+            {outcome}
+        }
+    )
+}
+
+fn percent(download: &ActiveDownload) -> Option<f32> {
+    let total = download.progress.total.filter(|total| *total > 0)?;
+    Some((download.progress.received as f64 / total as f64 * 100.0) as f32)
+}
+
+fn progress_label(download: &ActiveDownload) -> String {
+    let received = readable_size(download.progress.received);
+    let total = download
+        .progress
+        .total
+        .map(|total| format!(" of {}", readable_size(total)))
+        .unwrap_or_default();
+    let paused = if download.state == DownloadState::Paused {
+        ", paused"
+    } else {
+        ""
+    };
+    format!("{received}{total}{paused}")
+}
+
+fn open_containing_folder(path: &Path) {
+    let Some(folder) = path.parent() else {
+        return;
+    };
+    if let Err(err) = open::that(folder) {
+        warn!("failed to open {} {:?}", folder.display(), err);
+    }
+}
@@ -9,9 +9,16 @@ use crate::MatrixClientState;
 use crate::components::additional_authorization::AdditonalAuthHandler;
 use crate::components::additional_authorization::AuthenticationState;
 use crate::components::form::FormField;
+use crate::components::identity_provider_button::IdentityProviderButton;
+use crate::components::recent_servers::RecentServers;
 use crate::hook::CommonUserAuthData;
 use crate::hook::connect::use_matrix_connect;
+use crate::hook::login::use_matrix_guest_login;
 use crate::hook::login::use_matrix_login;
+use crate::hook::login::use_matrix_login_flows;
+use crate::hook::login::use_matrix_oidc_login;
+use crate::hook::login::use_matrix_sso_login;
+use crate::hook::login::use_matrix_token_login;
 use crate::hook::submit_additional_auth::AdditionalAuthType;
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -22,9 +29,10 @@ pub struct LoginForm {
 
 #[component]
 pub fn Login() -> Element {
-    let mut form_url = use_signal(|| String::from("http://127.0.0.1:8008"));
+    let mut form_url = use_signal(String::new);
     let mut form_username = use_signal(|| String::new());
     let mut form_password = use_signal(|| String::new());
+    let mut form_token = use_signal(String::new);
     let navigator = navigator();
 
     let (mut get_connect_err, run_matrix_connect) = use_matrix_connect(move || {
@@ -69,14 +77,250 @@ pub fn Login() -> Element {
 
     let (error_string, mut run_matrix_login, state_machine) = use_matrix_login(move || {});
 
-    // let on_login = move |_| {
-    //     let auth_data = CommonUserAuthData {
-    //         username: form().username,
-    //         password: form().password,
-    //         session_id: None,
-    //     };
-    //     run_matrix_login(auth_data);
-    // };
+    let on_login = move |_| {
+        let auth_data = CommonUserAuthData {
+            username: form_username(),
+            password: form_password(),
+            session_id: None,
+        };
+        run_matrix_login(auth_data);
+    };
+
+    let (sso_err, run_sso_login, sso_state) = use_matrix_sso_login(move || {});
+
+    let (oidc_err, run_oidc_login, oidc_state) = use_matrix_oidc_login(move || {});
+
+    use_effect(move || {
+        let authorized = [state_machine, sso_state, oidc_state]
+            .iter()
+            .any(|state| matches!(&*state.read(), Some(AuthenticationState::Authorized)));
+        if authorized {
+            navigator.replace(crate::Route::MainInterface);
+        }
+    });
+
+    let login_flows = use_matrix_login_flows();
+
+    let (token_err, mut run_token_login) = use_matrix_token_login(move || {
+        navigator.replace(crate::Route::MainInterface);
+    });
+
+    let (guest_err, mut run_guest_login, guest_available) = use_matrix_guest_login(move || {
+        navigator.replace(crate::Route::MainInterface);
+    });
+
+    let login_options = match &*login_flows.read() {
+        None | Some(None) => rsx!(Loader {}),
+        Some(Some(Err(err))) => rsx!(
+            label {
+                color: "red",
+
+                "Couldn't read how this server lets you sign in: {err}"
+            }
+        ),
+        Some(Some(Ok(flows))) if flows.is_empty() => {
+            let offered = if flows.unsupported.is_empty() {
+                "nothing".to_string()
+            } else {
+                flows.unsupported.join(", ")
+            };
+            rsx!(
+                label {
+                    color: "red",
+
+                    "This server offers no way of signing in that swift-wind supports. It offers: {offered}"
+                }
+            )
+        }
+        Some(Some(Ok(flows))) => rsx!(
+            if let Some(metadata) = &flows.oidc {
+                Button {
+                    onclick: {
+                        let mut run_oidc_login = run_oidc_login.clone();
+                        let metadata = metadata.clone();
+                        move |_| run_oidc_login(metadata.clone())
+                    },
+                    label {
+                        {format!("Continue with {}", metadata.issuer.host_str().unwrap_or_default())}
+                    }
+                }
+
+                label {
+                    color: "red",
+
+                    "{oidc_err}"
+                }
+            }
+
+            if flows.password {
+                FormField {
+                    name: "Username",
+                    value: form_username,
+                    onchange: move |txt| {
+                        *form_username.write() = txt;
+                     },
+                }
+
+                FormField {
+                    name: "Password",
+                    value: form_password,
+                    hidden: true,
+                    onchange: move |txt| {
+                        *form_password.write() = txt;
+                     },
+                }
+
+                label {
+                    color: "red",
+
+                    "{error_string}"
+                }
+
+                Link {
+                    to: crate::Route::ResetPassword,
+
+                    label {
+                        color: "#454545",
+                        font_size: "14",
+
+                        "Forgot password?"
+                    }
+                }
+            }
+
+            rect {
+                margin: "10 0 0 0",
+                content: "flex",
+                direction: "horizontal",
+                width: "100%",
+                main_align: "space-between",
+                cross_align: "center",
+
+                if flows.password {
+                    Button {
+                        theme: ButtonThemeWith {
+                            background: Some(Cow::Borrowed("#6ddbff")),
+                            hover_background: Some(Cow::Borrowed("rgb(88, 176, 206)")),
+                            border_fill: Some(Cow::Borrowed("rgb(109, 219, 255, 0)")),
+                            padding: Some(Cow::Borrowed("5 20")),
+                            ..Default::default()
+                        },
+                        onclick: on_login,
+                        label {
+                            font_size: "24",
+
+                            color: "white",
+                            font_weight: "bold",
+                            "Sign In"
+                        }
+                    }
+                }
+
+                Link {
+                    to: crate::Route::Register,
+
+
+                    rect {
+                        content: "flex",
+                        direction: "verticle",
+
+
+                        label {
+                            color: "#454545",
+                            font_size: "16",
+
+                            "Or register"
+                        }
+
+                        rect {
+                            width: "85",
+                            height: "2",
+                            background: "#454545",
+                        }
+                    }
+                }
+            }
+
+            if let Some(providers) = &flows.sso {
+                if providers.is_empty() {
+                    Button {
+                        onclick: {
+                            let mut run_sso_login = run_sso_login.clone();
+                            move |_| run_sso_login(None)
+                        },
+                        label { "Sign in with single sign-on" }
+                    }
+                }
+                {providers.iter().map(|provider| {
+                    let mut run_sso_login = run_sso_login.clone();
+                    let idp_id = provider.id.clone();
+                    rsx!(
+                        IdentityProviderButton {
+                            key: "{provider.id}",
+                            name: provider.name.clone(),
+                            icon: provider.icon.as_ref().map(|icon| icon.to_string()),
+                            onclick: move |_| run_sso_login(Some(idp_id.clone())),
+                        }
+                    )
+                })}
+
+                label {
+                    color: "red",
+
+                    "{sso_err}"
+                }
+            }
+
+            if flows.token {
+                FormField {
+                    name: "Login token",
+                    value: form_token,
+                    onchange: move |txt| {
+                        *form_token.write() = txt;
+                     },
+                }
+
+                Button {
+                    onclick: move |_| run_token_login(form_token()),
+                    label { "Sign in with token" }
+                }
+
+                label {
+                    color: "red",
+
+                    "{token_err}"
+                }
+            }
+
+            if guest_available() {
+                Button {
+                    onclick: move |_| run_guest_login(),
+                    label { "Continue as guest" }
+                }
+            }
+
+            label {
+                color: "red",
+
+                "{guest_err}"
+            }
+        ),
+    };
+
+    let additional_auth = match &*state_machine.read() {
+        Some(AuthenticationState::AdditionalAuthRequired { session, .. }) => {
+            let common_user_data = CommonUserAuthData {
+                username: form_username(),
+                password: form_password(),
+                session_id: session.clone(),
+            };
+            Some(rsx!(AdditonalAuthHandler {
+                state: state_machine,
+                additional_auth_type: AdditionalAuthType::Login(common_user_data),
+            }))
+        }
+        _ => None,
+    };
 
     rsx! {
 
@@ -110,89 +354,23 @@ pub fn Login() -> Element {
                 FormField {
                     name: "Server",
                     value: form_url,
+                    placeholder: "example.org or @you:example.org",
+                    errors: get_connect_err,
                     onchange: move |txt| {
                         *form_url.write() = txt;
-                        //get_connect_err.write().clear();
+                        get_connect_err.write().clear();
                      },
                 }
 
+                RecentServers { value: form_url }
+
                 match CLIENT() {
                     MatrixClientState::Connecting => {
                         rsx!(
                             Loader {}
                         )
                     }
-                    MatrixClientState::Connected(_) => {
-                        rsx!(
-                            FormField {
-                                name: "Username",
-                                value: form_username,
-                                onchange: move |txt| {
-                                    *form_username.write() = txt;
-                                 },
-                            }
-
-                            FormField {
-                                name: "Password",
-                                value: form_password,
-                                hidden: true,
-                                onchange: move |txt| {
-                                    *form_password.write() = txt;
-                                 },
-                            }
-
-                            rect {
-                                margin: "10 0 0 0",
-                                content: "flex",
-                                direction: "horizontal",
-                                width: "100%",
-                                main_align: "space-between",
-                                cross_align: "center",
-
-                                Button {
-                                    theme: ButtonThemeWith {
-                                        background: Some(Cow::Borrowed("#6ddbff")),
-                                        hover_background: Some(Cow::Borrowed("rgb(88, 176, 206)")),
-                                        border_fill: Some(Cow::Borrowed("rgb(109, 219, 255, 0)")),
-                                        padding: Some(Cow::Borrowed("5 20")),
-                                        ..Default::default()
-                                    },
-                                    onclick: on_connect,
-                                    label {
-                                        font_size: "24",
-
-                                        color: "white",
-                                        font_weight: "bold",
-                                        "Sign In"
-                                    }
-                                }
-
-                                Link {
-                                    to: crate::Route::Register,
-
-
-                                    rect {
-                                        content: "flex",
-                                        direction: "verticle",
-
-
-                                        label {
-                                            color: "#454545",
-                                            font_size: "16",
-
-                                            "Or register"
-                                        }
-
-                                        rect {
-                                            width: "85",
-                                            height: "2",
-                                            background: "#454545",
-                                        }
-                                    }
-                                }
-                            }
-                        )
-                    }
+                    MatrixClientState::Connected(_) => login_options,
                     MatrixClientState::Error(err) => {
                         let time = Utc::now();
                         rsx!(
@@ -243,6 +421,8 @@ pub fn Login() -> Element {
                         )
                     }
                 }
+
+                {additional_auth}
             }
         }
 
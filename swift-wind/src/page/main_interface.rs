@@ -1,10 +1,146 @@
+use std::collections::HashSet;
+
+use dioxus_router::prelude::navigator;
 use freya::prelude::*;
+use ruma::{OwnedUserId, RoomId};
+use swift_wind::matrix_oidc::AccountManagementAction;
+use swift_wind::matrix_recovery::EncryptionSetup;
+use tracing::warn;
+
+use crate::ACCOUNT_DB;
+use crate::ACCOUNTS;
+use crate::CLIENT;
+use crate::CURRENT_ROOM;
+use crate::MatrixClientState;
+use crate::Route;
+use crate::components::account_switcher::AccountSwitcher;
+use crate::components::composer::MessageComposer;
+use crate::components::room_trust::{IdentityChangeBanner, RoomMembers};
+use crate::components::timeline::RoomTimeline;
+use crate::hook::recovery::use_matrix_encryption_setup;
+use crate::hook::session::use_matrix_logout;
+
+//Accounts the encryption wizard was opened for, "Not now" holds until the next launch
+static ENCRYPTION_SETUP_OFFERED: GlobalSignal<HashSet<OwnedUserId>> = Global::new(HashSet::new);
 
 #[component]
 pub fn MainInterface() -> Element {
+    let navigator = navigator();
+
+    let (_, mut run_logout) = use_matrix_logout(move || {
+        if ACCOUNTS.peek().is_empty() {
+            navigator.replace(Route::Login);
+        }
+    });
+
+    // Remember the open room so a restored session lands back in it.
+    use_effect(move || {
+        let room = CURRENT_ROOM();
+        let Some(db) = ACCOUNT_DB() else {
+            return;
+        };
+        spawn(async move {
+            let room_id = room.as_deref().and_then(|room| RoomId::parse(room).ok());
+            if let Err(err) = db.set_last_room(room_id.as_deref()).await {
+                warn!("failed to save last open room {:?}", err);
+            }
+        });
+    });
+
+    let encryption_setup = use_matrix_encryption_setup();
+    use_effect(move || {
+        let Some(Ok(setup)) = &*encryption_setup.read() else {
+            return;
+        };
+        if *setup == EncryptionSetup::Done {
+            return;
+        }
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return;
+        };
+        let Some(user_id) = client.user_id().map(ToOwned::to_owned) else {
+            return;
+        };
+        if ENCRYPTION_SETUP_OFFERED.write().insert(user_id) {
+            navigator.push(Route::SetupEncryption);
+        }
+    });
+
+    // Accounts signed in through OIDC are managed in the authentication server's web UI.
+    let account_management = use_resource(move || async move {
+        let db = ACCOUNT_DB()?;
+        let oidc = match db.oidc_session().await {
+            Ok(oidc) => oidc?,
+            Err(err) => {
+                warn!("failed to read oidc session {:?}", err);
+                return None;
+            }
+        };
+        oidc.account_management_url(&AccountManagementAction::Profile)
+    });
+
+    let current_room = CURRENT_ROOM().and_then(|room| RoomId::parse(room).ok());
+
     rsx!(
-        label {
-            "hello"
+        rect {
+            width: "100%",
+            height: "100%",
+            direction: "horizontal",
+
+            AccountSwitcher {}
+
+            rect {
+                width: "fill",
+                height: "100%",
+                content: "flex",
+
+                label {
+                    "hello"
+                }
+
+                Button {
+                    onclick: move |_| run_logout(),
+                    label { "Log out" }
+                }
+
+                Button {
+                    onclick: move |_| {
+                        navigator.push(Route::Settings);
+                    },
+                    label { "Settings" }
+                }
+
+                Button {
+                    onclick: move |_| {
+                        navigator.push(Route::Downloads);
+                    },
+                    label { "Downloads" }
+                }
+
+                if let Some(Some(url)) = account_management.read().as_ref() {
+                    Button {
+                        onclick: {
+                            let url = url.clone();
+                            move |_| {
+                                if let Err(err) = open::that(url.as_str()) {
+                                    warn!("failed to open account management {:?}", err);
+                                }
+                            }
+                        },
+                        label { "Manage account" }
+                    }
+                }
+
+                if let Some(room_id) = current_room {
+                    IdentityChangeBanner { room_id: room_id.clone() }
+
+                    RoomMembers { room_id: room_id.clone() }
+
+                    RoomTimeline { room_id: room_id.clone() }
+
+                    MessageComposer { room_id }
+                }
+            }
         }
     )
 }
@@ -1,6 +1,7 @@
 use dioxus_router::prelude::navigator;
 use freya::dioxus_core;
 use freya::prelude::*;
+use swift_wind::matrix_register::UsernameAvailability;
 use tracing::debug;
 use tracing::trace;
 
@@ -9,9 +10,11 @@ use crate::MatrixClientState;
 use crate::components::additional_authorization::AdditonalAuthHandler;
 use crate::components::additional_authorization::AuthenticationState;
 use crate::components::form::FormField;
+use crate::components::recent_servers::RecentServers;
 use crate::hook::CommonUserAuthData;
 use crate::hook::connect::use_matrix_connect;
 use crate::hook::register::use_matrix_register;
+use crate::hook::register::use_matrix_username_availability;
 use crate::hook::submit_additional_auth::AdditionalAuthType;
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
@@ -22,7 +25,7 @@ pub struct LoginForm {
 
 #[component]
 pub fn Register() -> Element {
-    let mut form_url = use_signal(|| String::from("http://127.0.0.1:8008"));
+    let mut form_url = use_signal(String::new);
     let mut form_username = use_signal(|| String::new());
     let mut form_password = use_signal(|| String::new());
     let navigator = navigator();
@@ -54,6 +57,43 @@ pub fn Register() -> Element {
         run_matrix_register(auth_data);
     };
 
+    use_effect(move || {
+        if matches!(
+            &*state_machine.read(),
+            Some(AuthenticationState::Authorized)
+        ) {
+            navigator.replace(crate::Route::MainInterface);
+        }
+    });
+
+    let availability = use_matrix_username_availability(form_username.into());
+    let username_errors = use_memo(move || match &*availability.read() {
+        Some(Some(Ok(UsernameAvailability::Taken))) => "This username is taken".to_string(),
+        Some(Some(Ok(UsernameAvailability::Invalid))) => {
+            "Usernames can only have lowercase letters, digits and ._=-/".to_string()
+        }
+        Some(Some(Ok(UsernameAvailability::Reserved))) => {
+            "This username is reserved by the server".to_string()
+        }
+        Some(Some(Err(err))) => format!("Couldn't check this username: {err}"),
+        _ => String::new(),
+    });
+
+    let additional_auth = match &*state_machine.read() {
+        Some(AuthenticationState::AdditionalAuthRequired { session, .. }) => {
+            let common_user_data = CommonUserAuthData {
+                username: form_username(),
+                password: form_password(),
+                session_id: session.clone(),
+            };
+            Some(rsx!(AdditonalAuthHandler {
+                state: state_machine,
+                additional_auth_type: AdditionalAuthType::Register(common_user_data),
+            }))
+        }
+        _ => None,
+    };
+
     rsx! {
 
 
@@ -85,15 +125,20 @@ pub fn Register() -> Element {
                 FormField {
                     name: "Server",
                     value: form_url,
+                    placeholder: "example.org or @you:example.org",
+                    errors: get_matrix_connect,
                     onchange: move |txt| {
                         *form_url.write() = txt;
                         get_matrix_connect.write().clear();
                      },
                 }
 
+                RecentServers { value: form_url }
+
                 FormField {
                     name: "Username",
                     value: form_username,
+                    errors: username_errors(),
                     onchange: move |txt| {
                         *form_username.write() = txt;
                      },
@@ -158,83 +203,15 @@ pub fn Register() -> Element {
                         }
                     }
                 }
-            }
-        }
 
+                label {
+                    color: "red",
 
+                    "{error_string}"
+                }
 
-        // rect {
-        //     height: "100%",
-        //     width: "100%",
-        //     main_align: "center",
-        //     cross_align: "center",
-
-        //     if let Some(auth_state) = state_machine.read().as_ref() {
-
-        //         match auth_state{
-        //             AuthenticationState::Authorized { .. } => {
-        //                 // navigator.push("main_interface");
-        //                 rsx!{label { "Account created, loading main interface" }}
-        //             },
-        //             AuthenticationState::AdditionalAuthRequired { chosen_flow:_, common_user_data } => {
-        //                 rsx!{AdditonalAuthHandler { state: state_machine, additional_auth_type: AdditionalAuthType::Register(common_user_data.clone()) }}
-        //             },
-        //         }
-        //     }
-        //     else{
-        //         rect {
-        //             content: "flex",
-        //             direction: "vertical",
-        //             spacing: "10",
-
-        //             label {
-        //                 font_size: "24",
-
-        //                 "Register"
-        //             }
-
-        //             Input {
-        //                 value: form().username,
-        //                 placeholder: "username",
-        //                 onchange: move |txt| {
-        //                     form.write().username = txt;
-        //                 }
-        //             }
-        //             Input {
-        //                 value: form().password,
-        //                 placeholder: "password",
-        //                 mode: InputMode::Hidden('*'),
-        //                 onchange: move |txt| {
-        //                     form.write().password = txt;
-        //                 }
-        //             }
-        //             rect {
-        //                 width: "100%",
-        //                 content: "flex",
-        //                 direction: "horizontal",
-        //                 main_align: "space-between",
-
-        //                 Button {
-        //                     onclick: on_register,
-        //                     label {
-        //                         "Register"
-        //                     }
-        //                 }
-
-        //                 Link {
-        //                     to: crate::Route::Login,
-
-        //                     label {
-        //                         "Login"
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //         label {
-        //             color: "red",
-        //             "{error_string}"
-        //         }
-        //     }
-        // }
+                {additional_auth}
+            }
+        }
     }
 }
@@ -0,0 +1,162 @@
+use freya::prelude::*;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+use crate::components::form::FormField;
+use crate::hook::password_reset::{PasswordResetState, use_matrix_password_reset};
+
+#[component]
+pub fn ResetPassword() -> Element {
+    let mut form_email = use_signal(String::new);
+    let mut form_password = use_signal(String::new);
+    let mut form_confirm = use_signal(String::new);
+    let mut logout_devices = use_signal(|| true);
+    let mut form_err = use_signal(String::new);
+
+    let (error_string, run_reset, mut state) = use_matrix_password_reset(move || {});
+
+    let on_reset = {
+        let mut run_reset = run_reset.clone();
+        move |_| {
+            form_err.write().clear();
+            if form_password().is_empty() {
+                *form_err.write() = "Choose a new password".to_string();
+                return;
+            }
+            if form_password() != form_confirm() {
+                *form_err.write() = "The passwords don't match".to_string();
+                return;
+            }
+            run_reset(form_email(), form_password(), logout_devices());
+        }
+    };
+
+    let content = match state() {
+        _ if !matches!(CLIENT(), MatrixClientState::Connected(_)) => rsx!(
+            label {
+                color: "#454545",
+
+                "Connect to your server on the login page first"
+            }
+        ),
+        PasswordResetState::Idle => rsx!(
+            FormField {
+                name: "Email",
+                value: form_email,
+                placeholder: "The email address of your account",
+                onchange: move |txt| {
+                    *form_email.write() = txt;
+                 },
+            }
+
+            FormField {
+                name: "New password",
+                value: form_password,
+                hidden: true,
+                onchange: move |txt| {
+                    *form_password.write() = txt;
+                 },
+            }
+
+            FormField {
+                name: "Confirm new password",
+                value: form_confirm,
+                hidden: true,
+                errors: form_err,
+                onchange: move |txt| {
+                    *form_confirm.write() = txt;
+                 },
+            }
+
+            Tile {
+                onselect: move |_| logout_devices.toggle(),
+                leading: rsx!(
+                    Checkbox {
+                        selected: logout_devices(),
+                    }
+                ),
+                label { "Sign out all other devices" }
+            }
+
+            Button {
+                onclick: on_reset,
+                label { "Send reset email" }
+            }
+        ),
+        PasswordResetState::WaitingForEmail => rsx!(
+            label { "We sent an email to {form_email}, open the link in it to set your new password" }
+
+            Loader {}
+
+            rect {
+                content: "flex",
+                direction: "horizontal",
+                spacing: "10",
+
+                Button {
+                    onclick: {
+                        let mut run_reset = run_reset.clone();
+                        move |_| run_reset(form_email(), form_password(), logout_devices())
+                    },
+                    label { "Send again" }
+                }
+
+                Button {
+                    onclick: move |_| state.set(PasswordResetState::Idle),
+                    label { "Cancel" }
+                }
+            }
+        ),
+        PasswordResetState::Done => rsx!(
+            label { "Your password was changed, you can sign in with it now" }
+        ),
+    };
+
+    rsx! {
+        rect {
+            content: "flex",
+            direction: "horizontal",
+            width: "100%",
+            height: "100%",
+
+            main_align: "center",
+
+            rect {
+                content: "flex",
+                direction: "vertical",
+                spacing: "5",
+                max_width: "225",
+                cross_align: "center",
+
+                label {
+                    color: "#454545",
+                    font_size: "36",
+                    font_weight: "bold",
+                    text_align: "center",
+                    margin: "0 0 20 0",
+
+                    "Reset password"
+                }
+
+                {content}
+
+                label {
+                    color: "red",
+
+                    "{error_string}"
+                }
+
+                Link {
+                    to: crate::Route::Login,
+
+                    label {
+                        color: "#454545",
+                        font_size: "16",
+
+                        "Back to login"
+                    }
+                }
+            }
+        }
+    }
+}
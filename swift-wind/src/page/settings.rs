@@ -1,6 +1,703 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use dioxus_router::prelude::navigator;
 use freya::prelude::*;
+use matrix_sdk::media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings};
+use ruma::events::room::MediaSource;
+use ruma::{OwnedDeviceId, UInt, api::client::media::get_content_thumbnail::v3::Method};
+use swift_wind::matrix_uiaa::UiaaOperation;
+use tracing::warn;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+use crate::Route;
+use crate::components::additional_authorization::{PendingOperation, UiaaPrompt};
+use crate::components::form::FormField;
+use crate::components::session_tile::SessionTile;
+use crate::hook::account::{
+    use_matrix_profile, use_matrix_set_avatar, use_matrix_set_display_name,
+};
+use crate::hook::backup::{
+    BackupAction, use_matrix_backup_action, use_matrix_backup_status, use_matrix_backup_upload,
+    use_matrix_restore_backup,
+};
+use crate::hook::devices::{use_matrix_devices, use_matrix_rename_device};
+use crate::hook::key_export::{use_matrix_export_keys, use_matrix_import_keys};
+use crate::hook::session::use_matrix_logout;
+use crate::hook::verification::use_matrix_request_verification;
 
 #[component]
 pub fn Settings() -> Element {
-    rsx!()
+    let navigator = navigator();
+
+    let mut profile = use_matrix_profile();
+    let mut form_display_name = use_signal(String::new);
+    let mut form_avatar_path = use_signal(String::new);
+
+    // Fill the form with the current name once the profile is loaded
+    use_effect(move || {
+        if let Some(Ok(loaded)) = &*profile.read() {
+            form_display_name.set(loaded.display_name.clone().unwrap_or_default());
+        }
+    });
+
+    let (display_name_err, mut run_set_display_name) =
+        use_matrix_set_display_name(move || profile.restart());
+    let (avatar_err, run_set_avatar) = use_matrix_set_avatar(move || profile.restart());
+
+    let avatar_data = use_resource(move || async move {
+        let avatar_url = profile.read().as_ref()?.as_ref().ok()?.avatar_url.clone()?;
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return None;
+        };
+        let request = MediaRequestParameters {
+            source: MediaSource::Plain(avatar_url),
+            format: MediaFormat::Thumbnail(MediaThumbnailSettings::with_method(
+                Method::Crop,
+                UInt::from(96u32),
+                UInt::from(96u32),
+            )),
+        };
+        match client.media().get_media_content(&request, true).await {
+            Ok(data) => Some(data),
+            Err(err) => {
+                warn!("failed to load avatar {:?}", err);
+                None
+            }
+        }
+    });
+
+    let mut form_password = use_signal(String::new);
+    let mut form_confirm = use_signal(String::new);
+    let mut password_err = use_signal(String::new);
+    let mut logout_devices = use_signal(|| true);
+    let mut password_changed = use_signal(|| false);
+    let mut erase = use_signal(|| false);
+
+    let mut pending_password: Signal<Option<PendingOperation>> = use_signal(|| None);
+    let mut pending_deactivation: Signal<Option<PendingOperation>> = use_signal(|| None);
+    let mut pending_sign_out: Signal<Option<PendingOperation>> = use_signal(|| None);
+    let mut next_operation_id = use_signal(|| 0u32);
+    let mut new_operation = move |operation: UiaaOperation| {
+        *next_operation_id.write() += 1;
+        PendingOperation {
+            id: next_operation_id(),
+            operation,
+        }
+    };
+
+    let (logout_err, mut run_logout) = use_matrix_logout(move || {
+        navigator.replace(Route::Login);
+    });
+
+    let on_change_password = move |_| {
+        password_err.write().clear();
+        password_changed.set(false);
+        if form_password().is_empty() {
+            *password_err.write() = "Choose a new password".to_string();
+            return;
+        }
+        if form_password() != form_confirm() {
+            *password_err.write() = "The passwords don't match".to_string();
+            return;
+        }
+        let operation = UiaaOperation::change_password(form_password(), logout_devices());
+        *pending_password.write() = Some(new_operation(operation));
+    };
+
+    let mut devices = use_matrix_devices();
+    let mut selected_devices: Signal<BTreeSet<OwnedDeviceId>> = use_signal(BTreeSet::new);
+    let (rename_err, run_rename_device) = use_matrix_rename_device(move || devices.restart());
+    let (verification_err, run_request_verification) = use_matrix_request_verification();
+
+    let mut sign_out = move |device_ids: Vec<OwnedDeviceId>| {
+        if device_ids.is_empty() {
+            return;
+        }
+        let operation = UiaaOperation::delete_devices(device_ids);
+        *pending_sign_out.write() = Some(new_operation(operation));
+    };
+
+    let selected_count = selected_devices.read().len();
+
+    let mut backup = use_matrix_backup_status();
+    let (upload_progress, mut watch_upload) = use_matrix_backup_upload();
+    let (backup_err, run_backup_action, backup_recovery_key) =
+        use_matrix_backup_action(move || {
+            backup.restart();
+            watch_upload();
+        });
+    let (restore_err, mut run_restore_backup, restore_progress) =
+        use_matrix_restore_backup(move || backup.restart());
+    let mut form_backup_key = use_signal(String::new);
+    let mut form_backup_passphrase = use_signal(String::new);
+    let mut confirm_backup_delete = use_signal(|| false);
+
+    let backup_summary = match &*backup.read() {
+        None => "Checking your key backup…".to_string(),
+        Some(Err(err)) => format!("Couldn't check your key backup: {err}"),
+        Some(Ok(status)) if !status.exists_on_server => {
+            "Your keys aren't backed up, you'll lose your encrypted history if you lose all your sessions".to_string()
+        }
+        Some(Ok(status)) if status.enabled_here => {
+            "This session backs up its keys".to_string()
+        }
+        Some(Ok(_)) => {
+            "Your account has a key backup, restore it to use it on this session".to_string()
+        }
+    };
+    let (export_err, mut run_export_keys, exported_count) = use_matrix_export_keys();
+    let (import_err, mut run_import_keys, import_progress) = use_matrix_import_keys();
+    let mut form_export_path = use_signal(String::new);
+    let mut form_export_passphrase = use_signal(String::new);
+    let mut form_export_confirm = use_signal(String::new);
+    let mut form_import_path = use_signal(String::new);
+    let mut form_import_passphrase = use_signal(String::new);
+    let mut export_passphrase_err = use_signal(String::new);
+
+    let on_export_keys = move |_| {
+        export_passphrase_err.write().clear();
+        if form_export_passphrase() != form_export_confirm() {
+            *export_passphrase_err.write() = "The passphrases don't match".to_string();
+            return;
+        }
+        run_export_keys(
+            PathBuf::from(form_export_path().trim()),
+            form_export_passphrase(),
+        );
+    };
+
+    let import_summary = import_progress().map(|progress| {
+        if progress.total == 0 {
+            format!("Imported {} sessions…", progress.imported)
+        } else {
+            format!(
+                "Imported {} of {} sessions, the others were already known",
+                progress.imported, progress.total
+            )
+        }
+    });
+
+    let backup_exists = matches!(&*backup.read(), Some(Ok(status)) if status.exists_on_server);
+
+    let on_deactivate = move |_| {
+        let operation = UiaaOperation::deactivate(erase());
+        *pending_deactivation.write() = Some(new_operation(operation));
+    };
+
+    let set_avatar_from_drop = {
+        let mut run_set_avatar = run_set_avatar.clone();
+        move |e: Event<FileData>| {
+            if let Some(path) = e.file_path.clone() {
+                run_set_avatar(Some(path));
+            }
+        }
+    };
+
+    rsx!(
+        ScrollView {
+            rect {
+                width: "100%",
+                padding: "20",
+                spacing: "10",
+                direction: "vertical",
+
+                Link {
+                    to: Route::MainInterface,
+
+                    label {
+                        color: "#454545",
+                        font_size: "16",
+
+                        "Back"
+                    }
+                }
+
+                label {
+                    color: "#454545",
+                    font_size: "36",
+                    font_weight: "bold",
+
+                    "Settings"
+                }
+
+                label { font_size: "24", "Profile" }
+
+                if let Some(Err(err)) = &*profile.read() {
+                    label {
+                        color: "red",
+
+                        "Couldn't load your profile: {err}"
+                    }
+                }
+
+                rect {
+                    direction: "horizontal",
+                    spacing: "10",
+                    cross_align: "center",
+                    onfiledrop: set_avatar_from_drop,
+
+                    rect {
+                        width: "96",
+                        height: "96",
+                        corner_radius: "48",
+                        overflow: "clip",
+                        background: "#d9d9d9",
+                        main_align: "center",
+                        cross_align: "center",
+
+                        if let Some(Some(data)) = avatar_data.read().as_ref() {
+                            image {
+                                width: "96",
+                                height: "96",
+                                image_data: dynamic_bytes(data.clone()),
+                            }
+                        } else {
+                            label { "Drop a picture" }
+                        }
+                    }
+
+                    rect {
+                        spacing: "5",
+
+                        FormField {
+                            name: "Picture file",
+                            value: form_avatar_path,
+                            placeholder: "/path/to/picture.png",
+                            onchange: move |txt| {
+                                *form_avatar_path.write() = txt;
+                             },
+                        }
+
+                        rect {
+                            direction: "horizontal",
+                            spacing: "5",
+
+                            Button {
+                                onclick: {
+                                    let mut run_set_avatar = run_set_avatar.clone();
+                                    move |_| run_set_avatar(Some(PathBuf::from(form_avatar_path().trim())))
+                                },
+                                label { "Upload avatar" }
+                            }
+
+                            Button {
+                                onclick: {
+                                    let mut run_set_avatar = run_set_avatar.clone();
+                                    move |_| run_set_avatar(None)
+                                },
+                                label { "Remove avatar" }
+                            }
+                        }
+                    }
+                }
+
+                label {
+                    color: "red",
+
+                    "{avatar_err}"
+                }
+
+                FormField {
+                    name: "Display name",
+                    value: form_display_name,
+                    errors: display_name_err,
+                    onchange: move |txt| {
+                        *form_display_name.write() = txt;
+                     },
+                }
+
+                Button {
+                    onclick: move |_| run_set_display_name(form_display_name()),
+                    label { "Save display name" }
+                }
+
+                label { font_size: "24", "Password" }
+
+                FormField {
+                    name: "New password",
+                    value: form_password,
+                    hidden: true,
+                    onchange: move |txt| {
+                        *form_password.write() = txt;
+                     },
+                }
+
+                FormField {
+                    name: "Confirm new password",
+                    value: form_confirm,
+                    hidden: true,
+                    errors: password_err,
+                    onchange: move |txt| {
+                        *form_confirm.write() = txt;
+                     },
+                }
+
+                Tile {
+                    onselect: move |_| logout_devices.toggle(),
+                    leading: rsx!(
+                        Checkbox {
+                            selected: logout_devices(),
+                        }
+                    ),
+                    label { "Sign out other devices" }
+                }
+
+                Button {
+                    onclick: on_change_password,
+                    label { "Change password" }
+                }
+
+                if let Some(pending) = pending_password() {
+                    UiaaPrompt {
+                        key: "{pending.id}",
+                        operation: pending.operation,
+                        ondone: move |_| {
+                            pending_password.set(None);
+                            form_password.write().clear();
+                            form_confirm.write().clear();
+                            password_changed.set(true);
+                        },
+                    }
+                }
+
+                if password_changed() {
+                    label { "Your password was changed" }
+                }
+
+                label { font_size: "24", "Sessions" }
+
+                match &*devices.read() {
+                    None => rsx!(label { "Loading sessions…" }),
+                    Some(Err(err)) => rsx!(
+                        label {
+                            color: "red",
+
+                            "Couldn't load your sessions: {err}"
+                        }
+                    ),
+                    Some(Ok(list)) => rsx!(
+                        {list.iter().cloned().map(|device| {
+                            let device_id = device.device_id.clone();
+                            let is_selected = selected_devices.read().contains(&device_id);
+                            let mut run_rename_device = run_rename_device.clone();
+                            let mut run_request_verification = run_request_verification.clone();
+                            rsx!(
+                                SessionTile {
+                                    key: "{device_id}",
+                                    device,
+                                    selected: is_selected,
+                                    onselect: {
+                                        let device_id = device_id.clone();
+                                        move |_| {
+                                            let mut selected = selected_devices.write();
+                                            if !selected.remove(&device_id) {
+                                                selected.insert(device_id.clone());
+                                            }
+                                        }
+                                    },
+                                    onrename: {
+                                        let device_id = device_id.clone();
+                                        move |name| run_rename_device(device_id.clone(), name)
+                                    },
+                                    onsignout: {
+                                        let device_id = device_id.clone();
+                                        move |_| sign_out(vec![device_id.clone()])
+                                    },
+                                    onverify: move |_| {
+                                        let MatrixClientState::Connected(client) = CLIENT() else {
+                                            return;
+                                        };
+                                        let Some(user_id) = client.user_id() else {
+                                            return;
+                                        };
+                                        run_request_verification(user_id.to_owned(), Some(device_id.clone()));
+                                    },
+                                }
+                            )
+                        })}
+                    ),
+                }
+
+                label {
+                    color: "red",
+
+                    "{rename_err}"
+                }
+
+                label {
+                    color: "red",
+
+                    "{verification_err}"
+                }
+
+                if selected_count > 0 {
+                    Button {
+                        onclick: move |_| sign_out(selected_devices().into_iter().collect()),
+                        label {
+                            color: "red",
+
+                            "Sign out selected sessions ({selected_count})"
+                        }
+                    }
+                }
+
+                if let Some(pending) = pending_sign_out() {
+                    UiaaPrompt {
+                        key: "{pending.id}",
+                        operation: pending.operation,
+                        ondone: move |_| {
+                            pending_sign_out.set(None);
+                            selected_devices.write().clear();
+                            devices.restart();
+                        },
+                    }
+                }
+
+                label { font_size: "24", "Key backup" }
+
+                label { "{backup_summary}" }
+
+                if let Some((backed_up, total)) = upload_progress() {
+                    label {
+                        color: "#454545",
+
+                        "Backed up {backed_up} of {total} keys"
+                    }
+                }
+
+                if backup_exists {
+                    FormField {
+                        name: "Recovery key or passphrase",
+                        value: form_backup_key,
+                        hidden: true,
+                        errors: restore_err,
+                        onchange: move |txt| {
+                            *form_backup_key.write() = txt;
+                         },
+                    }
+
+                    Button {
+                        onclick: move |_| run_restore_backup(form_backup_key()),
+                        label { "Restore from backup" }
+                    }
+
+                    if let Some(progress) = restore_progress() {
+                        label {
+                            "Restored the keys of {progress.rooms_done} of {progress.rooms_total} rooms"
+                        }
+                    }
+
+                    FormField {
+                        name: "New passphrase for the reset (optional)",
+                        value: form_backup_passphrase,
+                        hidden: true,
+                        onchange: move |txt| {
+                            *form_backup_passphrase.write() = txt;
+                         },
+                    }
+
+                    rect {
+                        direction: "horizontal",
+                        spacing: "5",
+
+                        Button {
+                            onclick: {
+                                let mut run_backup_action = run_backup_action.clone();
+                                move |_| {
+                                    let passphrase = Some(form_backup_passphrase())
+                                        .filter(|passphrase| !passphrase.is_empty());
+                                    run_backup_action(BackupAction::Reset(passphrase));
+                                }
+                            },
+                            label { "Reset backup" }
+                        }
+
+                        if confirm_backup_delete() {
+                            Button {
+                                onclick: {
+                                    let mut run_backup_action = run_backup_action.clone();
+                                    move |_| {
+                                        confirm_backup_delete.set(false);
+                                        run_backup_action(BackupAction::Delete);
+                                    }
+                                },
+                                label {
+                                    color: "red",
+
+                                    "Really delete the backup"
+                                }
+                            }
+                        } else {
+                            Button {
+                                onclick: move |_| confirm_backup_delete.set(true),
+                                label {
+                                    color: "red",
+
+                                    "Delete backup"
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    Button {
+                        onclick: {
+                            let mut run_backup_action = run_backup_action.clone();
+                            move |_| run_backup_action(BackupAction::Enable)
+                        },
+                        label { "Back up my keys" }
+                    }
+                }
+
+                if let Some(key) = backup_recovery_key() {
+                    label {
+                        "Your recovery key changed, save the new one somewhere safe:"
+                    }
+
+                    label {
+                        font_size: "20",
+                        font_family: "monospace",
+
+                        "{key}"
+                    }
+                }
+
+                label {
+                    color: "red",
+
+                    "{backup_err}"
+                }
+
+                label { font_size: "24", "Export room keys" }
+
+                label {
+                    "Save the keys of your encrypted messages to a file protected by a passphrase, Element and other clients can import it."
+                }
+
+                FormField {
+                    name: "File",
+                    value: form_export_path,
+                    placeholder: "/path/to/element-keys.txt",
+                    onchange: move |txt| {
+                        *form_export_path.write() = txt;
+                     },
+                }
+
+                FormField {
+                    name: "Passphrase",
+                    value: form_export_passphrase,
+                    hidden: true,
+                    onchange: move |txt| {
+                        *form_export_passphrase.write() = txt;
+                     },
+                }
+
+                FormField {
+                    name: "Confirm passphrase",
+                    value: form_export_confirm,
+                    hidden: true,
+                    errors: export_passphrase_err,
+                    onchange: move |txt| {
+                        *form_export_confirm.write() = txt;
+                     },
+                }
+
+                Button {
+                    onclick: on_export_keys,
+                    label { "Export keys" }
+                }
+
+                if let Some(count) = exported_count() {
+                    label { "Exported {count} sessions" }
+                }
+
+                label {
+                    color: "red",
+
+                    "{export_err}"
+                }
+
+                label { font_size: "24", "Import room keys" }
+
+                FormField {
+                    name: "File",
+                    value: form_import_path,
+                    placeholder: "/path/to/element-keys.txt",
+                    onchange: move |txt| {
+                        *form_import_path.write() = txt;
+                     },
+                }
+
+                FormField {
+                    name: "Passphrase",
+                    value: form_import_passphrase,
+                    hidden: true,
+                    errors: import_err,
+                    onchange: move |txt| {
+                        *form_import_passphrase.write() = txt;
+                     },
+                }
+
+                Button {
+                    onclick: move |_| {
+                        run_import_keys(
+                            PathBuf::from(form_import_path().trim()),
+                            form_import_passphrase(),
+                        )
+                    },
+                    label { "Import keys" }
+                }
+
+                if let Some(summary) = import_summary {
+                    label { "{summary}" }
+                }
+
+                label { font_size: "24", "Deactivate account" }
+
+                label {
+                    color: "#454545",
+
+                    "Deactivating your account can't be undone, nobody will be able to sign in to it or register its username again."
+                }
+
+                Tile {
+                    onselect: move |_| erase.toggle(),
+                    leading: rsx!(
+                        Checkbox {
+                            selected: erase(),
+                        }
+                    ),
+                    label { "Also erase the messages I sent" }
+                }
+
+                Button {
+                    onclick: on_deactivate,
+                    label {
+                        color: "red",
+
+                        "Deactivate account"
+                    }
+                }
+
+                if let Some(pending) = pending_deactivation() {
+                    UiaaPrompt {
+                        key: "{pending.id}",
+                        operation: pending.operation,
+                        ondone: move |_| {
+                            pending_deactivation.set(None);
+                            // The homeserver already dropped the session, this only forgets it here
+                            run_logout();
+                        },
+                    }
+                }
+
+                label {
+                    color: "red",
+
+                    "{logout_err}"
+                }
+            }
+        }
+    )
 }
@@ -0,0 +1,272 @@
+use dioxus_router::prelude::navigator;
+use freya::prelude::*;
+use swift_wind::matrix_recovery::EncryptionSetup;
+use swift_wind::matrix_uiaa::UiaaOperation;
+
+use crate::CLIENT;
+use crate::MatrixClientState;
+use crate::Route;
+use crate::components::additional_authorization::{PendingOperation, UiaaPrompt};
+use crate::components::form::FormField;
+use crate::hook::backup::use_matrix_restore_backup;
+use crate::hook::recovery::{use_matrix_enable_recovery, use_matrix_encryption_setup};
+use crate::hook::verification::use_matrix_request_verification;
+
+/// First-login wizard for cross-signing and secret storage
+#[component]
+pub fn SetupEncryption() -> Element {
+    let navigator = navigator();
+
+    let mut setup = use_matrix_encryption_setup();
+    let (recovery_err, run_enable_recovery, recovery_key) = use_matrix_enable_recovery();
+    let (unlock_err, mut run_unlock, restore_progress) =
+        use_matrix_restore_backup(move || setup.restart());
+    let (verification_err, mut run_request_verification) = use_matrix_request_verification();
+
+    let mut form_passphrase = use_signal(String::new);
+    let mut form_confirm = use_signal(String::new);
+    let mut form_key = use_signal(String::new);
+    let mut passphrase_err = use_signal(String::new);
+    let mut pending_bootstrap: Signal<Option<PendingOperation>> = use_signal(|| None);
+    let mut next_operation_id = use_signal(|| 0u32);
+
+    // A blank passphrase leaves only the recovery key
+    let mut checked_passphrase = move || {
+        passphrase_err.write().clear();
+        if form_passphrase() != form_confirm() {
+            *passphrase_err.write() = "The passphrases don't match".to_string();
+            return None;
+        }
+        Some(Some(form_passphrase()).filter(|passphrase| !passphrase.is_empty()))
+    };
+
+    let on_bootstrap = move |_| {
+        if checked_passphrase().is_none() {
+            return;
+        }
+        *next_operation_id.write() += 1;
+        *pending_bootstrap.write() = Some(PendingOperation {
+            id: next_operation_id(),
+            operation: UiaaOperation::bootstrap_cross_signing(),
+        });
+    };
+
+    let on_enable_recovery = {
+        let mut run_enable_recovery = run_enable_recovery.clone();
+        move |_| {
+            if let Some(passphrase) = checked_passphrase() {
+                run_enable_recovery(passphrase);
+            }
+        }
+    };
+
+    let verify_with_other_session = move |_| {
+        let MatrixClientState::Connected(client) = CLIENT() else {
+            return;
+        };
+        let Some(user_id) = client.user_id() else {
+            return;
+        };
+        run_request_verification(user_id.to_owned(), None);
+    };
+
+    let passphrase_fields = rsx!(
+        FormField {
+            name: "Passphrase (optional)",
+            value: form_passphrase,
+            hidden: true,
+            placeholder: "Unlocks your encryption keys like the recovery key does",
+            onchange: move |txt| {
+                *form_passphrase.write() = txt;
+             },
+        }
+
+        FormField {
+            name: "Confirm passphrase",
+            value: form_confirm,
+            hidden: true,
+            errors: passphrase_err,
+            onchange: move |txt| {
+                *form_confirm.write() = txt;
+             },
+        }
+    );
+
+    let content = match (recovery_key(), &*setup.read()) {
+        (Some(key), _) => rsx!(
+            label {
+                "Save this recovery key somewhere safe, it's the only way to read your encrypted messages if you lose all your sessions."
+            }
+
+            label {
+                font_size: "20",
+                font_family: "monospace",
+
+                "{key}"
+            }
+
+            Button {
+                onclick: move |_| {
+                    navigator.replace(Route::MainInterface);
+                },
+                label { "I saved it" }
+            }
+        ),
+        (None, None) => rsx!(Loader {}),
+        (None, Some(Err(err))) => rsx!(
+            label {
+                color: "red",
+
+                "Couldn't check your encryption setup: {err}"
+            }
+
+            Button {
+                onclick: move |_| setup.restart(),
+                label { "Try again" }
+            }
+        ),
+        (None, Some(Ok(EncryptionSetup::Bootstrap))) => rsx!(
+            label {
+                "Encryption isn't set up for your account yet. swift-wind will create the keys that verify your sessions and a recovery key to get them back on new sessions."
+            }
+
+            {passphrase_fields}
+
+            Button {
+                onclick: on_bootstrap,
+                label { "Set up encryption" }
+            }
+
+            if let Some(pending) = pending_bootstrap() {
+                UiaaPrompt {
+                    key: "{pending.id}",
+                    operation: pending.operation,
+                    ondone: {
+                        let mut run_enable_recovery = run_enable_recovery.clone();
+                        move |_| {
+                            pending_bootstrap.set(None);
+                            run_enable_recovery(Some(form_passphrase()).filter(|passphrase| !passphrase.is_empty()));
+                        }
+                    },
+                }
+            }
+        ),
+        (None, Some(Ok(EncryptionSetup::EnableRecovery))) => rsx!(
+            label {
+                "Your sessions are verified but your keys can't be recovered yet. Create a recovery key to read your messages on new sessions."
+            }
+
+            {passphrase_fields}
+
+            Button {
+                onclick: on_enable_recovery,
+                label { "Create recovery key" }
+            }
+        ),
+        (None, Some(Ok(EncryptionSetup::Unlock))) => rsx!(
+            label {
+                "Enter your recovery key or passphrase to verify this session and read your encrypted messages."
+            }
+
+            FormField {
+                name: "Recovery key or passphrase",
+                value: form_key,
+                hidden: true,
+                errors: unlock_err,
+                onchange: move |txt| {
+                    *form_key.write() = txt;
+                 },
+            }
+
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+
+                Button {
+                    onclick: move |_| run_unlock(form_key()),
+                    label { "Unlock" }
+                }
+
+                Button {
+                    onclick: verify_with_other_session,
+                    label { "Verify with another session instead" }
+                }
+            }
+
+            if let Some(progress) = restore_progress() {
+                label {
+                    "Restored the keys of {progress.rooms_done} of {progress.rooms_total} rooms from your backup"
+                }
+            }
+        ),
+        (None, Some(Ok(EncryptionSetup::VerifyWithOtherSession))) => rsx!(
+            label {
+                "Your account has no recovery key, verify this session from one of your other sessions."
+            }
+
+            rect {
+                direction: "horizontal",
+                spacing: "5",
+
+                Button {
+                    onclick: verify_with_other_session,
+                    label { "Verify with another session" }
+                }
+
+                Button {
+                    onclick: move |_| setup.restart(),
+                    label { "Check again" }
+                }
+            }
+        ),
+        (None, Some(Ok(EncryptionSetup::Done))) => rsx!(
+            label {
+                color: "green",
+
+                "Encryption is set up for this session"
+            }
+        ),
+    };
+
+    rsx!(
+        ScrollView {
+            rect {
+                width: "100%",
+                padding: "20",
+                spacing: "10",
+                direction: "vertical",
+
+                label {
+                    color: "#454545",
+                    font_size: "36",
+                    font_weight: "bold",
+
+                    "Encryption"
+                }
+
+                {content}
+
+                label {
+                    color: "red",
+
+                    "{recovery_err}"
+                }
+
+                label {
+                    color: "red",
+
+                    "{verification_err}"
+                }
+
+                if recovery_key().is_none() {
+                    Button {
+                        onclick: move |_| {
+                            navigator.replace(Route::MainInterface);
+                        },
+                        label { "Not now" }
+                    }
+                }
+            }
+        }
+    )
+}
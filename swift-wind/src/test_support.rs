@@ -0,0 +1,80 @@
+//! Fixtures shared by the tests of the `matrix_*` modules.
+
+use matrix_sdk::{
+    Client, SessionMeta,
+    authentication::matrix::{MatrixSession, MatrixSessionTokens},
+};
+use ruma::{DeviceId, UserId, device_id, user_id};
+use serde_json::json;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+/// Answers `/versions` like a homeserver supporting the spec versions the SDK needs.
+pub(crate) async fn mock_versions(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["v1.1", "v1.11"]
+        })))
+        .mount(server)
+        .await;
+}
+
+/// Client of `server` that isn't logged in.
+pub(crate) async fn client(server: &MockServer) -> Client {
+    mock_versions(server).await;
+    Client::builder()
+        .homeserver_url(server.uri())
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Client of `server` logged in as `@alice:example.org` on device `DEVICE`.
+pub(crate) async fn logged_in_client(server: &MockServer) -> Client {
+    logged_in_client_on(server, device_id!("DEVICE")).await
+}
+
+/// Like [`logged_in_client`] on another device.
+pub(crate) async fn logged_in_client_on(server: &MockServer, device_id: &DeviceId) -> Client {
+    let client = client(server).await;
+    restore(&client, user_id!("@alice:example.org"), device_id).await;
+    client
+}
+
+/// Like [`logged_in_client`] as another user.
+pub(crate) async fn logged_in_client_as(server: &MockServer, user_id: &UserId) -> Client {
+    let client = client(server).await;
+    restore(&client, user_id, device_id!("DEVICE")).await;
+    client
+}
+
+/// Client logged in as `user_id` on a homeserver that is never contacted.
+pub(crate) async fn offline_client(user_id: &UserId) -> Client {
+    let client = Client::builder()
+        .homeserver_url("http://localhost:8008")
+        .build()
+        .await
+        .unwrap();
+    restore(&client, user_id, device_id!("DEVICE")).await;
+    client
+}
+
+async fn restore(client: &Client, user_id: &UserId, device_id: &DeviceId) {
+    client
+        .matrix_auth()
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id.to_owned(),
+                device_id: device_id.to_owned(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: "access".to_owned(),
+                refresh_token: None,
+            },
+        })
+        .await
+        .unwrap();
+}